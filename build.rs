@@ -0,0 +1,180 @@
+// Packs the `rootfs/` directory into a USTAR archive that the kernel embeds as its
+// initial ramdisk (see `kernel::fs::initrd`).
+//
+// root's account is locked unless ZERO_ROOT_PASSWORD is set at build time, in which case
+// its hash goes into the packed /etc/shadow.
+
+extern crate alloc;
+
+// shared with the kernel, so the hashes made here verify at login
+#[allow(dead_code)]
+#[path = "src/kernel/password.rs"]
+mod password;
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const BLOCK_SIZE: usize = 512;
+const ROOT_PASSWORD_VAR: &str = "ZERO_ROOT_PASSWORD";
+
+// modes git can't record; everything else keeps the mode it has on disk
+const MODES: &[(&str, u32)] = &[("etc/shadow", 0o600)];
+
+fn main() {
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("rootfs");
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("initrd.tar");
+
+    println!("cargo:rerun-if-changed=rootfs");
+    println!("cargo:rerun-if-env-changed={}", ROOT_PASSWORD_VAR);
+
+    let mut archive = Vec::new();
+    if root.is_dir() {
+        pack_dir(&root, &root, &mut archive).expect("failed to pack rootfs");
+    }
+    // end-of-archive marker
+    archive.extend_from_slice(&[0u8; BLOCK_SIZE * 2]);
+
+    fs::File::create(&out)
+        .and_then(|mut f| f.write_all(&archive))
+        .expect("failed to write initrd.tar");
+}
+
+fn pack_dir(root: &Path, dir: &Path, archive: &mut Vec<u8>) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = path
+            .strip_prefix(root)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        if entry.file_name() == ".gitkeep" {
+            continue;
+        }
+
+        println!("cargo:rerun-if-changed={}", path.display());
+        let meta = entry.metadata()?;
+        if meta.file_type().is_symlink() {
+            let target = fs::read_link(&path)?.to_string_lossy().replace('\\', "/");
+            write_header(archive, &name, 0o777, 0, b'2', &target);
+        } else if meta.is_dir() {
+            write_header(
+                archive,
+                &format!("{}/", name),
+                mode(&meta, 0o755),
+                0,
+                b'5',
+                "",
+            );
+            pack_dir(root, &path, archive)?;
+        } else if meta.is_file() {
+            let mut data = fs::read(&path)?;
+            if name == "etc/shadow" {
+                data = set_root_password(&data);
+            }
+            let mode = match MODES.iter().find(|(path, _)| *path == name) {
+                Some(&(_, mode)) => mode,
+                None => mode(&meta, 0o644),
+            };
+            write_header(archive, &name, mode, data.len(), b'0', "");
+            archive.extend_from_slice(&data);
+            let padding = (BLOCK_SIZE - data.len() % BLOCK_SIZE) % BLOCK_SIZE;
+            archive.extend(std::iter::repeat_n(0, padding));
+        }
+    }
+    Ok(())
+}
+
+// replaces root's hash in a shadow file with one of ZERO_ROOT_PASSWORD, if it is set
+fn set_root_password(shadow: &[u8]) -> Vec<u8> {
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+
+    let Ok(password) = env::var(ROOT_PASSWORD_VAR) else {
+        return shadow.to_vec();
+    };
+    let salt = format!("{:016x}", RandomState::new().hash_one(&password));
+    let hash = password::hash(&salt, &password);
+    let mut out = String::new();
+    for line in String::from_utf8_lossy(shadow).lines() {
+        match line.split_once(':') {
+            Some(("root", rest)) => {
+                let rest = rest.split_once(':').map_or("", |(_, rest)| rest);
+                out.push_str(&format!("root:{}:{}\n", hash, rest));
+            }
+            _ => {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+    out.into_bytes()
+}
+
+#[cfg(unix)]
+fn mode(meta: &fs::Metadata, _default: u32) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode(_meta: &fs::Metadata, default: u32) -> u32 {
+    default
+}
+
+fn write_header(
+    archive: &mut Vec<u8>,
+    name: &str,
+    mode: u32,
+    size: usize,
+    typeflag: u8,
+    linkname: &str,
+) {
+    let mut header = [0u8; BLOCK_SIZE];
+
+    // long names go into the 155 byte prefix field, split at a '/'
+    let (prefix, name) = if name.len() > 100 {
+        let split = name[..name.len().min(155)]
+            .rfind('/')
+            .expect("rootfs path too long for ustar");
+        (&name[..split], &name[split + 1..])
+    } else {
+        ("", name)
+    };
+    assert!(name.len() <= 100, "rootfs file name too long: {}", name);
+    assert!(
+        linkname.len() <= 100,
+        "rootfs link target too long: {}",
+        linkname
+    );
+
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], mode as u64);
+    write_octal(&mut header[108..116], 0); // uid
+    write_octal(&mut header[116..124], 0); // gid
+    write_octal(&mut header[124..136], size as u64);
+    write_octal(&mut header[136..148], 0); // mtime
+    header[156] = typeflag;
+    header[157..157 + linkname.len()].copy_from_slice(linkname.as_bytes());
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // checksum is computed with the checksum field itself set to spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    write_octal(&mut header[148..155], checksum as u64);
+
+    archive.extend_from_slice(&header);
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let s = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(s.as_bytes());
+    field[digits] = 0;
+}
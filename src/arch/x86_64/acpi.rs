@@ -0,0 +1,96 @@
+use crate::kernel::memory::memory::phys_to_virt;
+use x86_64::PhysAddr;
+
+// every system description table starts with this 36-byte header
+const HEADER_SIZE: usize = 36;
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+unsafe fn read<T: Copy>(addr: u64) -> T {
+    let ptr = phys_to_virt(PhysAddr::new(addr)).as_ptr::<T>();
+    unsafe { core::ptr::read_unaligned(ptr) }
+}
+
+fn checksum_ok(addr: u64, len: usize) -> bool {
+    let sum = (0..len as u64).fold(0u8, |sum, i| {
+        sum.wrapping_add(unsafe { read::<u8>(addr + i) })
+    });
+    sum == 0
+}
+
+/// Looks for the root system description pointer where the BIOS leaves it: in the first
+/// KiB of the extended BIOS data area, or in the read-only area below 1 MiB.
+fn find_rsdp() -> Option<u64> {
+    let ebda = (unsafe { read::<u16>(0x40E) } as u64) << 4;
+    let areas = [(ebda, ebda + 1024), (0xE0000, 0x100000)];
+    areas
+        .iter()
+        .filter(|(start, _)| *start != 0)
+        .flat_map(|&(start, end)| (start..end).step_by(16))
+        .find(|&addr| unsafe { read::<[u8; 8]>(addr) } == *RSDP_SIGNATURE && checksum_ok(addr, 20))
+}
+
+/// Returns the physical address and length of the first ACPI table with `signature`.
+pub fn find_table(signature: &[u8; 4]) -> Option<(PhysAddr, usize)> {
+    let rsdp = find_rsdp()?;
+    let revision: u8 = unsafe { read(rsdp + 15) };
+
+    // ACPI 2.0 adds the XSDT, whose entries are 64-bit
+    let (root, entry_size) = if revision >= 2 {
+        (unsafe { read::<u64>(rsdp + 24) }, 8)
+    } else {
+        (unsafe { read::<u32>(rsdp + 16) } as u64, 4)
+    };
+    let root_len: u32 = unsafe { read(root + 4) };
+    if !checksum_ok(root, root_len as usize) {
+        return None;
+    }
+
+    let entries = (root_len as usize).saturating_sub(HEADER_SIZE) / entry_size;
+    (0..entries)
+        .map(|i| {
+            let at = root + (HEADER_SIZE + i * entry_size) as u64;
+            if entry_size == 8 {
+                unsafe { read::<u64>(at) }
+            } else {
+                unsafe { read::<u32>(at) as u64 }
+            }
+        })
+        .find(|&table| unsafe { read::<[u8; 4]>(table) } == *signature)
+        .map(|table| {
+            (PhysAddr::new(table), unsafe { read::<u32>(table + 4) }
+                as usize)
+        })
+}
+
+/// An ECAM region from the MCFG table: configuration space for buses
+/// `start_bus..=end_bus` of one PCI segment, mapped at `base`.
+#[derive(Debug, Clone, Copy)]
+pub struct EcamRegion {
+    pub base: PhysAddr,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// Reads the PCI Express memory-mapped configuration regions from the MCFG table.
+pub fn mcfg_regions() -> alloc::vec::Vec<EcamRegion> {
+    let (table, len) = match find_table(b"MCFG") {
+        Some(found) => found,
+        None => return alloc::vec::Vec::new(),
+    };
+    let table = table.as_u64();
+    // 8 reserved bytes follow the header, then one 16-byte entry per region
+    (HEADER_SIZE + 8..len)
+        .step_by(16)
+        .filter(|at| at + 16 <= len)
+        .map(|at| unsafe {
+            let entry = table + at as u64;
+            EcamRegion {
+                base: PhysAddr::new(read::<u64>(entry)),
+                segment: read(entry + 8),
+                start_bus: read(entry + 10),
+                end_bus: read(entry + 11),
+            }
+        })
+        .collect()
+}
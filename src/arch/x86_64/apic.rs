@@ -0,0 +1,74 @@
+use crate::kernel::memory::memory;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, Size4KiB};
+use x86_64::PhysAddr;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+// register offsets in the local APIC's MMIO page
+const REG_ID: u64 = 0x20;
+const REG_EOI: u64 = 0xB0;
+const REG_SPURIOUS: u64 = 0xF0;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+// virtual address of the local APIC registers, 0 until init
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+/// Maps and software-enables the local APIC so it accepts message-signalled interrupts.
+///
+/// The 8259 PICs keep delivering the timer and keyboard through LINT0, which the
+/// firmware leaves in virtual wire mode.
+pub fn init(mapper: &mut OffsetPageTable, frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+    let mut msr = Msr::new(IA32_APIC_BASE);
+    let value = unsafe { msr.read() };
+    let phys = PhysAddr::new(value & 0x000F_FFFF_FFFF_F000);
+
+    let virt = match memory::map_mmio(mapper, frame_allocator, phys, 4096) {
+        Ok(virt) => virt,
+        Err(e) => {
+            crate::println!("apic: can't map registers: {:?}", e);
+            return;
+        }
+    };
+    unsafe {
+        msr.write(value | APIC_BASE_ENABLE);
+    }
+    BASE.store(virt.as_u64(), Ordering::Relaxed);
+
+    unsafe {
+        write(REG_SPURIOUS, SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+    }
+}
+
+pub fn is_enabled() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+unsafe fn read(reg: u64) -> u32 {
+    let ptr = (BASE.load(Ordering::Relaxed) + reg) as *const u32;
+    unsafe { core::ptr::read_volatile(ptr) }
+}
+
+unsafe fn write(reg: u64, value: u32) {
+    let ptr = (BASE.load(Ordering::Relaxed) + reg) as *mut u32;
+    unsafe { core::ptr::write_volatile(ptr, value) }
+}
+
+/// The local APIC ID of this CPU, the destination for MSIs.
+pub fn id() -> u8 {
+    if !is_enabled() {
+        return 0;
+    }
+    (unsafe { read(REG_ID) } >> 24) as u8
+}
+
+/// Acknowledges an interrupt delivered through the local APIC (not the PICs).
+pub fn end_of_interrupt() {
+    if is_enabled() {
+        unsafe { write(REG_EOI, 0) }
+    }
+}
@@ -1,12 +1,19 @@
-use crate::arch::x86_64::gdt;
+use crate::arch::x86_64::trap::{trap_entry, TrapFrame};
+use crate::arch::x86_64::{apic, gdt};
 use crate::hlt_loop;
+use crate::kernel::kthread;
+use crate::kernel::signal::{self, Signal};
+use crate::kernel::thread;
 use crate::println;
 use lazy_static::lazy_static;
 use x86_64::structures::idt::PageFaultErrorCode;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 
+use core::sync::atomic::{AtomicU64, Ordering};
 use pic8259::ChainedPics;
 use spin;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::VirtAddr;
 //offsets from 32-47 to not overlap with the exceptions interrupts
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -30,6 +37,90 @@ impl InterruptIndex {
     }
 }
 
+// how often each vector has fired, for /proc/interrupts
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+fn count(vector: u8) {
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Vectors that have fired at least once, with their counts.
+pub fn interrupt_counts() -> alloc::vec::Vec<(u8, u64)> {
+    COUNTS
+        .iter()
+        .enumerate()
+        .map(|(vector, n)| (vector as u8, n.load(Ordering::Relaxed)))
+        .filter(|&(_, n)| n > 0)
+        .collect()
+}
+
+pub fn vector_name(vector: u8) -> &'static str {
+    match vector {
+        0 => "divide error",
+        3 => "breakpoint",
+        6 => "invalid opcode",
+        8 => "double fault",
+        13 => "general protection",
+        14 => "page fault",
+        v if v == InterruptIndex::Timer.as_u8() => "timer",
+        v if v == InterruptIndex::KeyBoard.as_u8() => "keyboard",
+        kthread::YIELD_VECTOR => "kthread yield",
+        v if (DYNAMIC_BASE..DYNAMIC_BASE + DYNAMIC_COUNT as u8).contains(&v) => "msi",
+        apic::SPURIOUS_VECTOR => "spurious",
+        _ => "",
+    }
+}
+
+// vectors handed out to drivers at runtime, e.g. for MSI; they are acknowledged at the local APIC
+pub const DYNAMIC_BASE: u8 = 0x50;
+pub const DYNAMIC_COUNT: usize = 16;
+
+type DynamicHandlers = [Option<fn()>; DYNAMIC_COUNT];
+
+static DYNAMIC_HANDLERS: spin::Mutex<DynamicHandlers> = spin::Mutex::new([None; DYNAMIC_COUNT]);
+
+/// Reserves a free vector that runs `handler` on each interrupt.
+pub fn allocate_vector(handler: fn()) -> Option<u8> {
+    without_interrupts(|| {
+        let mut handlers = DYNAMIC_HANDLERS.lock();
+        let slot = handlers.iter().position(|h| h.is_none())?;
+        handlers[slot] = Some(handler);
+        Some(DYNAMIC_BASE + slot as u8)
+    })
+}
+
+pub fn free_vector(vector: u8) {
+    let slot = vector.wrapping_sub(DYNAMIC_BASE) as usize;
+    if slot < DYNAMIC_COUNT {
+        without_interrupts(|| DYNAMIC_HANDLERS.lock()[slot] = None);
+    }
+}
+
+fn dynamic_interrupt(slot: usize) {
+    count(DYNAMIC_BASE + slot as u8);
+    // copied out so the handler may allocate or free vectors itself
+    let handler = DYNAMIC_HANDLERS.lock()[slot];
+    if let Some(handler) = handler {
+        handler();
+    }
+    apic::end_of_interrupt();
+}
+
+// one entry point per dynamic vector, since a handler can't tell which vector it was called for
+macro_rules! dynamic_handlers {
+    ($($slot:literal)*) => {
+        [$({
+            extern "x86-interrupt" fn handler(_stack_frame: InterruptStackFrame) {
+                dynamic_interrupt($slot);
+            }
+            handler as extern "x86-interrupt" fn(InterruptStackFrame)
+        }),*]
+    };
+}
+
+static DYNAMIC_ENTRIES: [extern "x86-interrupt" fn(InterruptStackFrame); DYNAMIC_COUNT] =
+    dynamic_handlers!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
@@ -39,9 +130,26 @@ lazy_static! {
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(time_interrupt_handler);
+        // these save every register, so signals can be delivered on the way back to ring 3
+        unsafe {
+            idt[InterruptIndex::Timer.as_usize()]
+                .set_handler_addr(VirtAddr::new(time_interrupt_entry as *const () as u64));
+            idt.page_fault
+                .set_handler_addr(VirtAddr::new(page_fault_entry as *const () as u64));
+            idt.general_protection_fault
+                .set_handler_addr(VirtAddr::new(general_protection_entry as *const () as u64));
+            idt.invalid_opcode
+                .set_handler_addr(VirtAddr::new(invalid_opcode_entry as *const () as u64));
+            idt.divide_error
+                .set_handler_addr(VirtAddr::new(divide_error_entry as *const () as u64));
+            idt[kthread::YIELD_VECTOR as usize]
+                .set_handler_addr(VirtAddr::new(kthread_yield_entry as *const () as u64));
+        }
         idt[InterruptIndex::KeyBoard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        for (slot, &entry) in DYNAMIC_ENTRIES.iter().enumerate() {
+            idt[DYNAMIC_BASE as usize + slot].set_handler_fn(entry);
+        }
+        idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(spurious_interrupt_handler);
         idt
     };
 }
@@ -51,22 +159,64 @@ pub fn init_idt() {
 //we could allocate our idt on a heap use Box and convert it into a 'static' refernce but havent
 //implemented a heap yet
 
-extern "x86-interrupt" fn page_fault_handler(
-    _stack_frame: InterruptStackFrame,
-    _error_code: PageFaultErrorCode,
-) {
+trap_entry!(page_fault_entry => page_fault_handler, error_code);
+trap_entry!(general_protection_entry => general_protection_handler, error_code);
+trap_entry!(invalid_opcode_entry => invalid_opcode_handler);
+trap_entry!(divide_error_entry => divide_error_handler);
+trap_entry!(time_interrupt_entry => time_interrupt_handler, switch);
+trap_entry!(kthread_yield_entry => kthread_yield_handler, switch);
+
+// a fault in ring 3 becomes a signal for the process instead of stopping the machine
+fn user_fault(frame: &mut TrapFrame, sig: Signal) {
+    signal::force(sig);
+    signal::deliver(frame);
+}
+
+extern "C" fn page_fault_handler(frame: &mut TrapFrame) {
     use x86_64::registers::control::Cr2;
 
+    count(14);
+    if frame.from_user() {
+        return user_fault(frame, signal::SIGSEGV);
+    }
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", _error_code);
-    println!("{:#?}", _stack_frame);
+    println!(
+        "Error Code: {:?}",
+        PageFaultErrorCode::from_bits_truncate(frame.error_code)
+    );
+    println!("{:#?}", frame);
     hlt_loop();
 }
 
+extern "C" fn general_protection_handler(frame: &mut TrapFrame) {
+    count(13);
+    if frame.from_user() {
+        return user_fault(frame, signal::SIGSEGV);
+    }
+    panic!("EXCEPTION: GENERAL PROTECTION FAULT\n{:#?}", frame)
+}
+
+extern "C" fn invalid_opcode_handler(frame: &mut TrapFrame) {
+    count(6);
+    if frame.from_user() {
+        return user_fault(frame, signal::SIGILL);
+    }
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", frame)
+}
+
+extern "C" fn divide_error_handler(frame: &mut TrapFrame) {
+    count(0);
+    if frame.from_user() {
+        return user_fault(frame, signal::SIGFPE);
+    }
+    panic!("EXCEPTION: DIVIDE ERROR\n{:#?}", frame)
+}
+
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
     use x86_64::instructions::port::Port;
 
+    count(InterruptIndex::KeyBoard.as_u8());
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
     crate::drivers::keyboard::add_scancode(scancode);
@@ -77,23 +227,49 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     }
 }
 
-extern "x86-interrupt" fn time_interrupt_handler(_stack_frame: InterruptStackFrame) {
+extern "C" fn time_interrupt_handler(frame: &mut TrapFrame) -> *mut TrapFrame {
     //pics think we are busy processing the first timer interrupt and waits for the eoi signal to
     //send another
+    count(InterruptIndex::Timer.as_u8());
+    crate::arch::x86_64::timer::tick();
+    let ticks = crate::arch::x86_64::timer::ticks();
+    crate::kernel::net::on_timer_tick(ticks);
+    crate::kernel::task::time::on_timer_tick();
 
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
+
+    if frame.from_user() {
+        thread::on_timer_tick(frame, ticks);
+    }
+
+    // a process busy in ring 3 takes its signals here, so Ctrl-C reaches it without a syscall
+    signal::deliver(frame);
+
+    kthread::on_timer_tick(frame, ticks)
+}
+
+extern "C" fn kthread_yield_handler(frame: &mut TrapFrame) -> *mut TrapFrame {
+    count(kthread::YIELD_VECTOR);
+    kthread::on_yield(frame)
 }
 
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    count(8);
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame)
 }
 
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    count(3);
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
+
+// the local APIC raises this when an interrupt goes away before it is delivered; no EOI
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(apic::SPURIOUS_VECTOR);
+}
@@ -1,4 +1,8 @@
+pub mod acpi;
+pub mod apic;
 pub mod cpu;
 pub mod gdt;
 pub mod interrupts;
 pub mod syscall;
+pub mod timer;
+pub mod trap;
@@ -1,12 +1,23 @@
+use core::sync::atomic::{AtomicU64, Ordering};
 use core::u64;
 
 use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
 use x86_64::registers::rflags::RFlags;
 use x86_64::VirtAddr;
 
+use alloc::format;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+use crate::arch::x86_64::trap::TrapFrame;
+use crate::kernel::fs::{self, File, FileRef, FileType, OpenOptions, SeekFrom};
+use crate::kernel::net::socket::Kind;
+use crate::kernel::net::{Ipv4Addr, NetResult, Socket, SocketAddrV4};
+use crate::kernel::process;
+use crate::kernel::signal::{self, SigAction};
+use crate::kernel::thread;
+
 //some syscall numbers
 const SYS_READ: u64 = 0;
 const SYS_WRITE: u64 = 1;
@@ -21,6 +32,82 @@ const SYS_CLEAR: u64 = 9;
 const SYS_REBOOT: u64 = 10;
 const SYS_EXIT: u64 = 11;
 const SYS_YIELD: u64 = 12;
+const SYS_MOUNT: u64 = 13;
+const SYS_UMOUNT: u64 = 14;
+const SYS_RENAME: u64 = 15;
+const SYS_LSEEK: u64 = 16;
+const SYS_CHDIR: u64 = 17;
+const SYS_GETCWD: u64 = 18;
+const SYS_CHMOD: u64 = 19;
+const SYS_CHOWN: u64 = 20;
+const SYS_GETUID: u64 = 21;
+const SYS_GETGID: u64 = 22;
+const SYS_SYMLINK: u64 = 23;
+const SYS_READLINK: u64 = 24;
+const SYS_LINK: u64 = 25;
+const SYS_SYNC: u64 = 26;
+const SYS_SOCKET: u64 = 27;
+const SYS_BIND: u64 = 28;
+const SYS_LISTEN: u64 = 29;
+const SYS_ACCEPT: u64 = 30;
+const SYS_CONNECT: u64 = 31;
+const SYS_SEND: u64 = 32;
+const SYS_RECV: u64 = 33;
+const SYS_PIPE: u64 = 34;
+const SYS_DUP: u64 = 35;
+const SYS_DUP2: u64 = 36;
+const SYS_KILL: u64 = 37;
+const SYS_SIGACTION: u64 = 38;
+const SYS_SIGRETURN: u64 = 39;
+const SYS_SIGPROCMASK: u64 = 40;
+const SYS_CLONE: u64 = 41;
+const SYS_ARCH_PRCTL: u64 = 42;
+const SYS_FUTEX: u64 = 43;
+const SYS_GETTID: u64 = 44;
+const SYS_EXIT_THREAD: u64 = 45;
+const SYS_SET_TID_ADDRESS: u64 = 46;
+
+// open flags (same values as Linux so mlibc's headers can be used as-is)
+const O_ACCMODE: u64 = 0o3;
+const O_RDONLY: u64 = 0o0;
+const O_WRONLY: u64 = 0o1;
+const O_RDWR: u64 = 0o2;
+const O_CREAT: u64 = 0o100;
+const O_TRUNC: u64 = 0o1000;
+const O_APPEND: u64 = 0o2000;
+
+// lseek whence values
+const SEEK_SET: u64 = 0;
+const SEEK_CUR: u64 = 1;
+const SEEK_END: u64 = 2;
+
+// socket domains, types and struct sockaddr_in, also as on Linux
+const AF_INET: u64 = 2;
+const SOCK_STREAM: u64 = 1;
+const SOCK_DGRAM: u64 = 2;
+const SOCK_NONBLOCK: u64 = 0o4000;
+const SOCKADDR_IN_SIZE: usize = 16;
+
+// clone flags; threads must share the address space, handlers and thread group
+const CLONE_VM: u64 = 0x100;
+const CLONE_SIGHAND: u64 = 0x800;
+const CLONE_THREAD: u64 = 0x10000;
+const CLONE_SETTLS: u64 = 0x80000;
+const CLONE_PARENT_SETTID: u64 = 0x100000;
+const CLONE_CHILD_CLEARTID: u64 = 0x200000;
+const CLONE_CHILD_SETTID: u64 = 0x1000000;
+
+// arch_prctl codes
+const ARCH_SET_FS: u64 = 0x1002;
+const ARCH_GET_FS: u64 = 0x1003;
+
+// futex operations; the private flag changes nothing with a single address space
+const FUTEX_WAIT: u64 = 0;
+const FUTEX_WAKE: u64 = 1;
+const FUTEX_PRIVATE_FLAG: u64 = 128;
+
+// length of the `syscall` instruction, to step back over it
+const SYSCALL_INSN_LEN: u64 = 2;
 
 //kernel stack for he syscalls
 const SYSCALL_STACK_SIZE: usize = 4096 * 5;
@@ -48,6 +135,8 @@ pub fn init() {
     unsafe {
         let stack_top = VirtAddr::from_ptr(&raw const SYSCALL_STACK.data) + SYSCALL_STACK_SIZE;
         KERNEL_RSP = stack_top.as_u64();
+        USER_CS = crate::arch::x86_64::gdt::user_code_selector().0 as u64;
+        USER_SS = crate::arch::x86_64::gdt::user_data_selector().0 as u64;
     }
 
     LStar::write(VirtAddr::new(syscall_entry as u64));
@@ -67,6 +156,10 @@ pub fn init() {
 }
 
 //assembly syscall entry point
+//
+// Builds the same TrapFrame the interrupt entries do and leaves through iretq rather than
+// sysretq, so a frame changed by signal delivery or sigreturn comes back whole, rcx and r11
+// included.
 #[unsafe(naked)]
 extern "C" fn syscall_entry() {
     core::arch::naked_asm!(
@@ -76,34 +169,61 @@ extern "C" fn syscall_entry() {
         // Switch to kernel stack
         "mov rsp, [rip + KERNEL_RSP]",
 
+        // The iretq frame: user SS, RSP, RFLAGS (saved in r11 by the CPU), CS and RIP (rcx)
+        "push qword ptr [rip + USER_SS]",
+        "push qword ptr [rip + USER_RSP]",
+        "push r11",
+        "push qword ptr [rip + USER_CS]",
+        "push rcx",
+        "push 0",            // no error code
+
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "cld",
+
+        // Call the Rust handler with the frame, keeping it in rbx
         // Align stack to 16 bytes (required by System V ABI)
+        "mov rdi, rsp",
+        "mov rbx, rsp",
         "and rsp, ~0xf",
-        "sub rsp, 8",        // Make it 8-byte misaligned (call will push 8 bytes, making it aligned)
-
-        // Save user registers that we need to preserve
-        "push rcx",          // User RIP (saved by CPU)
-        "push r11",          // User RFLAGS (saved by CPU)
-
-        "mov rcx, rdx",      // arg3: rdx -> rcx
-        "mov rdx, rsi",      // arg2: rsi -> rdx
-        "mov rsi, rdi",      // arg1: rdi -> rsi
-        "mov rdi, rax",
-        // Call the Rust syscall handler
-        // rax = syscall number, rdi = arg1, rsi = arg2, rdx = arg3
         "call {handler}",
-        // Return value is in rax
-
-        // Restore user registers
-        "pop r11",           // User RFLAGS
-        "pop rcx",           // User RIP
+        "mov rsp, rbx",
 
-        // Restore user stack
-        "mov rsp, [rip + USER_RSP]",
+        // Restore user registers; rax now holds the return value
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        "add rsp, 8",
 
         // Return to userspace
-        "sysretq",
+        "iretq",
 
-        handler = sym syscall_handler,
+        handler = sym syscall_trap,
     );
 }
 
@@ -112,9 +232,36 @@ extern "C" fn syscall_entry() {
 static mut USER_RSP: u64 = 0;
 #[no_mangle]
 static mut KERNEL_RSP: u64 = 0;
+// user segment selectors for the iretq frame
+#[no_mangle]
+static mut USER_CS: u64 = 0;
+#[no_mangle]
+static mut USER_SS: u64 = 0;
+
+extern "C" fn syscall_trap(frame: &mut TrapFrame) {
+    let number = frame.rax;
+    match number {
+        // sigreturn replaces the whole frame, return value included
+        SYS_SIGRETURN => signal::sigreturn(frame),
+        // the new thread starts from a copy of the caller's frame
+        SYS_CLONE => frame.rax = sys_clone(frame),
+        // these take a fourth argument, in r10 as on Linux
+        SYS_FUTEX => frame.rax = sys_futex(frame.rdi, frame.rsi, frame.rdx, frame.r10),
+        number => frame.rax = syscall_handler(number, frame.rdi, frame.rsi, frame.rdx),
+    }
+    // a syscall that blocked starts over from the `syscall` instruction when the thread
+    // next runs, with the same arguments
+    if thread::current_blocked() {
+        frame.rax = number;
+        frame.rip -= SYSCALL_INSN_LEN;
+    }
+    // a thread that blocked or exited hands the CPU to another one here
+    thread::schedule(frame);
+    signal::deliver(frame);
+}
 
 // Rust syscall handler
-extern "C" fn syscall_handler(syscall_number: u64, arg1: u64, arg2: u64, arg3: u64) -> u64 {
+fn syscall_handler(syscall_number: u64, arg1: u64, arg2: u64, arg3: u64) -> u64 {
     match syscall_number {
         SYS_READ => sys_read(arg1, arg2, arg3),
         SYS_WRITE => sys_write(arg1, arg2, arg3),
@@ -129,6 +276,37 @@ extern "C" fn syscall_handler(syscall_number: u64, arg1: u64, arg2: u64, arg3: u
         SYS_RM => sys_rm(arg1),
         SYS_CLEAR => sys_clear(),
         SYS_REBOOT => sys_reboot(),
+        SYS_MOUNT => sys_mount(arg1, arg2, arg3),
+        SYS_UMOUNT => sys_umount(arg1),
+        SYS_RENAME => sys_rename(arg1, arg2),
+        SYS_LSEEK => sys_lseek(arg1, arg2, arg3),
+        SYS_CHDIR => sys_chdir(arg1),
+        SYS_GETCWD => sys_getcwd(arg1, arg2),
+        SYS_CHMOD => sys_chmod(arg1, arg2),
+        SYS_CHOWN => sys_chown(arg1, arg2, arg3),
+        SYS_GETUID => crate::kernel::user::current().uid as u64,
+        SYS_GETGID => crate::kernel::user::current().gid as u64,
+        SYS_SYMLINK => sys_symlink(arg1, arg2),
+        SYS_READLINK => sys_readlink(arg1, arg2, arg3),
+        SYS_LINK => sys_link(arg1, arg2),
+        SYS_SYNC => sys_sync(),
+        SYS_SOCKET => sys_socket(arg1, arg2),
+        SYS_BIND => sys_bind(arg1, arg2, arg3),
+        SYS_LISTEN => sys_listen(arg1, arg2),
+        SYS_ACCEPT => sys_accept(arg1, arg2, arg3),
+        SYS_CONNECT => sys_connect(arg1, arg2, arg3),
+        SYS_SEND => sys_send(arg1, arg2, arg3),
+        SYS_RECV => sys_recv(arg1, arg2, arg3),
+        SYS_PIPE => sys_pipe(arg1),
+        SYS_DUP => sys_dup(arg1),
+        SYS_DUP2 => sys_dup2(arg1, arg2),
+        SYS_KILL => sys_kill(arg1, arg2),
+        SYS_SIGACTION => sys_sigaction(arg1, arg2, arg3),
+        SYS_SIGPROCMASK => sys_sigprocmask(arg1, arg2, arg3),
+        SYS_ARCH_PRCTL => sys_arch_prctl(arg1, arg2),
+        SYS_GETTID => thread::current_tid().unwrap_or(u64::MAX),
+        SYS_EXIT_THREAD => sys_exit_thread(arg1),
+        SYS_SET_TID_ADDRESS => sys_set_tid_address(arg1),
         _ => {
             crate::println!("[SYSCALL] Unknown syscall: {}", syscall_number);
             u64::MAX // Error: -1
@@ -137,78 +315,335 @@ extern "C" fn syscall_handler(syscall_number: u64, arg1: u64, arg2: u64, arg3: u
 }
 
 //write text func
-fn sys_read(fd: u64, _buffer_ptr: u64, length: u64) -> u64 {
-    if fd != 0 || length == 0 {
-        // Only stdin supported
+// 0-2 are the console (/dev/console) unless the process reopened them
+fn sys_read(fd: u64, buffer_ptr: u64, length: u64) -> u64 {
+    if length == 0 {
         return 0;
     }
+    if !user_buffer_ok(buffer_ptr, length, true) {
+        return u64::MAX;
+    }
 
-    // For now, stub - will implement with keyboard driver integration
-    crate::println!("[SYSCALL] sys_read called - not yet implemented");
-    0
+    let buffer = unsafe { core::slice::from_raw_parts_mut(buffer_ptr as *mut u8, length as usize) };
+    match current_file(fd).map(|file| file.lock().read(buffer)) {
+        Some(Ok(read)) => read as u64,
+        _ => u64::MAX,
+    }
 }
 
 fn sys_write(fd: u64, buffer_ptr: u64, length: u64) -> u64 {
-    //only supporting fd=1, stdput
+    let buffer: &[u8] = match length {
+        0 => &[],
+        _ if user_buffer_ok(buffer_ptr, length, false) => unsafe {
+            core::slice::from_raw_parts(buffer_ptr as *const u8, length as usize)
+        },
+        _ => return u64::MAX,
+    };
 
-    if fd != 1 {
-        return u64::MAX; // Error
+    match current_file(fd).map(|file| file.lock().write(buffer)) {
+        Some(Ok(written)) => written as u64,
+        _ => u64::MAX,
     }
+}
+
+fn sys_exit(exit_code: u64) -> u64 {
+    crate::println!("[SYSCALL] User program exited with code: {}", exit_code);
+    process::exit(exit_code)
+}
 
+fn sys_yield() -> u64 {
+    crate::println!("[SYSCALL] Yield Called");
+    0
+}
+
+fn sys_open(path_ptr: u64, flags: u64) -> u64 {
     unsafe {
-        let buffer = core::slice::from_raw_parts(buffer_ptr as *const u8, length as usize);
+        let path_bytes = read_string_from_user(path_ptr);
+        if path_bytes.is_empty() {
+            return u64::MAX;
+        }
+        if let Ok(path) = core::str::from_utf8(&path_bytes) {
+            let access = flags & O_ACCMODE;
+            let options = OpenOptions::new()
+                .read(access == O_RDONLY || access == O_RDWR)
+                .write(access == O_WRONLY || access == O_RDWR)
+                .create(flags & O_CREAT != 0)
+                .truncate(flags & O_TRUNC != 0)
+                .append(flags & O_APPEND != 0);
 
-        // converting to string and print
-        if let Ok(s) = core::str::from_utf8(buffer) {
-            crate::print!("{}", s);
-            length // Return bytes written
-        } else {
-            0 // Error: invalid UTF-8
+            return match File::open(&process::resolve_path(path), options) {
+                Ok(file) => {
+                    process::with_current(|p| p.fds.insert(file) as u64).unwrap_or(u64::MAX)
+                }
+                Err(_) => u64::MAX,
+            };
         }
+        u64::MAX
     }
 }
 
-fn sys_exit(exit_code: u64) -> u64 {
-    crate::println!("[SYSCALL] User program exited with code: {}", exit_code);
-    // For now, we can't properly return to kernel from userspace
-    // The best we can do is halt the process
-    // TODO: Implement proper process termination and return to kernel
-    crate::println!("[SYSCALL] Halting - will implement proper process exit later");
-    crate::hlt_loop();
+//file close
+fn sys_close(fd: u64) -> u64 {
+    match process::with_current(|p| p.fds.close(fd as usize)) {
+        Some(Ok(_)) => 0,
+        _ => u64::MAX,
+    }
 }
 
-fn sys_yield() -> u64 {
-    crate::println!("[SYSCALL] Yield Called");
+// Create a pipe; its read and write descriptors are stored as two ints at fds_ptr
+fn sys_pipe(fds_ptr: u64) -> u64 {
+    static NEXT_PIPE: AtomicU64 = AtomicU64::new(1);
+    let name = format!("pipe:[{}]", NEXT_PIPE.fetch_add(1, Ordering::Relaxed));
+    let (reader, writer) = fs::pipe();
+    let fds = process::with_current(|p| {
+        let read_fd = p.fds.insert(File::from_stream(&name, Arc::new(reader)));
+        let write_fd = p.fds.insert(File::from_stream(&name, Arc::new(writer)));
+        [read_fd as i32, write_fd as i32]
+    });
+    match fds {
+        Some(fds) if user_buffer_ok(fds_ptr, 8, true) => {
+            unsafe { core::ptr::write_unaligned(fds_ptr as *mut [i32; 2], fds) };
+            0
+        }
+        Some([read_fd, write_fd]) => {
+            process::with_current(|p| {
+                let _ = p.fds.close(read_fd as usize);
+                let _ = p.fds.close(write_fd as usize);
+            });
+            u64::MAX
+        }
+        None => u64::MAX,
+    }
+}
+
+fn sys_dup(fd: u64) -> u64 {
+    match process::with_current(|p| p.fds.dup(fd as usize)) {
+        Some(Ok(new_fd)) => new_fd as u64,
+        _ => u64::MAX,
+    }
+}
+
+fn sys_dup2(fd: u64, new_fd: u64) -> u64 {
+    match process::with_current(|p| p.fds.dup2(fd as usize, new_fd as usize)) {
+        Some(Ok(new_fd)) => new_fd as u64,
+        _ => u64::MAX,
+    }
+}
+
+// Send a signal; only root may signal another user's processes
+fn sys_kill(pid: u64, sig: u64) -> u64 {
+    if sig != 0 && !signal::is_valid(sig as u32) {
+        return u64::MAX;
+    }
+    let sender = crate::kernel::user::current();
+    let allowed = process::with_process(pid, |p| sender.is_root() || sender.uid == p.cred.uid);
+    match allowed {
+        Some(true) if signal::send(pid, sig as u32) => 0,
+        _ => u64::MAX,
+    }
+}
+
+// Set and/or fetch the action for a signal; either pointer may be null
+fn sys_sigaction(sig: u64, act_ptr: u64, oldact_ptr: u64) -> u64 {
+    let size = core::mem::size_of::<SigAction>() as u64;
+    if (act_ptr != 0 && !user_buffer_ok(act_ptr, size, false))
+        || (oldact_ptr != 0 && !user_buffer_ok(oldact_ptr, size, true))
+    {
+        return u64::MAX;
+    }
+    let action = match act_ptr {
+        0 => None,
+        ptr => Some(unsafe { core::ptr::read_unaligned(ptr as *const SigAction) }),
+    };
+    match signal::set_action(sig as u32, action) {
+        Some(old) => {
+            if oldact_ptr != 0 {
+                unsafe { core::ptr::write_unaligned(oldact_ptr as *mut SigAction, old) };
+            }
+            0
+        }
+        None => u64::MAX,
+    }
+}
+
+// Change the blocked signal set; sets are u64 bitmasks with bit n-1 for signal n
+fn sys_sigprocmask(how: u64, set_ptr: u64, oldset_ptr: u64) -> u64 {
+    if (set_ptr != 0 && !user_buffer_ok(set_ptr, 8, false))
+        || (oldset_ptr != 0 && !user_buffer_ok(oldset_ptr, 8, true))
+    {
+        return u64::MAX;
+    }
+    let set = match set_ptr {
+        0 => None,
+        ptr => Some(unsafe { core::ptr::read_unaligned(ptr as *const u64) }),
+    };
+    match signal::set_mask(how, set) {
+        Some(old) => {
+            if oldset_ptr != 0 {
+                unsafe { core::ptr::write_unaligned(oldset_ptr as *mut u64, old) };
+            }
+            0
+        }
+        None => u64::MAX,
+    }
+}
+
+// Start a thread in the current process: clone(flags, stack, parent_tid, child_tid, tls)
+// with the arguments in rdi, rsi, rdx, r10 and r8. Returns the new tid, and 0 in the thread.
+fn sys_clone(frame: &TrapFrame) -> u64 {
+    let (flags, stack, parent_tid, child_tid, tls) =
+        (frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8);
+    let required = CLONE_VM | CLONE_SIGHAND | CLONE_THREAD;
+    if flags & required != required {
+        return u64::MAX;
+    }
+    let tid_ptr_ok = |ptr: u64| ptr.is_multiple_of(4) && user_buffer_ok(ptr, 4, true);
+    for (flag, ptr) in [
+        (CLONE_PARENT_SETTID, parent_tid),
+        (CLONE_CHILD_SETTID | CLONE_CHILD_CLEARTID, child_tid),
+    ] {
+        if flags & flag != 0 && !tid_ptr_ok(ptr) {
+            return u64::MAX;
+        }
+    }
+
+    let tls = (flags & CLONE_SETTLS != 0).then_some(tls);
+    let clear_tid = if flags & CLONE_CHILD_CLEARTID != 0 {
+        child_tid
+    } else {
+        0
+    };
+    let Some(tid) = thread::spawn(frame, stack, tls, clear_tid) else {
+        return u64::MAX;
+    };
+    // the address space is shared, so the child's tid can be stored before it runs
+    if flags & CLONE_PARENT_SETTID != 0 {
+        unsafe { *(parent_tid as *mut u32) = tid as u32 };
+    }
+    if flags & CLONE_CHILD_SETTID != 0 {
+        unsafe { *(child_tid as *mut u32) = tid as u32 };
+    }
+    tid
+}
+
+// Set or read the FS base the current thread's thread-local storage is addressed from
+fn sys_arch_prctl(code: u64, addr: u64) -> u64 {
+    match code {
+        ARCH_SET_FS if thread::set_fs_base(addr) => 0,
+        ARCH_GET_FS if user_buffer_ok(addr, 8, true) => match thread::fs_base() {
+            Some(base) => {
+                unsafe { core::ptr::write_unaligned(addr as *mut u64, base) };
+                0
+            }
+            None => u64::MAX,
+        },
+        _ => u64::MAX,
+    }
+}
+
+// futex(addr, op, val, timeout): WAIT sleeps while *addr == val, for at most the relative
+// timespec at timeout if it's non-null; WAKE wakes up to val waiters and returns how many
+fn sys_futex(addr: u64, op: u64, val: u64, timeout_ptr: u64) -> u64 {
+    match op & !FUTEX_PRIVATE_FLAG {
+        FUTEX_WAIT => {
+            let timeout_ms = match timeout_ptr {
+                0 => None,
+                ptr if user_buffer_ok(ptr, 16, false) => {
+                    let [secs, nanos] =
+                        unsafe { core::ptr::read_unaligned(ptr as *const [i64; 2]) };
+                    if secs < 0 || !(0..1_000_000_000).contains(&nanos) {
+                        return u64::MAX;
+                    }
+                    // a timeout too long to count is as good as none
+                    Some(
+                        (secs as u64)
+                            .saturating_mul(1000)
+                            .saturating_add((nanos as u64).div_ceil(1_000_000)),
+                    )
+                }
+                _ => return u64::MAX,
+            };
+            if thread::futex_wait(addr, val as u32, timeout_ms) {
+                0
+            } else {
+                u64::MAX
+            }
+        }
+        FUTEX_WAKE => thread::futex_wake(addr, val as usize) as u64,
+        _ => u64::MAX,
+    }
+}
+
+// End just the calling thread; the last one ends the process with `status`
+fn sys_exit_thread(status: u64) -> u64 {
+    if !thread::exit_current() {
+        process::exit(status);
+    }
     0
 }
 
-fn sys_open(path_ptr: u64, _flags: u64) -> u64 {
+// Remember where to clear and futex-wake the thread id when the thread exits
+fn sys_set_tid_address(ptr: u64) -> u64 {
+    if ptr != 0 && (!ptr.is_multiple_of(4) || !user_buffer_ok(ptr, 4, true)) {
+        return u64::MAX;
+    }
+    thread::set_clear_tid(ptr);
+    thread::current_tid().unwrap_or(u64::MAX)
+}
+
+// Move the file offset of an open descriptor, returns the new offset
+fn sys_lseek(fd: u64, offset: u64, whence: u64) -> u64 {
+    let pos = match whence {
+        SEEK_SET => SeekFrom::Start(offset),
+        SEEK_CUR => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => return u64::MAX,
+    };
+
+    match current_file(fd).map(|file| file.lock().seek(pos)) {
+        Some(Ok(new_offset)) => new_offset as u64,
+        _ => u64::MAX,
+    }
+}
+
+// Change the working directory of the calling process
+fn sys_chdir(path_ptr: u64) -> u64 {
     unsafe {
         let path_bytes = read_string_from_user(path_ptr);
         if path_bytes.is_empty() {
             return u64::MAX;
         }
+
         if let Ok(path) = core::str::from_utf8(&path_bytes) {
-            // Check if file exists in filesystem
+            let path = process::resolve_path(path);
             if let Some(fs) = crate::kernel::fs::root() {
-                match fs.stat(path) {
-                    Ok(_) => {
-                        // File exists - return a fake fd for now
-                        // Later: maintain fd table per process
-                        return 3; // Fake fd
+                return match fs.stat(&path) {
+                    Ok(inode) if inode.file_type == FileType::Directory => {
+                        process::with_current(|p| p.cwd = path);
+                        0
                     }
-                    Err(_) => return u64::MAX, // File not found
-                }
+                    _ => u64::MAX,
+                };
             }
         }
         u64::MAX
     }
 }
 
-//file close
-fn sys_close(_fd: u64) -> u64 {
-    // For now, always succeed
-    0
+// Copy the working directory (NUL terminated) into a user buffer, returns its length
+fn sys_getcwd(buffer_ptr: u64, buffer_size: u64) -> u64 {
+    let cwd = match process::with_current(|p| p.cwd.clone()) {
+        Some(cwd) => cwd,
+        None => return u64::MAX,
+    };
+    if cwd.len() + 1 > buffer_size as usize || !user_buffer_ok(buffer_ptr, buffer_size, true) {
+        return u64::MAX;
+    }
+    unsafe {
+        copy_to_user(buffer_ptr, cwd.as_bytes());
+        copy_to_user(buffer_ptr + cwd.len() as u64, &[0]);
+    }
+    cwd.len() as u64
 }
 
 // Read directory contents
@@ -220,6 +655,8 @@ fn sys_readdir(path_ptr: u64, buffer_ptr: u64, buffer_size: u64) -> u64 {
         }
 
         if let Ok(path) = core::str::from_utf8(&path_bytes) {
+            let path = process::resolve_path(path);
+            let path = path.as_str();
             if let Some(fs) = crate::kernel::fs::root() {
                 match fs.list_dir(path) {
                     Ok(entries) => {
@@ -227,8 +664,10 @@ fn sys_readdir(path_ptr: u64, buffer_ptr: u64, buffer_size: u64) -> u64 {
                         let mut output = String::new();
                         for entry in entries {
                             let type_char = match entry.file_type {
-                                crate::kernel::fs::FileType::Directory => 'd',
-                                crate::kernel::fs::FileType::File => 'f',
+                                FileType::Directory => 'd',
+                                FileType::File => 'f',
+                                FileType::Symlink => 'l',
+                                FileType::CharDevice => 'c',
                             };
                             output.push_str(&format!(
                                 "{} {:8} {}\n",
@@ -258,14 +697,18 @@ fn sys_stat(path_ptr: u64, statbuf_ptr: u64) -> u64 {
         }
 
         if let Ok(path) = core::str::from_utf8(&path_bytes) {
+            let path = process::resolve_path(path);
+            let path = path.as_str();
             if let Some(fs) = crate::kernel::fs::root() {
                 match fs.stat(path) {
                     Ok(inode) => {
                         // Write stat info to user buffer
                         // Format: [file_type (1 byte), size (8 bytes)]
                         let file_type = match inode.file_type {
-                            crate::kernel::fs::FileType::Directory => 1u8,
-                            crate::kernel::fs::FileType::File => 0u8,
+                            FileType::Directory => 1u8,
+                            FileType::File => 0u8,
+                            FileType::Symlink => 2u8,
+                            FileType::CharDevice => 3u8,
                         };
 
                         let stat_data = [
@@ -300,6 +743,8 @@ fn sys_mkdir(path_ptr: u64) -> u64 {
         }
 
         if let Ok(path) = core::str::from_utf8(&path_bytes) {
+            let path = process::resolve_path(path);
+            let path = path.as_str();
             if let Some(fs) = crate::kernel::fs::root() {
                 return match fs.create_dir(path) {
                     Ok(_) => 0,
@@ -320,6 +765,8 @@ fn sys_touch(path_ptr: u64) -> u64 {
         }
 
         if let Ok(path) = core::str::from_utf8(&path_bytes) {
+            let path = process::resolve_path(path);
+            let path = path.as_str();
             if let Some(fs) = crate::kernel::fs::root() {
                 return match fs.create_file(path) {
                     Ok(_) => 0,
@@ -340,6 +787,8 @@ fn sys_rm(path_ptr: u64) -> u64 {
         }
 
         if let Ok(path) = core::str::from_utf8(&path_bytes) {
+            let path = process::resolve_path(path);
+            let path = path.as_str();
             if let Some(fs) = crate::kernel::fs::root() {
                 return match fs.remove(path) {
                     Ok(_) => 0,
@@ -351,6 +800,278 @@ fn sys_rm(path_ptr: u64) -> u64 {
     }
 }
 
+// Mount a filesystem of the given type on a directory
+fn sys_mount(fs_type_ptr: u64, source_ptr: u64, target_ptr: u64) -> u64 {
+    unsafe {
+        let fs_type = read_string_from_user(fs_type_ptr);
+        let source = read_string_from_user(source_ptr);
+        let target = read_string_from_user(target_ptr);
+        if fs_type.is_empty() || target.is_empty() {
+            return u64::MAX;
+        }
+
+        if let (Ok(fs_type), Ok(source), Ok(target)) = (
+            core::str::from_utf8(&fs_type),
+            core::str::from_utf8(&source),
+            core::str::from_utf8(&target),
+        ) {
+            let target = process::resolve_path(target);
+            return match crate::kernel::fs::mount(fs_type, source, &target) {
+                Ok(_) => 0,
+                Err(_) => u64::MAX,
+            };
+        }
+        u64::MAX
+    }
+}
+
+// Unmount the filesystem mounted on a directory
+fn sys_umount(target_ptr: u64) -> u64 {
+    unsafe {
+        let path_bytes = read_string_from_user(target_ptr);
+        if path_bytes.is_empty() {
+            return u64::MAX;
+        }
+
+        if let Ok(path) = core::str::from_utf8(&path_bytes) {
+            return match crate::kernel::fs::umount(&process::resolve_path(path)) {
+                Ok(_) => 0,
+                Err(_) => u64::MAX,
+            };
+        }
+        u64::MAX
+    }
+}
+
+// Rename or move a file/directory, replacing the target if it exists
+fn sys_rename(from_ptr: u64, to_ptr: u64) -> u64 {
+    unsafe {
+        let from_bytes = read_string_from_user(from_ptr);
+        let to_bytes = read_string_from_user(to_ptr);
+        if from_bytes.is_empty() || to_bytes.is_empty() {
+            return u64::MAX;
+        }
+
+        if let (Ok(from), Ok(to)) = (
+            core::str::from_utf8(&from_bytes),
+            core::str::from_utf8(&to_bytes),
+        ) {
+            if let Some(fs) = crate::kernel::fs::root() {
+                let from = process::resolve_path(from);
+                let to = process::resolve_path(to);
+                return match fs.rename(&from, &to) {
+                    Ok(_) => 0,
+                    Err(_) => u64::MAX,
+                };
+            }
+        }
+        u64::MAX
+    }
+}
+
+// Change the permission bits of a file/directory (owner or root only)
+fn sys_chmod(path_ptr: u64, mode: u64) -> u64 {
+    if mode > 0o7777 {
+        return u64::MAX;
+    }
+    unsafe {
+        let path_bytes = read_string_from_user(path_ptr);
+        if path_bytes.is_empty() {
+            return u64::MAX;
+        }
+
+        if let Ok(path) = core::str::from_utf8(&path_bytes) {
+            if let Some(fs) = crate::kernel::fs::root() {
+                return match fs.chmod(&process::resolve_path(path), mode as u16) {
+                    Ok(_) => 0,
+                    Err(_) => u64::MAX,
+                };
+            }
+        }
+        u64::MAX
+    }
+}
+
+// Change the owner and group of a file/directory (root only)
+fn sys_chown(path_ptr: u64, uid: u64, gid: u64) -> u64 {
+    unsafe {
+        let path_bytes = read_string_from_user(path_ptr);
+        if path_bytes.is_empty() {
+            return u64::MAX;
+        }
+
+        if let Ok(path) = core::str::from_utf8(&path_bytes) {
+            if let Some(fs) = crate::kernel::fs::root() {
+                return match fs.chown(&process::resolve_path(path), uid as u32, gid as u32) {
+                    Ok(_) => 0,
+                    Err(_) => u64::MAX,
+                };
+            }
+        }
+        u64::MAX
+    }
+}
+
+// Create a symlink at `path` pointing to `target` (stored as given)
+fn sys_symlink(target_ptr: u64, path_ptr: u64) -> u64 {
+    unsafe {
+        let target_bytes = read_string_from_user(target_ptr);
+        let path_bytes = read_string_from_user(path_ptr);
+        if target_bytes.is_empty() || path_bytes.is_empty() {
+            return u64::MAX;
+        }
+
+        if let (Ok(target), Ok(path)) = (
+            core::str::from_utf8(&target_bytes),
+            core::str::from_utf8(&path_bytes),
+        ) {
+            if let Some(fs) = crate::kernel::fs::root() {
+                return match fs.symlink(target, &process::resolve_path(path)) {
+                    Ok(_) => 0,
+                    Err(_) => u64::MAX,
+                };
+            }
+        }
+        u64::MAX
+    }
+}
+
+// Copy a symlink's target into the buffer (not NUL terminated); returns its length
+fn sys_readlink(path_ptr: u64, buffer_ptr: u64, buffer_size: u64) -> u64 {
+    if !user_buffer_ok(buffer_ptr, buffer_size, true) {
+        return u64::MAX;
+    }
+    unsafe {
+        let path_bytes = read_string_from_user(path_ptr);
+        if path_bytes.is_empty() {
+            return u64::MAX;
+        }
+
+        if let Ok(path) = core::str::from_utf8(&path_bytes) {
+            if let Some(fs) = crate::kernel::fs::root() {
+                return match fs.readlink(&process::resolve_path(path)) {
+                    Ok(target) => {
+                        let bytes = target.as_bytes();
+                        let copy_len = bytes.len().min(buffer_size as usize);
+                        copy_to_user(buffer_ptr, &bytes[..copy_len]);
+                        copy_len as u64
+                    }
+                    Err(_) => u64::MAX,
+                };
+            }
+        }
+        u64::MAX
+    }
+}
+
+// Create a hard link `new` to the existing file `existing`
+fn sys_link(existing_ptr: u64, new_ptr: u64) -> u64 {
+    unsafe {
+        let existing_bytes = read_string_from_user(existing_ptr);
+        let new_bytes = read_string_from_user(new_ptr);
+        if existing_bytes.is_empty() || new_bytes.is_empty() {
+            return u64::MAX;
+        }
+
+        if let (Ok(existing), Ok(new)) = (
+            core::str::from_utf8(&existing_bytes),
+            core::str::from_utf8(&new_bytes),
+        ) {
+            if let Some(fs) = crate::kernel::fs::root() {
+                let existing = process::resolve_path(existing);
+                let new = process::resolve_path(new);
+                return match fs.link(&existing, &new) {
+                    Ok(_) => 0,
+                    Err(_) => u64::MAX,
+                };
+            }
+        }
+        u64::MAX
+    }
+}
+
+// Write all cached disk blocks back to their devices
+fn sys_sync() -> u64 {
+    match crate::drivers::block::cache::sync() {
+        Ok(()) => 0,
+        Err(_) => u64::MAX,
+    }
+}
+
+// Create an AF_INET socket; the protocol is implied by the type
+fn sys_socket(domain: u64, kind: u64) -> u64 {
+    if domain != AF_INET {
+        return u64::MAX;
+    }
+    let (socket_kind, name) = match kind & !SOCK_NONBLOCK {
+        SOCK_STREAM => (Kind::Stream, "socket:[tcp]"),
+        SOCK_DGRAM => (Kind::Datagram, "socket:[udp]"),
+        _ => return u64::MAX,
+    };
+    let socket = Socket::new(socket_kind);
+    socket.set_nonblocking(kind & SOCK_NONBLOCK != 0);
+    let file = File::from_stream(name, Arc::new(socket));
+    process::with_current(|p| p.fds.insert(file) as u64).unwrap_or(u64::MAX)
+}
+
+fn sys_bind(fd: u64, addr_ptr: u64, addr_len: u64) -> u64 {
+    match unsafe { read_sockaddr(addr_ptr, addr_len) } {
+        Some(addr) => with_socket(fd, |socket| socket.bind(addr).map(|_| 0)),
+        None => u64::MAX,
+    }
+}
+
+fn sys_listen(fd: u64, backlog: u64) -> u64 {
+    with_socket(fd, |socket| socket.listen(backlog as usize).map(|_| 0))
+}
+
+// Wait for a connection; the peer's address goes to addr_ptr unless it is null
+fn sys_accept(fd: u64, addr_ptr: u64, len_ptr: u64) -> u64 {
+    let mut accepted = None;
+    with_socket(fd, |socket| {
+        accepted = Some(socket.accept()?);
+        Ok(0)
+    });
+    let Some((socket, remote)) = accepted else {
+        return u64::MAX;
+    };
+    // a bad address buffer drops the connection, as the caller couldn't learn who it was
+    if addr_ptr != 0 && len_ptr != 0 && !unsafe { write_sockaddr(addr_ptr, len_ptr, remote) } {
+        return u64::MAX;
+    }
+    let file = File::from_stream("socket:[tcp]", Arc::new(socket));
+    process::with_current(|p| p.fds.insert(file) as u64).unwrap_or(u64::MAX)
+}
+
+fn sys_connect(fd: u64, addr_ptr: u64, addr_len: u64) -> u64 {
+    match unsafe { read_sockaddr(addr_ptr, addr_len) } {
+        Some(addr) => with_socket(fd, |socket| socket.connect(addr).map(|_| 0)),
+        None => u64::MAX,
+    }
+}
+
+fn sys_send(fd: u64, buffer_ptr: u64, length: u64) -> u64 {
+    let buffer: &[u8] = match length {
+        0 => &[],
+        _ if user_buffer_ok(buffer_ptr, length, false) => unsafe {
+            core::slice::from_raw_parts(buffer_ptr as *const u8, length as usize)
+        },
+        _ => return u64::MAX,
+    };
+    with_socket(fd, |socket| socket.send(buffer).map(|sent| sent as u64))
+}
+
+fn sys_recv(fd: u64, buffer_ptr: u64, length: u64) -> u64 {
+    let buffer: &mut [u8] = match length {
+        0 => &mut [],
+        _ if user_buffer_ok(buffer_ptr, length, true) => unsafe {
+            core::slice::from_raw_parts_mut(buffer_ptr as *mut u8, length as usize)
+        },
+        _ => return u64::MAX,
+    };
+    with_socket(fd, |socket| socket.recv(buffer).map(|read| read as u64))
+}
+
 // Clear terminal screen
 fn sys_clear() -> u64 {
     crate::ui::terminal::clear();
@@ -362,7 +1083,31 @@ fn sys_reboot() -> u64 {
     crate::arch::x86_64::cpu::reboot();
 }
 
+// Looks up an open file of the calling process
+fn current_file(fd: u64) -> Option<FileRef> {
+    process::with_current(|p| p.fds.get(fd as usize)).flatten()
+}
+
+// Runs `f` on a socket of the calling process
+fn with_socket(fd: u64, f: impl FnOnce(&Socket) -> NetResult<u64>) -> u64 {
+    let stream = match current_file(fd).and_then(|file| file.lock().stream().cloned()) {
+        Some(stream) => stream,
+        None => return u64::MAX,
+    };
+    match stream.as_any().downcast_ref::<Socket>().map(f) {
+        Some(Ok(result)) => result,
+        _ => u64::MAX,
+    }
+}
+
 // Helper functions for userspace memory access
+
+// whether the len bytes at ptr lie in the current process's mappings, writable if asked
+fn user_buffer_ok(ptr: u64, len: u64, writable: bool) -> bool {
+    ptr.checked_add(len)
+        .is_some_and(|end| process::user_range_mapped(ptr, end, writable))
+}
+
 unsafe fn read_string_from_user(ptr: u64) -> Vec<u8> {
     let mut bytes = Vec::new();
     let mut offset = 0;
@@ -385,3 +1130,39 @@ unsafe fn copy_to_user(ptr: u64, data: &[u8]) {
         *((ptr + i as u64) as *mut u8) = byte;
     }
 }
+
+// struct sockaddr_in: family, port and address in network byte order, padding
+unsafe fn read_sockaddr(ptr: u64, len: u64) -> Option<SocketAddrV4> {
+    if (len as usize) < SOCKADDR_IN_SIZE || !user_buffer_ok(ptr, SOCKADDR_IN_SIZE as u64, false) {
+        return None;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(ptr as *const u8, SOCKADDR_IN_SIZE) };
+    if u16::from_ne_bytes([bytes[0], bytes[1]]) as u64 != AF_INET {
+        return None;
+    }
+    let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+    let addr = Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]);
+    Some(SocketAddrV4::new(addr, port))
+}
+
+// fills a sockaddr_in of at most *len_ptr bytes and stores the full size in *len_ptr;
+// false if either buffer isn't writable user memory
+unsafe fn write_sockaddr(ptr: u64, len_ptr: u64, addr: SocketAddrV4) -> bool {
+    if !user_buffer_ok(len_ptr, 4, true) {
+        return false;
+    }
+    let len = unsafe { core::ptr::read_unaligned(len_ptr as *const u32) } as usize;
+    let len = len.min(SOCKADDR_IN_SIZE);
+    if !user_buffer_ok(ptr, len as u64, true) {
+        return false;
+    }
+    let mut bytes = [0u8; SOCKADDR_IN_SIZE];
+    bytes[0..2].copy_from_slice(&(AF_INET as u16).to_ne_bytes());
+    bytes[2..4].copy_from_slice(&addr.port().to_be_bytes());
+    bytes[4..8].copy_from_slice(&addr.ip().octets());
+    unsafe {
+        copy_to_user(ptr, &bytes[..len]);
+        core::ptr::write_unaligned(len_ptr as *mut u32, SOCKADDR_IN_SIZE as u32);
+    }
+    true
+}
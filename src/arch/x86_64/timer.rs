@@ -0,0 +1,54 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+
+// the 8253/8254 PIT counts down from a divisor at this rate
+const PIT_BASE_FREQUENCY: u64 = 1_193_182;
+pub const TIMER_HZ: u64 = 100;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+// the time-stamp counter when the PIT was programmed, to measure its rate against
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// Programs PIT channel 0 to fire IRQ 0 `TIMER_HZ` times a second.
+pub fn init() {
+    let divisor = (PIT_BASE_FREQUENCY / TIMER_HZ) as u16;
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel0: Port<u8> = Port::new(0x40);
+
+    unsafe {
+        // channel 0, lobyte/hibyte access, mode 3 (square wave)
+        command.write(0x36);
+        channel0.write((divisor & 0xff) as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+    BOOT_TSC.store(tsc(), Ordering::Relaxed);
+}
+
+/// Called from the timer interrupt handler.
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / TIMER_HZ
+}
+
+/// Reads the CPU's time-stamp counter, for timing things shorter than a tick.
+pub fn tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Converts time-stamp counter cycles to microseconds, going by how fast the counter
+/// has run against the PIT since `init`. Returns 0 until the first tick.
+pub fn cycles_to_us(cycles: u64) -> u64 {
+    let elapsed_us = uptime_ms() * 1000;
+    let elapsed_cycles = tsc().saturating_sub(BOOT_TSC.load(Ordering::Relaxed));
+    if elapsed_us == 0 || elapsed_cycles == 0 {
+        return 0;
+    }
+    (cycles as u128 * elapsed_us as u128 / elapsed_cycles as u128) as u64
+}
@@ -0,0 +1,105 @@
+/// Every register of the interrupted context, as saved by the entry stubs below and by
+/// the syscall entry. Changes made to it take effect when the handler returns.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    /// Pushed by the CPU for some exceptions, 0 otherwise.
+    pub error_code: u64,
+    // the frame iretq returns through
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl TrapFrame {
+    /// Whether the trap came from ring 3.
+    pub fn from_user(&self) -> bool {
+        self.cs & 3 == 3
+    }
+}
+
+/// Defines a naked interrupt entry that saves a `TrapFrame`, calls
+/// `extern "C" fn $handler(&mut TrapFrame)` and returns through the (possibly changed) frame.
+/// Add `error_code` for exceptions where the CPU pushes one.
+///
+/// With `switch`, the handler is `extern "C" fn(&mut TrapFrame) -> *mut TrapFrame` instead
+/// and the entry returns through the frame it hands back, which may be on another stack.
+macro_rules! trap_entry {
+    ($name:ident => $handler:path) => {
+        trap_entry!(@entry $name, $handler, "push 0", "mov rsp, rbx");
+    };
+    ($name:ident => $handler:path, error_code) => {
+        trap_entry!(@entry $name, $handler, "", "mov rsp, rbx");
+    };
+    ($name:ident => $handler:path, switch) => {
+        trap_entry!(@entry $name, $handler, "push 0", "mov rsp, rax");
+    };
+    (@entry $name:ident, $handler:path, $error_code:literal, $resume:literal) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            core::arch::naked_asm!(
+                $error_code,
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "cld",
+                // the frame is the argument; rbx keeps it across the aligned call
+                "mov rdi, rsp",
+                "mov rbx, rsp",
+                "and rsp, ~0xf",
+                "call {handler}",
+                $resume,
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                // skip the error code
+                "add rsp, 8",
+                "iretq",
+                handler = sym $handler,
+            );
+        }
+    };
+}
+
+pub(crate) use trap_entry;
@@ -0,0 +1,322 @@
+use super::{check_request, BlockDevice, BlockError, BlockRef, BlockResult};
+use crate::drivers::pci::{Match, PciDevice, PciDriver};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+pub const SECTOR_SIZE: usize = 512;
+
+// register offsets from the channel's I/O base
+const REG_DATA: u16 = 0;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DRIVE: u16 = 6;
+const REG_COMMAND: u16 = 7;
+const REG_STATUS: u16 = 7;
+
+// status register bits
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_READ_SECTORS_EXT: u8 = 0x24;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+const CMD_CACHE_FLUSH: u8 = 0xE7;
+const CMD_CACHE_FLUSH_EXT: u8 = 0xEA;
+const CMD_IDENTIFY: u8 = 0xEC;
+
+// largest transfer per command that both LBA28 and LBA48 accept
+const MAX_SECTORS_PER_COMMAND: usize = 256;
+// status polls before a drive is considered dead
+const TIMEOUT: usize = 1_000_000;
+
+/// The legacy IDE channels QEMU emulates: (I/O base, control base).
+const CHANNELS: [(u16, u16); 2] = [(0x1F0, 0x3F6), (0x170, 0x376)];
+
+// master and slave share their channel's registers, so commands are serialized per channel
+static CHANNEL_LOCKS: [Mutex<()>; 2] = [Mutex::new(()), Mutex::new(())];
+
+/// One drive on an ATA channel, driven with polled PIO.
+pub struct AtaDrive {
+    channel: usize,
+    io_base: u16,
+    control_base: u16,
+    slave: bool,
+    lba48: bool,
+    sectors: u64,
+    model: String,
+}
+
+impl AtaDrive {
+    /// Sends IDENTIFY to a drive and returns it if an ATA disk answers.
+    pub fn identify(channel: usize, slave: bool) -> Option<AtaDrive> {
+        let (io_base, control_base) = CHANNELS[channel];
+        let mut drive = AtaDrive {
+            channel,
+            io_base,
+            control_base,
+            slave,
+            lba48: false,
+            sectors: 0,
+            model: String::new(),
+        };
+
+        let _guard = CHANNEL_LOCKS[channel].lock();
+        let mut identify = [0u16; 256];
+        unsafe {
+            // a floating bus reads 0xFF: no controller on this channel
+            if drive.read_reg(REG_STATUS) == 0xFF {
+                return None;
+            }
+            drive.select(0);
+            drive.write_reg(REG_SECTOR_COUNT, 0);
+            drive.write_reg(REG_LBA_LOW, 0);
+            drive.write_reg(REG_LBA_MID, 0);
+            drive.write_reg(REG_LBA_HIGH, 0);
+            drive.write_reg(REG_COMMAND, CMD_IDENTIFY);
+
+            if drive.read_reg(REG_STATUS) == 0 {
+                return None;
+            }
+            drive.wait_not_busy().ok()?;
+
+            // ATAPI and SATA devices set the LBA mid/high registers to a signature
+            if drive.read_reg(REG_LBA_MID) != 0 || drive.read_reg(REG_LBA_HIGH) != 0 {
+                return None;
+            }
+            drive.wait_drq().ok()?;
+
+            let mut data: Port<u16> = Port::new(io_base + REG_DATA);
+            for word in identify.iter_mut() {
+                *word = data.read();
+            }
+        }
+
+        // word 83 bit 10: 48-bit addressing, with the sector count in words 100-103
+        drive.lba48 = identify[83] & (1 << 10) != 0;
+        drive.sectors = if drive.lba48 {
+            identify[100..104]
+                .iter()
+                .rev()
+                .fold(0u64, |acc, &w| acc << 16 | w as u64)
+        } else {
+            identify[60] as u64 | (identify[61] as u64) << 16
+        };
+        // words 27-46: the model name, two characters per word, high byte first
+        let model: Vec<u8> = identify[27..47]
+            .iter()
+            .flat_map(|w| w.to_be_bytes())
+            .collect();
+        drive.model = String::from_utf8_lossy(&model).trim().into();
+
+        if drive.sectors == 0 {
+            None
+        } else {
+            Some(drive)
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    unsafe fn read_reg(&self, reg: u16) -> u8 {
+        Port::<u8>::new(self.io_base + reg).read()
+    }
+
+    unsafe fn write_reg(&self, reg: u16, value: u8) {
+        Port::<u8>::new(self.io_base + reg).write(value)
+    }
+
+    // each read of the alternate status register takes ~100ns
+    unsafe fn delay_400ns(&self) {
+        let mut alt_status: Port<u8> = Port::new(self.control_base);
+        for _ in 0..4 {
+            alt_status.read();
+        }
+    }
+
+    /// Selects this drive in LBA mode; LBA28 keeps the top four address bits here.
+    unsafe fn select(&self, lba28_high: u8) {
+        let slave_bit = if self.slave { 0x10 } else { 0 };
+        self.write_reg(REG_DRIVE, 0xE0 | slave_bit | (lba28_high & 0x0F));
+        self.delay_400ns();
+    }
+
+    unsafe fn wait_not_busy(&self) -> BlockResult<u8> {
+        for _ in 0..TIMEOUT {
+            let status = self.read_reg(REG_STATUS);
+            if status & STATUS_BSY == 0 {
+                return Ok(status);
+            }
+        }
+        Err(BlockError::Io)
+    }
+
+    unsafe fn wait_drq(&self) -> BlockResult<()> {
+        for _ in 0..TIMEOUT {
+            let status = self.read_reg(REG_STATUS);
+            if status & (STATUS_ERR | STATUS_DF) != 0 {
+                return Err(BlockError::Io);
+            }
+            if status & STATUS_BSY == 0 && status & STATUS_DRQ != 0 {
+                return Ok(());
+            }
+        }
+        Err(BlockError::Io)
+    }
+
+    /// Issues a transfer of `count` (1..=256) sectors starting at `lba`.
+    unsafe fn start(&self, write: bool, lba: u64, count: usize) -> BlockResult<()> {
+        self.wait_not_busy()?;
+        if self.lba48 {
+            self.select(0);
+            // each register is a two-deep FIFO: high-order bytes go in first
+            self.write_reg(REG_SECTOR_COUNT, (count >> 8) as u8);
+            self.write_reg(REG_LBA_LOW, (lba >> 24) as u8);
+            self.write_reg(REG_LBA_MID, (lba >> 32) as u8);
+            self.write_reg(REG_LBA_HIGH, (lba >> 40) as u8);
+        } else {
+            self.select((lba >> 24) as u8);
+        }
+        // in LBA28 mode a sector count of 0 means 256
+        self.write_reg(REG_SECTOR_COUNT, count as u8);
+        self.write_reg(REG_LBA_LOW, lba as u8);
+        self.write_reg(REG_LBA_MID, (lba >> 8) as u8);
+        self.write_reg(REG_LBA_HIGH, (lba >> 16) as u8);
+        let command = match (write, self.lba48) {
+            (false, false) => CMD_READ_SECTORS,
+            (false, true) => CMD_READ_SECTORS_EXT,
+            (true, false) => CMD_WRITE_SECTORS,
+            (true, true) => CMD_WRITE_SECTORS_EXT,
+        };
+        self.write_reg(REG_COMMAND, command);
+        Ok(())
+    }
+
+    unsafe fn flush_cache(&self) -> BlockResult<()> {
+        self.wait_not_busy()?;
+        self.select(0);
+        let command = if self.lba48 {
+            CMD_CACHE_FLUSH_EXT
+        } else {
+            CMD_CACHE_FLUSH
+        };
+        self.write_reg(REG_COMMAND, command);
+        let status = self.wait_not_busy()?;
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            return Err(BlockError::Io);
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        let _guard = CHANNEL_LOCKS[self.channel].lock();
+        let mut data: Port<u16> = Port::new(self.io_base + REG_DATA);
+
+        let chunk_size = MAX_SECTORS_PER_COMMAND * SECTOR_SIZE;
+        for (i, chunk) in buf.chunks_mut(chunk_size).enumerate() {
+            let first = lba + (i * MAX_SECTORS_PER_COMMAND) as u64;
+            unsafe {
+                self.start(false, first, chunk.len() / SECTOR_SIZE)?;
+                for sector in chunk.chunks_mut(SECTOR_SIZE) {
+                    self.wait_drq()?;
+                    for pair in sector.chunks_mut(2) {
+                        pair.copy_from_slice(&data.read().to_le_bytes());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        let _guard = CHANNEL_LOCKS[self.channel].lock();
+        let mut data: Port<u16> = Port::new(self.io_base + REG_DATA);
+
+        let chunk_size = MAX_SECTORS_PER_COMMAND * SECTOR_SIZE;
+        for (i, chunk) in buf.chunks(chunk_size).enumerate() {
+            let first = lba + (i * MAX_SECTORS_PER_COMMAND) as u64;
+            unsafe {
+                self.start(true, first, chunk.len() / SECTOR_SIZE)?;
+                for sector in chunk.chunks(SECTOR_SIZE) {
+                    self.wait_drq()?;
+                    for pair in sector.chunks(2) {
+                        data.write(u16::from_le_bytes([pair[0], pair[1]]));
+                    }
+                }
+                // the drive may still be writing back its cache
+                self.flush_cache()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> BlockResult<()> {
+        let _guard = CHANNEL_LOCKS[self.channel].lock();
+        unsafe { self.flush_cache() }
+    }
+}
+
+/// Probes both channels and returns the disks found as `hda`..`hdd`.
+pub fn probe() -> Vec<(String, BlockRef)> {
+    let mut disks = Vec::new();
+    for (index, name) in ["hda", "hdb", "hdc", "hdd"].iter().enumerate() {
+        if let Some(drive) = AtaDrive::identify(index / 2, index % 2 == 1) {
+            crate::println!(
+                "ata: {}: {} ({} MiB{})",
+                name,
+                drive.model(),
+                drive.sectors * SECTOR_SIZE as u64 / (1024 * 1024),
+                if drive.lba48 { ", LBA48" } else { "" }
+            );
+            let drive: BlockRef = Arc::new(drive);
+            disks.push((String::from(*name), drive));
+        }
+    }
+    disks
+}
+
+/// Claims the PCI IDE controller; its channels sit at the legacy ports in compatibility mode.
+pub static PCI_DRIVER: PciDriver = PciDriver {
+    name: "ata",
+    matches: &[Match::Class {
+        class: 0x01,
+        subclass: 0x01,
+    }],
+    probe: probe_controller,
+};
+
+fn probe_controller(dev: &PciDevice) -> bool {
+    // programming interface bits 0 and 2: a channel runs in native mode, with ports in BARs 0-3
+    if dev.prog_if & 0x05 != 0 {
+        crate::println!(
+            "ata: {}: native-mode controllers are not supported",
+            dev.address
+        );
+        return false;
+    }
+    for (name, disk) in probe() {
+        super::register_disk(&name, disk);
+    }
+    true
+}
@@ -0,0 +1,350 @@
+use super::{check_request, BlockDevice, BlockRef, BlockResult};
+use crate::kernel::kthread;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+/// Memory the shared cache may hold before it evicts.
+pub const CACHE_BYTES: usize = 256 * 1024;
+/// Dirty buffers are written back at least this often.
+pub const WRITEBACK_SECS: u64 = 5;
+
+// (device, block)
+type Key = (u64, u64);
+
+struct Buffer {
+    data: Box<[u8]>,
+    dirty: bool,
+    // position in the LRU order
+    stamp: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Blocks written to a device, by sync, write-back or eviction.
+    pub writebacks: u64,
+    pub buffers: usize,
+    pub dirty: usize,
+    pub bytes: usize,
+    pub capacity: usize,
+}
+
+struct Inner {
+    buffers: BTreeMap<Key, Buffer>,
+    // stamp -> key, oldest first
+    lru: BTreeMap<u64, Key>,
+    // the uncached devices behind each id, for writing back evicted buffers
+    devices: BTreeMap<u64, BlockRef>,
+    clock: u64,
+    bytes: usize,
+    stats: CacheStats,
+}
+
+/// A write-back cache of device blocks with LRU eviction, shared by every device wrapped in it.
+pub struct BufferCache {
+    capacity: usize,
+    next_id: AtomicU64,
+    inner: Mutex<Inner>,
+}
+
+impl Inner {
+    fn touch(&mut self, key: Key) {
+        self.clock += 1;
+        let stamp = self.clock;
+        if let Some(buffer) = self.buffers.get_mut(&key) {
+            self.lru.remove(&buffer.stamp);
+            buffer.stamp = stamp;
+            self.lru.insert(stamp, key);
+        }
+    }
+
+    fn insert(&mut self, key: Key, data: &[u8], dirty: bool) {
+        if let Some(buffer) = self.buffers.get_mut(&key) {
+            buffer.data.copy_from_slice(data);
+            buffer.dirty |= dirty;
+        } else {
+            self.bytes += data.len();
+            self.buffers.insert(
+                key,
+                Buffer {
+                    data: data.into(),
+                    dirty,
+                    stamp: 0,
+                },
+            );
+        }
+        self.touch(key);
+    }
+
+    /// Drops clean least recently used buffers until `capacity` is met. Dirty ones are
+    /// left in place and returned, with their device and a copy of their data, to be
+    /// written back first.
+    fn evict_clean(&mut self, capacity: usize) -> Vec<(Key, BlockRef, Box<[u8]>)> {
+        let mut clean = Vec::new();
+        let mut dirty = Vec::new();
+        let mut freed = 0;
+        for (&stamp, &key) in &self.lru {
+            if self.bytes - freed <= capacity {
+                break;
+            }
+            let buffer = &self.buffers[&key];
+            freed += buffer.data.len();
+            if !buffer.dirty {
+                clean.push((stamp, key));
+            } else if let Some(dev) = self.devices.get(&key.0) {
+                dirty.push((key, dev.clone(), buffer.data.clone()));
+            }
+        }
+        for (stamp, key) in clean {
+            self.lru.remove(&stamp);
+            let buffer = self.buffers.remove(&key).unwrap();
+            self.bytes -= buffer.data.len();
+            self.stats.evictions += 1;
+        }
+        dirty
+    }
+
+    /// Copies out the dirty buffers of device `id`, merged into runs of adjacent blocks.
+    fn dirty_runs(&self, id: u64) -> Vec<(u64, Vec<u8>)> {
+        let mut runs: Vec<(u64, u64, Vec<u8>)> = Vec::new();
+        for (&(_, lba), buffer) in self.buffers.range((id, 0)..=(id, u64::MAX)) {
+            if !buffer.dirty {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((_, next, data)) if *next == lba => {
+                    data.extend_from_slice(&buffer.data);
+                    *next += 1;
+                }
+                _ => runs.push((lba, lba + 1, buffer.data.to_vec())),
+            }
+        }
+        runs.into_iter().map(|(lba, _, data)| (lba, data)).collect()
+    }
+
+    /// Marks `key` clean once `data` went to the device, unless it changed in the meantime.
+    fn mark_clean(&mut self, key: Key, data: &[u8]) {
+        if let Some(buffer) = self.buffers.get_mut(&key) {
+            if *buffer.data == *data {
+                buffer.dirty = false;
+            }
+        }
+    }
+}
+
+impl BufferCache {
+    pub const fn new(capacity: usize) -> Self {
+        BufferCache {
+            capacity,
+            next_id: AtomicU64::new(0),
+            inner: Mutex::new(Inner {
+                buffers: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                devices: BTreeMap::new(),
+                clock: 0,
+                bytes: 0,
+                stats: CacheStats {
+                    hits: 0,
+                    misses: 0,
+                    evictions: 0,
+                    writebacks: 0,
+                    buffers: 0,
+                    dirty: 0,
+                    bytes: 0,
+                    capacity: 0,
+                },
+            }),
+        }
+    }
+
+    /// Puts `dev` behind this cache. Everything that reads or writes the device should go
+    /// through the returned handle, or it may see stale data.
+    pub fn wrap(&'static self, dev: BlockRef) -> Arc<CachedDevice> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.inner.lock().devices.insert(id, dev.clone());
+        Arc::new(CachedDevice {
+            cache: self,
+            id,
+            dev,
+        })
+    }
+
+    /// Writes every dirty buffer back to its device.
+    pub fn sync(&self) -> BlockResult<()> {
+        let ids: Vec<u64> = self.inner.lock().devices.keys().copied().collect();
+        let mut result = Ok(());
+        for id in ids {
+            // keep going so one failing device doesn't hold back the others
+            if let Err(e) = self.write_back(id) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Writes the dirty buffers of device `id`, merging adjacent blocks into one request.
+    /// The lock is only held to copy the data out and to mark it clean afterwards, never
+    /// while the device works.
+    fn write_back(&self, id: u64) -> BlockResult<()> {
+        let (dev, runs) = {
+            let inner = self.inner.lock();
+            match inner.devices.get(&id) {
+                Some(dev) => (dev.clone(), inner.dirty_runs(id)),
+                None => return Ok(()),
+            }
+        };
+        let size = dev.block_size();
+        for (lba, data) in runs {
+            dev.write_blocks(lba, &data)?;
+            let mut inner = self.inner.lock();
+            for (n, block) in data.chunks(size).enumerate() {
+                inner.mark_clean((id, lba + n as u64), block);
+            }
+            inner.stats.writebacks += (data.len() / size) as u64;
+        }
+        dev.flush()
+    }
+
+    /// Drops least recently used buffers until the capacity is met. Dirty ones are written
+    /// back first, outside the lock, and stay cached until they are clean.
+    ///
+    /// A buffer whose write fails stays dirty and cached, and eviction stops there rather
+    /// than retrying it; the next sync or flush tries again and reports the error.
+    fn evict(&self) {
+        loop {
+            let dirty = self.inner.lock().evict_clean(self.capacity);
+            if dirty.is_empty() {
+                return;
+            }
+            for (key, dev, data) in dirty {
+                let written = dev.write_blocks(key.1, &data);
+                let mut inner = self.inner.lock();
+                if let Err(e) = written {
+                    crate::println!("cache: write-back of block {} failed: {}", key.1, e);
+                    return;
+                }
+                inner.stats.writebacks += 1;
+                inner.mark_clean(key, &data);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock();
+        CacheStats {
+            buffers: inner.buffers.len(),
+            dirty: inner.buffers.values().filter(|b| b.dirty).count(),
+            bytes: inner.bytes,
+            capacity: self.capacity,
+            ..inner.stats
+        }
+    }
+}
+
+/// A block device whose reads and writes go through a `BufferCache`.
+pub struct CachedDevice {
+    cache: &'static BufferCache,
+    id: u64,
+    dev: BlockRef,
+}
+
+impl BlockDevice for CachedDevice {
+    fn block_size(&self) -> usize {
+        self.dev.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.dev.block_count()
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        let size = self.block_size();
+        let count = buf.len() / size;
+
+        let mut i = 0;
+        while i < count {
+            let key = (self.id, lba + i as u64);
+            let mut inner = self.cache.inner.lock();
+            if let Some(buffer) = inner.buffers.get(&key) {
+                buf[i * size..(i + 1) * size].copy_from_slice(&buffer.data);
+                inner.touch(key);
+                inner.stats.hits += 1;
+                i += 1;
+                continue;
+            }
+
+            // read the whole run of missing blocks in one request, without the lock
+            let mut end = i + 1;
+            while end < count && !inner.buffers.contains_key(&(self.id, lba + end as u64)) {
+                end += 1;
+            }
+            drop(inner);
+            let run = &mut buf[i * size..end * size];
+            self.dev.read_blocks(lba + i as u64, run)?;
+
+            let mut inner = self.cache.inner.lock();
+            for (n, block) in run.chunks_mut(size).enumerate() {
+                let key = (self.id, lba + (i + n) as u64);
+                match inner.buffers.get(&key) {
+                    // written while the device was busy, so newer than what it returned
+                    Some(buffer) => block.copy_from_slice(&buffer.data),
+                    None => inner.insert(key, block, false),
+                }
+            }
+            inner.stats.misses += (end - i) as u64;
+            i = end;
+        }
+        self.cache.evict();
+        Ok(())
+    }
+
+    /// Only updates the cache; the device sees the data on sync, write-back or eviction.
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        let size = self.block_size();
+        let mut inner = self.cache.inner.lock();
+        for (i, block) in buf.chunks(size).enumerate() {
+            inner.insert((self.id, lba + i as u64), block, true);
+        }
+        drop(inner);
+        self.cache.evict();
+        Ok(())
+    }
+
+    fn flush(&self) -> BlockResult<()> {
+        self.cache.write_back(self.id)
+    }
+}
+
+/// The cache in front of every registered disk.
+pub static CACHE: BufferCache = BufferCache::new(CACHE_BYTES);
+
+pub fn cached(dev: BlockRef) -> BlockRef {
+    CACHE.wrap(dev)
+}
+
+pub fn sync() -> BlockResult<()> {
+    CACHE.sync()
+}
+
+pub fn stats() -> CacheStats {
+    CACHE.stats()
+}
+
+/// Writes dirty buffers back every `WRITEBACK_SECS`; runs as a kernel thread, so a slow
+/// disk holds up nobody else.
+pub fn writeback() {
+    loop {
+        kthread::sleep_ms(WRITEBACK_SECS * 1000);
+        if let Err(e) = sync() {
+            crate::println!("cache: write-back failed: {}", e);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+pub mod ata;
+pub mod cache;
+pub mod partition;
+pub mod virtio;
+
+use crate::drivers::pci;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use partition::Partition;
+use spin::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The request reaches past the end of the device.
+    OutOfRange,
+    /// The buffer is not a whole number of blocks.
+    BadBuffer,
+    /// The device reported an error or did not respond.
+    Io,
+    ReadOnly,
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockError::OutOfRange => write!(f, "Block out of range"),
+            BlockError::BadBuffer => write!(f, "Buffer is not a multiple of the block size"),
+            BlockError::Io => write!(f, "I/O error"),
+            BlockError::ReadOnly => write!(f, "Read-only device"),
+        }
+    }
+}
+
+pub type BlockResult<T> = Result<T, BlockError>;
+
+/// A random-access device addressed in fixed-size blocks (sectors).
+pub trait BlockDevice: Send + Sync {
+    fn block_size(&self) -> usize;
+    fn block_count(&self) -> u64;
+    /// Reads `buf.len() / block_size()` blocks starting at `lba`.
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()>;
+    /// Writes `buf.len() / block_size()` blocks starting at `lba`.
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()>;
+
+    /// Waits until written data has reached the medium.
+    fn flush(&self) -> BlockResult<()> {
+        Ok(())
+    }
+}
+
+/// Checks that `len` bytes starting at `lba` are whole blocks inside `dev`.
+pub fn check_request(dev: &dyn BlockDevice, lba: u64, len: usize) -> BlockResult<u64> {
+    let block_size = dev.block_size();
+    if !len.is_multiple_of(block_size) {
+        return Err(BlockError::BadBuffer);
+    }
+    let count = (len / block_size) as u64;
+    match lba.checked_add(count) {
+        Some(end) if end <= dev.block_count() => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+/// Reads `buf.len()` bytes at byte `offset`, which need not be block aligned.
+pub fn read_bytes(dev: &dyn BlockDevice, offset: u64, buf: &mut [u8]) -> BlockResult<()> {
+    let block_size = dev.block_size() as u64;
+    let mut block = vec![0u8; block_size as usize];
+    let mut done = 0;
+
+    while done < buf.len() {
+        let pos = offset + done as u64;
+        let lba = pos / block_size;
+        let within = (pos % block_size) as usize;
+        let len = (block_size as usize - within).min(buf.len() - done);
+
+        // whole blocks can go straight into the caller's buffer
+        if within == 0 && buf.len() - done >= block_size as usize {
+            let whole = (buf.len() - done) / block_size as usize * block_size as usize;
+            dev.read_blocks(lba, &mut buf[done..done + whole])?;
+            done += whole;
+            continue;
+        }
+
+        dev.read_blocks(lba, &mut block)?;
+        buf[done..done + len].copy_from_slice(&block[within..within + len]);
+        done += len;
+    }
+    Ok(())
+}
+
+/// Writes `data` at byte `offset`, reading back partially covered blocks first.
+pub fn write_bytes(dev: &dyn BlockDevice, offset: u64, data: &[u8]) -> BlockResult<()> {
+    let block_size = dev.block_size() as u64;
+    let mut block = vec![0u8; block_size as usize];
+    let mut done = 0;
+
+    while done < data.len() {
+        let pos = offset + done as u64;
+        let lba = pos / block_size;
+        let within = (pos % block_size) as usize;
+        let len = (block_size as usize - within).min(data.len() - done);
+
+        if within == 0 && data.len() - done >= block_size as usize {
+            let whole = (data.len() - done) / block_size as usize * block_size as usize;
+            dev.write_blocks(lba, &data[done..done + whole])?;
+            done += whole;
+            continue;
+        }
+
+        dev.read_blocks(lba, &mut block)?;
+        block[within..within + len].copy_from_slice(&data[done..done + len]);
+        dev.write_blocks(lba, &block)?;
+        done += len;
+    }
+    Ok(())
+}
+
+/// A block device backed by memory, for tests and scratch space.
+pub struct MemDisk {
+    block_size: usize,
+    data: Mutex<Vec<u8>>,
+}
+
+impl MemDisk {
+    pub fn new(block_size: usize, block_count: u64) -> Self {
+        MemDisk {
+            block_size,
+            data: Mutex::new(vec![0; block_size * block_count as usize]),
+        }
+    }
+
+    /// Wraps an existing disk image; a partial last block is zero-padded.
+    pub fn from_image(block_size: usize, image: &[u8]) -> Self {
+        let mut data = Vec::from(image);
+        let padded = data.len().div_ceil(block_size) * block_size;
+        data.resize(padded, 0);
+        MemDisk {
+            block_size,
+            data: Mutex::new(data),
+        }
+    }
+}
+
+impl BlockDevice for MemDisk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.lock().len() / self.block_size) as u64
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        let start = lba as usize * self.block_size;
+        buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        let start = lba as usize * self.block_size;
+        self.data.lock()[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+pub type BlockRef = Arc<dyn BlockDevice>;
+
+// registered devices by name ("hda", ...), used as mount sources
+static DEVICES: Mutex<BTreeMap<String, BlockRef>> = Mutex::new(BTreeMap::new());
+
+pub fn register(name: &str, dev: BlockRef) {
+    DEVICES.lock().insert(name.into(), dev);
+}
+
+/// Looks a device up by name; a `/dev/` prefix is accepted.
+pub fn get(name: &str) -> Option<BlockRef> {
+    let name = name.strip_prefix("/dev/").unwrap_or(name);
+    DEVICES.lock().get(name).cloned()
+}
+
+pub fn devices() -> Vec<(String, BlockRef)> {
+    DEVICES
+        .lock()
+        .iter()
+        .map(|(name, dev)| (name.clone(), dev.clone()))
+        .collect()
+}
+
+/// Registers a disk and each partition on it (`hda` and `hda1`, `hda2`, ...).
+pub fn register_disk(name: &str, disk: BlockRef) {
+    // partitions share the disk's cache, so they stay coherent with each other
+    let disk = cache::cached(disk);
+    match partition::scan(&*disk) {
+        Ok(partitions) => {
+            for p in partitions {
+                let part = format!("{}{}", name, p.number);
+                crate::println!("{}: {} blocks at {}", part, p.count, p.start);
+                register(
+                    &part,
+                    Arc::new(Partition::new(disk.clone(), p.start, p.count)),
+                );
+            }
+        }
+        Err(e) => crate::println!("{}: can't read partition table: {}", name, e),
+    }
+    register(name, disk);
+}
+
+/// Registers the storage drivers, which register every disk they find.
+pub fn init() {
+    pci::register_driver(&ata::PCI_DRIVER);
+    pci::register_driver(&virtio::PCI_DRIVER);
+}
@@ -0,0 +1,205 @@
+use super::{check_request, BlockDevice, BlockRef, BlockResult};
+use alloc::vec;
+use alloc::vec::Vec;
+
+const MBR_TABLE: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+const EXTENDED_TYPES: [u8; 3] = [0x05, 0x0F, 0x85];
+// logical partitions inside an extended one are numbered from 5, as on Linux
+const FIRST_LOGICAL: usize = 5;
+// guards against EBR chains that loop back on themselves
+const MAX_LOGICAL: usize = 64;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MAX_ENTRIES: u32 = 256;
+
+/// A slice of another block device, as described by a partition table.
+pub struct Partition {
+    dev: BlockRef,
+    start: u64,
+    count: u64,
+}
+
+impl Partition {
+    pub fn new(dev: BlockRef, start: u64, count: u64) -> Self {
+        Partition { dev, start, count }
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+}
+
+impl BlockDevice for Partition {
+    fn block_size(&self) -> usize {
+        self.dev.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.count
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        self.dev.read_blocks(self.start + lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        self.dev.write_blocks(self.start + lba, buf)
+    }
+
+    fn flush(&self) -> BlockResult<()> {
+        self.dev.flush()
+    }
+}
+
+/// A partition found on a disk: its number (1-based, as in `hda1`) and extent in blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionInfo {
+    pub number: usize,
+    pub start: u64,
+    pub count: u64,
+}
+
+fn le32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+fn le64(b: &[u8], at: usize) -> u64 {
+    le32(b, at) as u64 | (le32(b, at + 4) as u64) << 32
+}
+
+fn read_block(dev: &dyn BlockDevice, lba: u64) -> BlockResult<Vec<u8>> {
+    let mut block = vec![0u8; dev.block_size()];
+    dev.read_blocks(lba, &mut block)?;
+    Ok(block)
+}
+
+/// An MBR entry: (status, type, first block, block count).
+fn mbr_entries(sector: &[u8]) -> Option<[(u8, u8, u64, u64); 4]> {
+    if sector.len() < 512 || sector[510] != 0x55 || sector[511] != 0xAA {
+        return None;
+    }
+    let mut entries = [(0, 0, 0, 0); 4];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let raw = &sector[MBR_TABLE + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        *entry = (raw[0], raw[4], le32(raw, 8) as u64, le32(raw, 12) as u64);
+        // a FAT boot sector also ends in 55 AA; its code bytes won't pass this
+        if raw[0] != 0 && raw[0] != 0x80 {
+            return None;
+        }
+    }
+    Some(entries)
+}
+
+/// Reads the partition table of `dev`: GPT if the MBR is protective, MBR otherwise.
+/// A disk without a valid table has no partitions.
+pub fn scan(dev: &dyn BlockDevice) -> BlockResult<Vec<PartitionInfo>> {
+    let sector = read_block(dev, 0)?;
+    let entries = match mbr_entries(&sector) {
+        Some(entries) => entries,
+        None => return Ok(Vec::new()),
+    };
+    if entries.iter().any(|e| e.1 == TYPE_GPT_PROTECTIVE) {
+        return scan_gpt(dev);
+    }
+
+    let total = dev.block_count();
+    let fits = |start: u64, count: u64| count > 0 && start > 0 && start + count <= total;
+    let mut partitions = Vec::new();
+    for (i, &(_, kind, start, count)) in entries.iter().enumerate() {
+        if kind == 0 || !fits(start, count) {
+            continue;
+        }
+        if EXTENDED_TYPES.contains(&kind) {
+            scan_extended(dev, start, &mut partitions)?;
+            continue;
+        }
+        partitions.push(PartitionInfo {
+            number: i + 1,
+            start,
+            count,
+        });
+    }
+    partitions.sort_by_key(|p| p.number);
+    Ok(partitions)
+}
+
+/// Follows the chain of extended boot records starting at `base`.
+fn scan_extended(
+    dev: &dyn BlockDevice,
+    base: u64,
+    partitions: &mut Vec<PartitionInfo>,
+) -> BlockResult<()> {
+    let total = dev.block_count();
+    let mut ebr = base;
+    for n in 0..MAX_LOGICAL {
+        let entries = match mbr_entries(&read_block(dev, ebr)?) {
+            Some(entries) => entries,
+            None => break,
+        };
+
+        // the first entry is relative to this EBR, the link to the next one is relative to `base`
+        let (_, kind, start, count) = entries[0];
+        if kind != 0 && count > 0 && ebr + start + count <= total {
+            partitions.push(PartitionInfo {
+                number: FIRST_LOGICAL + n,
+                start: ebr + start,
+                count,
+            });
+        }
+        let (_, next_kind, next, _) = entries[1];
+        if next_kind == 0 || next == 0 || base + next >= total {
+            break;
+        }
+        ebr = base + next;
+    }
+    Ok(())
+}
+
+fn scan_gpt(dev: &dyn BlockDevice) -> BlockResult<Vec<PartitionInfo>> {
+    let header = read_block(dev, 1)?;
+    if &header[..8] != GPT_SIGNATURE {
+        return Ok(Vec::new());
+    }
+    let entries_lba = le64(&header, 72);
+    let entry_count = le32(&header, 80).min(GPT_MAX_ENTRIES) as usize;
+    let entry_size = le32(&header, 84) as usize;
+    if entry_size < 128 || entry_size > dev.block_size() {
+        return Ok(Vec::new());
+    }
+
+    let block_size = dev.block_size();
+    let per_block = block_size / entry_size;
+    let total = dev.block_count();
+    // the entry array has to fit on the disk
+    match entries_lba.checked_add(entry_count.div_ceil(per_block) as u64) {
+        Some(end) if end <= total => {}
+        _ => return Ok(Vec::new()),
+    }
+    let mut partitions = Vec::new();
+    let mut block = Vec::new();
+
+    for i in 0..entry_count {
+        if i.is_multiple_of(per_block) {
+            block = read_block(dev, entries_lba + (i / per_block) as u64)?;
+        }
+        let raw = &block[(i % per_block) * entry_size..][..entry_size];
+        // an all-zero type GUID marks an unused entry
+        if raw[..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+        let (first, last) = (le64(raw, 32), le64(raw, 40));
+        if first == 0 || last < first || last >= total {
+            continue;
+        }
+        partitions.push(PartitionInfo {
+            number: i + 1,
+            start: first,
+            count: last - first + 1,
+        });
+    }
+    Ok(partitions)
+}
@@ -0,0 +1,402 @@
+use super::{check_request, BlockDevice, BlockError, BlockRef, BlockResult};
+use crate::drivers::pci::{Match, PciDevice, PciDriver};
+use crate::drivers::virtio::{self, pci::NO_VECTOR, Segment, Transport, VirtQueue};
+use crate::kernel::memory::dma::DmaBuffer;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+pub const SECTOR_SIZE: usize = 512;
+
+// feature bits
+const F_RO: u64 = 1 << 5;
+const F_FLUSH: u64 = 1 << 9;
+
+// device config: capacity in 512-byte sectors
+const CONFIG_CAPACITY: u16 = 0;
+
+// request types
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+
+const S_OK: u8 = 0;
+
+// type, reserved, sector
+const HEADER_SIZE: usize = 16;
+// bytes moved per request; larger transfers are split
+const MAX_REQUEST: usize = 64 * 1024;
+// larger queues are allowed to shrink to this on the modern interface
+const MAX_QUEUE_SIZE: u16 = 256;
+
+// completion state per head descriptor; touched by the interrupt handler, so atomics only
+struct Slot {
+    done: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// A virtio block device. Requests complete on an MSI-X interrupt, or by polling the used
+/// ring when interrupts are unavailable.
+pub struct VirtioBlk {
+    transport: Transport,
+    // only locked with interrupts off: the interrupt handler takes it too
+    queue: Mutex<VirtQueue>,
+    slots: Vec<Slot>,
+    sectors: u64,
+    read_only: bool,
+    can_flush: bool,
+    interrupts: bool,
+}
+
+// a submitted request; waits for the device and frees its descriptors if dropped early
+struct Pending<'a> {
+    disk: &'a VirtioBlk,
+    head: u16,
+    buffer: DmaBuffer,
+    finished: bool,
+}
+
+impl Pending<'_> {
+    fn is_done(&self) -> bool {
+        self.disk.slots[self.head as usize]
+            .done
+            .load(Ordering::Acquire)
+    }
+
+    /// Releases the descriptors and reports the device's status byte.
+    fn finish(&mut self) -> BlockResult<()> {
+        self.finished = true;
+        self.disk.slots[self.head as usize]
+            .done
+            .store(false, Ordering::Release);
+        without_interrupts(|| self.disk.queue.lock().free_chain(self.head));
+        match self.buffer.as_slice().last() {
+            Some(&S_OK) => Ok(()),
+            _ => Err(BlockError::Io),
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        let len = self.buffer.len();
+        &self.buffer.as_slice()[HEADER_SIZE..len - 1]
+    }
+
+    fn wait(&mut self) -> BlockResult<()> {
+        while !self.is_done() {
+            self.disk.process_used();
+            core::hint::spin_loop();
+        }
+        self.finish()
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        // the device may still write into the buffer
+        if !self.finished {
+            let _ = self.wait();
+        }
+    }
+}
+
+impl Future for Pending<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let slot = &self.disk.slots[self.head as usize];
+        if slot.done.load(Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        slot.waker.register(cx.waker());
+        // catches completions that raced with registering, and drives polling mode
+        self.disk.process_used();
+        if slot.done.load(Ordering::Acquire) {
+            slot.waker.take();
+            return Poll::Ready(());
+        }
+        if !self.disk.interrupts {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+impl VirtioBlk {
+    fn new(dev: &PciDevice) -> Option<VirtioBlk> {
+        let mut transport = Transport::new(dev)?;
+        dev.enable();
+        let features = virtio::negotiate(&transport, F_RO | F_FLUSH)?;
+
+        // one vector for the request queue; config changes are not used
+        let interrupts = dev.enable_msix(&[interrupt]).is_some();
+        transport.set_msix(interrupts);
+        if interrupts {
+            transport.set_config_vector(NO_VECTOR);
+        }
+
+        let mut size = transport.max_queue_size(0);
+        if !transport.fixed_queue_size() {
+            size = size.min(MAX_QUEUE_SIZE);
+        }
+        if size == 0 {
+            transport.set_status(virtio::STATUS_FAILED);
+            return None;
+        }
+        let queue = VirtQueue::new(0, size)?;
+        let vector = if interrupts { 0 } else { NO_VECTOR };
+        if !transport.setup_queue(&queue, vector) {
+            transport.set_status(virtio::STATUS_FAILED);
+            return None;
+        }
+
+        let sectors = transport.read_config_u64(CONFIG_CAPACITY);
+        virtio::driver_ok(&transport);
+        Some(VirtioBlk {
+            transport,
+            queue: Mutex::new(queue),
+            slots: (0..size)
+                .map(|_| Slot {
+                    done: AtomicBool::new(false),
+                    waker: AtomicWaker::new(),
+                })
+                .collect(),
+            sectors,
+            read_only: features & F_RO != 0,
+            can_flush: features & F_FLUSH != 0,
+            interrupts,
+        })
+    }
+
+    /// Marks finished requests done and wakes their waiters.
+    fn process_used(&self) {
+        without_interrupts(|| {
+            let mut queue = self.queue.lock();
+            while let Some((head, _)) = queue.pop_used() {
+                let slot = &self.slots[head as usize];
+                slot.done.store(true, Ordering::Release);
+                slot.waker.wake();
+            }
+        });
+    }
+
+    /// Queues one request of `len` data bytes; `data` is copied in for writes. Returns None
+    /// while the queue or the DMA pool is full.
+    fn submit(&self, kind: u32, lba: u64, len: usize, data: Option<&[u8]>) -> Option<Pending<'_>> {
+        let mut buffer = DmaBuffer::new(HEADER_SIZE + len + 1)?;
+        let bytes = buffer.as_mut_slice();
+        bytes[0..4].copy_from_slice(&kind.to_le_bytes());
+        bytes[8..16].copy_from_slice(&lba.to_le_bytes());
+        if let Some(data) = data {
+            bytes[HEADER_SIZE..HEADER_SIZE + len].copy_from_slice(data);
+        }
+        // a status the device never writes reads as an error
+        bytes[HEADER_SIZE + len] = 0xFF;
+
+        let phys = buffer.phys();
+        let mut segments = Vec::with_capacity(3);
+        segments.push(Segment {
+            addr: phys,
+            len: HEADER_SIZE as u32,
+            device_writes: false,
+        });
+        if len > 0 {
+            segments.push(Segment {
+                addr: phys + HEADER_SIZE as u64,
+                len: len as u32,
+                device_writes: kind == T_IN,
+            });
+        }
+        segments.push(Segment {
+            addr: phys + (HEADER_SIZE + len) as u64,
+            len: 1,
+            device_writes: true,
+        });
+
+        let head = without_interrupts(|| self.queue.lock().add(&segments))?;
+        self.transport.notify(0);
+        Some(Pending {
+            disk: self,
+            head,
+            buffer,
+            finished: false,
+        })
+    }
+
+    // waits for room in the queue without an executor to yield to
+    fn submit_blocking(&self, kind: u32, lba: u64, len: usize, data: Option<&[u8]>) -> Pending<'_> {
+        loop {
+            if let Some(pending) = self.submit(kind, lba, len, data) {
+                return pending;
+            }
+            self.process_used();
+            core::hint::spin_loop();
+        }
+    }
+
+    async fn submit_async(
+        &self,
+        kind: u32,
+        lba: u64,
+        len: usize,
+        data: Option<&[u8]>,
+    ) -> Pending<'_> {
+        loop {
+            if let Some(pending) = self.submit(kind, lba, len, data) {
+                return pending;
+            }
+            self.process_used();
+            crate::kernel::task::yield_now().await;
+        }
+    }
+
+    /// Reads whole sectors starting at `lba`, yielding to other tasks while the device works.
+    pub async fn read(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        for (i, chunk) in buf.chunks_mut(MAX_REQUEST).enumerate() {
+            let first = lba + (i * MAX_REQUEST / SECTOR_SIZE) as u64;
+            let mut pending = self.submit_async(T_IN, first, chunk.len(), None).await;
+            (&mut pending).await;
+            pending.finish()?;
+            chunk.copy_from_slice(pending.data());
+        }
+        Ok(())
+    }
+
+    /// Writes whole sectors starting at `lba`, yielding to other tasks while the device works.
+    pub async fn write(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        for (i, chunk) in buf.chunks(MAX_REQUEST).enumerate() {
+            let first = lba + (i * MAX_REQUEST / SECTOR_SIZE) as u64;
+            let mut pending = self
+                .submit_async(T_OUT, first, chunk.len(), Some(chunk))
+                .await;
+            (&mut pending).await;
+            pending.finish()?;
+        }
+        Ok(())
+    }
+
+    pub async fn sync(&self) -> BlockResult<()> {
+        if !self.can_flush {
+            return Ok(());
+        }
+        let mut pending = self.submit_async(T_FLUSH, 0, 0, None).await;
+        (&mut pending).await;
+        pending.finish()
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn uses_interrupts(&self) -> bool {
+        self.interrupts
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        for (i, chunk) in buf.chunks_mut(MAX_REQUEST).enumerate() {
+            let first = lba + (i * MAX_REQUEST / SECTOR_SIZE) as u64;
+            let mut pending = self.submit_blocking(T_IN, first, chunk.len(), None);
+            pending.wait()?;
+            chunk.copy_from_slice(pending.data());
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        check_request(self, lba, buf.len())?;
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        for (i, chunk) in buf.chunks(MAX_REQUEST).enumerate() {
+            let first = lba + (i * MAX_REQUEST / SECTOR_SIZE) as u64;
+            self.submit_blocking(T_OUT, first, chunk.len(), Some(chunk))
+                .wait()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> BlockResult<()> {
+        if !self.can_flush {
+            return Ok(());
+        }
+        self.submit_blocking(T_FLUSH, 0, 0, None).wait()
+    }
+}
+
+// every virtio disk, for the interrupt handler and async users
+static DISKS: Mutex<Vec<Arc<VirtioBlk>>> = Mutex::new(Vec::new());
+
+fn interrupt() {
+    // interrupts are off here, and DISKS is only changed with them off
+    for disk in DISKS.lock().iter() {
+        disk.process_used();
+    }
+}
+
+/// The virtio disks in probe order (`vda`, `vdb`, ...), for callers that want the async API.
+pub fn disks() -> Vec<Arc<VirtioBlk>> {
+    without_interrupts(|| DISKS.lock().clone())
+}
+
+pub static PCI_DRIVER: PciDriver = PciDriver {
+    name: "virtio-blk",
+    matches: &[
+        // transitional and modern device IDs
+        Match::Device {
+            vendor: virtio::VENDOR_ID,
+            device: 0x1001,
+        },
+        Match::Device {
+            vendor: virtio::VENDOR_ID,
+            device: 0x1042,
+        },
+    ],
+    probe,
+};
+
+fn probe(dev: &PciDevice) -> bool {
+    let disk = match VirtioBlk::new(dev) {
+        Some(disk) => Arc::new(disk),
+        None => {
+            crate::println!("virtio-blk: {}: initialization failed", dev.address);
+            return false;
+        }
+    };
+    let name = without_interrupts(|| {
+        let mut disks = DISKS.lock();
+        disks.push(disk.clone());
+        format!("vd{}", (b'a' + disks.len() as u8 - 1) as char)
+    });
+    crate::println!(
+        "virtio-blk: {}: {} MiB{}{}",
+        name,
+        disk.sectors * SECTOR_SIZE as u64 / (1024 * 1024),
+        if disk.read_only { ", read-only" } else { "" },
+        if disk.interrupts { "" } else { ", polled" }
+    );
+    let disk: BlockRef = disk;
+    super::register_disk(&name, disk);
+    true
+}
@@ -3,6 +3,7 @@ use crate::ui::{input, terminal};
 use conquer_once::spin::OnceCell;
 use core::{
     pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
     task::{Context, Poll},
 };
 use crossbeam_queue::ArrayQueue;
@@ -15,10 +16,30 @@ use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
 
+// scan code set 1 make and break codes; right Ctrl sends the same after an 0xE0 prefix
+const CTRL_MAKE: u8 = 0x1D;
+const CTRL_BREAK: u8 = 0x9D;
+const C_MAKE: u8 = 0x2E;
+
+static CTRL_HELD: AtomicBool = AtomicBool::new(false);
+
+// Ctrl-C interrupts the foreground process right away, even if nothing reads the keyboard
+fn check_interrupt_key(scancode: u8) {
+    match scancode {
+        CTRL_MAKE => CTRL_HELD.store(true, Ordering::Relaxed),
+        CTRL_BREAK => CTRL_HELD.store(false, Ordering::Relaxed),
+        C_MAKE if CTRL_HELD.load(Ordering::Relaxed) => {
+            crate::kernel::signal::raise_foreground(crate::kernel::signal::SIGINT)
+        }
+        _ => {}
+    }
+}
+
 /// Called by the keyboard interrupt handler
 ///
 /// Must not block or allocate.
 pub(crate) fn add_scancode(scancode: u8) {
+    check_interrupt_key(scancode);
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if let Err(_) = queue.push(scancode) {
             println!("WARNING: scancode queue full; dropping keyboard input");
@@ -84,7 +105,9 @@ pub async fn print_keypresses() {
                     }
 
                     DecodedKey::Unicode(c) => {
-                        terminal::write_char(c);
+                        if input::echo_enabled() || c == '\n' {
+                            terminal::write_char(c);
+                        }
                         input::push_char(c);
                     }
 
@@ -1,3 +1,7 @@
+pub mod block;
 pub mod keyboard;
+pub mod net;
+pub mod pci;
 pub mod serial;
 pub mod vg_buffer;
+pub mod virtio;
@@ -0,0 +1,99 @@
+pub mod loopback;
+pub mod virtio;
+
+use crate::drivers::pci;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xFF; 6]);
+    pub const ZERO: MacAddress = MacAddress([0; 6]);
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            m[0], m[1], m[2], m[3], m[4], m[5]
+        )
+    }
+}
+
+/// A network interface card, or anything else that moves Ethernet frames.
+pub trait NetDevice: Send + Sync {
+    fn mac(&self) -> MacAddress;
+
+    /// Largest payload of a frame, not counting the 14-byte Ethernet header.
+    fn mtu(&self) -> usize;
+
+    /// Queues one frame for sending. Returns false if it was dropped.
+    fn transmit(&self, frame: &[u8]) -> bool;
+
+    /// Takes the next frame that arrived, if any.
+    fn receive(&self) -> Option<Vec<u8>>;
+}
+
+pub type NetRef = Arc<dyn NetDevice>;
+
+static DEVICES: Mutex<Vec<(String, NetRef)>> = Mutex::new(Vec::new());
+
+/// Makes `dev` available to the network stack as `name` (`eth0`, `lo`, ...).
+pub fn register(name: &str, dev: NetRef) {
+    DEVICES.lock().push((name.into(), dev));
+}
+
+pub fn devices() -> Vec<(String, NetRef)> {
+    DEVICES.lock().clone()
+}
+
+// set when a device has frames waiting or time-based work is due
+static PENDING: AtomicBool = AtomicBool::new(false);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Wakes whoever drives the network stack. Safe to call from interrupt handlers.
+pub fn signal() {
+    PENDING.store(true, Ordering::Release);
+    WAKER.wake();
+}
+
+/// Resolves after the next `signal`.
+pub fn signalled() -> impl Future<Output = ()> {
+    Signalled
+}
+
+struct Signalled;
+
+impl Future for Signalled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if PENDING.swap(false, Ordering::AcqRel) {
+            return Poll::Ready(());
+        }
+        WAKER.register(cx.waker());
+        if PENDING.swap(false, Ordering::AcqRel) {
+            WAKER.take();
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+/// Registers the loopback device and the network card drivers.
+pub fn init() {
+    register("lo", Arc::new(loopback::Loopback::new()));
+    pci::register_driver(&virtio::PCI_DRIVER);
+}
@@ -0,0 +1,240 @@
+use super::{MacAddress, NetDevice, NetRef};
+use crate::drivers::pci::{Match, PciDevice, PciDriver};
+use crate::drivers::virtio::{self, pci::NO_VECTOR, Segment, Transport, VirtQueue};
+use crate::kernel::memory::dma::DmaBuffer;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+pub const MTU: usize = 1500;
+const ETHERNET_HEADER: usize = 14;
+
+// feature bits
+const F_MAC: u64 = 1 << 5;
+
+// device config: the MAC address
+const CONFIG_MAC: u16 = 0;
+
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+
+// flags, gso_type, hdr_len, gso_size, csum_start, csum_offset; plus num_buffers on 1.0
+const LEGACY_HEADER_SIZE: usize = 10;
+const MODERN_HEADER_SIZE: usize = 12;
+// room for a header and a full-sized frame
+const RX_BUFFER_SIZE: usize = 2048;
+// receive buffers kept posted; each one takes a page of the DMA pool
+const RX_BUFFERS: u16 = 32;
+const MAX_QUEUE_SIZE: u16 = 256;
+
+// a virtqueue and the DMA buffer behind each chain in flight, indexed by head descriptor
+struct Ring {
+    queue: VirtQueue,
+    buffers: Vec<Option<DmaBuffer>>,
+}
+
+impl Ring {
+    fn new(transport: &Transport, index: u16, vector: u16) -> Option<Ring> {
+        let mut size = transport.max_queue_size(index);
+        if !transport.fixed_queue_size() {
+            size = size.min(MAX_QUEUE_SIZE);
+        }
+        if size == 0 {
+            return None;
+        }
+        let queue = VirtQueue::new(index, size)?;
+        if !transport.setup_queue(&queue, vector) {
+            return None;
+        }
+        Some(Ring {
+            queue,
+            buffers: (0..size).map(|_| None).collect(),
+        })
+    }
+
+    fn post(&mut self, buffer: DmaBuffer, len: usize, device_writes: bool) -> bool {
+        let segment = Segment {
+            addr: buffer.phys(),
+            len: len as u32,
+            device_writes,
+        };
+        match self.queue.add(&[segment]) {
+            Some(head) => {
+                self.buffers[head as usize] = Some(buffer);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A virtio network card. Received frames are picked up by polling; the interrupt only
+/// tells the network stack to look.
+pub struct VirtioNet {
+    transport: Transport,
+    mac: MacAddress,
+    header_size: usize,
+    // both only locked with interrupts off, like the block driver's queue
+    rx: Mutex<Ring>,
+    tx: Mutex<Ring>,
+    interrupts: bool,
+}
+
+impl VirtioNet {
+    fn new(dev: &PciDevice) -> Option<VirtioNet> {
+        let mut transport = Transport::new(dev)?;
+        dev.enable();
+        let features = virtio::negotiate(&transport, F_MAC)?;
+
+        // one vector shared by both queues
+        let interrupts = dev.enable_msix(&[interrupt]).is_some();
+        transport.set_msix(interrupts);
+        if interrupts {
+            transport.set_config_vector(NO_VECTOR);
+        }
+        let vector = if interrupts { 0 } else { NO_VECTOR };
+
+        let rings = Ring::new(&transport, RECEIVE_QUEUE, vector).zip(Ring::new(
+            &transport,
+            TRANSMIT_QUEUE,
+            vector,
+        ));
+        let (mut rx, tx) = match rings {
+            Some(rings) => rings,
+            None => {
+                transport.set_status(virtio::STATUS_FAILED);
+                return None;
+            }
+        };
+
+        let mut mac = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+        if features & F_MAC != 0 {
+            for (i, byte) in mac.0.iter_mut().enumerate() {
+                *byte = transport.read_config_u8(CONFIG_MAC + i as u16);
+            }
+        }
+
+        for _ in 0..RX_BUFFERS.min(rx.queue.size()) {
+            let buffer = DmaBuffer::new(RX_BUFFER_SIZE)?;
+            rx.post(buffer, RX_BUFFER_SIZE, true);
+        }
+
+        virtio::driver_ok(&transport);
+        transport.notify(RECEIVE_QUEUE);
+        Some(VirtioNet {
+            header_size: if transport.is_modern() {
+                MODERN_HEADER_SIZE
+            } else {
+                LEGACY_HEADER_SIZE
+            },
+            transport,
+            mac,
+            rx: Mutex::new(rx),
+            tx: Mutex::new(tx),
+            interrupts,
+        })
+    }
+
+    pub fn uses_interrupts(&self) -> bool {
+        self.interrupts
+    }
+}
+
+impl NetDevice for VirtioNet {
+    fn mac(&self) -> MacAddress {
+        self.mac
+    }
+
+    fn mtu(&self) -> usize {
+        MTU
+    }
+
+    fn transmit(&self, frame: &[u8]) -> bool {
+        if frame.len() > ETHERNET_HEADER + MTU {
+            return false;
+        }
+        // the header stays zeroed: no checksum offload or segmentation
+        let mut buffer = match DmaBuffer::new(self.header_size + frame.len()) {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+        buffer.as_mut_slice()[self.header_size..].copy_from_slice(frame);
+        let len = buffer.len();
+
+        let queued = without_interrupts(|| {
+            let mut tx = self.tx.lock();
+            // reclaim whatever the device has sent since last time
+            while let Some((head, _)) = tx.queue.pop_used() {
+                tx.queue.free_chain(head);
+                tx.buffers[head as usize] = None;
+            }
+            tx.post(buffer, len, false)
+        });
+        if queued {
+            self.transport.notify(TRANSMIT_QUEUE);
+        }
+        queued
+    }
+
+    fn receive(&self) -> Option<Vec<u8>> {
+        let frame = without_interrupts(|| {
+            let mut rx = self.rx.lock();
+            let (head, len) = rx.queue.pop_used()?;
+            rx.queue.free_chain(head);
+            let buffer = rx.buffers[head as usize].take()?;
+            let end = (len as usize).min(buffer.len());
+            let frame = buffer.as_slice()[self.header_size.min(end)..end].to_vec();
+            // the same buffer goes straight back to the device
+            rx.post(buffer, RX_BUFFER_SIZE, true);
+            Some(frame)
+        })?;
+        self.transport.notify(RECEIVE_QUEUE);
+        Some(frame)
+    }
+}
+
+fn interrupt() {
+    super::signal();
+}
+
+pub static PCI_DRIVER: PciDriver = PciDriver {
+    name: "virtio-net",
+    matches: &[
+        // transitional and modern device IDs
+        Match::Device {
+            vendor: virtio::VENDOR_ID,
+            device: 0x1000,
+        },
+        Match::Device {
+            vendor: virtio::VENDOR_ID,
+            device: 0x1041,
+        },
+    ],
+    probe,
+};
+
+fn probe(dev: &PciDevice) -> bool {
+    let nic = match VirtioNet::new(dev) {
+        Some(nic) => Arc::new(nic),
+        None => {
+            crate::println!("virtio-net: {}: initialization failed", dev.address);
+            return false;
+        }
+    };
+    let count = super::devices()
+        .iter()
+        .filter(|(name, _)| name.starts_with("eth"))
+        .count();
+    let name = format!("eth{}", count);
+    crate::println!(
+        "virtio-net: {}: {}{}",
+        name,
+        nic.mac,
+        if nic.interrupts { "" } else { ", polled" }
+    );
+    let nic: NetRef = nic;
+    super::register(&name, nic);
+    true
+}
@@ -0,0 +1,123 @@
+use super::PciAddress;
+use crate::arch::x86_64::acpi;
+use crate::kernel::memory::memory;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, Size4KiB};
+use x86_64::PhysAddr;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+// legacy port I/O reaches only the first 256 bytes of each function's 4 KiB
+const PORT_SPACE: u16 = 256;
+
+// memory-mapped configuration space of segment 0, if the firmware describes one
+struct Ecam {
+    base: u64,
+    start_bus: u8,
+    end_bus: u8,
+}
+
+static ECAM: Mutex<Option<Ecam>> = Mutex::new(None);
+// the address/data port pair is shared state
+static PORT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Switches to ECAM if the ACPI MCFG table lists a region for segment 0.
+pub fn init_ecam(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) {
+    let region = match acpi::mcfg_regions().into_iter().find(|r| r.segment == 0) {
+        Some(region) => region,
+        None => return,
+    };
+    // the region's base is where bus 0 would be, even if it starts at a later bus
+    let first = region.base.as_u64() + ((region.start_bus as u64) << 20);
+    let size = (region.end_bus as u64 - region.start_bus as u64 + 1) << 20;
+
+    match memory::map_mmio(mapper, frame_allocator, PhysAddr::new(first), size) {
+        Ok(_) => {
+            crate::println!(
+                "pci: ECAM at {:#x} for buses {}-{}",
+                region.base.as_u64(),
+                region.start_bus,
+                region.end_bus
+            );
+            *ECAM.lock() = Some(Ecam {
+                base: memory::phys_to_virt(region.base).as_u64(),
+                start_bus: region.start_bus,
+                end_bus: region.end_bus,
+            });
+        }
+        Err(e) => crate::println!("pci: can't map ECAM, using port I/O: {:?}", e),
+    }
+}
+
+pub fn has_ecam() -> bool {
+    ECAM.lock().is_some()
+}
+
+// the ECAM address of a register, or None to go through the I/O ports
+fn ecam_address(addr: PciAddress, offset: u16) -> Option<u64> {
+    let ecam = ECAM.lock();
+    let ecam = ecam.as_ref()?;
+    if addr.bus < ecam.start_bus || addr.bus > ecam.end_bus {
+        return None;
+    }
+    Some(
+        ecam.base
+            + ((addr.bus as u64) << 20)
+            + ((addr.device as u64) << 15)
+            + ((addr.function as u64) << 12)
+            + offset as u64,
+    )
+}
+
+fn port_address(addr: PciAddress, offset: u16) -> u32 {
+    0x8000_0000
+        | (addr.bus as u32) << 16
+        | (addr.device as u32) << 11
+        | (addr.function as u32) << 8
+        | (offset as u32 & 0xFC)
+}
+
+macro_rules! accessors {
+    ($read:ident, $write:ident, $ty:ty) => {
+        /// Reads a configuration register; offsets past what the access method reaches read as all ones.
+        pub fn $read(addr: PciAddress, offset: u16) -> $ty {
+            let offset = offset & !(core::mem::size_of::<$ty>() as u16 - 1);
+            if let Some(at) = ecam_address(addr, offset) {
+                return unsafe { core::ptr::read_volatile(at as *const $ty) };
+            }
+            if offset >= PORT_SPACE {
+                return <$ty>::MAX;
+            }
+            let _guard = PORT_LOCK.lock();
+            unsafe {
+                Port::<u32>::new(CONFIG_ADDRESS).write(port_address(addr, offset));
+                Port::<$ty>::new(CONFIG_DATA + (offset & 3)).read()
+            }
+        }
+
+        pub fn $write(addr: PciAddress, offset: u16, value: $ty) {
+            let offset = offset & !(core::mem::size_of::<$ty>() as u16 - 1);
+            if let Some(at) = ecam_address(addr, offset) {
+                unsafe { core::ptr::write_volatile(at as *mut $ty, value) };
+                return;
+            }
+            if offset >= PORT_SPACE {
+                return;
+            }
+            let _guard = PORT_LOCK.lock();
+            unsafe {
+                Port::<u32>::new(CONFIG_ADDRESS).write(port_address(addr, offset));
+                Port::<$ty>::new(CONFIG_DATA + (offset & 3)).write(value);
+            }
+        }
+    };
+}
+
+accessors!(read8, write8, u8);
+accessors!(read16, write16, u16);
+accessors!(read32, write32, u32);
@@ -0,0 +1,393 @@
+pub mod config;
+pub mod msi;
+
+use crate::kernel::memory::memory;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, Size4KiB};
+use x86_64::PhysAddr;
+
+// configuration space header offsets shared by all header types
+const REG_VENDOR_ID: u16 = 0x00;
+const REG_DEVICE_ID: u16 = 0x02;
+const REG_COMMAND: u16 = 0x04;
+const REG_STATUS: u16 = 0x06;
+const REG_REVISION: u16 = 0x08;
+const REG_PROG_IF: u16 = 0x09;
+const REG_SUBCLASS: u16 = 0x0A;
+const REG_CLASS: u16 = 0x0B;
+const REG_HEADER_TYPE: u16 = 0x0E;
+const REG_BAR0: u16 = 0x10;
+const REG_CAPABILITIES: u16 = 0x34;
+const REG_INTERRUPT_LINE: u16 = 0x3C;
+const REG_INTERRUPT_PIN: u16 = 0x3D;
+
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+
+/// Bus, device and function of a PCI function, printed the way `lspci` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub fn new(bus: u8, device: u8, function: u8) -> Self {
+        PciAddress {
+            bus,
+            device,
+            function,
+        }
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// A decoded base address register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        base: u64,
+        size: u64,
+        prefetchable: bool,
+        /// Takes two BAR slots.
+        wide: bool,
+    },
+    Io {
+        base: u16,
+        size: u16,
+    },
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bar::Memory {
+                base,
+                size,
+                prefetchable,
+                wide,
+            } => {
+                write!(
+                    f,
+                    "memory at {:#x} ({}-bit",
+                    base,
+                    if wide { 64 } else { 32 }
+                )?;
+                if prefetchable {
+                    write!(f, ", prefetchable")?;
+                }
+                write!(f, ") [size={}K]", size / 1024)
+            }
+            Bar::Io { base, size } => write!(f, "I/O ports at {:#x} [size={}]", base, size),
+        }
+    }
+}
+
+/// A PCI function found during enumeration.
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    pub interrupt_line: u8,
+    pub interrupt_pin: u8,
+}
+
+impl PciDevice {
+    /// Reads a function's header, or None if nothing answers at `address`.
+    pub fn probe(address: PciAddress) -> Option<PciDevice> {
+        let vendor_id = config::read16(address, REG_VENDOR_ID);
+        if vendor_id == 0xFFFF {
+            return None;
+        }
+        Some(PciDevice {
+            address,
+            vendor_id,
+            device_id: config::read16(address, REG_DEVICE_ID),
+            class: config::read8(address, REG_CLASS),
+            subclass: config::read8(address, REG_SUBCLASS),
+            prog_if: config::read8(address, REG_PROG_IF),
+            revision: config::read8(address, REG_REVISION),
+            header_type: config::read8(address, REG_HEADER_TYPE) & !HEADER_MULTIFUNCTION,
+            interrupt_line: config::read8(address, REG_INTERRUPT_LINE),
+            interrupt_pin: config::read8(address, REG_INTERRUPT_PIN),
+        })
+    }
+
+    pub fn command(&self) -> u16 {
+        config::read16(self.address, REG_COMMAND)
+    }
+
+    pub fn set_command(&self, command: u16) {
+        config::write16(self.address, REG_COMMAND, command);
+    }
+
+    /// Turns on decoding of the device's I/O and memory BARs and lets it master the bus (DMA).
+    pub fn enable(&self) {
+        self.set_command(self.command() | COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER);
+    }
+
+    fn bar_count(&self) -> usize {
+        match self.header_type {
+            0 => 6,
+            // PCI-to-PCI bridges
+            1 => 2,
+            _ => 0,
+        }
+    }
+
+    /// Decodes BAR `index`, sizing it by writing all ones and reading back the mask.
+    /// Returns None for unimplemented BARs; a 64-bit BAR is read at the index of its low half.
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        if index >= self.bar_count() {
+            return None;
+        }
+        let offset = REG_BAR0 + index as u16 * 4;
+        let raw = config::read32(self.address, offset);
+
+        // decoding is off while the BAR holds the all-ones pattern
+        let command = self.command();
+        self.set_command(command & !(COMMAND_IO | COMMAND_MEMORY));
+        let bar = if raw & 1 == 1 {
+            config::write32(self.address, offset, 0xFFFF_FFFF);
+            let mask = config::read32(self.address, offset) & 0xFFFF_FFFC;
+            config::write32(self.address, offset, raw);
+            let size = (!mask).wrapping_add(1) & 0xFFFF;
+            (mask != 0).then_some(Bar::Io {
+                base: (raw & 0xFFFC) as u16,
+                size: size as u16,
+            })
+        } else {
+            // a 64-bit BAR in the last slot is malformed; read it as 32-bit
+            let wide = (raw >> 1) & 3 == 2 && index + 1 < self.bar_count();
+            let high = if wide {
+                config::read32(self.address, offset + 4)
+            } else {
+                0
+            };
+            config::write32(self.address, offset, 0xFFFF_FFFF);
+            let mut mask = (config::read32(self.address, offset) & 0xFFFF_FFF0) as u64;
+            config::write32(self.address, offset, raw);
+            if wide {
+                config::write32(self.address, offset + 4, 0xFFFF_FFFF);
+                mask |= (config::read32(self.address, offset + 4) as u64) << 32;
+                config::write32(self.address, offset + 4, high);
+            } else {
+                mask |= 0xFFFF_FFFF << 32;
+            }
+            let implemented = mask as u32 != 0 || (wide && mask >> 32 != 0);
+            implemented.then(|| Bar::Memory {
+                base: (high as u64) << 32 | (raw & 0xFFFF_FFF0) as u64,
+                size: (!mask).wrapping_add(1),
+                prefetchable: raw & 8 != 0,
+                wide,
+            })
+        };
+        self.set_command(command);
+        bar
+    }
+
+    /// All implemented BARs with their indices.
+    pub fn bars(&self) -> Vec<(usize, Bar)> {
+        let mut bars = Vec::new();
+        let mut index = 0;
+        while index < self.bar_count() {
+            let bar = self.bar(index);
+            if let Some(bar) = bar {
+                bars.push((index, bar));
+            }
+            index += match bar {
+                Some(Bar::Memory { wide: true, .. }) => 2,
+                _ => 1,
+            };
+        }
+        bars
+    }
+
+    /// The capability list as (id, offset) pairs.
+    pub fn capabilities(&self) -> Vec<(u8, u16)> {
+        let mut caps = Vec::new();
+        if config::read16(self.address, REG_STATUS) & STATUS_CAPABILITIES == 0 {
+            return caps;
+        }
+        let mut offset = (config::read8(self.address, REG_CAPABILITIES) & 0xFC) as u16;
+        // the list lives in the 192 bytes after the header; this bounds a looping list
+        while offset >= 0x40 && caps.len() < 48 {
+            caps.push((config::read8(self.address, offset), offset));
+            offset = (config::read8(self.address, offset + 1) & 0xFC) as u16;
+        }
+        caps
+    }
+
+    pub fn find_capability(&self, id: u8) -> Option<u16> {
+        self.capabilities()
+            .into_iter()
+            .find(|&(cap, _)| cap == id)
+            .map(|(_, offset)| offset)
+    }
+
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass)
+    }
+}
+
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x00) => "SCSI storage controller",
+        (0x01, 0x01) => "IDE interface",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "Non-Volatile memory controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, 0x01) | (0x04, 0x03) => "Audio device",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus",
+        (0x0C, _) => "Serial bus controller",
+        (0xFF, _) => "Unassigned class",
+        _ => "Unknown device",
+    }
+}
+
+/// Finds every function on every bus, checking functions 1-7 only on multi-function devices.
+pub fn scan() -> Vec<PciDevice> {
+    let mut found = Vec::new();
+    for bus in 0..=255u8 {
+        for device in 0..32u8 {
+            let first = PciAddress::new(bus, device, 0);
+            if PciDevice::probe(first).is_none() {
+                continue;
+            }
+            let functions = if config::read8(first, REG_HEADER_TYPE) & HEADER_MULTIFUNCTION != 0 {
+                8
+            } else {
+                1
+            };
+            found.extend(
+                (0..functions).filter_map(|f| PciDevice::probe(PciAddress::new(bus, device, f))),
+            );
+        }
+    }
+    found
+}
+
+/// One way a driver can match a device.
+#[derive(Debug, Clone, Copy)]
+pub enum Match {
+    Device { vendor: u16, device: u16 },
+    Vendor(u16),
+    Class { class: u8, subclass: u8 },
+}
+
+impl Match {
+    pub fn matches(&self, dev: &PciDevice) -> bool {
+        match *self {
+            Match::Device { vendor, device } => dev.vendor_id == vendor && dev.device_id == device,
+            Match::Vendor(vendor) => dev.vendor_id == vendor,
+            Match::Class { class, subclass } => dev.class == class && dev.subclass == subclass,
+        }
+    }
+}
+
+pub struct PciDriver {
+    pub name: &'static str,
+    pub matches: &'static [Match],
+    /// Called for each matching device that has no driver yet; returns whether it took the device.
+    pub probe: fn(&PciDevice) -> bool,
+}
+
+impl PciDriver {
+    pub fn matches(&self, dev: &PciDevice) -> bool {
+        self.matches.iter().any(|m| m.matches(dev))
+    }
+}
+
+static DEVICES: Mutex<Vec<PciDevice>> = Mutex::new(Vec::new());
+static DRIVERS: Mutex<Vec<&'static PciDriver>> = Mutex::new(Vec::new());
+// which driver took each device
+static BOUND: Mutex<BTreeMap<PciAddress, &'static str>> = Mutex::new(BTreeMap::new());
+
+pub fn devices() -> Vec<PciDevice> {
+    DEVICES.lock().clone()
+}
+
+pub fn driver_of(address: PciAddress) -> Option<&'static str> {
+    BOUND.lock().get(&address).copied()
+}
+
+// offers each unbound device to `driver`; locks are dropped before probing
+fn bind(driver: &'static PciDriver) {
+    for dev in devices() {
+        if BOUND.lock().contains_key(&dev.address) || !driver.matches(&dev) {
+            continue;
+        }
+        if (driver.probe)(&dev) {
+            BOUND.lock().insert(dev.address, driver.name);
+        }
+    }
+}
+
+/// Adds a driver and offers it the devices found so far.
+pub fn register_driver(driver: &'static PciDriver) {
+    DRIVERS.lock().push(driver);
+    bind(driver);
+}
+
+/// Picks the configuration access method, enumerates the buses and binds registered drivers.
+///
+/// Memory BARs are mapped here so drivers can reach them through `memory::phys_to_virt`.
+pub fn init(mapper: &mut OffsetPageTable, frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+    config::init_ecam(mapper, frame_allocator);
+    let found = scan();
+    crate::println!("pci: {} functions found", found.len());
+
+    for dev in &found {
+        for (index, bar) in dev.bars() {
+            if let Bar::Memory { base, size, .. } = bar {
+                // the firmware left it unassigned
+                if base == 0 {
+                    continue;
+                }
+                if let Err(e) = memory::map_mmio(mapper, frame_allocator, PhysAddr::new(base), size)
+                {
+                    crate::println!("pci: {}: can't map BAR{}: {:?}", dev.address, index, e);
+                }
+            }
+        }
+    }
+    *DEVICES.lock() = found;
+
+    let drivers = DRIVERS.lock().clone();
+    for driver in drivers {
+        bind(driver);
+    }
+}
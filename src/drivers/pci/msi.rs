@@ -0,0 +1,138 @@
+use super::{config, Bar, PciDevice, COMMAND_INTX_DISABLE};
+use crate::arch::x86_64::{apic, interrupts};
+use crate::kernel::memory::memory::phys_to_virt;
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+pub const CAP_POWER_MANAGEMENT: u8 = 0x01;
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_VENDOR: u8 = 0x09;
+pub const CAP_PCI_EXPRESS: u8 = 0x10;
+pub const CAP_MSIX: u8 = 0x11;
+
+// message control bits
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_MULTIPLE_ENABLE: u16 = 0b111 << 4;
+const MSI_64BIT: u16 = 1 << 7;
+
+// MSI-X message control bits
+const MSIX_TABLE_SIZE: u16 = 0x7FF;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_ENTRY_SIZE: u64 = 16;
+
+// writes to this window become interrupts at the local APIC whose ID is in bits 12-19
+const MSI_ADDRESS_BASE: u32 = 0xFEE0_0000;
+
+pub fn capability_name(id: u8) -> &'static str {
+    match id {
+        CAP_POWER_MANAGEMENT => "Power Management",
+        CAP_MSI => "MSI",
+        CAP_VENDOR => "Vendor Specific",
+        CAP_PCI_EXPRESS => "Express",
+        CAP_MSIX => "MSI-X",
+        _ => "",
+    }
+}
+
+impl PciDevice {
+    /// Routes the device's interrupt through MSI to a newly allocated vector running `handler`,
+    /// and masks its legacy INTx line. Returns the vector, or None if the device has no MSI
+    /// capability, the local APIC is off, or no vector is free.
+    pub fn enable_msi(&self, handler: fn()) -> Option<u8> {
+        if !apic::is_enabled() {
+            return None;
+        }
+        let cap = self.find_capability(CAP_MSI)?;
+        let vector = interrupts::allocate_vector(handler)?;
+
+        let control = config::read16(self.address, cap + 2);
+        config::write32(self.address, cap + 4, message_address());
+        if control & MSI_64BIT != 0 {
+            config::write32(self.address, cap + 8, 0);
+            config::write16(self.address, cap + 12, vector as u16);
+        } else {
+            config::write16(self.address, cap + 8, vector as u16);
+        }
+        // one vector even if the device could use several
+        let control = (control & !MSI_MULTIPLE_ENABLE) | MSI_ENABLE;
+        config::write16(self.address, cap + 2, control);
+
+        self.set_command(self.command() | COMMAND_INTX_DISABLE);
+        Some(vector)
+    }
+
+    /// Turns MSI off again and releases `vector`.
+    pub fn disable_msi(&self, vector: u8) {
+        if let Some(cap) = self.find_capability(CAP_MSI) {
+            let control = config::read16(self.address, cap + 2);
+            config::write16(self.address, cap + 2, control & !MSI_ENABLE);
+        }
+        interrupts::free_vector(vector);
+        self.set_command(self.command() & !COMMAND_INTX_DISABLE);
+    }
+
+    /// Programs MSI-X table entries `0..handlers.len()` with a newly allocated vector each and
+    /// masks the legacy INTx line. Returns the vectors in entry order, or None if the device
+    /// has no MSI-X capability or too few entries, the local APIC is off, or vectors run out.
+    ///
+    /// The table lives in a memory BAR, which `pci::init` has mapped.
+    pub fn enable_msix(&self, handlers: &[fn()]) -> Option<Vec<u8>> {
+        if !apic::is_enabled() {
+            return None;
+        }
+        let cap = self.find_capability(CAP_MSIX)?;
+        let control = config::read16(self.address, cap + 2);
+        if handlers.len() > (control & MSIX_TABLE_SIZE) as usize + 1 {
+            return None;
+        }
+        // low three bits pick the BAR, the rest is the offset into it
+        let table = config::read32(self.address, cap + 4);
+        let base = match self.bar((table & 7) as usize)? {
+            Bar::Memory { base, .. } => base,
+            Bar::Io { .. } => return None,
+        };
+        let table = phys_to_virt(PhysAddr::new(base + (table & !7) as u64)).as_u64();
+
+        let mut vectors = Vec::new();
+        for &handler in handlers {
+            match interrupts::allocate_vector(handler) {
+                Some(vector) => vectors.push(vector),
+                None => {
+                    vectors.into_iter().for_each(interrupts::free_vector);
+                    return None;
+                }
+            }
+        }
+
+        // the function mask holds off all entries while they are being written
+        config::write16(
+            self.address,
+            cap + 2,
+            control | MSIX_ENABLE | MSIX_FUNCTION_MASK,
+        );
+        for (i, &vector) in vectors.iter().enumerate() {
+            let entry = (table + i as u64 * MSIX_ENTRY_SIZE) as *mut u32;
+            unsafe {
+                entry.write_volatile(message_address());
+                entry.add(1).write_volatile(0);
+                entry.add(2).write_volatile(vector as u32);
+                // vector control: bit 0 masks the entry
+                entry.add(3).write_volatile(0);
+            }
+        }
+        config::write16(
+            self.address,
+            cap + 2,
+            (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK,
+        );
+
+        self.set_command(self.command() | COMMAND_INTX_DISABLE);
+        Some(vectors)
+    }
+}
+
+// fixed delivery, edge triggered, to this CPU
+fn message_address() -> u32 {
+    MSI_ADDRESS_BASE | (apic::id() as u32) << 12
+}
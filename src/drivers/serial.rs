@@ -10,6 +10,38 @@ lazy_static! {
     };
 }
 
+/// Returns a byte from the receive buffer of `SERIAL1`, if one has arrived.
+pub fn try_receive() -> Option<u8> {
+    use x86_64::instructions::interrupts;
+    use x86_64::instructions::port::Port;
+
+    // bit 0 of the line status register is "data ready"; `SerialPort::receive` would spin
+    let mut line_status: Port<u8> = Port::new(0x3F8 + 5);
+    let mut data: Port<u8> = Port::new(0x3F8);
+    interrupts::without_interrupts(|| {
+        let _port = SERIAL1.lock();
+        unsafe {
+            if line_status.read() & 1 != 0 {
+                Some(data.read())
+            } else {
+                None
+            }
+        }
+    })
+}
+
+/// Writes raw bytes to `SERIAL1`.
+pub fn write_bytes(bytes: &[u8]) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut port = SERIAL1.lock();
+        for &b in bytes {
+            port.send(b);
+        }
+    });
+}
+
 //maxros
 
 #[doc(hidden)]
@@ -168,6 +168,8 @@ pub fn _print(args: fmt::Arguments) {
     use x86_64::instructions::interrupts;
     interrupts::without_interrupts(|| {
         WRITER.lock().write_fmt(args).unwrap();
+        // keep a copy for /dev/kmsg
+        crate::kernel::kmsg::KMSG.lock().write_fmt(args).unwrap();
     });
 }
 
@@ -0,0 +1,51 @@
+pub mod pci;
+pub mod queue;
+
+pub use pci::Transport;
+pub use queue::{Segment, VirtQueue};
+
+pub const VENDOR_ID: u16 = 0x1AF4;
+
+// device status bits, set in this order during initialization
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 2;
+pub const STATUS_DRIVER_OK: u8 = 4;
+pub const STATUS_FEATURES_OK: u8 = 8;
+pub const STATUS_FAILED: u8 = 128;
+
+/// Virtio 1.0 conformance; required of the driver on the modern interface.
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// Resets the device and agrees on the features in `wanted` that it offers.
+/// Returns the accepted set, or None (with the device marked failed) if it won't take them.
+pub fn negotiate(transport: &Transport, wanted: u64) -> Option<u64> {
+    transport.set_status(0);
+    transport.set_status(STATUS_ACKNOWLEDGE);
+    transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+    let mut wanted = wanted;
+    if transport.is_modern() {
+        wanted |= F_VERSION_1;
+    }
+    let features = transport.device_features() & wanted;
+    if transport.is_modern() && features & F_VERSION_1 == 0 {
+        transport.set_status(STATUS_FAILED);
+        return None;
+    }
+    transport.set_driver_features(features);
+
+    // legacy devices have no FEATURES_OK handshake
+    if transport.is_modern() {
+        transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+        if transport.status() & STATUS_FEATURES_OK == 0 {
+            transport.set_status(STATUS_FAILED);
+            return None;
+        }
+    }
+    Some(features)
+}
+
+/// Marks initialization finished: the device may start using its queues.
+pub fn driver_ok(transport: &Transport) {
+    transport.set_status(transport.status() | STATUS_DRIVER_OK);
+}
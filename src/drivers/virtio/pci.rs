@@ -0,0 +1,316 @@
+use super::queue::VirtQueue;
+use crate::drivers::pci::msi::CAP_VENDOR;
+use crate::drivers::pci::{config, Bar, PciDevice};
+use crate::kernel::memory::memory::phys_to_virt;
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+
+// legacy register block in BAR0 (I/O ports)
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_PFN: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0C;
+const LEGACY_QUEUE_SELECT: u16 = 0x0E;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_STATUS: u16 = 0x12;
+const LEGACY_ISR: u16 = 0x13;
+const LEGACY_CONFIG_VECTOR: u16 = 0x14;
+const LEGACY_QUEUE_VECTOR: u16 = 0x16;
+// device config follows the registers; two more when MSI-X is on
+const LEGACY_CONFIG: u16 = 0x14;
+const LEGACY_CONFIG_MSIX: u16 = 0x18;
+
+// modern common configuration structure
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+const COMMON_DRIVER_FEATURE: u64 = 0x0C;
+const COMMON_CONFIG_VECTOR: u64 = 0x10;
+const COMMON_STATUS: u64 = 0x14;
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+const COMMON_QUEUE_VECTOR: u64 = 0x1A;
+const COMMON_QUEUE_ENABLE: u64 = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1E;
+const COMMON_QUEUE_DESC: u64 = 0x20;
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+
+// cfg_type of the vendor capabilities that locate the modern structures
+const CFG_COMMON: u8 = 1;
+const CFG_NOTIFY: u8 = 2;
+const CFG_ISR: u8 = 3;
+const CFG_DEVICE: u8 = 4;
+
+/// "No interrupt" in the MSI-X vector registers.
+pub const NO_VECTOR: u16 = 0xFFFF;
+
+enum Kind {
+    /// Pre-1.0 register layout in an I/O BAR.
+    Legacy { io: u16, msix: bool },
+    /// Virtio 1.0 structures in memory BARs, at the virtual addresses here.
+    Modern {
+        common: u64,
+        notify: u64,
+        notify_multiplier: u32,
+        isr: u64,
+        device: u64,
+    },
+}
+
+/// Access to a virtio device's registers over PCI.
+pub struct Transport {
+    kind: Kind,
+}
+
+unsafe fn mmio_read<T>(addr: u64) -> T {
+    unsafe { core::ptr::read_volatile(addr as *const T) }
+}
+
+unsafe fn mmio_write<T>(addr: u64, value: T) {
+    unsafe { core::ptr::write_volatile(addr as *mut T, value) }
+}
+
+unsafe fn port_read<T: x86_64::instructions::port::PortRead>(port: u16) -> T {
+    unsafe { Port::<T>::new(port).read() }
+}
+
+unsafe fn port_write<T: x86_64::instructions::port::PortWrite>(port: u16, value: T) {
+    unsafe { Port::<T>::new(port).write(value) }
+}
+
+impl Transport {
+    /// Uses the modern interface if the device describes one, the legacy one otherwise.
+    pub fn new(dev: &PciDevice) -> Option<Transport> {
+        Self::modern(dev).or_else(|| match dev.bar(0)? {
+            Bar::Io { base, .. } => Some(Transport {
+                kind: Kind::Legacy {
+                    io: base,
+                    msix: false,
+                },
+            }),
+            Bar::Memory { .. } => None,
+        })
+    }
+
+    fn modern(dev: &PciDevice) -> Option<Transport> {
+        let (mut common, mut notify, mut isr, mut device) = (None, None, None, None);
+        let mut notify_multiplier = 0;
+        for (id, cap) in dev.capabilities() {
+            if id != CAP_VENDOR {
+                continue;
+            }
+            let cfg_type = config::read8(dev.address, cap + 3);
+            let bar = config::read8(dev.address, cap + 4) as usize;
+            let offset = config::read32(dev.address, cap + 8) as u64;
+            let base = match dev.bar(bar) {
+                Some(Bar::Memory { base, .. }) if base != 0 => base,
+                _ => continue,
+            };
+            let addr = phys_to_virt(PhysAddr::new(base + offset)).as_u64();
+            match cfg_type {
+                CFG_COMMON => common = common.or(Some(addr)),
+                CFG_NOTIFY => {
+                    if notify.is_none() {
+                        notify = Some(addr);
+                        notify_multiplier = config::read32(dev.address, cap + 16);
+                    }
+                }
+                CFG_ISR => isr = isr.or(Some(addr)),
+                CFG_DEVICE => device = device.or(Some(addr)),
+                _ => {}
+            }
+        }
+        Some(Transport {
+            kind: Kind::Modern {
+                common: common?,
+                notify: notify?,
+                notify_multiplier,
+                isr: isr?,
+                device: device?,
+            },
+        })
+    }
+
+    pub fn is_modern(&self) -> bool {
+        matches!(self.kind, Kind::Modern { .. })
+    }
+
+    /// Tells a legacy device whether MSI-X is on, which moves its device config.
+    pub fn set_msix(&mut self, on: bool) {
+        if let Kind::Legacy { msix, .. } = &mut self.kind {
+            *msix = on;
+        }
+    }
+
+    pub fn status(&self) -> u8 {
+        unsafe {
+            match self.kind {
+                Kind::Legacy { io, .. } => port_read(io + LEGACY_STATUS),
+                Kind::Modern { common, .. } => mmio_read(common + COMMON_STATUS),
+            }
+        }
+    }
+
+    pub fn set_status(&self, status: u8) {
+        unsafe {
+            match self.kind {
+                Kind::Legacy { io, .. } => port_write(io + LEGACY_STATUS, status),
+                Kind::Modern { common, .. } => mmio_write(common + COMMON_STATUS, status),
+            }
+        }
+    }
+
+    pub fn device_features(&self) -> u64 {
+        unsafe {
+            match self.kind {
+                Kind::Legacy { io, .. } => port_read::<u32>(io + LEGACY_DEVICE_FEATURES) as u64,
+                Kind::Modern { common, .. } => {
+                    mmio_write::<u32>(common + COMMON_DEVICE_FEATURE_SELECT, 0);
+                    let low = mmio_read::<u32>(common + COMMON_DEVICE_FEATURE) as u64;
+                    mmio_write::<u32>(common + COMMON_DEVICE_FEATURE_SELECT, 1);
+                    let high = mmio_read::<u32>(common + COMMON_DEVICE_FEATURE) as u64;
+                    high << 32 | low
+                }
+            }
+        }
+    }
+
+    pub fn set_driver_features(&self, features: u64) {
+        unsafe {
+            match self.kind {
+                Kind::Legacy { io, .. } => port_write(io + LEGACY_DRIVER_FEATURES, features as u32),
+                Kind::Modern { common, .. } => {
+                    mmio_write::<u32>(common + COMMON_DRIVER_FEATURE_SELECT, 0);
+                    mmio_write(common + COMMON_DRIVER_FEATURE, features as u32);
+                    mmio_write::<u32>(common + COMMON_DRIVER_FEATURE_SELECT, 1);
+                    mmio_write(common + COMMON_DRIVER_FEATURE, (features >> 32) as u32);
+                }
+            }
+        }
+    }
+
+    /// Routes configuration-change interrupts to MSI-X entry `vector`.
+    pub fn set_config_vector(&self, vector: u16) {
+        unsafe {
+            match self.kind {
+                Kind::Legacy { io, .. } => port_write(io + LEGACY_CONFIG_VECTOR, vector),
+                Kind::Modern { common, .. } => mmio_write(common + COMMON_CONFIG_VECTOR, vector),
+            }
+        }
+    }
+
+    /// The most entries queue `index` can have; 0 if there is no such queue.
+    pub fn max_queue_size(&self, index: u16) -> u16 {
+        unsafe {
+            match self.kind {
+                Kind::Legacy { io, .. } => {
+                    port_write(io + LEGACY_QUEUE_SELECT, index);
+                    port_read(io + LEGACY_QUEUE_SIZE)
+                }
+                Kind::Modern { common, .. } => {
+                    mmio_write(common + COMMON_QUEUE_SELECT, index);
+                    mmio_read(common + COMMON_QUEUE_SIZE)
+                }
+            }
+        }
+    }
+
+    /// Whether a legacy device insists on its own queue size.
+    pub fn fixed_queue_size(&self) -> bool {
+        !self.is_modern()
+    }
+
+    /// Hands `queue` to the device, interrupting on MSI-X entry `vector` (or `NO_VECTOR`).
+    /// Returns false if the device refused the vector.
+    pub fn setup_queue(&self, queue: &VirtQueue, vector: u16) -> bool {
+        unsafe {
+            match self.kind {
+                Kind::Legacy { io, msix } => {
+                    port_write(io + LEGACY_QUEUE_SELECT, queue.index());
+                    if msix {
+                        port_write(io + LEGACY_QUEUE_VECTOR, vector);
+                        if port_read::<u16>(io + LEGACY_QUEUE_VECTOR) != vector {
+                            return false;
+                        }
+                    }
+                    let pfn = queue.desc_addr().as_u64() >> 12;
+                    port_write(io + LEGACY_QUEUE_PFN, pfn as u32);
+                }
+                Kind::Modern { common, .. } => {
+                    mmio_write(common + COMMON_QUEUE_SELECT, queue.index());
+                    mmio_write(common + COMMON_QUEUE_SIZE, queue.size());
+                    mmio_write(common + COMMON_QUEUE_VECTOR, vector);
+                    if mmio_read::<u16>(common + COMMON_QUEUE_VECTOR) != vector {
+                        return false;
+                    }
+                    mmio_write(common + COMMON_QUEUE_DESC, queue.desc_addr().as_u64());
+                    mmio_write(common + COMMON_QUEUE_DRIVER, queue.avail_addr().as_u64());
+                    mmio_write(common + COMMON_QUEUE_DEVICE, queue.used_addr().as_u64());
+                    mmio_write::<u16>(common + COMMON_QUEUE_ENABLE, 1);
+                }
+            }
+        }
+        true
+    }
+
+    /// Tells the device there are new buffers in queue `index`.
+    pub fn notify(&self, index: u16) {
+        unsafe {
+            match self.kind {
+                Kind::Legacy { io, .. } => port_write(io + LEGACY_QUEUE_NOTIFY, index),
+                Kind::Modern {
+                    common,
+                    notify,
+                    notify_multiplier,
+                    ..
+                } => {
+                    mmio_write(common + COMMON_QUEUE_SELECT, index);
+                    let offset = mmio_read::<u16>(common + COMMON_QUEUE_NOTIFY_OFF) as u64;
+                    mmio_write(notify + offset * notify_multiplier as u64, index);
+                }
+            }
+        }
+    }
+
+    /// Reads and clears the interrupt status; only needed without MSI-X.
+    pub fn ack_interrupt(&self) -> u8 {
+        unsafe {
+            match self.kind {
+                Kind::Legacy { io, .. } => port_read(io + LEGACY_ISR),
+                Kind::Modern { isr, .. } => mmio_read(isr),
+            }
+        }
+    }
+
+    fn legacy_config(io: u16, msix: bool) -> u16 {
+        io + if msix {
+            LEGACY_CONFIG_MSIX
+        } else {
+            LEGACY_CONFIG
+        }
+    }
+
+    pub fn read_config_u8(&self, offset: u16) -> u8 {
+        unsafe {
+            match self.kind {
+                Kind::Legacy { io, msix } => port_read(Self::legacy_config(io, msix) + offset),
+                Kind::Modern { device, .. } => mmio_read(device + offset as u64),
+            }
+        }
+    }
+
+    pub fn read_config_u32(&self, offset: u16) -> u32 {
+        unsafe {
+            match self.kind {
+                Kind::Legacy { io, msix } => port_read(Self::legacy_config(io, msix) + offset),
+                Kind::Modern { device, .. } => mmio_read(device + offset as u64),
+            }
+        }
+    }
+
+    pub fn read_config_u64(&self, offset: u16) -> u64 {
+        // the halves may tear if the device changes it meanwhile; fine for static fields
+        self.read_config_u32(offset) as u64 | (self.read_config_u32(offset + 4) as u64) << 32
+    }
+}
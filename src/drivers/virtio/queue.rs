@@ -0,0 +1,153 @@
+use crate::kernel::memory::dma::DmaBuffer;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+use x86_64::PhysAddr;
+
+const DESC_SIZE: usize = 16;
+// legacy devices expect the used ring on the next page boundary
+const RING_ALIGN: usize = 4096;
+
+// descriptor flags
+const DESC_NEXT: u16 = 1;
+const DESC_WRITE: u16 = 2;
+
+/// One buffer of a request: where it is, how long, and whether the device writes it.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub addr: PhysAddr,
+    pub len: u32,
+    pub device_writes: bool,
+}
+
+/// A split virtqueue: descriptor table, available ring and used ring in one DMA buffer,
+/// laid out as legacy devices require.
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    mem: DmaBuffer,
+    avail_offset: usize,
+    used_offset: usize,
+    // descriptors not part of any chain; never grows past `size`
+    free: Vec<u16>,
+    avail_idx: u16,
+    last_used: u16,
+}
+
+impl VirtQueue {
+    /// Allocates queue number `index` with `size` entries (a power of two).
+    pub fn new(index: u16, size: u16) -> Option<VirtQueue> {
+        let n = size as usize;
+        let avail_offset = DESC_SIZE * n;
+        // flags, idx, ring, used_event
+        let used_offset = (avail_offset + 6 + 2 * n).next_multiple_of(RING_ALIGN);
+        let total = used_offset + (6 + 8 * n).next_multiple_of(RING_ALIGN);
+
+        Some(VirtQueue {
+            index,
+            size,
+            mem: DmaBuffer::new(total)?,
+            avail_offset,
+            used_offset,
+            free: (0..size).rev().collect(),
+            avail_idx: 0,
+            last_used: 0,
+        })
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn desc_addr(&self) -> PhysAddr {
+        self.mem.phys()
+    }
+
+    pub fn avail_addr(&self) -> PhysAddr {
+        self.mem.phys() + self.avail_offset as u64
+    }
+
+    pub fn used_addr(&self) -> PhysAddr {
+        self.mem.phys() + self.used_offset as u64
+    }
+
+    pub fn free_count(&self) -> usize {
+        self.free.len()
+    }
+
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        unsafe { self.mem.as_ptr().add(offset) as *mut T }
+    }
+
+    /// Chains `segments` into descriptors and makes them available to the device.
+    /// Returns the head descriptor, which `pop_used` reports when the device is done,
+    /// or None if there are not enough free descriptors. The caller notifies the device.
+    pub fn add(&mut self, segments: &[Segment]) -> Option<u16> {
+        if segments.is_empty() || segments.len() > self.free.len() {
+            return None;
+        }
+        let descs: Vec<u16> = (0..segments.len())
+            .map(|_| self.free.pop().unwrap())
+            .collect();
+
+        for (i, segment) in segments.iter().enumerate() {
+            let mut flags = if segment.device_writes { DESC_WRITE } else { 0 };
+            let next = descs.get(i + 1).copied().unwrap_or(0);
+            if i + 1 < descs.len() {
+                flags |= DESC_NEXT;
+            }
+            let desc = descs[i] as usize * DESC_SIZE;
+            unsafe {
+                self.ptr::<u64>(desc).write_volatile(segment.addr.as_u64());
+                self.ptr::<u32>(desc + 8).write_volatile(segment.len);
+                self.ptr::<u16>(desc + 12).write_volatile(flags);
+                self.ptr::<u16>(desc + 14).write_volatile(next);
+            }
+        }
+
+        let head = descs[0];
+        let slot = self.avail_offset + 4 + 2 * (self.avail_idx % self.size) as usize;
+        unsafe { self.ptr::<u16>(slot).write_volatile(head) };
+        // the descriptors and ring entry must be visible before the index moves
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe {
+            self.ptr::<u16>(self.avail_offset + 2)
+                .write_volatile(self.avail_idx)
+        };
+        fence(Ordering::SeqCst);
+        Some(head)
+    }
+
+    /// Takes the next chain the device has finished with: its head and the number of bytes
+    /// written. The chain stays allocated until `free_chain`.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_idx = unsafe { self.ptr::<u16>(self.used_offset + 2).read_volatile() };
+        if used_idx == self.last_used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let elem = self.used_offset + 4 + 8 * (self.last_used % self.size) as usize;
+        let id = unsafe { self.ptr::<u32>(elem).read_volatile() };
+        let len = unsafe { self.ptr::<u32>(elem + 4).read_volatile() };
+        self.last_used = self.last_used.wrapping_add(1);
+        Some((id as u16, len))
+    }
+
+    /// Returns the descriptors of the chain starting at `head` to the free list.
+    pub fn free_chain(&mut self, head: u16) {
+        let mut desc = head;
+        loop {
+            self.free.push(desc);
+            let at = desc as usize * DESC_SIZE;
+            let flags = unsafe { self.ptr::<u16>(at + 12).read_volatile() };
+            if flags & DESC_NEXT == 0 {
+                break;
+            }
+            desc = unsafe { self.ptr::<u16>(at + 14).read_volatile() };
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use super::vfs::{FileSystem, FileType, FsError, FsResult, INode, VFS};
+use crate::kernel::process;
+use crate::ui::input;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+const ROOT_INO: u64 = 1;
+// cap for `read_file` on endless devices like /dev/zero
+const READ_FILE_LIMIT: usize = 4096;
+
+/// A character device: a byte stream served by a driver instead of stored data.
+pub trait CharDevice: Send + Sync {
+    /// `offset` is the position of the open file; stream devices ignore it.
+    fn read(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize>;
+    fn write(&self, offset: usize, data: &[u8]) -> FsResult<usize>;
+}
+
+struct DevNode {
+    ino: u64,
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    device: Arc<dyn CharDevice>,
+}
+
+struct Inner {
+    nodes: BTreeMap<String, DevNode>,
+    next_ino: u64,
+}
+
+/// A flat directory of character devices, normally mounted on `/dev`.
+pub struct DevFs {
+    inner: Mutex<Inner>,
+}
+
+impl DevFs {
+    pub fn new() -> Self {
+        DevFs {
+            inner: Mutex::new(Inner {
+                nodes: BTreeMap::new(),
+                next_ino: ROOT_INO + 1,
+            }),
+        }
+    }
+
+    /// A devfs populated with the standard devices.
+    pub fn with_default_devices() -> Self {
+        let devfs = DevFs::new();
+        let console: Arc<dyn CharDevice> = Arc::new(Console);
+        let devices: [(&str, u16, Arc<dyn CharDevice>); 7] = [
+            ("null", 0o666, Arc::new(Null)),
+            ("zero", 0o666, Arc::new(Zero)),
+            ("random", 0o666, Arc::new(Random::new())),
+            ("console", 0o666, console.clone()),
+            ("tty0", 0o666, console),
+            ("ttyS0", 0o660, Arc::new(Serial)),
+            ("kmsg", 0o644, Arc::new(Kmsg)),
+        ];
+        for (name, mode, device) in devices {
+            devfs
+                .register(name, mode, device)
+                .expect("duplicate device name");
+        }
+        devfs
+    }
+
+    /// Adds a device node called `name`.
+    pub fn register(&self, name: &str, mode: u16, device: Arc<dyn CharDevice>) -> FsResult<()> {
+        if name.is_empty() || name.contains('/') {
+            return Err(FsError::InvalidPath);
+        }
+        let mut inner = self.inner.lock();
+        if inner.nodes.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let ino = inner.next_ino;
+        inner.next_ino += 1;
+        inner.nodes.insert(
+            name.into(),
+            DevNode {
+                ino,
+                mode,
+                uid: 0,
+                gid: 0,
+                device,
+            },
+        );
+        Ok(())
+    }
+
+    fn device(&self, path: &str) -> FsResult<Arc<dyn CharDevice>> {
+        let name = node_name(path)?.ok_or(FsError::NotAFile)?;
+        let inner = self.inner.lock();
+        let node = inner.nodes.get(&name).ok_or(FsError::NotFound)?;
+        Ok(node.device.clone())
+    }
+
+    fn update(&self, path: &str, f: impl FnOnce(&mut DevNode)) -> FsResult<()> {
+        // the directory itself is fixed
+        let name = node_name(path)?.ok_or(FsError::PermissionDenied)?;
+        let mut inner = self.inner.lock();
+        f(inner.nodes.get_mut(&name).ok_or(FsError::NotFound)?);
+        Ok(())
+    }
+}
+
+impl Default for DevFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// `None` for the devfs root, `Some(name)` for a device node
+fn node_name(path: &str) -> FsResult<Option<String>> {
+    let normalized = VFS::normalize_path(path);
+    if normalized == "/" {
+        return Ok(None);
+    }
+    let name = &normalized[1..];
+    if name.contains('/') {
+        return Err(FsError::NotADirectory);
+    }
+    Ok(Some(name.into()))
+}
+
+fn node_inode(name: &str, node: &DevNode) -> INode {
+    INode {
+        name: name.into(),
+        ino: node.ino,
+        file_type: FileType::CharDevice,
+        size: 0,
+        mode: node.mode,
+        uid: node.uid,
+        gid: node.gid,
+        nlink: 1,
+    }
+}
+
+impl FileSystem for DevFs {
+    fn create_file(&self, _path: &str) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn create_dir(&self, _path: &str) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn remove(&self, _path: &str) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn read_file(&self, path: &str) -> FsResult<Vec<u8>> {
+        let device = self.device(path)?;
+        let mut data = Vec::new();
+        let mut chunk = [0u8; 512];
+        while data.len() < READ_FILE_LIMIT {
+            let want = chunk.len().min(READ_FILE_LIMIT - data.len());
+            let read = device.read(data.len(), &mut chunk[..want])?;
+            if read == 0 {
+                break;
+            }
+            data.extend_from_slice(&chunk[..read]);
+        }
+        Ok(data)
+    }
+
+    fn write_file(&self, path: &str, data: &[u8]) -> FsResult<()> {
+        self.device(path)?.write(0, data)?;
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &str) -> FsResult<Vec<INode>> {
+        if node_name(path)?.is_some() {
+            return Err(FsError::NotADirectory);
+        }
+        let inner = self.inner.lock();
+        Ok(inner
+            .nodes
+            .iter()
+            .map(|(name, node)| node_inode(name, node))
+            .collect())
+    }
+
+    fn stat(&self, path: &str) -> FsResult<INode> {
+        let inner = self.inner.lock();
+        match node_name(path)? {
+            None => Ok(INode {
+                name: String::from("/"),
+                ino: ROOT_INO,
+                file_type: FileType::Directory,
+                size: inner.nodes.len(),
+                mode: 0o755,
+                uid: 0,
+                gid: 0,
+                nlink: 2,
+            }),
+            Some(name) => {
+                let node = inner.nodes.get(&name).ok_or(FsError::NotFound)?;
+                Ok(node_inode(&name, node))
+            }
+        }
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.stat(path).is_ok()
+    }
+
+    fn chmod(&self, path: &str, mode: u16) -> FsResult<()> {
+        self.update(path, |node| node.mode = mode & 0o7777)
+    }
+
+    fn chown(&self, path: &str, uid: u32, gid: u32) -> FsResult<()> {
+        self.update(path, |node| {
+            node.uid = uid;
+            node.gid = gid;
+        })
+    }
+
+    fn rename(&self, _from: &str, _to: &str) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn read_at(&self, path: &str, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        self.device(path)?.read(offset, buf)
+    }
+
+    fn write_at(&self, path: &str, offset: usize, data: &[u8]) -> FsResult<usize> {
+        self.device(path)?.write(offset, data)
+    }
+
+    fn append(&self, path: &str, data: &[u8]) -> FsResult<usize> {
+        self.device(path)?.write(0, data)
+    }
+
+    // O_TRUNC on a device is a no-op
+    fn truncate(&self, path: &str, _len: usize) -> FsResult<()> {
+        self.device(path).map(|_| ())
+    }
+}
+
+// ---- standard devices ----
+
+/// `/dev/null`: reads hit end of file, writes are discarded.
+pub struct Null;
+
+impl CharDevice for Null {
+    fn read(&self, _offset: usize, _buf: &mut [u8]) -> FsResult<usize> {
+        Ok(0)
+    }
+
+    fn write(&self, _offset: usize, data: &[u8]) -> FsResult<usize> {
+        Ok(data.len())
+    }
+}
+
+/// `/dev/zero`: an endless stream of zero bytes.
+pub struct Zero;
+
+impl CharDevice for Zero {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, _offset: usize, data: &[u8]) -> FsResult<usize> {
+        Ok(data.len())
+    }
+}
+
+/// `/dev/random`: xorshift64* seeded from the TSC. Not suitable for cryptography.
+pub struct Random {
+    state: Mutex<u64>,
+}
+
+impl Random {
+    pub fn new() -> Self {
+        let seed = unsafe { core::arch::x86_64::_rdtsc() };
+        Random {
+            // xorshift must never start from 0
+            state: Mutex::new(seed | 1),
+        }
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CharDevice for Random {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let mut state = self.state.lock();
+        for chunk in buf.chunks_mut(8) {
+            *state ^= *state >> 12;
+            *state ^= *state << 25;
+            *state ^= *state >> 27;
+            let value = state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+            chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+
+    // written bytes are mixed into the state, like feeding entropy on Linux
+    fn write(&self, _offset: usize, data: &[u8]) -> FsResult<usize> {
+        let mut state = self.state.lock();
+        for &b in data {
+            *state = (state.rotate_left(8) ^ b as u64) | 1;
+        }
+        Ok(data.len())
+    }
+}
+
+/// `/dev/console` and `/dev/tty0`: the VGA terminal and keyboard.
+pub struct Console;
+
+impl CharDevice for Console {
+    // a user process waits for a full line; kernel callers run on the executor that
+    // delivers keystrokes, so they can't wait and only get a line already typed
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        if !buf.is_empty() && process::current_pid().is_some() {
+            process::park_until(input::line_ready)?;
+        }
+        Ok(input::try_read(buf))
+    }
+
+    fn write(&self, _offset: usize, data: &[u8]) -> FsResult<usize> {
+        crate::ui::terminal::write(&String::from_utf8_lossy(data));
+        Ok(data.len())
+    }
+}
+
+/// `/dev/ttyS0`: the first serial port (`SERIAL1`).
+pub struct Serial;
+
+impl CharDevice for Serial {
+    // only returns what has already arrived
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match crate::drivers::serial::try_receive() {
+                Some(b) => {
+                    buf[read] = b;
+                    read += 1;
+                }
+                None => break,
+            }
+        }
+        Ok(read)
+    }
+
+    fn write(&self, _offset: usize, data: &[u8]) -> FsResult<usize> {
+        crate::drivers::serial::write_bytes(data);
+        Ok(data.len())
+    }
+}
+
+/// `/dev/kmsg`: the kernel log. Reads start from the oldest retained message.
+pub struct Kmsg;
+
+impl CharDevice for Kmsg {
+    fn read(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        Ok(crate::kernel::kmsg::read_at(offset, buf))
+    }
+
+    fn write(&self, _offset: usize, data: &[u8]) -> FsResult<usize> {
+        crate::kernel::kmsg::write(data);
+        Ok(data.len())
+    }
+}
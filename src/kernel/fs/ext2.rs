@@ -0,0 +1,1215 @@
+use super::vfs::{FileSystem, FileType, FsError, FsResult, INode, VFS};
+use crate::drivers::block::{self, BlockRef};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const MAGIC: u16 = 0xEF53;
+const ROOT_INO: u32 = 2;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GOOD_OLD_FIRST_INO: u32 = 11;
+const DESCRIPTOR_SIZE: usize = 32;
+
+// the only incompatible feature understood: a type byte in directory entries
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+// sparse_super, large_file and btree_dir don't change anything done here
+const RO_COMPAT_KNOWN: u32 = 0x0001 | 0x0002 | 0x0004;
+
+const S_IFMT: u16 = 0xF000;
+const S_IFCHR: u16 = 0x2000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFREG: u16 = 0x8000;
+const S_IFLNK: u16 = 0xA000;
+
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_CHRDEV: u8 = 3;
+const FT_SYMLINK: u8 = 7;
+
+// hashed directory index; it goes stale once we modify the directory
+const INDEX_FL: u32 = 0x1000;
+
+const DIRECT_BLOCKS: usize = 12;
+// symlink targets shorter than this live in the block pointers ("fast" symlinks)
+const FAST_SYMLINK_MAX: usize = 60;
+const MAX_NAME: usize = 255;
+
+/// An ext2 filesystem on a block device.
+pub struct Ext2 {
+    volume: Mutex<Volume>,
+}
+
+struct Group {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks: u16,
+    free_inodes: u16,
+    used_dirs: u16,
+}
+
+struct Volume {
+    dev: BlockRef,
+    block_size: u64,
+    blocks_count: u32,
+    first_data_block: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inode_size: usize,
+    first_ino: u32,
+    free_blocks: u32,
+    free_inodes: u32,
+    filetype: bool,
+    read_only: bool,
+    // stands in for the current time, which the kernel doesn't know
+    now: u32,
+    groups: Vec<Group>,
+}
+
+/// The first 128 bytes of an on-disk inode; later fields are left untouched.
+#[derive(Clone)]
+struct Inode {
+    raw: [u8; GOOD_OLD_INODE_SIZE],
+}
+
+fn le16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn le32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+fn put16(b: &mut [u8], at: usize, value: u16) {
+    b[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32(b: &mut [u8], at: usize, value: u32) {
+    b[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+impl Inode {
+    fn new(mode: u16, now: u32) -> Self {
+        let mut inode = Inode {
+            raw: [0; GOOD_OLD_INODE_SIZE],
+        };
+        put16(&mut inode.raw, 0, mode);
+        put32(&mut inode.raw, 8, now);
+        put32(&mut inode.raw, 12, now);
+        put32(&mut inode.raw, 16, now);
+        inode
+    }
+
+    fn mode(&self) -> u16 {
+        le16(&self.raw, 0)
+    }
+
+    fn set_mode(&mut self, mode: u16) {
+        put16(&mut self.raw, 0, mode)
+    }
+
+    fn kind(&self) -> u16 {
+        self.mode() & S_IFMT
+    }
+
+    fn is_dir(&self) -> bool {
+        self.kind() == S_IFDIR
+    }
+
+    fn uid(&self) -> u32 {
+        le16(&self.raw, 2) as u32 | (le16(&self.raw, 120) as u32) << 16
+    }
+
+    fn gid(&self) -> u32 {
+        le16(&self.raw, 24) as u32 | (le16(&self.raw, 122) as u32) << 16
+    }
+
+    fn set_owner(&mut self, uid: u32, gid: u32) {
+        put16(&mut self.raw, 2, uid as u16);
+        put16(&mut self.raw, 120, (uid >> 16) as u16);
+        put16(&mut self.raw, 24, gid as u16);
+        put16(&mut self.raw, 122, (gid >> 16) as u16);
+    }
+
+    // the high half of the size is only meaningful for regular files
+    fn size(&self) -> u64 {
+        let high = if self.kind() == S_IFREG {
+            le32(&self.raw, 108) as u64
+        } else {
+            0
+        };
+        le32(&self.raw, 4) as u64 | high << 32
+    }
+
+    fn set_size(&mut self, size: u64) {
+        put32(&mut self.raw, 4, size as u32);
+        if self.kind() == S_IFREG {
+            put32(&mut self.raw, 108, (size >> 32) as u32);
+        }
+    }
+
+    fn links(&self) -> u16 {
+        le16(&self.raw, 26)
+    }
+
+    fn set_links(&mut self, links: u16) {
+        put16(&mut self.raw, 26, links)
+    }
+
+    /// Allocated space in 512-byte units.
+    fn sectors(&self) -> u32 {
+        le32(&self.raw, 28)
+    }
+
+    fn set_sectors(&mut self, sectors: u32) {
+        put32(&mut self.raw, 28, sectors)
+    }
+
+    fn flags(&self) -> u32 {
+        le32(&self.raw, 32)
+    }
+
+    fn set_flags(&mut self, flags: u32) {
+        put32(&mut self.raw, 32, flags)
+    }
+
+    fn block(&self, index: usize) -> u32 {
+        le32(&self.raw, 40 + index * 4)
+    }
+
+    fn set_block(&mut self, index: usize, block: u32) {
+        put32(&mut self.raw, 40 + index * 4, block)
+    }
+
+    fn touch(&mut self, now: u32) {
+        put32(&mut self.raw, 12, now); // ctime
+        put32(&mut self.raw, 16, now); // mtime
+    }
+
+    fn set_dtime(&mut self, now: u32) {
+        put32(&mut self.raw, 20, now)
+    }
+
+    // the file ACL block counts towards `sectors` even for fast symlinks
+    fn is_fast_symlink(&self, block_size: u64) -> bool {
+        let acl_sectors = if le32(&self.raw, 104) != 0 {
+            (block_size / 512) as u32
+        } else {
+            0
+        };
+        self.kind() == S_IFLNK && self.sectors() == acl_sectors
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.kind() {
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            S_IFCHR => FileType::CharDevice,
+            _ => FileType::File,
+        }
+    }
+
+    fn dir_entry_type(&self) -> u8 {
+        match self.kind() {
+            S_IFDIR => FT_DIR,
+            S_IFLNK => FT_SYMLINK,
+            S_IFCHR => FT_CHRDEV,
+            _ => FT_REG_FILE,
+        }
+    }
+}
+
+/// A parsed directory entry and where it sits in the directory.
+struct DirEntry {
+    ino: u32,
+    name: String,
+    // byte position within the directory, and of the entry before it in the same block
+    pos: u64,
+    prev: Option<u64>,
+    rec_len: usize,
+}
+
+// entries are padded to four bytes
+fn entry_len(name_len: usize) -> usize {
+    (8 + name_len + 3) & !3
+}
+
+fn check_name(name: &str) -> FsResult<()> {
+    if name.is_empty() || name.len() > MAX_NAME {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(())
+}
+
+fn components(path: &str) -> Vec<String> {
+    VFS::normalize_path(path)
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+impl Volume {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> FsResult<()> {
+        Ok(block::read_bytes(&*self.dev, offset, buf)?)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> FsResult<()> {
+        if self.read_only {
+            return Err(FsError::PermissionDenied);
+        }
+        Ok(block::write_bytes(&*self.dev, offset, data)?)
+    }
+
+    fn read_block(&self, block: u32) -> FsResult<Vec<u8>> {
+        let mut data = vec![0u8; self.block_size as usize];
+        self.read(block as u64 * self.block_size, &mut data)?;
+        Ok(data)
+    }
+
+    fn write_block(&self, block: u32, data: &[u8]) -> FsResult<()> {
+        self.write(block as u64 * self.block_size, data)
+    }
+
+    fn pointers_per_block(&self) -> u64 {
+        self.block_size / 4
+    }
+
+    // ---- metadata ----
+
+    fn write_superblock_counts(&self) -> FsResult<()> {
+        let mut raw = [0u8; 8];
+        put32(&mut raw, 0, self.free_blocks);
+        put32(&mut raw, 4, self.free_inodes);
+        self.write(SUPERBLOCK_OFFSET + 12, &raw)
+    }
+
+    fn write_group(&self, index: usize) -> FsResult<()> {
+        let group = &self.groups[index];
+        let mut raw = [0u8; 6];
+        put16(&mut raw, 0, group.free_blocks);
+        put16(&mut raw, 2, group.free_inodes);
+        put16(&mut raw, 4, group.used_dirs);
+        let table = (self.first_data_block as u64 + 1) * self.block_size;
+        self.write(table + (index * DESCRIPTOR_SIZE) as u64 + 12, &raw)
+    }
+
+    fn inode_offset(&self, ino: u32) -> FsResult<u64> {
+        let index = ino.checked_sub(1).ok_or(FsError::InvalidData)?;
+        let group = self
+            .groups
+            .get((index / self.inodes_per_group) as usize)
+            .ok_or(FsError::InvalidData)?;
+        let within = (index % self.inodes_per_group) as u64;
+        Ok(group.inode_table as u64 * self.block_size + within * self.inode_size as u64)
+    }
+
+    fn read_inode(&self, ino: u32) -> FsResult<Inode> {
+        let mut inode = Inode {
+            raw: [0; GOOD_OLD_INODE_SIZE],
+        };
+        self.read(self.inode_offset(ino)?, &mut inode.raw)?;
+        Ok(inode)
+    }
+
+    fn write_inode(&self, ino: u32, inode: &Inode) -> FsResult<()> {
+        self.write(self.inode_offset(ino)?, &inode.raw)
+    }
+
+    // ---- allocation ----
+
+    /// Finds and sets a clear bit in a group bitmap, trying `first` group first.
+    fn alloc_bit(&mut self, first: usize, inodes: bool) -> FsResult<(usize, u32)> {
+        let count = self.groups.len();
+        for step in 0..count {
+            let index = (first + step) % count;
+            let group = &self.groups[index];
+            let (free, bitmap, bits) = if inodes {
+                (group.free_inodes, group.inode_bitmap, self.inodes_per_group)
+            } else {
+                (group.free_blocks, group.block_bitmap, self.blocks_per_group)
+            };
+            if free == 0 {
+                continue;
+            }
+
+            let mut data = self.read_block(bitmap)?;
+            for bit in 0..bits.min(self.block_size as u32 * 8) {
+                let (byte, mask) = ((bit / 8) as usize, 1u8 << (bit % 8));
+                if data[byte] & mask == 0 {
+                    data[byte] |= mask;
+                    self.write_block(bitmap, &data)?;
+                    return Ok((index, bit));
+                }
+            }
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn clear_bit(&self, bitmap: u32, bit: u32) -> FsResult<()> {
+        let offset = bitmap as u64 * self.block_size + (bit / 8) as u64;
+        let mut byte = [0u8; 1];
+        self.read(offset, &mut byte)?;
+        byte[0] &= !(1 << (bit % 8));
+        self.write(offset, &byte)
+    }
+
+    /// Allocates a zeroed block, preferring the group of `ino`.
+    fn alloc_block(&mut self, ino: u32) -> FsResult<u32> {
+        let first = ((ino - 1) / self.inodes_per_group) as usize;
+        let (group, bit) = self.alloc_bit(first, false)?;
+        let block = self.first_data_block + group as u32 * self.blocks_per_group + bit;
+        if block >= self.blocks_count {
+            return Err(FsError::InvalidData);
+        }
+
+        self.groups[group].free_blocks -= 1;
+        self.free_blocks -= 1;
+        self.write_group(group)?;
+        self.write_superblock_counts()?;
+        self.write_block(block, &vec![0u8; self.block_size as usize])?;
+        Ok(block)
+    }
+
+    fn free_block(&mut self, block: u32) -> FsResult<()> {
+        let relative = block
+            .checked_sub(self.first_data_block)
+            .ok_or(FsError::InvalidData)?;
+        let group = (relative / self.blocks_per_group) as usize;
+        let bitmap = self
+            .groups
+            .get(group)
+            .ok_or(FsError::InvalidData)?
+            .block_bitmap;
+        self.clear_bit(bitmap, relative % self.blocks_per_group)?;
+
+        self.groups[group].free_blocks += 1;
+        self.free_blocks += 1;
+        self.write_group(group)?;
+        self.write_superblock_counts()
+    }
+
+    /// Allocates an inode number near `parent` and writes `inode` there.
+    fn alloc_inode(&mut self, parent: u32, inode: &Inode) -> FsResult<u32> {
+        let first = ((parent - 1) / self.inodes_per_group) as usize;
+        let (group, bit) = self.alloc_bit(first, true)?;
+        let ino = group as u32 * self.inodes_per_group + bit + 1;
+        if ino < self.first_ino {
+            // reserved inodes are marked in use by mkfs, so this means corruption
+            return Err(FsError::InvalidData);
+        }
+
+        self.groups[group].free_inodes -= 1;
+        if inode.is_dir() {
+            self.groups[group].used_dirs += 1;
+        }
+        self.free_inodes -= 1;
+        self.write_group(group)?;
+        self.write_superblock_counts()?;
+        self.write_inode(ino, inode)?;
+        Ok(ino)
+    }
+
+    /// Releases the blocks and the number of an inode whose last link is gone.
+    fn free_inode(&mut self, ino: u32, mut inode: Inode) -> FsResult<()> {
+        if !inode.is_fast_symlink(self.block_size) {
+            self.truncate_blocks(&mut inode, 0)?;
+        }
+        inode.set_links(0);
+        inode.set_dtime(self.now.max(1));
+        self.write_inode(ino, &inode)?;
+
+        let group = ((ino - 1) / self.inodes_per_group) as usize;
+        self.clear_bit(
+            self.groups[group].inode_bitmap,
+            (ino - 1) % self.inodes_per_group,
+        )?;
+        self.groups[group].free_inodes += 1;
+        if inode.is_dir() {
+            self.groups[group].used_dirs -= 1;
+        }
+        self.free_inodes += 1;
+        self.write_group(group)?;
+        self.write_superblock_counts()
+    }
+
+    // ---- block mapping ----
+
+    /// The disk block holding logical block `index` of a file, or 0 for a hole.
+    /// With `alloc`, holes (and missing indirect blocks) are filled in.
+    fn map_block(&mut self, ino: u32, inode: &mut Inode, index: u64, alloc: bool) -> FsResult<u32> {
+        let per = self.pointers_per_block();
+        let sectors_per_block = (self.block_size / 512) as u32;
+
+        // which slot of the inode, how many levels of indirection below it,
+        // and the index within the tree that slot roots
+        let (slot, depth, mut within) = if index < DIRECT_BLOCKS as u64 {
+            (index as usize, 0, 0)
+        } else if index - (DIRECT_BLOCKS as u64) < per {
+            (DIRECT_BLOCKS, 1, index - DIRECT_BLOCKS as u64)
+        } else if index - (DIRECT_BLOCKS as u64) - per < per * per {
+            (DIRECT_BLOCKS + 1, 2, index - DIRECT_BLOCKS as u64 - per)
+        } else if index - (DIRECT_BLOCKS as u64) - per - per * per < per * per * per {
+            (
+                DIRECT_BLOCKS + 2,
+                3,
+                index - DIRECT_BLOCKS as u64 - per - per * per,
+            )
+        } else {
+            return Err(FsError::NoSpace);
+        };
+
+        let mut block = inode.block(slot);
+        if block == 0 {
+            if !alloc {
+                return Ok(0);
+            }
+            block = self.alloc_block(ino)?;
+            inode.set_block(slot, block);
+            inode.set_sectors(inode.sectors() + sectors_per_block);
+        }
+
+        for level in (0..depth).rev() {
+            let span = per.pow(level);
+            let entry = (within / span) as usize;
+            within %= span;
+
+            let mut table = self.read_block(block)?;
+            let mut next = le32(&table, entry * 4);
+            if next == 0 {
+                if !alloc {
+                    return Ok(0);
+                }
+                next = self.alloc_block(ino)?;
+                put32(&mut table, entry * 4, next);
+                self.write_block(block, &table)?;
+                inode.set_sectors(inode.sectors() + sectors_per_block);
+            }
+            block = next;
+        }
+        Ok(block)
+    }
+
+    /// Frees what `block` maps at or past logical index `keep`, where `block`
+    /// has `depth` levels of indirection and maps indexes from `first` on.
+    /// Returns true if `block` itself was freed.
+    fn truncate_tree(
+        &mut self,
+        inode: &mut Inode,
+        block: u32,
+        depth: u32,
+        first: u64,
+        keep: u64,
+    ) -> FsResult<bool> {
+        let sectors_per_block = (self.block_size / 512) as u32;
+        if depth > 0 {
+            let per = self.pointers_per_block();
+            let span = per.pow(depth - 1);
+            let mut table = self.read_block(block)?;
+            let mut changed = false;
+
+            for entry in 0..per as usize {
+                let child = le32(&table, entry * 4);
+                let child_first = first + entry as u64 * span;
+                if child == 0 || child_first + span <= keep {
+                    continue;
+                }
+                if self.truncate_tree(inode, child, depth - 1, child_first, keep)? {
+                    put32(&mut table, entry * 4, 0);
+                    changed = true;
+                }
+            }
+            if changed && first < keep {
+                self.write_block(block, &table)?;
+            }
+        }
+
+        if first >= keep {
+            self.free_block(block)?;
+            inode.set_sectors(inode.sectors().saturating_sub(sectors_per_block));
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Frees every block of the file from logical index `keep` on.
+    fn truncate_blocks(&mut self, inode: &mut Inode, keep: u64) -> FsResult<()> {
+        let per = self.pointers_per_block();
+        let roots = [
+            (DIRECT_BLOCKS, 1, DIRECT_BLOCKS as u64),
+            (DIRECT_BLOCKS + 1, 2, DIRECT_BLOCKS as u64 + per),
+            (DIRECT_BLOCKS + 2, 3, DIRECT_BLOCKS as u64 + per + per * per),
+        ];
+
+        for slot in 0..DIRECT_BLOCKS {
+            let block = inode.block(slot);
+            if block != 0 && self.truncate_tree(inode, block, 0, slot as u64, keep)? {
+                inode.set_block(slot, 0);
+            }
+        }
+        for (slot, depth, first) in roots {
+            let block = inode.block(slot);
+            if block != 0 && self.truncate_tree(inode, block, depth, first, keep)? {
+                inode.set_block(slot, 0);
+            }
+        }
+        Ok(())
+    }
+
+    // ---- file contents ----
+
+    fn read_data(
+        &mut self,
+        ino: u32,
+        inode: &mut Inode,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> FsResult<usize> {
+        let size = inode.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = (buf.len() as u64).min(size - offset) as usize;
+
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let within = (pos % self.block_size) as usize;
+            let count = (self.block_size as usize - within).min(len - done);
+            match self.map_block(ino, inode, pos / self.block_size, false)? {
+                // a hole reads as zeros
+                0 => buf[done..done + count].fill(0),
+                block => self.read(
+                    block as u64 * self.block_size + within as u64,
+                    &mut buf[done..done + count],
+                )?,
+            }
+            done += count;
+        }
+        Ok(len)
+    }
+
+    fn write_data(
+        &mut self,
+        ino: u32,
+        inode: &mut Inode,
+        offset: u64,
+        data: &[u8],
+    ) -> FsResult<()> {
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done as u64;
+            let within = (pos % self.block_size) as usize;
+            let count = (self.block_size as usize - within).min(data.len() - done);
+            let block = self.map_block(ino, inode, pos / self.block_size, true)?;
+            self.write(
+                block as u64 * self.block_size + within as u64,
+                &data[done..done + count],
+            )?;
+            done += count;
+        }
+
+        let end = offset + data.len() as u64;
+        if end > inode.size() {
+            inode.set_size(end);
+        }
+        inode.touch(self.now);
+        self.write_inode(ino, inode)
+    }
+
+    fn resize(&mut self, ino: u32, inode: &mut Inode, len: u64) -> FsResult<()> {
+        let size = inode.size();
+        if len < size {
+            let keep = len.div_ceil(self.block_size);
+            self.truncate_blocks(inode, keep)?;
+
+            // later growth must not expose what used to be past the new end
+            let within = (len % self.block_size) as usize;
+            if within != 0 {
+                let block = self.map_block(ino, inode, len / self.block_size, false)?;
+                if block != 0 {
+                    let zeros = vec![0u8; self.block_size as usize - within];
+                    self.write(block as u64 * self.block_size + within as u64, &zeros)?;
+                }
+            }
+        }
+        // growing just moves the end: the gap is a hole
+        inode.set_size(len);
+        inode.touch(self.now);
+        self.write_inode(ino, inode)
+    }
+
+    // ---- directories ----
+
+    fn read_dir(&mut self, ino: u32, inode: &mut Inode) -> FsResult<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        let blocks = inode.size() / self.block_size;
+        for index in 0..blocks {
+            let block = self.map_block(ino, inode, index, false)?;
+            if block == 0 {
+                continue;
+            }
+            let data = self.read_block(block)?;
+            let mut at = 0;
+            let mut prev = None;
+            while at + 8 <= data.len() {
+                let rec_len = le16(&data, at + 4) as usize;
+                if rec_len < 8 || at + rec_len > data.len() {
+                    return Err(FsError::InvalidData);
+                }
+                let entry_ino = le32(&data, at);
+                let name_len = data[at + 6] as usize;
+                let pos = index * self.block_size + at as u64;
+                if entry_ino != 0 {
+                    let name = data
+                        .get(at + 8..at + 8 + name_len)
+                        .ok_or(FsError::InvalidData)?;
+                    entries.push(DirEntry {
+                        ino: entry_ino,
+                        name: String::from_utf8_lossy(name).into_owned(),
+                        pos,
+                        prev,
+                        rec_len,
+                    });
+                }
+                prev = Some(pos);
+                at += rec_len;
+            }
+        }
+        Ok(entries)
+    }
+
+    fn find(&mut self, dir: u32, name: &str) -> FsResult<Option<u32>> {
+        let mut inode = self.read_inode(dir)?;
+        if !inode.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        let entries = self.read_dir(dir, &mut inode)?;
+        Ok(entries.into_iter().find(|e| e.name == name).map(|e| e.ino))
+    }
+
+    fn lookup(&mut self, path: &str) -> FsResult<u32> {
+        let mut ino = ROOT_INO;
+        for name in components(path) {
+            ino = self.find(ino, &name)?.ok_or(FsError::NotFound)?;
+        }
+        Ok(ino)
+    }
+
+    /// The parent directory's inode number and the final name of `path`.
+    fn lookup_parent(&mut self, path: &str) -> FsResult<(u32, String)> {
+        let parent = VFS::parent_path(path).ok_or(FsError::PermissionDenied)?;
+        let name = VFS::filename(path).ok_or(FsError::InvalidPath)?;
+        let dir = self.lookup(&parent)?;
+        if !self.read_inode(dir)?.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        Ok((dir, name))
+    }
+
+    /// Links `name` to `ino` in directory `dir`, splitting an entry with spare room
+    /// or adding a block.
+    fn add_entry(&mut self, dir: u32, name: &str, ino: u32, file_type: u8) -> FsResult<()> {
+        check_name(name)?;
+        let mut dir_inode = self.read_inode(dir)?;
+        let needed = entry_len(name.len());
+        let mut new = vec![0u8; needed];
+        put32(&mut new, 0, ino);
+        new[6] = name.len() as u8;
+        new[7] = if self.filetype { file_type } else { 0 };
+        new[8..8 + name.len()].copy_from_slice(name.as_bytes());
+
+        let blocks = dir_inode.size() / self.block_size;
+        for index in 0..blocks {
+            let block = self.map_block(dir, &mut dir_inode, index, false)?;
+            if block == 0 {
+                continue;
+            }
+            let mut data = self.read_block(block)?;
+            let mut at = 0;
+            while at + 8 <= data.len() {
+                let rec_len = le16(&data, at + 4) as usize;
+                if rec_len < 8 {
+                    return Err(FsError::InvalidData);
+                }
+                let used = if le32(&data, at) == 0 {
+                    0
+                } else {
+                    entry_len(data[at + 6] as usize)
+                };
+                if rec_len - used >= needed {
+                    let start = at + used;
+                    if used != 0 {
+                        put16(&mut data, at + 4, used as u16);
+                    }
+                    put16(&mut new, 4, (rec_len - used) as u16);
+                    data[start..start + needed].copy_from_slice(&new);
+                    self.write_block(block, &data)?;
+                    return self.touch_dir(dir, &mut dir_inode);
+                }
+                at += rec_len;
+            }
+        }
+
+        // every block is full: append one holding just this entry
+        let block = self.map_block(dir, &mut dir_inode, blocks, true)?;
+        let mut data = vec![0u8; self.block_size as usize];
+        put16(&mut new, 4, self.block_size as u16);
+        data[..needed].copy_from_slice(&new);
+        self.write_block(block, &data)?;
+        dir_inode.set_size((blocks + 1) * self.block_size);
+        self.touch_dir(dir, &mut dir_inode)
+    }
+
+    fn remove_entry(&mut self, dir: u32, name: &str) -> FsResult<()> {
+        let mut dir_inode = self.read_inode(dir)?;
+        let entries = self.read_dir(dir, &mut dir_inode)?;
+        let entry = entries
+            .into_iter()
+            .find(|e| e.name == name)
+            .ok_or(FsError::NotFound)?;
+
+        let block = self.map_block(dir, &mut dir_inode, entry.pos / self.block_size, false)?;
+        let mut data = self.read_block(block)?;
+        let at = (entry.pos % self.block_size) as usize;
+        match entry.prev {
+            // the previous entry absorbs this one's space
+            Some(prev) => {
+                let prev_at = (prev % self.block_size) as usize;
+                let merged = le16(&data, prev_at + 4) as usize + entry.rec_len;
+                put16(&mut data, prev_at + 4, merged as u16);
+            }
+            // the first entry of a block can only be marked unused
+            None => put32(&mut data, at, 0),
+        }
+        self.write_block(block, &data)?;
+        self.touch_dir(dir, &mut dir_inode)
+    }
+
+    /// Points the entry `name` in `dir` at a different inode.
+    fn retarget_entry(&mut self, dir: u32, name: &str, ino: u32, file_type: u8) -> FsResult<()> {
+        let mut dir_inode = self.read_inode(dir)?;
+        let entries = self.read_dir(dir, &mut dir_inode)?;
+        let entry = entries
+            .into_iter()
+            .find(|e| e.name == name)
+            .ok_or(FsError::NotFound)?;
+        let block = self.map_block(dir, &mut dir_inode, entry.pos / self.block_size, false)?;
+        let at = block as u64 * self.block_size + entry.pos % self.block_size;
+        self.write(at, &ino.to_le_bytes())?;
+        if self.filetype {
+            self.write(at + 7, &[file_type])?;
+        }
+        Ok(())
+    }
+
+    // a modified directory no longer matches its hash index, so drop it
+    fn touch_dir(&mut self, dir: u32, inode: &mut Inode) -> FsResult<()> {
+        inode.set_flags(inode.flags() & !INDEX_FL);
+        inode.touch(self.now);
+        self.write_inode(dir, inode)
+    }
+
+    fn is_empty_dir(&mut self, ino: u32, inode: &mut Inode) -> FsResult<bool> {
+        let entries = self.read_dir(ino, inode)?;
+        Ok(entries.iter().all(|e| e.name == "." || e.name == ".."))
+    }
+
+    fn add_links(&mut self, ino: u32, delta: i32) -> FsResult<()> {
+        let mut inode = self.read_inode(ino)?;
+        inode.set_links((inode.links() as i32 + delta).max(0) as u16);
+        inode.touch(self.now);
+        self.write_inode(ino, &inode)
+    }
+
+    /// Creates an inode of `mode` and links it at `path`.
+    fn create(&mut self, path: &str, mode: u16) -> FsResult<(u32, u32, Inode)> {
+        let (dir, name) = self.lookup_parent(path)?;
+        check_name(&name)?;
+        if self.find(dir, &name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        let mut inode = Inode::new(mode, self.now);
+        inode.set_links(1);
+        let ino = self.alloc_inode(dir, &inode)?;
+        if let Err(e) = self.add_entry(dir, &name, ino, inode.dir_entry_type()) {
+            self.free_inode(ino, inode)?;
+            return Err(e);
+        }
+        Ok((dir, ino, inode))
+    }
+
+    fn file(&mut self, path: &str) -> FsResult<(u32, Inode)> {
+        let ino = self.lookup(path)?;
+        let inode = self.read_inode(ino)?;
+        if inode.kind() != S_IFREG {
+            return Err(FsError::NotAFile);
+        }
+        Ok((ino, inode))
+    }
+
+    fn stat_inode(&mut self, ino: u32, name: &str) -> FsResult<INode> {
+        let inode = self.read_inode(ino)?;
+        Ok(INode {
+            name: name.into(),
+            ino: ino as u64,
+            file_type: inode.file_type(),
+            size: inode.size() as usize,
+            mode: inode.mode() & 0o7777,
+            uid: inode.uid(),
+            gid: inode.gid(),
+            nlink: inode.links() as usize,
+        })
+    }
+}
+
+impl Ext2 {
+    /// Mounts the ext2 filesystem on `dev`, checking its superblock.
+    pub fn new(dev: BlockRef) -> FsResult<Self> {
+        let mut sb = [0u8; 1024];
+        block::read_bytes(&*dev, SUPERBLOCK_OFFSET, &mut sb)?;
+        if le16(&sb, 56) != MAGIC {
+            return Err(FsError::InvalidData);
+        }
+
+        let rev = le32(&sb, 76);
+        let (inode_size, first_ino, incompat, ro_compat) = if rev == 0 {
+            (GOOD_OLD_INODE_SIZE, GOOD_OLD_FIRST_INO, 0, 0)
+        } else {
+            (
+                le16(&sb, 88) as usize,
+                le32(&sb, 84),
+                le32(&sb, 96),
+                le32(&sb, 100),
+            )
+        };
+        // ext3/ext4 features like extents would be misread, so refuse them outright
+        if incompat & !INCOMPAT_FILETYPE != 0 {
+            return Err(FsError::NotSupported);
+        }
+
+        let log_block_size = le32(&sb, 24);
+        if log_block_size > 6 || inode_size < GOOD_OLD_INODE_SIZE || !inode_size.is_power_of_two() {
+            return Err(FsError::InvalidData);
+        }
+        let block_size = 1024u64 << log_block_size;
+        let blocks_count = le32(&sb, 4);
+        let first_data_block = le32(&sb, 20);
+        let blocks_per_group = le32(&sb, 32);
+        let inodes_per_group = le32(&sb, 40);
+        if blocks_per_group == 0
+            || inodes_per_group == 0
+            || blocks_count <= first_data_block
+            || blocks_count as u64 * block_size > dev.block_count() * dev.block_size() as u64
+        {
+            return Err(FsError::InvalidData);
+        }
+
+        let group_count = (blocks_count - first_data_block).div_ceil(blocks_per_group) as usize;
+        let mut table = vec![0u8; group_count * DESCRIPTOR_SIZE];
+        block::read_bytes(
+            &*dev,
+            (first_data_block as u64 + 1) * block_size,
+            &mut table,
+        )?;
+        let groups = table
+            .chunks(DESCRIPTOR_SIZE)
+            .map(|d| Group {
+                block_bitmap: le32(d, 0),
+                inode_bitmap: le32(d, 4),
+                inode_table: le32(d, 8),
+                free_blocks: le16(d, 12),
+                free_inodes: le16(d, 14),
+                used_dirs: le16(d, 16),
+            })
+            .collect();
+
+        let volume = Volume {
+            dev,
+            block_size,
+            blocks_count,
+            first_data_block,
+            blocks_per_group,
+            inodes_per_group,
+            inode_size,
+            first_ino,
+            free_blocks: le32(&sb, 12),
+            free_inodes: le32(&sb, 16),
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            // unknown read-only features are safe to read but not to modify
+            read_only: ro_compat & !RO_COMPAT_KNOWN != 0,
+            now: le32(&sb, 48),
+            groups,
+        };
+        if !volume.read_inode(ROOT_INO)?.is_dir() {
+            return Err(FsError::InvalidData);
+        }
+
+        Ok(Ext2 {
+            volume: Mutex::new(volume),
+        })
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn create_file(&self, path: &str) -> FsResult<()> {
+        self.volume.lock().create(path, S_IFREG | 0o644).map(|_| ())
+    }
+
+    fn create_dir(&self, path: &str) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let (parent, ino, mut inode) = volume.create(path, S_IFDIR | 0o755)?;
+
+        // "." and ".." fill the first block
+        let block_size = volume.block_size as usize;
+        let mut data = vec![0u8; block_size];
+        let dot = entry_len(1);
+        let file_type = if volume.filetype { FT_DIR } else { 0 };
+        put32(&mut data, 0, ino);
+        put16(&mut data, 4, dot as u16);
+        data[6] = 1;
+        data[7] = file_type;
+        data[8] = b'.';
+        put32(&mut data, dot, parent);
+        put16(&mut data, dot + 4, (block_size - dot) as u16);
+        data[dot + 6] = 2;
+        data[dot + 7] = file_type;
+        data[dot + 8..dot + 10].copy_from_slice(b"..");
+
+        let block = volume.map_block(ino, &mut inode, 0, true)?;
+        volume.write_block(block, &data)?;
+        inode.set_links(2);
+        inode.set_size(block_size as u64);
+        volume.write_inode(ino, &inode)?;
+        volume.add_links(parent, 1)
+    }
+
+    fn remove(&self, path: &str) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let (dir, name) = volume.lookup_parent(path)?;
+        let ino = volume.find(dir, &name)?.ok_or(FsError::NotFound)?;
+        let mut inode = volume.read_inode(ino)?;
+
+        if inode.is_dir() {
+            if !volume.is_empty_dir(ino, &mut inode)? {
+                return Err(FsError::PermissionDenied);
+            }
+            volume.remove_entry(dir, &name)?;
+            volume.add_links(dir, -1)?;
+            return volume.free_inode(ino, inode);
+        }
+
+        volume.remove_entry(dir, &name)?;
+        if inode.links() <= 1 {
+            volume.free_inode(ino, inode)
+        } else {
+            inode.set_links(inode.links() - 1);
+            inode.touch(volume.now);
+            volume.write_inode(ino, &inode)
+        }
+    }
+
+    fn read_file(&self, path: &str) -> FsResult<Vec<u8>> {
+        let mut volume = self.volume.lock();
+        let (ino, mut inode) = volume.file(path)?;
+        let mut data = vec![0u8; inode.size() as usize];
+        volume.read_data(ino, &mut inode, 0, &mut data)?;
+        Ok(data)
+    }
+
+    fn write_file(&self, path: &str, data: &[u8]) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let (ino, mut inode) = volume.file(path)?;
+        volume.resize(ino, &mut inode, 0)?;
+        volume.write_data(ino, &mut inode, 0, data)
+    }
+
+    fn list_dir(&self, path: &str) -> FsResult<Vec<INode>> {
+        let mut volume = self.volume.lock();
+        let ino = volume.lookup(path)?;
+        let mut inode = volume.read_inode(ino)?;
+        if !inode.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        let entries = volume.read_dir(ino, &mut inode)?;
+        entries
+            .iter()
+            .filter(|e| e.name != "." && e.name != "..")
+            .map(|e| volume.stat_inode(e.ino, &e.name))
+            .collect()
+    }
+
+    fn stat(&self, path: &str) -> FsResult<INode> {
+        let mut volume = self.volume.lock();
+        let ino = volume.lookup(path)?;
+        let name = VFS::filename(path).unwrap_or_else(|| String::from("/"));
+        volume.stat_inode(ino, &name)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.volume.lock().lookup(path).is_ok()
+    }
+
+    fn chmod(&self, path: &str, mode: u16) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let ino = volume.lookup(path)?;
+        let mut inode = volume.read_inode(ino)?;
+        inode.set_mode(inode.kind() | (mode & 0o7777));
+        inode.touch(volume.now);
+        volume.write_inode(ino, &inode)
+    }
+
+    fn chown(&self, path: &str, uid: u32, gid: u32) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let ino = volume.lookup(path)?;
+        let mut inode = volume.read_inode(ino)?;
+        inode.set_owner(uid, gid);
+        inode.touch(volume.now);
+        volume.write_inode(ino, &inode)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> FsResult<()> {
+        let from = VFS::normalize_path(from);
+        let to = VFS::normalize_path(to);
+        if from == "/" || to == "/" {
+            return Err(FsError::PermissionDenied);
+        }
+        if from == to {
+            return Ok(());
+        }
+        if VFS::is_within(&to, &from) {
+            return Err(FsError::InvalidPath);
+        }
+
+        let mut volume = self.volume.lock();
+        let (from_dir, from_name) = volume.lookup_parent(&from)?;
+        let (to_dir, to_name) = volume.lookup_parent(&to)?;
+        let ino = volume
+            .find(from_dir, &from_name)?
+            .ok_or(FsError::NotFound)?;
+        let mut inode = volume.read_inode(ino)?;
+        let is_dir = inode.is_dir();
+
+        // an existing target is replaced, but only by the same kind of node
+        match volume.find(to_dir, &to_name)? {
+            // two hard links to the same file: nothing to do
+            Some(target) if target == ino => return Ok(()),
+            Some(target) => {
+                let mut target_inode = volume.read_inode(target)?;
+                match (is_dir, target_inode.is_dir()) {
+                    (true, true) if !volume.is_empty_dir(target, &mut target_inode)? => {
+                        return Err(FsError::PermissionDenied)
+                    }
+                    (true, false) => return Err(FsError::NotADirectory),
+                    (false, true) => return Err(FsError::NotAFile),
+                    _ => {}
+                }
+                volume.retarget_entry(to_dir, &to_name, ino, inode.dir_entry_type())?;
+                if target_inode.is_dir() {
+                    // the replaced directory's ".." no longer counts
+                    volume.add_links(to_dir, -1)?;
+                    volume.free_inode(target, target_inode)?;
+                } else if target_inode.links() <= 1 {
+                    volume.free_inode(target, target_inode)?;
+                } else {
+                    volume.add_links(target, -1)?;
+                }
+            }
+            None => volume.add_entry(to_dir, &to_name, ino, inode.dir_entry_type())?,
+        }
+        volume.remove_entry(from_dir, &from_name)?;
+
+        // a moved directory's ".." must follow it
+        if is_dir && from_dir != to_dir {
+            volume.retarget_entry(ino, "..", to_dir, FT_DIR)?;
+            volume.add_links(from_dir, -1)?;
+            volume.add_links(to_dir, 1)?;
+        }
+        inode = volume.read_inode(ino)?;
+        inode.touch(volume.now);
+        volume.write_inode(ino, &inode)
+    }
+
+    fn read_at(&self, path: &str, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let mut volume = self.volume.lock();
+        let (ino, mut inode) = volume.file(path)?;
+        volume.read_data(ino, &mut inode, offset as u64, buf)
+    }
+
+    fn write_at(&self, path: &str, offset: usize, data: &[u8]) -> FsResult<usize> {
+        let mut volume = self.volume.lock();
+        let (ino, mut inode) = volume.file(path)?;
+        volume.write_data(ino, &mut inode, offset as u64, data)?;
+        Ok(data.len())
+    }
+
+    fn append(&self, path: &str, data: &[u8]) -> FsResult<usize> {
+        let mut volume = self.volume.lock();
+        let (ino, mut inode) = volume.file(path)?;
+        let offset = inode.size();
+        volume.write_data(ino, &mut inode, offset, data)?;
+        Ok(data.len())
+    }
+
+    fn truncate(&self, path: &str, len: usize) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let (ino, mut inode) = volume.file(path)?;
+        volume.resize(ino, &mut inode, len as u64)
+    }
+
+    fn symlink(&self, target: &str, path: &str) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let (_, ino, mut inode) = volume.create(path, S_IFLNK | 0o777)?;
+        if target.len() < FAST_SYMLINK_MAX {
+            inode.raw[40..40 + target.len()].copy_from_slice(target.as_bytes());
+            inode.set_size(target.len() as u64);
+            volume.write_inode(ino, &inode)
+        } else {
+            volume.write_data(ino, &mut inode, 0, target.as_bytes())
+        }
+    }
+
+    fn readlink(&self, path: &str) -> FsResult<String> {
+        let mut volume = self.volume.lock();
+        let ino = volume.lookup(path)?;
+        let mut inode = volume.read_inode(ino)?;
+        if inode.kind() != S_IFLNK {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let size = inode.size() as usize;
+        let target = if inode.is_fast_symlink(volume.block_size) {
+            inode
+                .raw
+                .get(40..40 + size)
+                .ok_or(FsError::InvalidData)?
+                .to_vec()
+        } else {
+            let mut data = vec![0u8; size];
+            volume.read_data(ino, &mut inode, 0, &mut data)?;
+            data
+        };
+        String::from_utf8(target).map_err(|_| FsError::InvalidData)
+    }
+
+    fn link(&self, existing: &str, new: &str) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let ino = volume.lookup(existing)?;
+        let mut inode = volume.read_inode(ino)?;
+        if inode.is_dir() {
+            return Err(FsError::PermissionDenied);
+        }
+
+        let (dir, name) = volume.lookup_parent(new)?;
+        if volume.find(dir, &name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        volume.add_entry(dir, &name, ino, inode.dir_entry_type())?;
+        inode.set_links(inode.links() + 1);
+        inode.touch(volume.now);
+        volume.write_inode(ino, &inode)
+    }
+}
@@ -0,0 +1,1104 @@
+use super::vfs::{FileSystem, FileType, FsError, FsResult, INode, VFS};
+use crate::drivers::block::{self, BlockDevice, BlockRef};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+// FAT32 entries are 28 bits wide; the top nibble is reserved
+const FAT_MASK: u32 = 0x0FFF_FFFF;
+const FAT_EOC: u32 = 0x0FFF_FFF8;
+const FAT_BAD: u32 = 0x0FFF_FFF7;
+
+const ENTRY_SIZE: usize = 32;
+const DELETED: u8 = 0xE5;
+// a short name starting with 0xE5 is stored as 0x05
+const KANJI_E5: u8 = 0x05;
+// NT case flags in byte 12 of a short entry
+const LOWER_BASE: u8 = 0x08;
+const LOWER_EXT: u8 = 0x10;
+
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+// byte offsets of the 13 UCS-2 characters in a long-name slot
+const LFN_POSITIONS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_NAME: usize = 255;
+
+const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIG: u32 = 0x6141_7272;
+const FSINFO_TRAIL_SIG: u32 = 0xAA55_0000;
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+// there is no RTC driver; stamp everything 1980-01-01
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+const ROOT_INO: u64 = 1;
+
+/// A FAT32 volume on a block device.
+pub struct Fat32 {
+    volume: Mutex<Volume>,
+}
+
+struct Volume {
+    dev: BlockRef,
+    bytes_per_sector: u64,
+    sectors_per_cluster: u64,
+    reserved_sectors: u64,
+    fat_count: u64,
+    fat_sectors: u64,
+    data_start: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+    fsinfo_sector: u64,
+    free_count: u32,
+    next_free: u32,
+}
+
+/// A directory entry: its long (or short) name plus the slots it occupies.
+#[derive(Clone)]
+struct DirEntry {
+    name: String,
+    short: [u8; 11],
+    attr: u8,
+    cluster: u32,
+    size: u32,
+    // index of the first long-name slot and of the short entry in the directory
+    first_slot: usize,
+    slot: usize,
+    // byte offset of the short entry on disk
+    offset: u64,
+}
+
+impl DirEntry {
+    fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || short_display(&self.short).eq_ignore_ascii_case(name)
+    }
+}
+
+fn le16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn le32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+fn put16(b: &mut [u8], at: usize, value: u16) {
+    b[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32(b: &mut [u8], at: usize, value: u32) {
+    b[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn components(path: &str) -> Vec<String> {
+    VFS::normalize_path(path)
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+// ---- names ----
+
+/// "README  TXT" -> "README.TXT", honouring the NT lowercase flags.
+fn short_display(short: &[u8; 11]) -> String {
+    short_display_with(short, 0)
+}
+
+fn short_display_with(short: &[u8; 11], case: u8) -> String {
+    let decode = |bytes: &[u8], lower: bool| -> String {
+        let mut s = String::new();
+        for (i, &b) in bytes.iter().enumerate() {
+            let b = if i == 0 && b == KANJI_E5 { DELETED } else { b };
+            let c = b as char;
+            s.push(if lower { c.to_ascii_lowercase() } else { c });
+        }
+        String::from(s.trim_end())
+    };
+    let base = decode(&short[..8], case & LOWER_BASE != 0);
+    let ext = decode(&short[8..], case & LOWER_EXT != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        base + "." + &ext
+    }
+}
+
+fn short_checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+fn is_short_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || "$%'-_@~`!(){}^#&".contains(c)
+}
+
+fn check_name(name: &str) -> FsResult<()> {
+    let invalid = |c: char| c.is_control() || "\"*/:<>?\\|".contains(c);
+    if name.is_empty() || name.encode_utf16().count() > MAX_NAME || name.chars().any(invalid) {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(())
+}
+
+/// The 8.3 form of `name` if it fits one exactly, so no long name is needed.
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    let valid = |part: &str, max: usize| part.len() <= max && part.chars().all(is_short_char);
+    if base.is_empty()
+        || !valid(base, 8)
+        || !valid(ext, 3)
+        || (name.contains('.') && ext.is_empty())
+    {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short)
+}
+
+/// Builds a `BASIS~N` alias for a long name that is unique among `taken`.
+fn alias_short_name(name: &str, taken: &[[u8; 11]]) -> FsResult<[u8; 11]> {
+    let to_short = |part: &str, max: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| c.to_ascii_uppercase())
+            .map(|c| if is_short_char(c) { c as u8 } else { b'_' })
+            .take(max)
+            .collect()
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(dot) => (
+            to_short(&trimmed[..dot], 8),
+            to_short(&trimmed[dot + 1..], 3),
+        ),
+        None => (to_short(trimmed, 8), Vec::new()),
+    };
+    let base = if base.is_empty() { vec![b'_'] } else { base };
+
+    for n in 1..1_000_000u32 {
+        let tail = alloc::format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+        if !taken.contains(&short) {
+            return Ok(short);
+        }
+    }
+    Err(FsError::NoSpace)
+}
+
+/// The raw 32-byte slots for `name`: long-name entries (last part first) then the short entry.
+fn encode_entry(
+    name: &str,
+    short: [u8; 11],
+    needs_long: bool,
+    attr: u8,
+    cluster: u32,
+    size: u32,
+) -> Vec<u8> {
+    let mut slots = Vec::new();
+
+    if needs_long {
+        let units: Vec<u16> = name.encode_utf16().collect();
+        let parts = units.len().div_ceil(LFN_CHARS);
+        let checksum = short_checksum(&short);
+        for ord in (1..=parts).rev() {
+            let mut slot = [0u8; ENTRY_SIZE];
+            slot[0] = ord as u8 | if ord == parts { LFN_LAST } else { 0 };
+            slot[11] = ATTR_LONG_NAME;
+            slot[13] = checksum;
+            // the name is NUL-terminated, then padded with 0xFFFF
+            let start = (ord - 1) * LFN_CHARS;
+            for (i, &at) in LFN_POSITIONS.iter().enumerate() {
+                let unit = match (start + i).cmp(&units.len()) {
+                    core::cmp::Ordering::Less => units[start + i],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xFFFF,
+                };
+                put16(&mut slot, at, unit);
+            }
+            slots.extend_from_slice(&slot);
+        }
+    }
+
+    let mut slot = [0u8; ENTRY_SIZE];
+    slot[..11].copy_from_slice(&short);
+    if slot[0] == DELETED {
+        slot[0] = KANJI_E5;
+    }
+    slot[11] = attr;
+    put16(&mut slot, 16, DEFAULT_DATE); // creation date
+    put16(&mut slot, 18, DEFAULT_DATE); // last access date
+    put16(&mut slot, 20, (cluster >> 16) as u16);
+    put16(&mut slot, 24, DEFAULT_DATE); // write date
+    put16(&mut slot, 26, cluster as u16);
+    put32(&mut slot, 28, size);
+    slots.extend_from_slice(&slot);
+    slots
+}
+
+/// Collects the entries of a directory from its raw contents.
+fn parse_dir(data: &[u8], offsets: impl Fn(usize) -> u64) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    let mut long: Vec<u16> = Vec::new();
+    let mut long_start = 0;
+    let mut long_checksum = 0;
+    // the ordinal the next long-name slot must have; 0 once the name is complete
+    let mut long_next = 0u8;
+
+    for (slot, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+        match raw[0] {
+            0 => break,
+            DELETED => {
+                long.clear();
+                continue;
+            }
+            _ => {}
+        }
+
+        let attr = raw[11];
+        if attr & 0x3F == ATTR_LONG_NAME {
+            let ord = raw[0] & 0x1F;
+            if raw[0] & LFN_LAST != 0 && ord != 0 {
+                long = vec![0xFFFF; ord as usize * LFN_CHARS];
+                long_start = slot;
+                long_checksum = raw[13];
+            } else if long.is_empty() || ord != long_next || raw[13] != long_checksum {
+                // an orphaned fragment: ignore the whole sequence
+                long.clear();
+                continue;
+            }
+            let start = (ord as usize - 1) * LFN_CHARS;
+            for (i, &at) in LFN_POSITIONS.iter().enumerate() {
+                long[start + i] = le16(raw, at);
+            }
+            long_next = ord - 1;
+            continue;
+        }
+        if attr & ATTR_VOLUME_ID != 0 {
+            long.clear();
+            continue;
+        }
+
+        let mut short = [0u8; 11];
+        short.copy_from_slice(&raw[..11]);
+        let has_long =
+            long_next == 0 && !long.is_empty() && long_checksum == short_checksum(&short);
+        let name = if has_long {
+            let end = long
+                .iter()
+                .position(|&u| u == 0 || u == 0xFFFF)
+                .unwrap_or(long.len());
+            String::from_utf16_lossy(&long[..end])
+        } else {
+            short_display_with(&short, raw[12])
+        };
+        entries.push(DirEntry {
+            name,
+            short,
+            attr,
+            cluster: (le16(raw, 20) as u32) << 16 | le16(raw, 26) as u32,
+            size: le32(raw, 28),
+            first_slot: if has_long { long_start } else { slot },
+            slot,
+            offset: offsets(slot),
+        });
+        long.clear();
+    }
+    entries
+}
+
+impl Volume {
+    fn cluster_size(&self) -> usize {
+        (self.sectors_per_cluster * self.bytes_per_sector) as usize
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        (self.data_start + (cluster as u64 - 2) * self.sectors_per_cluster) * self.bytes_per_sector
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> FsResult<()> {
+        Ok(block::read_bytes(&*self.dev, offset, buf)?)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> FsResult<()> {
+        Ok(block::write_bytes(&*self.dev, offset, data)?)
+    }
+
+    fn valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2
+    }
+
+    // ---- the allocation table ----
+
+    fn fat_offset(&self, copy: u64, cluster: u32) -> u64 {
+        (self.reserved_sectors + copy * self.fat_sectors) * self.bytes_per_sector
+            + cluster as u64 * 4
+    }
+
+    fn fat_get(&self, cluster: u32) -> FsResult<u32> {
+        let mut raw = [0u8; 4];
+        self.read(self.fat_offset(0, cluster), &mut raw)?;
+        Ok(u32::from_le_bytes(raw) & FAT_MASK)
+    }
+
+    // every copy is kept in sync; the reserved top nibble is preserved
+    fn fat_set(&self, cluster: u32, value: u32) -> FsResult<()> {
+        for copy in 0..self.fat_count {
+            let offset = self.fat_offset(copy, cluster);
+            let mut raw = [0u8; 4];
+            self.read(offset, &mut raw)?;
+            let old = u32::from_le_bytes(raw);
+            self.write(
+                offset,
+                &((old & !FAT_MASK) | (value & FAT_MASK)).to_le_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The clusters of the chain starting at `first`, in order.
+    fn chain(&self, first: u32) -> FsResult<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 {
+            if !self.valid_cluster(cluster) || chain.len() > self.cluster_count as usize {
+                return Err(FsError::InvalidData);
+            }
+            chain.push(cluster);
+            match self.fat_get(cluster)? {
+                next if next >= FAT_EOC => break,
+                FAT_BAD => return Err(FsError::InvalidData),
+                next => cluster = next,
+            }
+        }
+        Ok(chain)
+    }
+
+    fn find_free(&self) -> FsResult<u32> {
+        let per_sector = (self.bytes_per_sector / 4) as u32;
+        let mut sector = vec![0u8; self.bytes_per_sector as usize];
+        let mut loaded = None;
+        let start = if self.valid_cluster(self.next_free) {
+            self.next_free
+        } else {
+            2
+        };
+
+        for i in 0..self.cluster_count {
+            let cluster = 2 + (start - 2 + i) % self.cluster_count;
+            let index = cluster / per_sector;
+            if loaded != Some(index) {
+                let offset = self.fat_offset(0, index * per_sector);
+                self.read(offset, &mut sector)?;
+                loaded = Some(index);
+            }
+            let at = (cluster % per_sector) as usize * 4;
+            if le32(&sector, at) & FAT_MASK == 0 {
+                return Ok(cluster);
+            }
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// Allocates a zeroed cluster and links it after `prev`.
+    fn alloc_cluster(&mut self, prev: Option<u32>) -> FsResult<u32> {
+        let cluster = self.find_free()?;
+        self.fat_set(cluster, FAT_MASK)?;
+        self.write(
+            self.cluster_offset(cluster),
+            &vec![0u8; self.cluster_size()],
+        )?;
+        if let Some(prev) = prev {
+            self.fat_set(prev, cluster)?;
+        }
+        self.next_free = cluster + 1;
+        if self.free_count != FSINFO_UNKNOWN {
+            self.free_count = self.free_count.saturating_sub(1);
+        }
+        self.write_fsinfo()?;
+        Ok(cluster)
+    }
+
+    fn free_chain(&mut self, first: u32) -> FsResult<()> {
+        let chain = self.chain(first)?;
+        for &cluster in &chain {
+            self.fat_set(cluster, 0)?;
+        }
+        if self.free_count != FSINFO_UNKNOWN {
+            self.free_count += chain.len() as u32;
+        }
+        self.write_fsinfo()
+    }
+
+    fn write_fsinfo(&self) -> FsResult<()> {
+        if self.fsinfo_sector == 0 {
+            return Ok(());
+        }
+        let offset = self.fsinfo_sector * self.bytes_per_sector;
+        let mut raw = [0u8; 8];
+        put32(&mut raw, 0, self.free_count);
+        put32(&mut raw, 4, self.next_free);
+        self.write(offset + 488, &raw)
+    }
+
+    // ---- directories ----
+
+    /// The cluster chain and raw contents of the directory at `cluster`.
+    fn dir_data(&self, cluster: u32) -> FsResult<(Vec<u32>, Vec<u8>)> {
+        let chain = self.chain(cluster)?;
+        let cluster_size = self.cluster_size();
+        let mut data = vec![0u8; chain.len() * cluster_size];
+        for (i, &c) in chain.iter().enumerate() {
+            self.read(
+                self.cluster_offset(c),
+                &mut data[i * cluster_size..(i + 1) * cluster_size],
+            )?;
+        }
+        Ok((chain, data))
+    }
+
+    fn read_dir(&self, cluster: u32) -> FsResult<(Vec<u32>, Vec<DirEntry>)> {
+        let (chain, data) = self.dir_data(cluster)?;
+        let entries = parse_dir(&data, |slot| self.slot_offset(&chain, slot));
+        Ok((chain, entries))
+    }
+
+    fn slot_offset(&self, chain: &[u32], slot: usize) -> u64 {
+        let byte = slot * ENTRY_SIZE;
+        let cluster_size = self.cluster_size();
+        self.cluster_offset(chain[byte / cluster_size]) + (byte % cluster_size) as u64
+    }
+
+    fn find(&self, dir: u32, name: &str) -> FsResult<Option<DirEntry>> {
+        let (_, entries) = self.read_dir(dir)?;
+        Ok(entries.into_iter().find(|e| e.matches(name)))
+    }
+
+    /// Resolves `path` to its entry; `None` is the root directory.
+    fn lookup(&self, path: &str) -> FsResult<Option<DirEntry>> {
+        let mut dir = self.root_cluster;
+        let mut found: Option<DirEntry> = None;
+        for name in components(path) {
+            if let Some(entry) = &found {
+                if !entry.is_dir() {
+                    return Err(FsError::NotADirectory);
+                }
+                dir = self.dir_cluster(entry);
+            }
+            found = Some(self.find(dir, &name)?.ok_or(FsError::NotFound)?);
+        }
+        Ok(found)
+    }
+
+    /// The parent directory's cluster and the final name of `path`.
+    fn lookup_parent(&self, path: &str) -> FsResult<(u32, String)> {
+        let parent = VFS::parent_path(path).ok_or(FsError::PermissionDenied)?;
+        let name = VFS::filename(path).ok_or(FsError::InvalidPath)?;
+        match self.lookup(&parent)? {
+            None => Ok((self.root_cluster, name)),
+            Some(entry) if entry.is_dir() => Ok((self.dir_cluster(&entry), name)),
+            Some(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    // ".." entries pointing at the root store cluster 0
+    fn dir_cluster(&self, entry: &DirEntry) -> u32 {
+        if entry.cluster == 0 {
+            self.root_cluster
+        } else {
+            entry.cluster
+        }
+    }
+
+    /// Writes `name` into `dir`, reusing free slots or growing the directory.
+    fn insert(&mut self, dir: u32, name: &str, attr: u8, cluster: u32, size: u32) -> FsResult<()> {
+        check_name(name)?;
+        let (mut chain, data) = self.dir_data(dir)?;
+        let entries = parse_dir(&data, |slot| self.slot_offset(&chain, slot));
+        if entries.iter().any(|e| e.matches(name)) {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let (short, needs_long) = match exact_short_name(name) {
+            Some(short) => (short, false),
+            None => {
+                let taken: Vec<[u8; 11]> = entries.iter().map(|e| e.short).collect();
+                (alias_short_name(name, &taken)?, true)
+            }
+        };
+        let slots = encode_entry(name, short, needs_long, attr, cluster, size);
+        let needed = slots.len() / ENTRY_SIZE;
+
+        // the first run of free slots; slots past the end of the data are free too
+        let mut run = 0;
+        let mut slot = 0;
+        while run < needed {
+            match data.get(slot * ENTRY_SIZE) {
+                Some(&b) if b != 0 && b != DELETED => run = 0,
+                _ => run += 1,
+            }
+            slot += 1;
+        }
+        let start = slot - needed;
+
+        // grow the directory when the run reaches past its last cluster
+        let per_cluster = self.cluster_size() / ENTRY_SIZE;
+        while chain.len() * per_cluster < slot {
+            let last = *chain.last().ok_or(FsError::InvalidData)?;
+            chain.push(self.alloc_cluster(Some(last))?);
+        }
+        for (i, raw) in slots.chunks(ENTRY_SIZE).enumerate() {
+            self.write(self.slot_offset(&chain, start + i), raw)?;
+        }
+        Ok(())
+    }
+
+    /// Marks the slots of `entry` in `dir` as deleted; its clusters are left alone.
+    fn erase(&self, dir: u32, entry: &DirEntry) -> FsResult<()> {
+        let chain = self.chain(dir)?;
+        for slot in entry.first_slot..=entry.slot {
+            self.write(self.slot_offset(&chain, slot), &[DELETED])?;
+        }
+        Ok(())
+    }
+
+    /// Stores the first cluster and size of `entry` back into its short entry.
+    fn update_entry(&self, entry: &DirEntry) -> FsResult<()> {
+        // bytes 22..26 in between hold the write time and date
+        self.write(
+            entry.offset + 20,
+            &((entry.cluster >> 16) as u16).to_le_bytes(),
+        )?;
+        let mut raw = [0u8; 6];
+        put16(&mut raw, 0, entry.cluster as u16);
+        put32(&mut raw, 2, entry.size);
+        self.write(entry.offset + 26, &raw)
+    }
+
+    fn is_empty_dir(&self, entry: &DirEntry) -> FsResult<bool> {
+        let (_, entries) = self.read_dir(self.dir_cluster(entry))?;
+        Ok(entries.iter().all(|e| e.name == "." || e.name == ".."))
+    }
+
+    // ---- file contents ----
+
+    fn read_data(&self, entry: &DirEntry, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let size = entry.size as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        let cluster_size = self.cluster_size();
+        let chain = self.chain(entry.cluster)?;
+
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let cluster = *chain.get(pos / cluster_size).ok_or(FsError::InvalidData)?;
+            let within = pos % cluster_size;
+            let count = (cluster_size - within).min(len - done);
+            self.read(
+                self.cluster_offset(cluster) + within as u64,
+                &mut buf[done..done + count],
+            )?;
+            done += count;
+        }
+        Ok(len)
+    }
+
+    fn write_data(&mut self, entry: &mut DirEntry, offset: usize, data: &[u8]) -> FsResult<()> {
+        let end = offset + data.len();
+        if end > u32::MAX as usize {
+            return Err(FsError::NoSpace);
+        }
+        let cluster_size = self.cluster_size();
+        let mut chain = self.chain(entry.cluster)?;
+        while chain.len() * cluster_size < end {
+            let cluster = self.alloc_cluster(chain.last().copied())?;
+            if chain.is_empty() {
+                entry.cluster = cluster;
+            }
+            chain.push(cluster);
+        }
+
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done;
+            let cluster = chain[pos / cluster_size];
+            let within = pos % cluster_size;
+            let count = (cluster_size - within).min(data.len() - done);
+            self.write(
+                self.cluster_offset(cluster) + within as u64,
+                &data[done..done + count],
+            )?;
+            done += count;
+        }
+
+        entry.size = entry.size.max(end as u32);
+        self.update_entry(entry)
+    }
+
+    /// Grows (zero-filled) or shrinks the file to `len` bytes.
+    fn resize(&mut self, entry: &mut DirEntry, len: usize) -> FsResult<()> {
+        let size = entry.size as usize;
+        if len > size {
+            // clusters past the old size may hold stale data, so write real zeros
+            let zeros = vec![0u8; self.cluster_size()];
+            let mut pos = size;
+            while pos < len {
+                let count = zeros.len().min(len - pos);
+                self.write_data(entry, pos, &zeros[..count])?;
+                pos += count;
+            }
+            return Ok(());
+        }
+
+        let keep = len.div_ceil(self.cluster_size());
+        let chain = self.chain(entry.cluster)?;
+        if keep == 0 {
+            if entry.cluster != 0 {
+                self.free_chain(entry.cluster)?;
+            }
+            entry.cluster = 0;
+        } else if keep < chain.len() {
+            self.fat_set(chain[keep - 1], FAT_MASK)?;
+            self.free_chain(chain[keep])?;
+        }
+        entry.size = len as u32;
+        self.update_entry(entry)
+    }
+
+    fn file_entry(&self, path: &str) -> FsResult<DirEntry> {
+        match self.lookup(path)? {
+            Some(entry) if !entry.is_dir() => Ok(entry),
+            _ => Err(FsError::NotAFile),
+        }
+    }
+
+    fn inode(&self, entry: Option<&DirEntry>) -> INode {
+        match entry {
+            None => INode {
+                name: String::from("/"),
+                ino: ROOT_INO,
+                file_type: FileType::Directory,
+                size: 0,
+                mode: 0o755,
+                uid: 0,
+                gid: 0,
+                nlink: 2,
+            },
+            Some(entry) => {
+                // FAT has no owners or permission bits, only a read-only flag
+                let mode = if entry.is_dir() {
+                    0o755
+                } else if entry.attr & ATTR_READ_ONLY != 0 {
+                    0o444
+                } else {
+                    0o644
+                };
+                INode {
+                    name: entry.name.clone(),
+                    // the position of the short entry is unique on the volume
+                    ino: entry.offset / ENTRY_SIZE as u64,
+                    file_type: if entry.is_dir() {
+                        FileType::Directory
+                    } else {
+                        FileType::File
+                    },
+                    size: entry.size as usize,
+                    mode,
+                    uid: 0,
+                    gid: 0,
+                    nlink: if entry.is_dir() { 2 } else { 1 },
+                }
+            }
+        }
+    }
+}
+
+impl Fat32 {
+    /// Mounts the FAT32 volume on `dev`, checking its boot sector.
+    pub fn new(dev: BlockRef) -> FsResult<Self> {
+        let mut boot = [0u8; 512];
+        block::read_bytes(&*dev, 0, &mut boot)?;
+        if boot[510] != 0x55 || boot[511] != 0xAA {
+            return Err(FsError::InvalidData);
+        }
+
+        let bytes_per_sector = le16(&boot, 11) as u64;
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved_sectors = le16(&boot, 14) as u64;
+        let fat_count = boot[16] as u64;
+        let root_entries = le16(&boot, 17);
+        let total_sectors = match le16(&boot, 19) {
+            0 => le32(&boot, 32) as u64,
+            n => n as u64,
+        };
+        let fat16_sectors = le16(&boot, 22);
+        let fat_sectors = le32(&boot, 36) as u64;
+
+        // FAT12/16 keep a fixed root directory and a 16-bit FAT size
+        let fat32 = root_entries == 0 && fat16_sectors == 0 && fat_sectors != 0;
+        if !fat32
+            || ![512, 1024, 2048, 4096].contains(&bytes_per_sector)
+            || !sectors_per_cluster.is_power_of_two()
+            || fat_count == 0
+            || reserved_sectors == 0
+        {
+            return Err(FsError::InvalidData);
+        }
+
+        let data_start = reserved_sectors + fat_count * fat_sectors;
+        if total_sectors <= data_start
+            || total_sectors * bytes_per_sector > dev.block_count() * dev.block_size() as u64
+        {
+            return Err(FsError::InvalidData);
+        }
+        // the FAT may cover fewer clusters than the data area holds
+        let cluster_count = ((total_sectors - data_start) / sectors_per_cluster)
+            .min(fat_sectors * bytes_per_sector / 4 - 2) as u32;
+
+        let mut volume = Volume {
+            dev,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            fat_count,
+            fat_sectors,
+            data_start,
+            cluster_count,
+            root_cluster: le32(&boot, 44),
+            fsinfo_sector: le16(&boot, 48) as u64,
+            free_count: FSINFO_UNKNOWN,
+            next_free: 2,
+        };
+        if !volume.valid_cluster(volume.root_cluster) {
+            return Err(FsError::InvalidData);
+        }
+
+        // FSInfo only holds hints; ignore it if it doesn't look right
+        if volume.fsinfo_sector != 0 && volume.fsinfo_sector < reserved_sectors {
+            let mut info = vec![0u8; bytes_per_sector as usize];
+            volume.read(volume.fsinfo_sector * bytes_per_sector, &mut info)?;
+            if le32(&info, 0) == FSINFO_LEAD_SIG && le32(&info, 484) == FSINFO_STRUCT_SIG {
+                volume.free_count = le32(&info, 488);
+                volume.next_free = le32(&info, 492);
+            } else {
+                volume.fsinfo_sector = 0;
+            }
+        } else {
+            volume.fsinfo_sector = 0;
+        }
+
+        Ok(Fat32 {
+            volume: Mutex::new(volume),
+        })
+    }
+
+    /// Writes an empty FAT32 filesystem over the whole of `dev`.
+    pub fn format(dev: &dyn BlockDevice, label: &str) -> FsResult<()> {
+        const SECTOR: u64 = 512;
+        const RESERVED: u64 = 32;
+        const FATS: u64 = 2;
+
+        let total = dev.block_count() * dev.block_size() as u64 / SECTOR;
+        if total < RESERVED + 64 {
+            return Err(FsError::NoSpace);
+        }
+        let sectors_per_cluster: u64 = match total * SECTOR {
+            n if n <= 260 << 20 => 1,
+            n if n <= 8 << 30 => 8,
+            n if n <= 16 << 30 => 16,
+            n if n <= 32 << 30 => 32,
+            _ => 64,
+        };
+        // the sizing formula from Microsoft's FAT specification
+        let per_fat_sector = (256 * sectors_per_cluster + FATS) / 2;
+        let fat_sectors = (total - RESERVED).div_ceil(per_fat_sector);
+        if total > u32::MAX as u64
+            || total < RESERVED + FATS * fat_sectors + 2 * sectors_per_cluster
+        {
+            return Err(FsError::NoSpace);
+        }
+        let data_start = RESERVED + FATS * fat_sectors;
+        let clusters = ((total - data_start) / sectors_per_cluster) as u32;
+
+        let mut boot = [0u8; SECTOR as usize];
+        boot[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        boot[3..11].copy_from_slice(b"ZEROOS  ");
+        put16(&mut boot, 11, SECTOR as u16);
+        boot[13] = sectors_per_cluster as u8;
+        put16(&mut boot, 14, RESERVED as u16);
+        boot[16] = FATS as u8;
+        boot[21] = 0xF8; // fixed disk
+        put16(&mut boot, 24, 63); // sectors per track
+        put16(&mut boot, 26, 255); // heads
+        put32(&mut boot, 32, total as u32);
+        put32(&mut boot, 36, fat_sectors as u32);
+        put32(&mut boot, 44, 2); // root directory cluster
+        put16(&mut boot, 48, 1); // FSInfo sector
+        put16(&mut boot, 50, 6); // backup boot sector
+        boot[64] = 0x80;
+        boot[66] = 0x29;
+        put32(&mut boot, 67, unsafe { core::arch::x86_64::_rdtsc() }
+            as u32);
+        let mut volume_label = [b' '; 11];
+        for (dst, c) in volume_label.iter_mut().zip(label.bytes()) {
+            *dst = c.to_ascii_uppercase();
+        }
+        boot[71..82].copy_from_slice(&volume_label);
+        boot[82..90].copy_from_slice(b"FAT32   ");
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+
+        let mut info = [0u8; SECTOR as usize];
+        put32(&mut info, 0, FSINFO_LEAD_SIG);
+        put32(&mut info, 484, FSINFO_STRUCT_SIG);
+        put32(&mut info, 488, clusters - 1);
+        put32(&mut info, 492, 3);
+        put32(&mut info, 508, FSINFO_TRAIL_SIG);
+
+        // clear the reserved area, both FATs and the root cluster
+        let zeros = vec![0u8; (SECTOR * sectors_per_cluster).max(4096) as usize];
+        let clear_end = (data_start + sectors_per_cluster) * SECTOR;
+        let mut pos = 0;
+        while pos < clear_end {
+            let count = (zeros.len() as u64).min(clear_end - pos) as usize;
+            block::write_bytes(dev, pos, &zeros[..count])?;
+            pos += count as u64;
+        }
+
+        for base in [0, 6] {
+            block::write_bytes(dev, base * SECTOR, &boot)?;
+            block::write_bytes(dev, (base + 1) * SECTOR, &info)?;
+        }
+
+        // entries 0 and 1 are reserved; cluster 2 is the root directory
+        let mut fat_start = [0u8; 12];
+        put32(&mut fat_start, 0, FAT_EOC); // 0x0FFFFF00 | media byte 0xF8
+        put32(&mut fat_start, 4, FAT_MASK);
+        put32(&mut fat_start, 8, FAT_MASK);
+        for copy in 0..FATS {
+            block::write_bytes(dev, (RESERVED + copy * fat_sectors) * SECTOR, &fat_start)?;
+        }
+        Ok(dev.flush()?)
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn create_file(&self, path: &str) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let (dir, name) = volume.lookup_parent(path)?;
+        volume.insert(dir, &name, ATTR_ARCHIVE, 0, 0)
+    }
+
+    fn create_dir(&self, path: &str) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let (dir, name) = volume.lookup_parent(path)?;
+        check_name(&name)?;
+        if volume.find(dir, &name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let cluster = volume.alloc_cluster(None)?;
+        let parent = if dir == volume.root_cluster { 0 } else { dir };
+        let mut dots = encode_entry(".", *b".          ", false, ATTR_DIRECTORY, cluster, 0);
+        dots.extend(encode_entry(
+            "..",
+            *b"..         ",
+            false,
+            ATTR_DIRECTORY,
+            parent,
+            0,
+        ));
+        volume.write(volume.cluster_offset(cluster), &dots)?;
+
+        if let Err(e) = volume.insert(dir, &name, ATTR_DIRECTORY, cluster, 0) {
+            volume.free_chain(cluster)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn remove(&self, path: &str) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let (dir, _) = volume.lookup_parent(path)?;
+        let entry = volume.lookup(path)?.ok_or(FsError::PermissionDenied)?;
+        if entry.is_dir() && !volume.is_empty_dir(&entry)? {
+            return Err(FsError::PermissionDenied);
+        }
+
+        volume.erase(dir, &entry)?;
+        if entry.cluster != 0 {
+            volume.free_chain(entry.cluster)?;
+        }
+        Ok(())
+    }
+
+    fn read_file(&self, path: &str) -> FsResult<Vec<u8>> {
+        let volume = self.volume.lock();
+        let entry = volume.file_entry(path)?;
+        let mut data = vec![0u8; entry.size as usize];
+        volume.read_data(&entry, 0, &mut data)?;
+        Ok(data)
+    }
+
+    fn write_file(&self, path: &str, data: &[u8]) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let mut entry = volume.file_entry(path)?;
+        volume.resize(&mut entry, 0)?;
+        volume.write_data(&mut entry, 0, data)
+    }
+
+    fn list_dir(&self, path: &str) -> FsResult<Vec<INode>> {
+        let volume = self.volume.lock();
+        let dir = match volume.lookup(path)? {
+            None => volume.root_cluster,
+            Some(entry) if entry.is_dir() => volume.dir_cluster(&entry),
+            Some(_) => return Err(FsError::NotADirectory),
+        };
+        let (_, entries) = volume.read_dir(dir)?;
+        Ok(entries
+            .iter()
+            .filter(|e| e.name != "." && e.name != "..")
+            .map(|e| volume.inode(Some(e)))
+            .collect())
+    }
+
+    fn stat(&self, path: &str) -> FsResult<INode> {
+        let volume = self.volume.lock();
+        let entry = volume.lookup(path)?;
+        Ok(volume.inode(entry.as_ref()))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.stat(path).is_ok()
+    }
+
+    // only the owner write bit maps onto FAT's read-only attribute
+    fn chmod(&self, path: &str, mode: u16) -> FsResult<()> {
+        let volume = self.volume.lock();
+        let entry = volume.lookup(path)?.ok_or(FsError::NotSupported)?;
+        let attr = if mode & 0o200 == 0 {
+            entry.attr | ATTR_READ_ONLY
+        } else {
+            entry.attr & !ATTR_READ_ONLY
+        };
+        volume.write(entry.offset + 11, &[attr])
+    }
+
+    fn chown(&self, _path: &str, _uid: u32, _gid: u32) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> FsResult<()> {
+        let from = VFS::normalize_path(from);
+        let to = VFS::normalize_path(to);
+        if from == "/" || to == "/" {
+            return Err(FsError::PermissionDenied);
+        }
+        if from == to {
+            return Ok(());
+        }
+        if VFS::is_within(&to, &from) {
+            return Err(FsError::InvalidPath);
+        }
+
+        let mut volume = self.volume.lock();
+        let (from_dir, _) = volume.lookup_parent(&from)?;
+        let (to_dir, to_name) = volume.lookup_parent(&to)?;
+        let entry = volume.lookup(&from)?.ok_or(FsError::NotFound)?;
+
+        if let Some(target) = volume.find(to_dir, &to_name)? {
+            // a case-only rename finds the source itself
+            if target.offset != entry.offset {
+                match (entry.is_dir(), target.is_dir()) {
+                    (true, true) if !volume.is_empty_dir(&target)? => {
+                        return Err(FsError::PermissionDenied)
+                    }
+                    (true, false) => return Err(FsError::NotADirectory),
+                    (false, true) => return Err(FsError::NotAFile),
+                    _ => {}
+                }
+                volume.erase(to_dir, &target)?;
+                if target.cluster != 0 {
+                    volume.free_chain(target.cluster)?;
+                }
+            } else {
+                volume.erase(from_dir, &entry)?;
+                return volume.insert(to_dir, &to_name, entry.attr, entry.cluster, entry.size);
+            }
+        }
+
+        volume.insert(to_dir, &to_name, entry.attr, entry.cluster, entry.size)?;
+        volume.erase(from_dir, &entry)?;
+
+        // a moved directory's ".." must follow it
+        if entry.is_dir() && from_dir != to_dir {
+            let (_, children) = volume.read_dir(entry.cluster)?;
+            if let Some(mut dotdot) = children.into_iter().find(|e| e.name == "..") {
+                dotdot.cluster = if to_dir == volume.root_cluster {
+                    0
+                } else {
+                    to_dir
+                };
+                volume.update_entry(&dotdot)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_at(&self, path: &str, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let volume = self.volume.lock();
+        let entry = volume.file_entry(path)?;
+        volume.read_data(&entry, offset, buf)
+    }
+
+    fn write_at(&self, path: &str, offset: usize, data: &[u8]) -> FsResult<usize> {
+        let mut volume = self.volume.lock();
+        let mut entry = volume.file_entry(path)?;
+        if offset > entry.size as usize {
+            volume.resize(&mut entry, offset)?;
+        }
+        volume.write_data(&mut entry, offset, data)?;
+        Ok(data.len())
+    }
+
+    fn append(&self, path: &str, data: &[u8]) -> FsResult<usize> {
+        let mut volume = self.volume.lock();
+        let mut entry = volume.file_entry(path)?;
+        let offset = entry.size as usize;
+        volume.write_data(&mut entry, offset, data)?;
+        Ok(data.len())
+    }
+
+    fn truncate(&self, path: &str, len: usize) -> FsResult<()> {
+        let mut volume = self.volume.lock();
+        let mut entry = volume.file_entry(path)?;
+        volume.resize(&mut entry, len)
+    }
+}
@@ -0,0 +1,214 @@
+use super::vfs::{FileType, FsError, FsResult, OpenOptions, VFS};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+use spin::Mutex;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// Something other than a VFS file that a descriptor can refer to, such as a socket.
+/// Streams have no offset: reads and writes go straight to the object.
+pub trait Stream: Send + Sync {
+    fn read(&self, buf: &mut [u8]) -> FsResult<usize>;
+    fn write(&self, data: &[u8]) -> FsResult<usize>;
+
+    /// For syscalls that only apply to one kind of stream.
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// An open file: a path in the VFS plus a cursor, or a stream.
+pub struct File {
+    path: String,
+    offset: usize,
+    options: OpenOptions,
+    stream: Option<Arc<dyn Stream>>,
+}
+
+impl File {
+    pub fn open(path: &str, options: OpenOptions) -> FsResult<File> {
+        let fs = super::root().ok_or(FsError::NotFound)?;
+        let path = VFS::normalize_path(path);
+
+        if !fs.exists(&path) {
+            if !options.create {
+                return Err(FsError::NotFound);
+            }
+            fs.create_file(&path)?;
+        }
+        if fs.stat(&path)?.file_type == FileType::Directory {
+            return Err(FsError::NotAFile);
+        }
+        if options.truncate && options.write {
+            fs.truncate(&path, 0)?;
+        }
+
+        Ok(File {
+            path,
+            offset: 0,
+            options,
+            stream: None,
+        })
+    }
+
+    /// Wraps `stream`; `name` stands in for the path, e.g. in `/proc/<pid>/fd`.
+    pub fn from_stream(name: &str, stream: Arc<dyn Stream>) -> File {
+        File {
+            path: name.into(),
+            offset: 0,
+            options: OpenOptions::new().read(true).write(true),
+            stream: Some(stream),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn stream(&self) -> Option<&Arc<dyn Stream>> {
+        self.stream.as_ref()
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> FsResult<usize> {
+        if !self.options.read {
+            return Err(FsError::PermissionDenied);
+        }
+        if let Some(stream) = &self.stream {
+            return stream.read(buf);
+        }
+        let fs = super::root().ok_or(FsError::NotFound)?;
+        let read = fs.read_at(&self.path, self.offset, buf)?;
+        self.offset += read;
+        Ok(read)
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> FsResult<usize> {
+        if !self.options.write {
+            return Err(FsError::PermissionDenied);
+        }
+        if let Some(stream) = &self.stream {
+            return stream.write(data);
+        }
+        let fs = super::root().ok_or(FsError::NotFound)?;
+        let written = if self.options.append {
+            let written = fs.append(&self.path, data)?;
+            self.offset = fs.stat(&self.path)?.size;
+            written
+        } else {
+            let written = fs.write_at(&self.path, self.offset, data)?;
+            self.offset += written;
+            written
+        };
+        Ok(written)
+    }
+
+    pub fn seek(&mut self, pos: SeekFrom) -> FsResult<usize> {
+        if self.stream.is_some() {
+            return Err(FsError::InvalidArgument);
+        }
+        let base = match pos {
+            // offsets past i64::MAX are negative ones to lseek
+            SeekFrom::Start(offset) if offset > i64::MAX as u64 => {
+                return Err(FsError::InvalidArgument);
+            }
+            SeekFrom::Start(offset) => {
+                self.offset = offset as usize;
+                return Ok(self.offset);
+            }
+            SeekFrom::Current(_) => self.offset as i64,
+            SeekFrom::End(_) => {
+                let fs = super::root().ok_or(FsError::NotFound)?;
+                fs.stat(&self.path)?.size as i64
+            }
+        };
+        let delta = match pos {
+            SeekFrom::Current(delta) | SeekFrom::End(delta) => delta,
+            SeekFrom::Start(_) => 0,
+        };
+
+        let offset = base.checked_add(delta).ok_or(FsError::InvalidArgument)?;
+        if offset < 0 {
+            return Err(FsError::InvalidArgument);
+        }
+        self.offset = offset as usize;
+        Ok(self.offset)
+    }
+}
+
+pub type FileRef = Arc<Mutex<File>>;
+
+/// Maps file descriptors to open files.
+///
+/// Entries are shared handles so callers can drop the table's lock before doing I/O.
+pub struct FdTable {
+    files: BTreeMap<usize, FileRef>,
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        FdTable {
+            files: BTreeMap::new(),
+        }
+    }
+
+    fn lowest_free(&self) -> usize {
+        let mut fd = 0;
+        while self.files.contains_key(&fd) {
+            fd += 1;
+        }
+        fd
+    }
+
+    /// Stores `file` under the lowest free descriptor.
+    pub fn insert(&mut self, file: File) -> usize {
+        let fd = self.lowest_free();
+        self.files.insert(fd, Arc::new(Mutex::new(file)));
+        fd
+    }
+
+    /// Makes the lowest free descriptor refer to the same open file as `fd`,
+    /// sharing its offset.
+    pub fn dup(&mut self, fd: usize) -> FsResult<usize> {
+        let file = self.get(fd).ok_or(FsError::BadDescriptor)?;
+        let new_fd = self.lowest_free();
+        self.files.insert(new_fd, file);
+        Ok(new_fd)
+    }
+
+    /// Like `dup`, but into `new_fd`, closing whatever was open there first.
+    pub fn dup2(&mut self, fd: usize, new_fd: usize) -> FsResult<usize> {
+        let file = self.get(fd).ok_or(FsError::BadDescriptor)?;
+        self.files.insert(new_fd, file);
+        Ok(new_fd)
+    }
+
+    pub fn fds(&self) -> impl Iterator<Item = (usize, &FileRef)> {
+        self.files.iter().map(|(&fd, file)| (fd, file))
+    }
+
+    pub fn get(&self, fd: usize) -> Option<FileRef> {
+        self.files.get(&fd).cloned()
+    }
+
+    pub fn close(&mut self, fd: usize) -> FsResult<()> {
+        self.files
+            .remove(&fd)
+            .map(|_| ())
+            .ok_or(FsError::BadDescriptor)
+    }
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
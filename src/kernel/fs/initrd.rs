@@ -0,0 +1,298 @@
+use super::vfs::{FileSystem, FsError, FsResult, VFS};
+use alloc::format;
+use alloc::string::String;
+
+// initial ramdisk support: unpacks a USTAR tar or newc cpio archive into a filesystem
+
+const TAR_BLOCK_SIZE: usize = 512;
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+
+// file type bits of a unix mode (st_mode & S_IFMT)
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Tar,
+    Cpio,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    // tar only: another name for an earlier member
+    HardLink,
+    Other,
+}
+
+struct Entry<'a> {
+    path: String,
+    kind: EntryKind,
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    // file contents, or the link target for symlinks and hard links
+    data: &'a [u8],
+}
+
+pub fn detect(image: &[u8]) -> Option<Format> {
+    if image.len() >= 6 && (&image[..6] == b"070701" || &image[..6] == b"070702") {
+        return Some(Format::Cpio);
+    }
+    if image.len() >= TAR_BLOCK_SIZE && &image[257..262] == b"ustar" {
+        return Some(Format::Tar);
+    }
+    None
+}
+
+/// Unpacks `image` into `fs`, returning the number of files and directories created.
+///
+/// Missing parent directories are created on the way; entries that already exist
+/// (e.g. `/home`) only get their mode and owner updated. Device nodes and other special
+/// entries are skipped.
+pub fn unpack(fs: &dyn FileSystem, image: &[u8]) -> FsResult<usize> {
+    let mut count = 0;
+    let mut install = |entry: Entry| -> FsResult<()> {
+        if install_entry(fs, &entry)? {
+            count += 1;
+        }
+        Ok(())
+    };
+
+    match detect(image) {
+        Some(Format::Tar) => parse_tar(image, &mut install)?,
+        Some(Format::Cpio) => parse_cpio(image, &mut install)?,
+        None => return Err(FsError::InvalidData),
+    }
+    Ok(count)
+}
+
+fn install_entry(fs: &dyn FileSystem, entry: &Entry) -> FsResult<bool> {
+    if entry.path == "/" || entry.kind == EntryKind::Other {
+        return Ok(false);
+    }
+
+    if let Some(parent) = VFS::parent_path(&entry.path) {
+        create_dir_all(fs, &parent)?;
+    }
+
+    let created = match entry.kind {
+        EntryKind::Directory => match fs.create_dir(&entry.path) {
+            Ok(()) => true,
+            Err(FsError::AlreadyExists) => false,
+            Err(e) => return Err(e),
+        },
+        EntryKind::File => {
+            let created = match fs.create_file(&entry.path) {
+                Ok(()) => true,
+                Err(FsError::AlreadyExists) => false,
+                Err(e) => return Err(e),
+            };
+            fs.write_file(&entry.path, entry.data)?;
+            created
+        }
+        EntryKind::Symlink => {
+            let target = core::str::from_utf8(entry.data).map_err(|_| FsError::InvalidData)?;
+            return match fs.symlink(target, &entry.path) {
+                Ok(()) => fs.chown(&entry.path, entry.uid, entry.gid).map(|_| true),
+                Err(FsError::AlreadyExists) => Ok(false),
+                Err(e) => Err(e),
+            };
+        }
+        EntryKind::HardLink => {
+            let target = archive_path(cstr(entry.data)?);
+            // the header describes the original; there is nothing more to apply
+            return match fs.link(&target, &entry.path) {
+                Ok(()) => Ok(true),
+                Err(FsError::AlreadyExists) => Ok(false),
+                Err(e) => Err(e),
+            };
+        }
+        EntryKind::Other => false,
+    };
+
+    fs.chmod(&entry.path, entry.mode)?;
+    fs.chown(&entry.path, entry.uid, entry.gid)?;
+    Ok(created)
+}
+
+fn create_dir_all(fs: &dyn FileSystem, path: &str) -> FsResult<()> {
+    if fs.exists(path) {
+        return Ok(());
+    }
+    if let Some(parent) = VFS::parent_path(path) {
+        create_dir_all(fs, &parent)?;
+    }
+    match fs.create_dir(path) {
+        Ok(()) | Err(FsError::AlreadyExists) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// archive members are usually stored as "./etc/motd" or "etc/motd"
+fn archive_path(name: &str) -> String {
+    let trimmed = name.trim_start_matches("./").trim_start_matches('/');
+    VFS::normalize_path(&format!("/{}", trimmed))
+}
+
+fn parse_tar<'a>(
+    image: &'a [u8],
+    install: &mut dyn FnMut(Entry<'a>) -> FsResult<()>,
+) -> FsResult<()> {
+    let mut offset = 0;
+
+    while offset + TAR_BLOCK_SIZE <= image.len() {
+        let header = &image[offset..offset + TAR_BLOCK_SIZE];
+
+        // two zero blocks mark the end of the archive, one is enough to stop
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if &header[257..262] != b"ustar" {
+            return Err(FsError::InvalidData);
+        }
+
+        let name = cstr(&header[0..100])?;
+        let prefix = cstr(&header[345..500])?;
+        let mode = parse_octal(&header[100..108])? as u16;
+        let uid = parse_octal(&header[108..116])? as u32;
+        let gid = parse_octal(&header[116..124])? as u32;
+        let size = parse_octal(&header[124..136])? as usize;
+        let typeflag = header[156];
+
+        let data_start = offset + TAR_BLOCK_SIZE;
+        let data_end = data_start.checked_add(size).ok_or(FsError::InvalidData)?;
+        if data_end > image.len() {
+            return Err(FsError::InvalidData);
+        }
+
+        let full_name = if prefix.is_empty() {
+            String::from(name)
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+
+        let kind = match typeflag {
+            b'0' | b'\0' | b'7' => EntryKind::File,
+            b'1' => EntryKind::HardLink,
+            b'2' => EntryKind::Symlink,
+            b'5' => EntryKind::Directory,
+            _ => EntryKind::Other,
+        };
+        // links keep their target in the header's linkname field
+        let data = match kind {
+            EntryKind::HardLink | EntryKind::Symlink => cstr(&header[157..257])?.as_bytes(),
+            _ => &image[data_start..data_end],
+        };
+
+        install(Entry {
+            path: archive_path(&full_name),
+            kind,
+            mode: mode & 0o7777,
+            uid,
+            gid,
+            data,
+        })?;
+
+        offset = data_start + align_up(size, TAR_BLOCK_SIZE);
+    }
+    Ok(())
+}
+
+fn parse_cpio<'a>(
+    image: &'a [u8],
+    install: &mut dyn FnMut(Entry<'a>) -> FsResult<()>,
+) -> FsResult<()> {
+    let mut offset = 0;
+
+    loop {
+        if offset + CPIO_HEADER_SIZE > image.len() {
+            return Err(FsError::InvalidData);
+        }
+        let header = &image[offset..offset + CPIO_HEADER_SIZE];
+        if &header[..6] != b"070701" && &header[..6] != b"070702" {
+            return Err(FsError::InvalidData);
+        }
+
+        // 13 fields of 8 hex digits follow the magic
+        let field = |index: usize| parse_hex(&header[6 + index * 8..14 + index * 8]);
+        let mode = field(1)?;
+        let uid = field(2)?;
+        let gid = field(3)?;
+        let file_size = field(6)? as usize;
+        let name_size = field(11)? as usize;
+
+        let name_start = offset + CPIO_HEADER_SIZE;
+        let name_end = name_start + name_size;
+        if name_size == 0 || name_end > image.len() {
+            return Err(FsError::InvalidData);
+        }
+        // name_size includes the trailing NUL
+        let name = cstr(&image[name_start..name_end])?;
+        if name == CPIO_TRAILER {
+            break;
+        }
+
+        let data_start = align_up(name_end, 4);
+        let data_end = data_start + file_size;
+        if data_end > image.len() {
+            return Err(FsError::InvalidData);
+        }
+
+        let kind = match mode & S_IFMT {
+            S_IFREG => EntryKind::File,
+            S_IFDIR => EntryKind::Directory,
+            // the target is stored as the member's data
+            S_IFLNK => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        };
+
+        install(Entry {
+            path: archive_path(name),
+            kind,
+            mode: (mode & 0o7777) as u16,
+            uid,
+            gid,
+            data: &image[data_start..data_end],
+        })?;
+
+        offset = align_up(data_end, 4);
+    }
+    Ok(())
+}
+
+fn cstr(bytes: &[u8]) -> FsResult<&str> {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).map_err(|_| FsError::InvalidData)
+}
+
+fn parse_octal(field: &[u8]) -> FsResult<u64> {
+    let mut value = 0u64;
+    for &b in field {
+        match b {
+            b'0'..=b'7' => value = value * 8 + (b - b'0') as u64,
+            // fields are NUL or space terminated
+            b'\0' | b' ' => {
+                if value != 0 {
+                    break;
+                }
+            }
+            _ => return Err(FsError::InvalidData),
+        }
+    }
+    Ok(value)
+}
+
+fn parse_hex(field: &[u8]) -> FsResult<u32> {
+    let s = core::str::from_utf8(field).map_err(|_| FsError::InvalidData)?;
+    u32::from_str_radix(s, 16).map_err(|_| FsError::InvalidData)
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
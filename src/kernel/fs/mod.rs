@@ -1,25 +1,115 @@
+pub mod devfs;
+pub mod ext2;
+pub mod fat32;
+pub mod file;
+pub mod initrd;
+pub mod mount;
+pub mod perm;
+pub mod pipe;
+pub mod procfs;
 pub mod ramfs;
 pub mod vfs;
 
+pub use devfs::{CharDevice, DevFs};
+pub use ext2::Ext2;
+pub use fat32::Fat32;
+pub use file::{FdTable, File, FileRef, SeekFrom, Stream};
+pub use mount::{FsRef, MountInfo, MountTable};
+pub use pipe::{pipe, PipeReader, PipeWriter};
+pub use procfs::ProcFs;
 pub use ramfs::RamFs;
-pub use vfs::{FileSystem, FileType, INode, OpenOptions, VFS};
+pub use vfs::{FileSystem, FileType, FsError, FsResult, INode, OpenOptions, VFS};
 
+use crate::drivers::block;
 use alloc::sync::Arc;
-use spin::Mutex;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
 
-static ROOT_FS: Mutex<Option<Arc<dyn FileSystem + Send + Sync>>> = Mutex::new(None);
+lazy_static! {
+    static ref MOUNTS: Arc<MountTable> = Arc::new(MountTable::new());
+}
+
+// packed from `rootfs/` by build.rs
+static INITRD: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initrd.tar"));
 
 pub fn init() {
     let ramfs = Arc::new(RamFs::new());
-    *ROOT_FS.lock() = Some(ramfs.clone());
+    MOUNTS
+        .mount("/", "ramfs", "none", ramfs.clone())
+        .expect("failed to mount root filesystem");
+
+    let _ = ramfs.create_dir("/home");
+    let _ = ramfs.create_dir("/tmp");
+    let _ = ramfs.create_dir("/bin");
+    let _ = ramfs.create_dir("/dev");
+    let _ = ramfs.create_dir("/proc");
+    let _ = ramfs.create_dir("/mnt");
+
+    match initrd::unpack(&*ramfs, INITRD) {
+        Ok(count) => crate::println!("initrd: unpacked {} entries", count),
+        Err(e) => crate::println!("initrd: {}", e),
+    }
 
-    if let Some(fs) = ROOT_FS.lock().as_ref() {
-        let _ = fs.create_dir("/home");
-        let _ = fs.create_dir("/tmp");
-        let _ = fs.create_dir("/bin");
+    // world-writable with the sticky bit, like on any unix
+    match mount("ramfs", "none", "/tmp") {
+        Ok(()) => {
+            let _ = MOUNTS.chmod("/tmp", 0o1777);
+        }
+        Err(e) => crate::println!("mount /tmp: {}", e),
+    }
+    if let Err(e) = mount("devfs", "none", "/dev") {
+        crate::println!("mount /dev: {}", e);
+    }
+    if let Err(e) = mount("proc", "none", "/proc") {
+        crate::println!("mount /proc: {}", e);
     }
 }
 
+/// The root of the VFS tree, dispatching through the mount table.
 pub fn root() -> Option<Arc<dyn FileSystem + Send + Sync>> {
-    ROOT_FS.lock().as_ref().map(Arc::clone)
+    if MOUNTS.is_mounted("/") {
+        Some(MOUNTS.clone())
+    } else {
+        None
+    }
+}
+
+/// Creates a new filesystem instance of `fs_type` backed by `source`.
+fn create_fs(fs_type: &str, source: &str) -> FsResult<FsRef> {
+    match fs_type {
+        "ramfs" | "tmpfs" => Ok(Arc::new(RamFs::new())),
+        "devfs" => Ok(Arc::new(DevFs::with_default_devices())),
+        "proc" | "procfs" => Ok(Arc::new(ProcFs::new())),
+        "fat32" | "vfat" => {
+            let dev = block::get(source).ok_or(FsError::NotFound)?;
+            Ok(Arc::new(Fat32::new(dev)?))
+        }
+        "ext2" => {
+            let dev = block::get(source).ok_or(FsError::NotFound)?;
+            Ok(Arc::new(Ext2::new(dev)?))
+        }
+        _ => Err(FsError::NotSupported),
+    }
+}
+
+pub fn mount(fs_type: &str, source: &str, target: &str) -> FsResult<()> {
+    if !crate::kernel::user::current().is_root() {
+        return Err(FsError::PermissionDenied);
+    }
+    let fs = create_fs(fs_type, source)?;
+    MOUNTS.mount(target, fs_type, source, fs)
+}
+
+pub fn umount(target: &str) -> FsResult<()> {
+    if !crate::kernel::user::current().is_root() {
+        return Err(FsError::PermissionDenied);
+    }
+    MOUNTS.umount(target)?;
+    // the medium may be removed next; don't leave its blocks in the cache
+    crate::drivers::block::cache::sync()?;
+    Ok(())
+}
+
+pub fn mounts() -> Vec<MountInfo> {
+    MOUNTS.mounts()
 }
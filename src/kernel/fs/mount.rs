@@ -0,0 +1,406 @@
+use super::perm;
+use super::vfs::{FileSystem, FileType, FsError, FsResult, INode, VFS};
+use crate::kernel::user::{self, Credentials};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+pub type FsRef = Arc<dyn FileSystem + Send + Sync>;
+
+// symlinks followed in one lookup before giving up, as in Linux
+const MAX_SYMLINKS: usize = 40;
+
+#[derive(Clone)]
+pub struct MountInfo {
+    pub target: String,
+    pub fs_type: String,
+    pub source: String,
+}
+
+struct Mount {
+    info: MountInfo,
+    fs: FsRef,
+}
+
+/// Dispatches filesystem operations to whichever filesystem is mounted at the
+/// longest directory prefix of the requested path.
+pub struct MountTable {
+    mounts: Mutex<Vec<Mount>>,
+}
+
+impl MountTable {
+    pub const fn new() -> Self {
+        MountTable {
+            mounts: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn mount(&self, target: &str, fs_type: &str, source: &str, fs: FsRef) -> FsResult<()> {
+        let mut target = VFS::normalize_path(target);
+
+        // everything but the root mount needs an existing directory to cover
+        if target != "/" || self.is_mounted("/") {
+            target = self.lookup(&target, true)?;
+            let dir = self.stat(&target)?;
+            if dir.file_type != FileType::Directory {
+                return Err(FsError::NotADirectory);
+            }
+        }
+
+        let mut mounts = self.mounts.lock();
+        if mounts.iter().any(|m| m.info.target == target) {
+            return Err(FsError::Busy);
+        }
+        mounts.push(Mount {
+            info: MountInfo {
+                target,
+                fs_type: fs_type.into(),
+                source: source.into(),
+            },
+            fs,
+        });
+        Ok(())
+    }
+
+    pub fn umount(&self, target: &str) -> FsResult<()> {
+        let target = self
+            .lookup(target, true)
+            .unwrap_or_else(|_| VFS::normalize_path(target));
+        if target == "/" {
+            return Err(FsError::Busy);
+        }
+
+        let mut mounts = self.mounts.lock();
+        let index = mounts
+            .iter()
+            .position(|m| m.info.target == target)
+            .ok_or(FsError::NotFound)?;
+
+        // refuse to unmount a filesystem that still has others mounted below it
+        if mounts
+            .iter()
+            .any(|m| m.info.target != target && VFS::is_within(&m.info.target, &target))
+        {
+            return Err(FsError::Busy);
+        }
+
+        mounts.remove(index);
+        Ok(())
+    }
+
+    pub fn is_mounted(&self, target: &str) -> bool {
+        let target = VFS::normalize_path(target);
+        self.mounts.lock().iter().any(|m| m.info.target == target)
+    }
+
+    pub fn mounts(&self) -> Vec<MountInfo> {
+        self.mounts.lock().iter().map(|m| m.info.clone()).collect()
+    }
+
+    /// Returns the filesystem responsible for `path` and the path relative to its root.
+    pub fn resolve(&self, path: &str) -> FsResult<(FsRef, String)> {
+        let normalized = VFS::normalize_path(path);
+        let mounts = self.mounts.lock();
+
+        let mount = mounts
+            .iter()
+            .filter(|m| VFS::is_within(&normalized, &m.info.target))
+            .max_by_key(|m| m.info.target.len())
+            .ok_or(FsError::NotFound)?;
+
+        let relative = if mount.info.target == "/" {
+            normalized
+        } else {
+            let rest = &normalized[mount.info.target.len()..];
+            if rest.is_empty() {
+                String::from("/")
+            } else {
+                String::from(rest)
+            }
+        };
+        Ok((mount.fs.clone(), relative))
+    }
+}
+
+impl Default for MountTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MountTable {
+    /// Resolves the symlinks in `path` and returns the path they lead to. The last
+    /// component is only followed if `follow` is set, and may be missing so the
+    /// result can name something about to be created.
+    pub fn lookup(&self, path: &str, follow: bool) -> FsResult<String> {
+        // components still to walk, next one last
+        let mut pending: Vec<String> = Vec::new();
+        push_components(&mut pending, path);
+        let mut resolved = String::from("/");
+        let mut links = 0;
+
+        while let Some(component) = pending.pop() {
+            if component == ".." {
+                resolved = VFS::parent_path(&resolved).unwrap_or_else(|| String::from("/"));
+                continue;
+            }
+
+            let candidate = VFS::join(&resolved, &component);
+            let last = pending.is_empty();
+            if last && !follow {
+                resolved = candidate;
+                break;
+            }
+
+            match self.raw_stat(&candidate) {
+                Ok(inode) if inode.file_type == FileType::Symlink => {
+                    links += 1;
+                    if links > MAX_SYMLINKS {
+                        return Err(FsError::TooManyLinks);
+                    }
+                    let (fs, rel) = self.resolve(&candidate)?;
+                    let target = fs.readlink(&rel)?;
+                    // relative targets are walked from the directory holding the link
+                    if target.starts_with('/') {
+                        resolved = String::from("/");
+                    }
+                    push_components(&mut pending, &target);
+                }
+                Ok(_) => resolved = candidate,
+                Err(FsError::NotFound) if last => resolved = candidate,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn raw_stat(&self, path: &str) -> FsResult<INode> {
+        let (fs, rel) = self.resolve(path)?;
+        let mut inode = fs.stat(&rel)?;
+        // the root of a mounted filesystem is known by its mount point's name
+        if rel == "/" {
+            inode.name = VFS::filename(path).unwrap_or_else(|| String::from("/"));
+        }
+        Ok(inode)
+    }
+
+    /// Every directory on the way to `path` needs search (x) permission.
+    fn check_search(&self, path: &str, cred: &Credentials) -> FsResult<()> {
+        if cred.is_root() {
+            return Ok(());
+        }
+        let mut dir = VFS::parent_path(&VFS::normalize_path(path));
+        let mut ancestors = Vec::new();
+        while let Some(d) = dir {
+            dir = VFS::parent_path(&d);
+            ancestors.push(d);
+        }
+        for ancestor in ancestors.iter().rev() {
+            perm::check(&self.raw_stat(ancestor)?, cred, perm::X_OK)?;
+        }
+        Ok(())
+    }
+
+    /// Looks `path` up as `cred` and checks `want` on the node itself.
+    fn check_access(&self, path: &str, want: u16) -> FsResult<INode> {
+        let cred = user::current();
+        self.check_search(path, &cred)?;
+        let inode = self.raw_stat(path)?;
+        perm::check(&inode, &cred, want)?;
+        Ok(inode)
+    }
+
+    /// Adding or removing an entry needs write and search permission on the parent.
+    fn check_parent(&self, path: &str) -> FsResult<INode> {
+        let parent = VFS::parent_path(&VFS::normalize_path(path)).ok_or(FsError::InvalidPath)?;
+        self.check_access(&parent, perm::W_OK | perm::X_OK)
+    }
+
+    fn check_unlink(&self, path: &str) -> FsResult<()> {
+        let dir = self.check_parent(path)?;
+        let entry = self.raw_stat(path)?;
+        perm::check_sticky(&dir, &entry, &user::current())
+    }
+}
+
+fn push_components(pending: &mut Vec<String>, path: &str) {
+    for part in path.rsplit('/') {
+        if !part.is_empty() && part != "." {
+            pending.push(part.into());
+        }
+    }
+}
+
+// New nodes belong to the caller; filesystems without ownership (FAT) keep their fixed owner.
+fn give_to_caller(fs: &FsRef, rel: &str) -> FsResult<()> {
+    let cred = user::current();
+    match fs.chown(rel, cred.uid, cred.gid) {
+        Err(FsError::NotSupported) => Ok(()),
+        result => result,
+    }
+}
+
+// Paths reaching the methods below may contain symlinks; each one resolves them
+// with `lookup` first, leaving the last component alone where POSIX operates on
+// the link itself (remove, rename, lstat, readlink, link).
+impl FileSystem for MountTable {
+    fn create_file(&self, path: &str) -> FsResult<()> {
+        let path = &self.lookup(path, false)?;
+        self.check_parent(path)?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.create_file(&rel)?;
+        give_to_caller(&fs, &rel)
+    }
+
+    fn create_dir(&self, path: &str) -> FsResult<()> {
+        let path = &self.lookup(path, false)?;
+        self.check_parent(path)?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.create_dir(&rel)?;
+        give_to_caller(&fs, &rel)
+    }
+
+    fn remove(&self, path: &str) -> FsResult<()> {
+        let path = &self.lookup(path, false)?;
+        if self.is_mounted(path) {
+            return Err(FsError::Busy);
+        }
+        self.check_unlink(path)?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.remove(&rel)
+    }
+
+    fn read_file(&self, path: &str) -> FsResult<Vec<u8>> {
+        let path = &self.lookup(path, true)?;
+        self.check_access(path, perm::R_OK)?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.read_file(&rel)
+    }
+
+    fn write_file(&self, path: &str, data: &[u8]) -> FsResult<()> {
+        let path = &self.lookup(path, true)?;
+        self.check_access(path, perm::W_OK)?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.write_file(&rel, data)
+    }
+
+    fn list_dir(&self, path: &str) -> FsResult<Vec<INode>> {
+        let path = &self.lookup(path, true)?;
+        self.check_access(path, perm::R_OK)?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.list_dir(&rel)
+    }
+
+    fn stat(&self, path: &str) -> FsResult<INode> {
+        self.lstat(&self.lookup(path, true)?)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        match self.lookup(path, true).and_then(|path| self.resolve(&path)) {
+            Ok((fs, rel)) => fs.exists(&rel),
+            Err(_) => false,
+        }
+    }
+
+    fn chmod(&self, path: &str, mode: u16) -> FsResult<()> {
+        let path = &self.lookup(path, true)?;
+        let inode = self.check_access(path, 0)?;
+        perm::check_owner(&inode, &user::current())?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.chmod(&rel, mode)
+    }
+
+    fn chown(&self, path: &str, uid: u32, gid: u32) -> FsResult<()> {
+        let path = &self.lookup(path, true)?;
+        self.check_access(path, 0)?;
+        if !user::current().is_root() {
+            return Err(FsError::PermissionDenied);
+        }
+        let (fs, rel) = self.resolve(path)?;
+        fs.chown(&rel, uid, gid)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> FsResult<()> {
+        let from = &self.lookup(from, false)?;
+        let to = &self.lookup(to, false)?;
+        if self.is_mounted(from) || self.is_mounted(to) {
+            return Err(FsError::Busy);
+        }
+        self.check_unlink(from)?;
+        match self.raw_stat(to) {
+            Ok(_) => self.check_unlink(to)?,
+            Err(_) => {
+                self.check_parent(to)?;
+            }
+        }
+        let (from_fs, from_rel) = self.resolve(from)?;
+        let (to_fs, to_rel) = self.resolve(to)?;
+        if !Arc::ptr_eq(&from_fs, &to_fs) {
+            return Err(FsError::CrossDevice);
+        }
+        from_fs.rename(&from_rel, &to_rel)
+    }
+
+    fn read_at(&self, path: &str, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let path = &self.lookup(path, true)?;
+        self.check_access(path, perm::R_OK)?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.read_at(&rel, offset, buf)
+    }
+
+    fn write_at(&self, path: &str, offset: usize, data: &[u8]) -> FsResult<usize> {
+        let path = &self.lookup(path, true)?;
+        self.check_access(path, perm::W_OK)?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.write_at(&rel, offset, data)
+    }
+
+    fn append(&self, path: &str, data: &[u8]) -> FsResult<usize> {
+        let path = &self.lookup(path, true)?;
+        self.check_access(path, perm::W_OK)?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.append(&rel, data)
+    }
+
+    fn truncate(&self, path: &str, len: usize) -> FsResult<()> {
+        let path = &self.lookup(path, true)?;
+        self.check_access(path, perm::W_OK)?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.truncate(&rel, len)
+    }
+
+    fn lstat(&self, path: &str) -> FsResult<INode> {
+        let path = &self.lookup(path, false)?;
+        self.check_search(path, &user::current())?;
+        self.raw_stat(path)
+    }
+
+    fn symlink(&self, target: &str, path: &str) -> FsResult<()> {
+        let path = &self.lookup(path, false)?;
+        self.check_parent(path)?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.symlink(target, &rel)?;
+        give_to_caller(&fs, &rel)
+    }
+
+    fn readlink(&self, path: &str) -> FsResult<String> {
+        let path = &self.lookup(path, false)?;
+        self.check_search(path, &user::current())?;
+        let (fs, rel) = self.resolve(path)?;
+        fs.readlink(&rel)
+    }
+
+    fn link(&self, existing: &str, new: &str) -> FsResult<()> {
+        let existing = &self.lookup(existing, false)?;
+        let new = &self.lookup(new, false)?;
+        self.check_search(existing, &user::current())?;
+        self.check_parent(new)?;
+        let (from_fs, from_rel) = self.resolve(existing)?;
+        let (to_fs, to_rel) = self.resolve(new)?;
+        if !Arc::ptr_eq(&from_fs, &to_fs) {
+            return Err(FsError::CrossDevice);
+        }
+        from_fs.link(&from_rel, &to_rel)
+    }
+}
@@ -0,0 +1,73 @@
+use super::vfs::{FileType, FsError, FsResult, INode};
+use crate::kernel::user::Credentials;
+use alloc::string::String;
+
+// access bits, as in access(2)
+pub const R_OK: u16 = 4;
+pub const W_OK: u16 = 2;
+pub const X_OK: u16 = 1;
+
+pub const S_ISVTX: u16 = 0o1000;
+
+/// Checks `want` (a mask of R_OK/W_OK/X_OK) against the owner, group or other
+/// bits of `inode`. Root passes every check.
+pub fn check(inode: &INode, cred: &Credentials, want: u16) -> FsResult<()> {
+    if cred.is_root() {
+        return Ok(());
+    }
+
+    let bits = if cred.uid == inode.uid {
+        (inode.mode >> 6) & 0o7
+    } else if cred.gid == inode.gid {
+        (inode.mode >> 3) & 0o7
+    } else {
+        inode.mode & 0o7
+    };
+
+    if bits & want == want {
+        Ok(())
+    } else {
+        Err(FsError::PermissionDenied)
+    }
+}
+
+/// Only the owner (or root) may change a node's mode.
+pub fn check_owner(inode: &INode, cred: &Credentials) -> FsResult<()> {
+    if cred.is_root() || cred.uid == inode.uid {
+        Ok(())
+    } else {
+        Err(FsError::PermissionDenied)
+    }
+}
+
+/// In a sticky directory (like /tmp) entries may only be removed or renamed by
+/// their owner, the directory's owner or root.
+pub fn check_sticky(dir: &INode, entry: &INode, cred: &Credentials) -> FsResult<()> {
+    if dir.mode & S_ISVTX == 0 || cred.is_root() || cred.uid == entry.uid || cred.uid == dir.uid {
+        Ok(())
+    } else {
+        Err(FsError::PermissionDenied)
+    }
+}
+
+/// Renders a mode the way `ls -l` does, e.g. `drwxr-xr-x`.
+pub fn mode_string(file_type: FileType, mode: u16) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(match file_type {
+        FileType::Directory => 'd',
+        FileType::File => '-',
+        FileType::Symlink => 'l',
+        FileType::CharDevice => 'c',
+    });
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        s.push(if bits & 4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    if mode & S_ISVTX != 0 {
+        s.pop();
+        s.push(if mode & 1 != 0 { 't' } else { 'T' });
+    }
+    s
+}
@@ -0,0 +1,127 @@
+use super::file::Stream;
+use super::vfs::{FsError, FsResult};
+use crate::kernel::process;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::any::Any;
+use spin::Mutex;
+
+/// Bytes a pipe holds before writers have to wait for a reader.
+pub const CAPACITY: usize = 4096;
+
+struct Buffer {
+    data: VecDeque<u8>,
+    reader_closed: bool,
+    writer_closed: bool,
+}
+
+type Shared = Arc<Mutex<Buffer>>;
+
+/// The read end of a pipe. Reads wait for data and return 0 once the writer is gone.
+pub struct PipeReader {
+    buffer: Shared,
+}
+
+/// The write end of a pipe. Writes wait for room and fail once the reader is gone.
+pub struct PipeWriter {
+    buffer: Shared,
+}
+
+/// Creates an anonymous pipe: bytes written to one end come out of the other in order.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let buffer = Arc::new(Mutex::new(Buffer {
+        data: VecDeque::new(),
+        reader_closed: false,
+        writer_closed: false,
+    }));
+    (
+        PipeReader {
+            buffer: buffer.clone(),
+        },
+        PipeWriter { buffer },
+    )
+}
+
+impl Stream for PipeReader {
+    fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        process::park_until(|| {
+            let buffer = self.buffer.lock();
+            !buffer.data.is_empty() || buffer.writer_closed
+        })?;
+
+        let mut buffer = self.buffer.lock();
+        let len = buf.len().min(buffer.data.len());
+        for (dst, src) in buf.iter_mut().zip(buffer.data.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+
+    fn write(&self, _data: &[u8]) -> FsResult<usize> {
+        Err(FsError::BadDescriptor)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Stream for PipeWriter {
+    fn read(&self, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(FsError::BadDescriptor)
+    }
+
+    // waits for room, then writes as much as fits; a write taking less than all of `data`
+    // returns then rather than wait again, since a blocked write is run again from the start
+    fn write(&self, data: &[u8]) -> FsResult<usize> {
+        let has_room = || {
+            let buffer = self.buffer.lock();
+            buffer.reader_closed || buffer.data.len() < CAPACITY
+        };
+        let mut written = 0;
+        while written < data.len() {
+            let parked = if written == 0 {
+                process::park_until(has_room)
+            } else if has_room() {
+                Ok(())
+            } else {
+                return Ok(written);
+            };
+
+            let mut buffer = self.buffer.lock();
+            if parked.is_err() || buffer.reader_closed {
+                return match (written, parked) {
+                    (0, Ok(())) => Err(FsError::BrokenPipe),
+                    (0, Err(e)) => Err(e),
+                    (written, _) => Ok(written),
+                };
+            }
+            let len = (CAPACITY - buffer.data.len()).min(data.len() - written);
+            buffer.data.extend(&data[written..written + len]);
+            written += len;
+        }
+        Ok(written)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut buffer = self.buffer.lock();
+        buffer.reader_closed = true;
+        // nobody can read what's left
+        buffer.data.clear();
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.buffer.lock().writer_closed = true;
+    }
+}
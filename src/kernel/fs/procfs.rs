@@ -0,0 +1,391 @@
+use super::vfs::{FileSystem, FileType, FsError, FsResult, INode};
+use crate::kernel::process::{self, Pid};
+use crate::kernel::thread;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+// Synthetic view of kernel state. Nothing is stored: every read regenerates the
+// file from the live data structures, so sizes are reported as 0 like on Linux.
+
+const ROOT_INO: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProcFile {
+    Meminfo,
+    Uptime,
+    Interrupts,
+    Mounts,
+}
+
+const PROC_FILES: [(&str, ProcFile); 4] = [
+    ("meminfo", ProcFile::Meminfo),
+    ("uptime", ProcFile::Uptime),
+    ("interrupts", ProcFile::Interrupts),
+    ("mounts", ProcFile::Mounts),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Root,
+    File(ProcFile),
+    // /proc/self, a symlink to the calling process's directory
+    SelfLink,
+    PidDir(Pid),
+    Status(Pid),
+    Maps(Pid),
+    FdDir(Pid),
+    // /proc/<pid>/fd/<n>, a symlink to the open file
+    Fd(Pid, usize),
+}
+
+impl Node {
+    fn parse(path: &str) -> FsResult<Node> {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let node = match parts.as_slice() {
+            [] => Node::Root,
+            ["self"] => Node::SelfLink,
+            [name] => match PROC_FILES.iter().find(|(n, _)| n == name) {
+                Some(&(_, file)) => Node::File(file),
+                None => Node::PidDir(parse_pid(name)?),
+            },
+            [pid, rest @ ..] => {
+                let pid = parse_pid(pid)?;
+                match rest {
+                    ["status"] => Node::Status(pid),
+                    ["maps"] => Node::Maps(pid),
+                    ["fd"] => Node::FdDir(pid),
+                    ["fd", fd] => {
+                        let fd = fd.parse().map_err(|_| FsError::NotFound)?;
+                        Node::Fd(pid, fd)
+                    }
+                    _ => return Err(FsError::NotFound),
+                }
+            }
+        };
+        node.check_exists()?;
+        Ok(node)
+    }
+
+    fn pid(&self) -> Option<Pid> {
+        match *self {
+            Node::PidDir(pid) | Node::Status(pid) | Node::Maps(pid) | Node::FdDir(pid) => Some(pid),
+            Node::Fd(pid, _) => Some(pid),
+            _ => None,
+        }
+    }
+
+    fn check_exists(&self) -> FsResult<()> {
+        if let Node::Fd(pid, fd) = *self {
+            return match process::with_process(pid, |p| p.fds.get(fd).is_some()) {
+                Some(true) => Ok(()),
+                _ => Err(FsError::NotFound),
+            };
+        }
+        if *self == Node::SelfLink && process::current_pid().is_none() {
+            return Err(FsError::NotFound);
+        }
+        match self.pid() {
+            Some(pid) if process::with_process(pid, |_| ()).is_none() => Err(FsError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    fn file_type(&self) -> FileType {
+        match self {
+            Node::Root | Node::PidDir(_) | Node::FdDir(_) => FileType::Directory,
+            Node::SelfLink | Node::Fd(..) => FileType::Symlink,
+            _ => FileType::File,
+        }
+    }
+
+    // stable, distinct numbers without storing anything
+    fn ino(&self) -> u64 {
+        match *self {
+            Node::Root => ROOT_INO,
+            Node::File(file) => 2 + file as u64,
+            Node::SelfLink => 16,
+            Node::PidDir(pid) => pid << 16,
+            Node::Status(pid) => (pid << 16) | 1,
+            Node::Maps(pid) => (pid << 16) | 2,
+            Node::FdDir(pid) => (pid << 16) | 3,
+            Node::Fd(pid, fd) => (pid << 16) | (0x100 + fd as u64),
+        }
+    }
+
+    fn inode(&self, name: &str) -> INode {
+        let file_type = self.file_type();
+        let mode = match file_type {
+            FileType::Directory => 0o555,
+            FileType::Symlink => 0o777,
+            _ => 0o444,
+        };
+        // per-process entries belong to the process's user
+        let cred = self
+            .pid()
+            .and_then(|pid| process::with_process(pid, |p| p.cred));
+        INode {
+            name: name.into(),
+            ino: self.ino(),
+            file_type,
+            size: 0,
+            mode,
+            uid: cred.map_or(0, |c| c.uid),
+            gid: cred.map_or(0, |c| c.gid),
+            nlink: if file_type == FileType::Directory {
+                2
+            } else {
+                1
+            },
+        }
+    }
+
+    fn children(&self) -> FsResult<Vec<(String, Node)>> {
+        match *self {
+            Node::Root => {
+                let mut entries: Vec<(String, Node)> = PROC_FILES
+                    .iter()
+                    .map(|&(name, file)| (name.to_string(), Node::File(file)))
+                    .collect();
+                if process::current_pid().is_some() {
+                    entries.push((String::from("self"), Node::SelfLink));
+                }
+                for pid in process::pids() {
+                    entries.push((pid.to_string(), Node::PidDir(pid)));
+                }
+                Ok(entries)
+            }
+            Node::PidDir(pid) => Ok(vec![
+                (String::from("status"), Node::Status(pid)),
+                (String::from("maps"), Node::Maps(pid)),
+                (String::from("fd"), Node::FdDir(pid)),
+            ]),
+            Node::FdDir(pid) => {
+                let fds = process::with_process(pid, |p| {
+                    p.fds.fds().map(|(fd, _)| fd).collect::<Vec<_>>()
+                })
+                .ok_or(FsError::NotFound)?;
+                Ok(fds
+                    .into_iter()
+                    .map(|fd| (fd.to_string(), Node::Fd(pid, fd)))
+                    .collect())
+            }
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn generate(&self) -> FsResult<String> {
+        match *self {
+            Node::File(ProcFile::Meminfo) => Ok(meminfo()),
+            Node::File(ProcFile::Uptime) => Ok(uptime()),
+            Node::File(ProcFile::Interrupts) => Ok(interrupts()),
+            Node::File(ProcFile::Mounts) => Ok(mounts()),
+            Node::Status(pid) => status(pid),
+            Node::Maps(pid) => maps(pid),
+            _ => Err(FsError::NotAFile),
+        }
+    }
+}
+
+fn parse_pid(name: &str) -> FsResult<Pid> {
+    name.parse().map_err(|_| FsError::NotFound)
+}
+
+fn meminfo() -> String {
+    let frames = crate::kernel::memory::memory::frame_stats();
+    let heap = crate::kernel::memory::allocator::heap_stats();
+    let mut out = String::new();
+    let _ = writeln!(out, "MemTotal:    {:>10} kB", frames.total * 4);
+    let _ = writeln!(
+        out,
+        "MemFree:     {:>10} kB",
+        frames.total.saturating_sub(frames.used) * 4
+    );
+    let _ = writeln!(out, "FramesTotal: {:>10}", frames.total);
+    let _ = writeln!(out, "FramesUsed:  {:>10}", frames.used);
+    let _ = writeln!(out, "HeapTotal:   {:>10} kB", heap.size / 1024);
+    let _ = writeln!(out, "HeapUsed:    {:>10} kB", heap.used / 1024);
+    let _ = writeln!(out, "HeapFree:    {:>10} kB", heap.free / 1024);
+    out
+}
+
+fn uptime() -> String {
+    let ms = crate::arch::x86_64::timer::uptime_ms();
+    format!("{}.{:02}\n", ms / 1000, (ms % 1000) / 10)
+}
+
+fn interrupts() -> String {
+    use crate::arch::x86_64::interrupts::{interrupt_counts, vector_name};
+
+    let mut out = String::from("            CPU0\n");
+    for (vector, count) in interrupt_counts() {
+        let _ = writeln!(
+            out,
+            "{:>4}: {:>10}   {}",
+            vector,
+            count,
+            vector_name(vector)
+        );
+    }
+    out
+}
+
+fn mounts() -> String {
+    let mut out = String::new();
+    for info in super::mounts() {
+        let _ = writeln!(
+            out,
+            "{} {} {} rw 0 0",
+            info.source, info.target, info.fs_type
+        );
+    }
+    out
+}
+
+fn status(pid: Pid) -> FsResult<String> {
+    let threads = thread::tids(pid).len().max(1);
+    process::with_process(pid, |p| {
+        let mut out = String::new();
+        let _ = writeln!(out, "Name:\t{}", p.name);
+        let _ = writeln!(out, "State:\t{}", p.state);
+        let _ = writeln!(out, "Pid:\t{}", p.pid);
+        let _ = writeln!(out, "Uid:\t{}", p.cred.uid);
+        let _ = writeln!(out, "Gid:\t{}", p.cred.gid);
+        let _ = writeln!(out, "Cwd:\t{}", p.cwd);
+        let _ = writeln!(out, "FDSize:\t{}", p.fds.fds().count());
+        let _ = writeln!(out, "Threads:\t{}", threads);
+        let _ = writeln!(out, "SigPnd:\t{:016x}", p.signals.pending);
+        let _ = writeln!(out, "SigBlk:\t{:016x}", p.signals.blocked);
+        let _ = writeln!(out, "SigIgn:\t{:016x}", p.signals.ignored());
+        let _ = writeln!(out, "SigCgt:\t{:016x}", p.signals.caught());
+        out
+    })
+    .ok_or(FsError::NotFound)
+}
+
+fn maps(pid: Pid) -> FsResult<String> {
+    process::with_process(pid, |p| {
+        let mut out = String::new();
+        for region in &p.regions {
+            let _ = writeln!(
+                out,
+                "{:016x}-{:016x} r{}{}p {}",
+                region.start,
+                region.end,
+                if region.writable { 'w' } else { '-' },
+                if region.executable { 'x' } else { '-' },
+                region.name
+            );
+        }
+        out
+    })
+    .ok_or(FsError::NotFound)
+}
+
+pub struct ProcFs;
+
+impl ProcFs {
+    pub fn new() -> Self {
+        ProcFs
+    }
+}
+
+impl Default for ProcFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn create_file(&self, _path: &str) -> FsResult<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn create_dir(&self, _path: &str) -> FsResult<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn remove(&self, _path: &str) -> FsResult<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn read_file(&self, path: &str) -> FsResult<Vec<u8>> {
+        Ok(Node::parse(path)?.generate()?.into_bytes())
+    }
+
+    fn write_file(&self, _path: &str, _data: &[u8]) -> FsResult<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn list_dir(&self, path: &str) -> FsResult<Vec<INode>> {
+        Ok(Node::parse(path)?
+            .children()?
+            .iter()
+            .map(|(name, node)| node.inode(name))
+            .collect())
+    }
+
+    fn stat(&self, path: &str) -> FsResult<INode> {
+        let node = Node::parse(path)?;
+        let name = path.rsplit('/').find(|s| !s.is_empty()).unwrap_or("/");
+        Ok(node.inode(name))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        Node::parse(path).is_ok()
+    }
+
+    fn chmod(&self, _path: &str, _mode: u16) -> FsResult<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn chown(&self, _path: &str, _uid: u32, _gid: u32) -> FsResult<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn rename(&self, _from: &str, _to: &str) -> FsResult<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn read_at(&self, path: &str, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let content = Node::parse(path)?.generate()?;
+        let bytes = content.as_bytes();
+        if offset >= bytes.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(bytes.len() - offset);
+        buf[..len].copy_from_slice(&bytes[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _path: &str, _offset: usize, _data: &[u8]) -> FsResult<usize> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn append(&self, _path: &str, _data: &[u8]) -> FsResult<usize> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn truncate(&self, _path: &str, _len: usize) -> FsResult<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn readlink(&self, path: &str) -> FsResult<String> {
+        match Node::parse(path)? {
+            Node::SelfLink => process::current_pid()
+                .map(|pid| pid.to_string())
+                .ok_or(FsError::NotFound),
+            Node::Fd(pid, fd) => {
+                let file = process::with_process(pid, |p| p.fds.get(fd))
+                    .flatten()
+                    .ok_or(FsError::NotFound)?;
+                // the reading process may hold this very file locked while it reads
+                let path = file.try_lock().map(|f| String::from(f.path()));
+                Ok(path.unwrap_or_else(|| String::from("?")))
+            }
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+}
@@ -4,264 +4,446 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use spin::Mutex;
 
-#[derive(Clone)]
-struct FileData {
-    content: Vec<u8>,
-}
+pub type Ino = u64;
 
-#[derive(Clone)]
-struct DirData {
-    entries: Vec<String>,
-}
+const ROOT_INO: Ino = 1;
+
+// the most a file may hold, so a write at a huge offset fails instead of running the heap dry
+const MAX_FILE_SIZE: usize = 1024 * 1024;
 
-#[derive(Clone)]
-enum NodeData {
-    File(FileData),
-    Directory(DirData),
+enum InodeData {
+    File(Vec<u8>),
+    // entry name -> inode number
+    Directory(BTreeMap<String, Ino>),
+    // target path, stored verbatim
+    Symlink(String),
 }
 
-#[derive(Clone)]
-struct Node {
-    name: String,
+struct Inode {
     file_type: FileType,
-    data: NodeData,
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    // number of directory entries naming this inode; directories always have one
+    nlink: usize,
+    data: InodeData,
 }
 
-impl Node {
-    fn new_file(name: String) -> Self {
-        Node {
-            name,
+impl Inode {
+    fn new_file() -> Self {
+        Inode {
             file_type: FileType::File,
-            data: NodeData::File(FileData {
-                content: Vec::new(),
-            }),
+            mode: 0o644,
+            uid: 0,
+            gid: 0,
+            nlink: 1,
+            data: InodeData::File(Vec::new()),
         }
     }
 
-    fn new_dir(name: String) -> Self {
-        Node {
-            name,
+    fn new_dir() -> Self {
+        Inode {
             file_type: FileType::Directory,
-            data: NodeData::Directory(DirData {
-                entries: Vec::new(),
-            }),
+            mode: 0o755,
+            uid: 0,
+            gid: 0,
+            nlink: 1,
+            data: InodeData::Directory(BTreeMap::new()),
+        }
+    }
+
+    fn new_symlink(target: &str) -> Self {
+        Inode {
+            file_type: FileType::Symlink,
+            mode: 0o777,
+            uid: 0,
+            gid: 0,
+            nlink: 1,
+            data: InodeData::Symlink(target.into()),
         }
     }
 
     fn size(&self) -> usize {
         match &self.data {
-            NodeData::File(file) => file.content.len(),
-            NodeData::Directory(dir) => dir.entries.len(),
+            InodeData::File(content) => content.len(),
+            InodeData::Directory(entries) => entries.len(),
+            InodeData::Symlink(target) => target.len(),
         }
     }
 }
 
-pub struct RamFs {
-    nodes: Mutex<BTreeMap<String, Node>>,
+struct Inner {
+    inodes: BTreeMap<Ino, Inode>,
+    next_ino: Ino,
 }
 
-impl RamFs {
-    pub fn new() -> Self {
-        let mut nodes = BTreeMap::new();
-        
-        // Create root directory
-        nodes.insert("/".into(), Node::new_dir("/".into()));
-        
-        RamFs {
-            nodes: Mutex::new(nodes),
+impl Inner {
+    fn alloc(&mut self, inode: Inode) -> Ino {
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.inodes.insert(ino, inode);
+        ino
+    }
+
+    fn get(&self, ino: Ino) -> FsResult<&Inode> {
+        self.inodes.get(&ino).ok_or(FsError::NotFound)
+    }
+
+    fn get_mut(&mut self, ino: Ino) -> FsResult<&mut Inode> {
+        self.inodes.get_mut(&ino).ok_or(FsError::NotFound)
+    }
+
+    fn entries(&self, ino: Ino) -> FsResult<&BTreeMap<String, Ino>> {
+        match &self.get(ino)?.data {
+            InodeData::Directory(entries) => Ok(entries),
+            _ => Err(FsError::NotADirectory),
         }
     }
 
-    fn get_node(&self, path: &str) -> FsResult<Node> {
-        let normalized = VFS::normalize_path(path);
-        let nodes = self.nodes.lock();
-        nodes.get(&normalized).cloned().ok_or(FsError::NotFound)
-    }
-
-    fn add_to_parent_dir(&self, path: &str, name: &str) -> FsResult<()> {
-        if let Some(parent_path) = VFS::parent_path(path) {
-            let mut nodes = self.nodes.lock();
-            if let Some(parent) = nodes.get_mut(&parent_path) {
-                if let NodeData::Directory(ref mut dir) = parent.data {
-                    if !dir.entries.contains(&name.into()) {
-                        dir.entries.push(name.into());
-                    }
-                    return Ok(());
-                }
-                return Err(FsError::NotADirectory);
-            }
-            return Err(FsError::NotFound);
+    fn entries_mut(&mut self, ino: Ino) -> FsResult<&mut BTreeMap<String, Ino>> {
+        match &mut self.get_mut(ino)?.data {
+            InodeData::Directory(entries) => Ok(entries),
+            _ => Err(FsError::NotADirectory),
         }
-        Ok(())
     }
 
-    fn remove_from_parent_dir(&self, path: &str, name: &str) -> FsResult<()> {
-        if let Some(parent_path) = VFS::parent_path(path) {
-            let mut nodes = self.nodes.lock();
-            if let Some(parent) = nodes.get_mut(&parent_path) {
-                if let NodeData::Directory(ref mut dir) = parent.data {
-                    dir.entries.retain(|e| e != name);
-                    return Ok(());
-                }
-                return Err(FsError::NotADirectory);
+    fn content_mut(&mut self, path: &str) -> FsResult<&mut Vec<u8>> {
+        let ino = self.lookup(path)?;
+        match &mut self.get_mut(ino)?.data {
+            InodeData::File(content) => Ok(content),
+            _ => Err(FsError::NotAFile),
+        }
+    }
+
+    fn to_inode(&self, ino: Ino, name: String) -> FsResult<INode> {
+        let inode = self.get(ino)?;
+        // a directory is linked from its parent, its own "." and each child's ".."
+        let nlink = match &inode.data {
+            InodeData::Directory(entries) => {
+                2 + entries
+                    .values()
+                    .filter(|child| {
+                        self.get(**child)
+                            .is_ok_and(|c| c.file_type == FileType::Directory)
+                    })
+                    .count()
             }
-            return Err(FsError::NotFound);
+            _ => inode.nlink,
+        };
+        Ok(INode {
+            name,
+            ino,
+            file_type: inode.file_type,
+            size: inode.size(),
+            mode: inode.mode,
+            uid: inode.uid,
+            gid: inode.gid,
+            nlink,
+        })
+    }
+
+    /// Drops one link to `ino`, freeing the inode once nothing names it.
+    fn unlink(&mut self, ino: Ino) -> FsResult<()> {
+        let inode = self.get_mut(ino)?;
+        inode.nlink = inode.nlink.saturating_sub(1);
+        if inode.nlink == 0 {
+            self.inodes.remove(&ino);
         }
         Ok(())
     }
-}
 
-impl FileSystem for RamFs {
-    fn create_file(&self, path: &str) -> FsResult<()> {
+    /// Walks `path` from the root directory to its inode.
+    fn lookup(&self, path: &str) -> FsResult<Ino> {
         let normalized = VFS::normalize_path(path);
-        
-        // Check if already exists
-        if self.exists(&normalized) {
-            return Err(FsError::AlreadyExists);
+        let mut ino = ROOT_INO;
+        for component in normalized.split('/').filter(|s| !s.is_empty()) {
+            ino = *self.entries(ino)?.get(component).ok_or(FsError::NotFound)?;
         }
+        Ok(ino)
+    }
 
-        // Check if parent directory exists
-        if let Some(parent_path) = VFS::parent_path(&normalized) {
-            let parent = self.get_node(&parent_path)?;
-            if parent.file_type != FileType::Directory {
-                return Err(FsError::NotADirectory);
-            }
+    /// Resolves the directory that would contain `path`, plus the final component.
+    fn lookup_parent(&self, path: &str) -> FsResult<(Ino, String)> {
+        let normalized = VFS::normalize_path(path);
+        let parent = VFS::parent_path(&normalized).ok_or(FsError::InvalidPath)?;
+        let name = VFS::filename(&normalized).ok_or(FsError::InvalidPath)?;
+        let parent_ino = self.lookup(&parent)?;
+        if self.get(parent_ino)?.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
         }
-
-        let filename = VFS::filename(&normalized).ok_or(FsError::InvalidPath)?;
-        let node = Node::new_file(filename.clone());
-        
-        self.nodes.lock().insert(normalized.clone(), node);
-        self.add_to_parent_dir(&normalized, &filename)?;
-        
-        Ok(())
+        Ok((parent_ino, name))
     }
 
-    fn create_dir(&self, path: &str) -> FsResult<()> {
-        let normalized = VFS::normalize_path(path);
-        
-        // Check if already exists
-        if self.exists(&normalized) {
+    fn create(&mut self, path: &str, inode: Inode) -> FsResult<Ino> {
+        let (parent, name) = self.lookup_parent(path)?;
+        if self.entries(parent)?.contains_key(&name) {
             return Err(FsError::AlreadyExists);
         }
+        let ino = self.alloc(inode);
+        self.entries_mut(parent)?.insert(name, ino);
+        Ok(ino)
+    }
+}
 
-        // Check if parent directory exists
-        if let Some(parent_path) = VFS::parent_path(&normalized) {
-            let parent = self.get_node(&parent_path)?;
-            if parent.file_type != FileType::Directory {
-                return Err(FsError::NotADirectory);
-            }
+pub struct RamFs {
+    inner: Mutex<Inner>,
+}
+
+impl RamFs {
+    pub fn new() -> Self {
+        let mut inodes = BTreeMap::new();
+
+        // Create root directory
+        inodes.insert(ROOT_INO, Inode::new_dir());
+
+        RamFs {
+            inner: Mutex::new(Inner {
+                inodes,
+                next_ino: ROOT_INO + 1,
+            }),
         }
+    }
+}
 
-        let dirname = VFS::filename(&normalized).ok_or(FsError::InvalidPath)?;
-        let node = Node::new_dir(dirname.clone());
-        
-        self.nodes.lock().insert(normalized.clone(), node);
-        self.add_to_parent_dir(&normalized, &dirname)?;
-        
+impl Default for RamFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem for RamFs {
+    fn create_file(&self, path: &str) -> FsResult<()> {
+        self.inner.lock().create(path, Inode::new_file())?;
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &str) -> FsResult<()> {
+        self.inner.lock().create(path, Inode::new_dir())?;
         Ok(())
     }
 
     fn remove(&self, path: &str) -> FsResult<()> {
         let normalized = VFS::normalize_path(path);
-        
         if normalized == "/" {
             return Err(FsError::PermissionDenied);
         }
 
-        let node = self.get_node(&normalized)?;
-        
+        let mut inner = self.inner.lock();
+        let (parent, name) = inner.lookup_parent(&normalized)?;
+        let ino = *inner.entries(parent)?.get(&name).ok_or(FsError::NotFound)?;
+
         // Check if directory is empty
-        if node.file_type == FileType::Directory {
-            if let NodeData::Directory(dir) = &node.data {
-                if !dir.entries.is_empty() {
-                    return Err(FsError::PermissionDenied);
-                }
+        if let InodeData::Directory(entries) = &inner.get(ino)?.data {
+            if !entries.is_empty() {
+                return Err(FsError::PermissionDenied);
             }
         }
 
-        let filename = VFS::filename(&normalized).ok_or(FsError::InvalidPath)?;
-        self.remove_from_parent_dir(&normalized, &filename)?;
-        self.nodes.lock().remove(&normalized);
-        
-        Ok(())
+        inner.entries_mut(parent)?.remove(&name);
+        inner.unlink(ino)
     }
 
     fn read_file(&self, path: &str) -> FsResult<Vec<u8>> {
-        let normalized = VFS::normalize_path(path);
-        let node = self.get_node(&normalized)?;
-        
-        match node.data {
-            NodeData::File(file) => Ok(file.content.clone()),
-            NodeData::Directory(_) => Err(FsError::NotAFile),
+        let inner = self.inner.lock();
+        let ino = inner.lookup(path)?;
+
+        match &inner.get(ino)?.data {
+            InodeData::File(content) => Ok(content.clone()),
+            _ => Err(FsError::NotAFile),
         }
     }
 
     fn write_file(&self, path: &str, data: &[u8]) -> FsResult<()> {
-        let normalized = VFS::normalize_path(path);
-        let mut nodes = self.nodes.lock();
-        
-        if let Some(node) = nodes.get_mut(&normalized) {
-            match &mut node.data {
-                NodeData::File(file) => {
-                    file.content = data.to_vec();
-                    Ok(())
-                }
-                NodeData::Directory(_) => Err(FsError::NotAFile),
+        let mut inner = self.inner.lock();
+        let ino = inner.lookup(path)?;
+
+        match &mut inner.get_mut(ino)?.data {
+            // checked up front so a failed write leaves the old content in place
+            InodeData::File(_) if data.len() > MAX_FILE_SIZE => Err(FsError::NoSpace),
+            InodeData::File(content) => {
+                content.clear();
+                resize(content, data.len())?;
+                content.copy_from_slice(data);
+                Ok(())
             }
-        } else {
-            Err(FsError::NotFound)
+            _ => Err(FsError::NotAFile),
         }
     }
 
     fn list_dir(&self, path: &str) -> FsResult<Vec<INode>> {
-        let normalized = VFS::normalize_path(path);
-        let node = self.get_node(&normalized)?;
-        
-        match node.data {
-            NodeData::Directory(dir) => {
-                let nodes = self.nodes.lock();
-                let mut result = Vec::new();
-                
-                for entry in dir.entries.iter() {
-                    let child_path = if normalized == "/" {
-                        alloc::format!("/{}", entry)
-                    } else {
-                        alloc::format!("{}/{}", normalized, entry)
-                    };
-                    
-                    if let Some(child_node) = nodes.get(&child_path) {
-                        result.push(INode {
-                            name: entry.clone(),
-                            file_type: child_node.file_type,
-                            size: child_node.size(),
-                        });
-                    }
-                }
-                
-                Ok(result)
-            }
-            NodeData::File(_) => Err(FsError::NotADirectory),
+        let inner = self.inner.lock();
+        let ino = inner.lookup(path)?;
+
+        let mut result = Vec::new();
+        for (name, &child) in inner.entries(ino)?.iter() {
+            result.push(inner.to_inode(child, name.clone())?);
         }
+        Ok(result)
     }
 
     fn stat(&self, path: &str) -> FsResult<INode> {
-        let normalized = VFS::normalize_path(path);
-        let node = self.get_node(&normalized)?;
-        
-        let name = node.name.clone();
-        let file_type = node.file_type;
-        let size = node.size();
-        
-        Ok(INode {
-            name,
-            file_type,
-            size,
-        })
+        let inner = self.inner.lock();
+        let ino = inner.lookup(path)?;
+        let name = VFS::filename(path).unwrap_or_else(|| String::from("/"));
+        inner.to_inode(ino, name)
     }
 
     fn exists(&self, path: &str) -> bool {
-        let normalized = VFS::normalize_path(path);
-        self.nodes.lock().contains_key(&normalized)
+        self.inner.lock().lookup(path).is_ok()
+    }
+
+    fn chmod(&self, path: &str, mode: u16) -> FsResult<()> {
+        let mut inner = self.inner.lock();
+        let ino = inner.lookup(path)?;
+        inner.get_mut(ino)?.mode = mode & 0o7777;
+        Ok(())
+    }
+
+    fn chown(&self, path: &str, uid: u32, gid: u32) -> FsResult<()> {
+        let mut inner = self.inner.lock();
+        let ino = inner.lookup(path)?;
+        let inode = inner.get_mut(ino)?;
+        inode.uid = uid;
+        inode.gid = gid;
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> FsResult<()> {
+        let from = VFS::normalize_path(from);
+        let to = VFS::normalize_path(to);
+        if from == "/" || to == "/" {
+            return Err(FsError::PermissionDenied);
+        }
+
+        let mut inner = self.inner.lock();
+        let (from_parent, from_name) = inner.lookup_parent(&from)?;
+        let ino = *inner
+            .entries(from_parent)?
+            .get(&from_name)
+            .ok_or(FsError::NotFound)?;
+        if from == to {
+            return Ok(());
+        }
+        // a directory can't be moved into its own subtree
+        if VFS::is_within(&to, &from) {
+            return Err(FsError::InvalidPath);
+        }
+        let (to_parent, to_name) = inner.lookup_parent(&to)?;
+        let is_dir = inner.get(ino)?.file_type == FileType::Directory;
+
+        // an existing target is replaced in the same step, but only by the same kind of node
+        let replaced = inner.entries(to_parent)?.get(&to_name).copied();
+        if let Some(target) = replaced {
+            // two hard links to the same file: nothing to do
+            if target == ino {
+                return Ok(());
+            }
+            match (is_dir, &inner.get(target)?.data) {
+                (true, InodeData::Directory(entries)) if entries.is_empty() => {}
+                (true, InodeData::Directory(_)) => return Err(FsError::PermissionDenied),
+                (true, _) => return Err(FsError::NotADirectory),
+                (false, InodeData::Directory(_)) => return Err(FsError::NotAFile),
+                (false, _) => {}
+            }
+        }
+
+        inner.entries_mut(from_parent)?.remove(&from_name);
+        inner.entries_mut(to_parent)?.insert(to_name, ino);
+        if let Some(target) = replaced {
+            inner.unlink(target)?;
+        }
+        Ok(())
+    }
+
+    fn read_at(&self, path: &str, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let inner = self.inner.lock();
+        let ino = inner.lookup(path)?;
+
+        match &inner.get(ino)?.data {
+            InodeData::File(content) => {
+                if offset >= content.len() {
+                    return Ok(0);
+                }
+                let len = buf.len().min(content.len() - offset);
+                buf[..len].copy_from_slice(&content[offset..offset + len]);
+                Ok(len)
+            }
+            _ => Err(FsError::NotAFile),
+        }
+    }
+
+    fn write_at(&self, path: &str, offset: usize, data: &[u8]) -> FsResult<usize> {
+        let mut inner = self.inner.lock();
+        let content = inner.content_mut(path)?;
+
+        let end = offset.checked_add(data.len()).ok_or(FsError::NoSpace)?;
+        if content.len() < end {
+            resize(content, end)?;
+        }
+        content[offset..end].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn append(&self, path: &str, data: &[u8]) -> FsResult<usize> {
+        let mut inner = self.inner.lock();
+        let content = inner.content_mut(path)?;
+        let start = content.len();
+        let end = start.checked_add(data.len()).ok_or(FsError::NoSpace)?;
+        resize(content, end)?;
+        content[start..].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn truncate(&self, path: &str, len: usize) -> FsResult<()> {
+        let mut inner = self.inner.lock();
+        resize(inner.content_mut(path)?, len)
+    }
+
+    fn symlink(&self, target: &str, path: &str) -> FsResult<()> {
+        if target.is_empty() {
+            return Err(FsError::NotFound);
+        }
+        self.inner.lock().create(path, Inode::new_symlink(target))?;
+        Ok(())
+    }
+
+    fn readlink(&self, path: &str) -> FsResult<String> {
+        let inner = self.inner.lock();
+        let ino = inner.lookup(path)?;
+
+        match &inner.get(ino)?.data {
+            InodeData::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn link(&self, existing: &str, new: &str) -> FsResult<()> {
+        let mut inner = self.inner.lock();
+        let ino = inner.lookup(existing)?;
+        // hard links to directories would let the tree grow cycles
+        if inner.get(ino)?.file_type == FileType::Directory {
+            return Err(FsError::PermissionDenied);
+        }
+
+        let (parent, name) = inner.lookup_parent(new)?;
+        if inner.entries(parent)?.contains_key(&name) {
+            return Err(FsError::AlreadyExists);
+        }
+        inner.entries_mut(parent)?.insert(name, ino);
+        inner.get_mut(ino)?.nlink += 1;
+        Ok(())
+    }
+}
+
+// zero-fills or cuts `content` to `len` bytes, failing with NoSpace past MAX_FILE_SIZE or
+// when the heap can't hold it
+fn resize(content: &mut Vec<u8>, len: usize) -> FsResult<()> {
+    if len > MAX_FILE_SIZE {
+        return Err(FsError::NoSpace);
     }
+    content
+        .try_reserve(len.saturating_sub(content.len()))
+        .map_err(|_| FsError::NoSpace)?;
+    content.resize(len, 0);
+    Ok(())
 }
@@ -1,3 +1,4 @@
+use crate::drivers::block::BlockError;
 use alloc::string::String;
 use alloc::vec::Vec;
 use core::fmt;
@@ -6,6 +7,8 @@ use core::fmt;
 pub enum FileType {
     File,
     Directory,
+    Symlink,
+    CharDevice,
 }
 
 #[derive(Debug)]
@@ -17,6 +20,18 @@ pub enum FsError {
     InvalidPath,
     NoSpace,
     PermissionDenied,
+    InvalidData,
+    Busy,
+    NotSupported,
+    CrossDevice,
+    InvalidArgument,
+    BadDescriptor,
+    TooManyLinks,
+    BrokenPipe,
+    Interrupted,
+    /// The call has to wait for something; see `process::park_until`.
+    WouldBlock,
+    Io,
 }
 
 impl fmt::Display for FsError {
@@ -29,6 +44,26 @@ impl fmt::Display for FsError {
             FsError::InvalidPath => write!(f, "Invalid path"),
             FsError::NoSpace => write!(f, "No space left"),
             FsError::PermissionDenied => write!(f, "Permission denied"),
+            FsError::InvalidData => write!(f, "Invalid or corrupted data"),
+            FsError::Busy => write!(f, "Device or resource busy"),
+            FsError::NotSupported => write!(f, "Operation not supported"),
+            FsError::CrossDevice => write!(f, "Invalid cross-device link"),
+            FsError::InvalidArgument => write!(f, "Invalid argument"),
+            FsError::BadDescriptor => write!(f, "Bad file descriptor"),
+            FsError::TooManyLinks => write!(f, "Too many levels of symbolic links"),
+            FsError::BrokenPipe => write!(f, "Broken pipe"),
+            FsError::Interrupted => write!(f, "Interrupted system call"),
+            FsError::WouldBlock => write!(f, "Resource temporarily unavailable"),
+            FsError::Io => write!(f, "Input/output error"),
+        }
+    }
+}
+
+impl From<BlockError> for FsError {
+    fn from(err: BlockError) -> Self {
+        match err {
+            BlockError::OutOfRange => FsError::InvalidData,
+            _ => FsError::Io,
         }
     }
 }
@@ -38,8 +73,13 @@ pub type FsResult<T> = Result<T, FsError>;
 #[derive(Clone)]
 pub struct INode {
     pub name: String,
+    pub ino: u64,
     pub file_type: FileType,
     pub size: usize,
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: usize,
 }
 
 #[derive(Default)]
@@ -48,6 +88,7 @@ pub struct OpenOptions {
     pub write: bool,
     pub create: bool,
     pub truncate: bool,
+    pub append: bool,
 }
 
 impl OpenOptions {
@@ -74,6 +115,11 @@ impl OpenOptions {
         self.truncate = truncate;
         self
     }
+
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
 }
 
 pub trait FileSystem {
@@ -85,21 +131,54 @@ pub trait FileSystem {
     fn list_dir(&self, path: &str) -> FsResult<Vec<INode>>;
     fn stat(&self, path: &str) -> FsResult<INode>;
     fn exists(&self, path: &str) -> bool;
+    fn chmod(&self, path: &str, mode: u16) -> FsResult<()>;
+    fn chown(&self, path: &str, uid: u32, gid: u32) -> FsResult<()>;
+    /// Moves `from` to `to`, atomically replacing `to` if it is a file or an empty directory.
+    fn rename(&self, from: &str, to: &str) -> FsResult<()>;
+    /// Reads up to `buf.len()` bytes starting at `offset`; returns 0 at end of file.
+    fn read_at(&self, path: &str, offset: usize, buf: &mut [u8]) -> FsResult<usize>;
+    /// Writes `data` at `offset`, growing the file (zero-filled) as needed.
+    fn write_at(&self, path: &str, offset: usize, data: &[u8]) -> FsResult<usize>;
+    fn append(&self, path: &str, data: &[u8]) -> FsResult<usize>;
+    fn truncate(&self, path: &str, len: usize) -> FsResult<()>;
+
+    /// Like [`FileSystem::stat`], but describes a symlink itself rather than its target.
+    fn lstat(&self, path: &str) -> FsResult<INode> {
+        self.stat(path)
+    }
+
+    /// Creates a symlink at `path`; `target` is stored as given and resolved on use.
+    fn symlink(&self, _target: &str, _path: &str) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn readlink(&self, _path: &str) -> FsResult<String> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Adds `new` as another name (hard link) for the file at `existing`.
+    fn link(&self, _existing: &str, _new: &str) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
 }
 
 pub struct VFS;
 
 impl VFS {
+    /// Collapses `.`, `..` and repeated slashes. Relative paths are taken to be
+    /// relative to `/`; use [`VFS::resolve`] to resolve them against a working directory.
     pub fn normalize_path(path: &str) -> String {
-        if path.is_empty() || !path.starts_with('/') {
-            return "/".into();
-        }
-        
-        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        if parts.is_empty() {
-            return "/".into();
+        let mut parts: Vec<&str> = Vec::new();
+        for part in path.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    parts.pop();
+                }
+                part => parts.push(part),
+            }
         }
-        
+
         let mut normalized = String::from("/");
         for (i, part) in parts.iter().enumerate() {
             if i > 0 {
@@ -110,6 +189,15 @@ impl VFS {
         normalized
     }
 
+    /// Turns `path` into an absolute, normalized path, interpreting relative paths from `cwd`.
+    pub fn resolve(cwd: &str, path: &str) -> String {
+        if path.starts_with('/') {
+            Self::normalize_path(path)
+        } else {
+            Self::join(cwd, path)
+        }
+    }
+
     pub fn parent_path(path: &str) -> Option<String> {
         if path == "/" {
             return None;
@@ -136,6 +224,24 @@ impl VFS {
         Some(parent)
     }
 
+    pub fn join(dir: &str, name: &str) -> String {
+        let mut joined = String::from(dir);
+        if !joined.ends_with('/') {
+            joined.push('/');
+        }
+        joined.push_str(name);
+        Self::normalize_path(&joined)
+    }
+
+    /// True if `path` is `ancestor` or lies below it.
+    pub fn is_within(path: &str, ancestor: &str) -> bool {
+        let path = Self::normalize_path(path);
+        let ancestor = Self::normalize_path(ancestor);
+        ancestor == "/"
+            || path == ancestor
+            || (path.starts_with(&ancestor) && path.as_bytes().get(ancestor.len()) == Some(&b'/'))
+    }
+
     pub fn filename(path: &str) -> Option<String> {
         let normalized = Self::normalize_path(path);
         let parts: Vec<&str> = normalized.split('/').filter(|s| !s.is_empty()).collect();
@@ -0,0 +1,80 @@
+use core::fmt;
+use spin::Mutex;
+
+// kernel log: everything printed with print!/println! is also kept here so it can be
+// read back later through /dev/kmsg
+
+const LOG_SIZE: usize = 8192;
+
+/// Fixed-size ring of log bytes; once full, the oldest bytes are overwritten.
+pub struct LogBuffer {
+    data: [u8; LOG_SIZE],
+    // total bytes ever written; the buffer holds the last LOG_SIZE of them
+    written: usize,
+}
+
+impl LogBuffer {
+    pub const fn new() -> Self {
+        LogBuffer {
+            data: [0; LOG_SIZE],
+            written: 0,
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.data[self.written % LOG_SIZE] = b;
+            self.written += 1;
+        }
+    }
+
+    /// Number of bytes currently held.
+    pub fn len(&self) -> usize {
+        self.written.min(LOG_SIZE)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.written == 0
+    }
+
+    /// Copies held bytes starting `offset` bytes into the log (oldest first).
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let len = self.len();
+        if offset >= len {
+            return 0;
+        }
+        let start = self.written - len + offset;
+        let count = buf.len().min(len - offset);
+        for (i, b) in buf[..count].iter_mut().enumerate() {
+            *b = self.data[(start + i) % LOG_SIZE];
+        }
+        count
+    }
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+pub static KMSG: Mutex<LogBuffer> = Mutex::new(LogBuffer::new());
+
+pub fn write(bytes: &[u8]) {
+    x86_64::instructions::interrupts::without_interrupts(|| KMSG.lock().write(bytes));
+}
+
+pub fn read_at(offset: usize, buf: &mut [u8]) -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| KMSG.lock().read_at(offset, buf))
+}
+
+pub fn len() -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| KMSG.lock().len())
+}
@@ -0,0 +1,277 @@
+use crate::arch::x86_64::trap::TrapFrame;
+use crate::arch::x86_64::{gdt, timer};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::{self, without_interrupts};
+
+pub type KthreadId = u64;
+
+/// The software interrupt a kernel thread raises to give up the CPU.
+pub const YIELD_VECTOR: u8 = 0x81;
+
+const STACK_SIZE: usize = 4096 * 16;
+
+// timer ticks a kernel thread runs before the next runnable one gets the CPU
+const TIME_SLICE: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KthreadState {
+    Runnable,
+    /// In `sleep_ms` until the uptime reaches `until` (in ms).
+    Sleeping {
+        until: u64,
+    },
+    /// Returned from its function; the stack is freed by the next `spawn_kthread`.
+    Exited,
+}
+
+impl fmt::Display for KthreadState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KthreadState::Runnable => write!(f, "R (runnable)"),
+            KthreadState::Sleeping { .. } => write!(f, "S (sleeping)"),
+            KthreadState::Exited => write!(f, "X (exited)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KthreadInfo {
+    pub id: KthreadId,
+    pub name: String,
+    pub state: KthreadState,
+}
+
+struct Kthread {
+    name: String,
+    state: KthreadState,
+    /// Where its registers were saved when it last lost the CPU; stale while it runs.
+    frame: *mut TrapFrame,
+    /// Held until the thread is reaped; None for the boot thread, which keeps the
+    /// bootloader's stack.
+    _stack: Option<Box<[u8]>>,
+}
+
+struct Kthreads {
+    threads: BTreeMap<KthreadId, Kthread>,
+    current: KthreadId,
+    slice_start: u64,
+}
+
+// the frame pointers only ever point into the threads' own stacks
+unsafe impl Send for Kthreads {}
+
+// Only locked with interrupts off, since the timer interrupt switches threads under it.
+static KTHREADS: Mutex<Kthreads> = Mutex::new(Kthreads {
+    threads: BTreeMap::new(),
+    // the boot thread, which becomes a kernel thread like any other on the first spawn
+    current: 0,
+    slice_start: 0,
+});
+
+/// Starts `f` on a kernel thread of its own stack. It runs alongside the others, including
+/// the one driving the async executor, and is preempted by the timer like them.
+///
+/// Code that runs in ring 3 stays on the thread that called `userspace::run`, since the
+/// syscall and ring 0 stacks exist once.
+pub fn spawn_kthread<F>(name: &str, f: F) -> KthreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+    reap();
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let entry: Box<Box<dyn FnOnce() + Send>> = Box::new(Box::new(f));
+    let mut stack = vec![0u8; STACK_SIZE].into_boxed_slice();
+    let top = (stack.as_mut_ptr() as u64 + STACK_SIZE as u64) & !0xF;
+
+    // the thread starts by "returning" from this frame into kthread_start, with the stack
+    // aligned as if kthread_start had been called
+    let frame = (top - 256) as *mut TrapFrame;
+    let selectors = gdt::selectors();
+    unsafe {
+        frame.write(TrapFrame {
+            rip: kthread_start as *const () as u64,
+            cs: selectors.kernel_code_selector.0 as u64,
+            rflags: 0x202,
+            rsp: top - 8,
+            ss: selectors.kernel_data_selector.0 as u64,
+            rdi: Box::into_raw(entry) as u64,
+            ..TrapFrame::default()
+        });
+    }
+
+    without_interrupts(|| {
+        let mut kthreads = KTHREADS.lock();
+        let boot = kthreads.current;
+        kthreads.threads.entry(boot).or_insert_with(|| Kthread {
+            name: "main".to_string(),
+            state: KthreadState::Runnable,
+            frame: core::ptr::null_mut(),
+            _stack: None,
+        });
+        kthreads.threads.insert(
+            id,
+            Kthread {
+                name: name.to_string(),
+                state: KthreadState::Runnable,
+                frame,
+                _stack: Some(stack),
+            },
+        );
+    });
+    id
+}
+
+extern "C" fn kthread_start(entry: *mut Box<dyn FnOnce() + Send>) -> ! {
+    let entry = unsafe { Box::from_raw(entry) };
+    entry();
+    exit()
+}
+
+/// Ends the calling kernel thread.
+pub fn exit() -> ! {
+    set_state(KthreadState::Exited);
+    loop {
+        yield_now();
+        // nothing else could run; wait for a sleeper to come due
+        interrupts::enable_and_hlt();
+    }
+}
+
+/// Lets the next runnable kernel thread have the CPU, if there is one.
+pub fn yield_now() {
+    unsafe { core::arch::asm!("int {}", const YIELD_VECTOR) };
+}
+
+/// Blocks the calling kernel thread for at least `ms` milliseconds, running others meanwhile.
+pub fn sleep_ms(ms: u64) {
+    let until = timer::uptime_ms() + ms;
+    while timer::uptime_ms() < until {
+        set_state(KthreadState::Sleeping { until });
+        yield_now();
+        if timer::uptime_ms() < until {
+            interrupts::enable_and_hlt();
+        }
+    }
+    set_state(KthreadState::Runnable);
+}
+
+/// For a kernel thread with nothing to do until the next interrupt, called with interrupts
+/// off: halts, or if another kernel thread can use the CPU, yields to it. Returns with
+/// interrupts on.
+pub fn idle() {
+    let others = KTHREADS.lock().next_runnable(timer::uptime_ms()).is_some();
+    interrupts::enable();
+    if others {
+        yield_now();
+    } else {
+        interrupts::enable_and_hlt();
+    }
+}
+
+pub fn current() -> KthreadId {
+    without_interrupts(|| KTHREADS.lock().current)
+}
+
+pub fn list() -> Vec<KthreadInfo> {
+    without_interrupts(|| {
+        KTHREADS
+            .lock()
+            .threads
+            .iter()
+            .map(|(&id, t)| KthreadInfo {
+                id,
+                name: t.name.clone(),
+                state: t.state,
+            })
+            .collect()
+    })
+}
+
+fn set_state(state: KthreadState) {
+    without_interrupts(|| {
+        let mut kthreads = KTHREADS.lock();
+        let id = kthreads.current;
+        if let Some(thread) = kthreads.threads.get_mut(&id) {
+            thread.state = state;
+        }
+    });
+}
+
+// frees the stacks of exited threads; never from an interrupt, which may not allocate
+fn reap() {
+    let dead: Vec<Kthread> = without_interrupts(|| {
+        let mut kthreads = KTHREADS.lock();
+        let current = kthreads.current;
+        let ids: Vec<KthreadId> = kthreads
+            .threads
+            .iter()
+            .filter(|&(&id, t)| t.state == KthreadState::Exited && id != current)
+            .map(|(&id, _)| id)
+            .collect();
+        ids.iter()
+            .filter_map(|id| kthreads.threads.remove(id))
+            .collect()
+    });
+    drop(dead);
+}
+
+impl Kthreads {
+    // the next thread after the current one, in id order, that can run
+    fn next_runnable(&self, now: u64) -> Option<KthreadId> {
+        let ready = |t: &Kthread| match t.state {
+            KthreadState::Runnable => true,
+            KthreadState::Sleeping { until } => until <= now,
+            KthreadState::Exited => false,
+        };
+        self.threads
+            .range(self.current + 1..)
+            .chain(self.threads.range(..self.current))
+            .find(|(_, t)| ready(t))
+            .map(|(&id, _)| id)
+    }
+
+    // saves `frame` for the current thread and returns the one to resume
+    fn switch(&mut self, frame: *mut TrapFrame, ticks: u64) -> *mut TrapFrame {
+        self.slice_start = ticks;
+        let Some(next) = self.next_runnable(timer::uptime_ms()) else {
+            return frame;
+        };
+        if let Some(thread) = self.threads.get_mut(&self.current) {
+            thread.frame = frame;
+        }
+        let thread = self.threads.get_mut(&next).unwrap();
+        if let KthreadState::Sleeping { .. } = thread.state {
+            thread.state = KthreadState::Runnable;
+        }
+        self.current = next;
+        thread.frame
+    }
+}
+
+/// Called by the timer interrupt handler with the interrupted context; returns the context
+/// to resume, which is another thread's once the time slice is used up.
+pub(crate) fn on_timer_tick(frame: &mut TrapFrame, ticks: u64) -> *mut TrapFrame {
+    let mut kthreads = KTHREADS.lock();
+    let running = kthreads
+        .threads
+        .get(&kthreads.current)
+        .is_none_or(|t| t.state == KthreadState::Runnable);
+    if running && ticks.saturating_sub(kthreads.slice_start) < TIME_SLICE {
+        return frame;
+    }
+    kthreads.switch(frame, ticks)
+}
+
+/// Called by the `YIELD_VECTOR` handler.
+pub(crate) fn on_yield(frame: &mut TrapFrame) -> *mut TrapFrame {
+    KTHREADS.lock().switch(frame, timer::ticks())
+}
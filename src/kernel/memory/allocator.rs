@@ -1,4 +1,6 @@
+use core::alloc::{GlobalAlloc, Layout};
 use linked_list_allocator::LockedHeap;
+use x86_64::instructions::interrupts::without_interrupts;
 use x86_64::{
     structures::paging::{
         mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
@@ -7,10 +9,25 @@ use x86_64::{
 };
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024;
+pub const HEAP_SIZE: usize = 2 * 1024 * 1024;
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: Allocator = Allocator(LockedHeap::empty());
+
+/// The heap, locked with interrupts off: the timer switches kernel threads, and one
+/// switched away while holding the lock would leave the next thread to allocate with
+/// interrupts off spinning forever.
+struct Allocator(LockedHeap);
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| self.0.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| self.0.dealloc(ptr, layout))
+    }
+}
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
@@ -32,13 +49,29 @@ pub fn init_heap(
         unsafe {
             mapper.map_to(page, frame, flags, frame_allocator)?.flush();
         };
-        unsafe {
-            ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
-        }
+        without_interrupts(|| unsafe { ALLOCATOR.0.lock().init(HEAP_START, HEAP_SIZE) });
     }
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+}
+
+pub fn heap_stats() -> HeapStats {
+    without_interrupts(|| {
+        let heap = ALLOCATOR.0.lock();
+        HeapStats {
+            size: heap.size(),
+            used: heap.used(),
+            free: heap.free(),
+        }
+    })
+}
+
 //pub struct Dummy;
 
 //unsafe impl GlobalAlloc for Dummy {
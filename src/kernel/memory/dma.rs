@@ -0,0 +1,134 @@
+use super::memory::phys_to_virt;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, Size4KiB};
+use x86_64::PhysAddr;
+
+const PAGE_SIZE: usize = 4096;
+/// Size of the pool set aside for device DMA.
+pub const POOL_PAGES: usize = 512;
+
+// a physically contiguous run of frames, handed out in whole pages
+struct Pool {
+    start: u64,
+    pages: usize,
+    used: [u64; POOL_PAGES / 64],
+}
+
+impl Pool {
+    fn is_used(&self, page: usize) -> bool {
+        self.used[page / 64] & (1 << (page % 64)) != 0
+    }
+
+    fn set_used(&mut self, page: usize, used: bool) {
+        if used {
+            self.used[page / 64] |= 1 << (page % 64);
+        } else {
+            self.used[page / 64] &= !(1 << (page % 64));
+        }
+    }
+}
+
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    start: 0,
+    pages: 0,
+    used: [0; POOL_PAGES / 64],
+});
+
+/// Reserves `POOL_PAGES` physically contiguous frames for DMA.
+///
+/// The frame allocator hands out frames in address order, so a run only breaks at the
+/// end of a usable region; the frames before such a break are given up.
+pub fn init(frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+    let mut start = 0;
+    let mut pages = 0;
+    while pages < POOL_PAGES {
+        let frame = match frame_allocator.allocate_frame() {
+            Some(frame) => frame.start_address().as_u64(),
+            None => break,
+        };
+        if pages == 0 || frame != start + (pages * PAGE_SIZE) as u64 {
+            start = frame;
+            pages = 0;
+        }
+        pages += 1;
+    }
+
+    let mut pool = POOL.lock();
+    pool.start = start;
+    pool.pages = pages;
+}
+
+/// Memory a device can read and write: physically contiguous and reachable at a known
+/// physical address. Freed when dropped.
+pub struct DmaBuffer {
+    phys: PhysAddr,
+    len: usize,
+}
+
+impl DmaBuffer {
+    /// Allocates `len` zeroed bytes, page aligned; None if the pool has no run that large.
+    pub fn new(len: usize) -> Option<DmaBuffer> {
+        let count = len.max(1).div_ceil(PAGE_SIZE);
+        let mut pool = POOL.lock();
+        let mut run = 0;
+        let mut first = None;
+        for page in 0..pool.pages {
+            run = if pool.is_used(page) { 0 } else { run + 1 };
+            if run == count {
+                first = Some(page + 1 - count);
+                break;
+            }
+        }
+        let first = first?;
+        for page in first..first + count {
+            pool.set_used(page, true);
+        }
+        let phys = PhysAddr::new(pool.start + (first * PAGE_SIZE) as u64);
+        drop(pool);
+
+        let mut buffer = DmaBuffer { phys, len };
+        buffer.as_mut_slice().fill(0);
+        Some(buffer)
+    }
+
+    pub fn phys(&self) -> PhysAddr {
+        self.phys
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        phys_to_virt(self.phys).as_mut_ptr()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        let mut pool = POOL.lock();
+        let first = ((self.phys.as_u64() - pool.start) as usize) / PAGE_SIZE;
+        for page in first..first + self.len.max(1).div_ceil(PAGE_SIZE) {
+            pool.set_used(page, false);
+        }
+    }
+}
+
+/// Pages of the DMA pool in use and in total.
+pub fn stats() -> (usize, usize) {
+    let pool = POOL.lock();
+    let used = (0..pool.pages).filter(|&p| pool.is_used(p)).count();
+    (used, pool.pages)
+}
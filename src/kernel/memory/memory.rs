@@ -1,11 +1,30 @@
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use x86_64::{
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+        mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, PageTable,
+        PageTableFlags, PhysFrame, Size2MiB, Size4KiB, Translate,
     },
     PhysAddr, VirtAddr,
 };
 
+// frame counts for /proc/meminfo
+static FRAMES_TOTAL: AtomicUsize = AtomicUsize::new(0);
+static FRAMES_USED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub total: usize,
+    pub used: usize,
+}
+
+pub fn frame_stats() -> FrameStats {
+    FrameStats {
+        total: FRAMES_TOTAL.load(Ordering::Relaxed),
+        used: FRAMES_USED.load(Ordering::Relaxed),
+    }
+}
+
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
@@ -13,10 +32,13 @@ pub struct BootInfoFrameAllocator {
 
 impl BootInfoFrameAllocator {
     pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
+        let allocator = BootInfoFrameAllocator {
             memory_map,
             next: 0,
-        }
+        };
+        FRAMES_TOTAL.store(allocator.usable_frames().count(), Ordering::Relaxed);
+        FRAMES_USED.store(0, Ordering::Relaxed);
+        allocator
     }
 
     fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
@@ -36,11 +58,18 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
+        if frame.is_some() {
+            FRAMES_USED.fetch_add(1, Ordering::Relaxed);
+        }
         frame
     }
 }
 
+// where the bootloader mapped all of physical memory
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYS_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
     unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
         OffsetPageTable::new(level_4_table, physical_memory_offset)
@@ -59,6 +88,71 @@ pub unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static
     unsafe { &mut *page_table_ptr }
 }
 
+/// The virtual address of `addr` in the bootloader's physical memory mapping.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYS_OFFSET.load(Ordering::Relaxed) + addr.as_u64())
+}
+
+/// Makes `size` bytes of device memory at `phys` reachable through `phys_to_virt`.
+///
+/// The bootloader only maps the ranges in the memory map, so MMIO regions above RAM
+/// are mapped here, uncached, with 2 MiB pages where the range allows it.
+pub fn map_mmio(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    phys: PhysAddr,
+    size: u64,
+) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    const HUGE: u64 = 2 * 1024 * 1024;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    let end = phys.as_u64() + size;
+    let mut addr = phys.align_down(4096u64).as_u64();
+
+    while addr < end {
+        let virt = phys_to_virt(PhysAddr::new(addr));
+        if mapper.translate_addr(virt).is_some() {
+            addr += 4096;
+            continue;
+        }
+
+        let huge = addr & (HUGE - 1) == 0 && end - addr >= HUGE;
+        if huge {
+            let page = Page::<Size2MiB>::containing_address(virt);
+            let frame = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(addr));
+            let mapped = unsafe {
+                mapper.map_to(
+                    page,
+                    frame,
+                    flags | PageTableFlags::HUGE_PAGE,
+                    frame_allocator,
+                )
+            };
+            match mapped {
+                Ok(flush) => {
+                    flush.flush();
+                    addr += HUGE;
+                    continue;
+                }
+                // part of the range is mapped already: go page by page
+                Err(MapToError::PageAlreadyMapped(_)) | Err(MapToError::ParentEntryHugePage) => {}
+                Err(MapToError::FrameAllocationFailed) => {
+                    return Err(MapToError::FrameAllocationFailed)
+                }
+            }
+        }
+
+        let page = Page::<Size4KiB>::containing_address(virt);
+        let frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(addr));
+        match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => flush.flush(),
+            Err(MapToError::PageAlreadyMapped(_)) => {}
+            Err(e) => return Err(e),
+        }
+        addr += 4096;
+    }
+    Ok(phys_to_virt(phys))
+}
+
 // Creates an example mapping for the given page to frame `0xb8000`.
 pub fn create_example_mapping(
     page: Page,
@@ -1,2 +1,3 @@
 pub mod allocator;
+pub mod dma;
 pub mod memory;
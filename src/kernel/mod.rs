@@ -1,4 +1,12 @@
 pub mod fs;
+pub mod kmsg;
+pub mod kthread;
 pub mod memory;
+pub mod net;
+pub mod password;
+pub mod process;
+pub mod signal;
 pub mod task;
+pub mod thread;
+pub mod user;
 pub mod userspace;
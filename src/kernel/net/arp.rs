@@ -0,0 +1,101 @@
+use super::Ipv4Addr;
+use crate::drivers::net::MacAddress;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+pub const OP_REQUEST: u16 = 1;
+pub const OP_REPLY: u16 = 2;
+
+const PACKET_SIZE: usize = 28;
+const HTYPE_ETHERNET: u16 = 1;
+const PTYPE_IPV4: u16 = 0x0800;
+
+/// How long a learned address is trusted.
+const ENTRY_LIFETIME_MS: u64 = 60_000;
+
+pub struct Packet {
+    pub op: u16,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Addr,
+}
+
+/// Parses an Ethernet/IPv4 ARP packet; other hardware or protocol types are ignored.
+pub fn parse(data: &[u8]) -> Option<Packet> {
+    if data.len() < PACKET_SIZE
+        || u16::from_be_bytes([data[0], data[1]]) != HTYPE_ETHERNET
+        || u16::from_be_bytes([data[2], data[3]]) != PTYPE_IPV4
+        || data[4] != 6
+        || data[5] != 4
+    {
+        return None;
+    }
+    let mac = |at: usize| {
+        let mut mac = [0; 6];
+        mac.copy_from_slice(&data[at..at + 6]);
+        MacAddress(mac)
+    };
+    let ip = |at: usize| Ipv4Addr::new(data[at], data[at + 1], data[at + 2], data[at + 3]);
+    Some(Packet {
+        op: u16::from_be_bytes([data[6], data[7]]),
+        sender_mac: mac(8),
+        sender_ip: ip(14),
+        target_mac: mac(18),
+        target_ip: ip(24),
+    })
+}
+
+pub fn build(packet: &Packet) -> Vec<u8> {
+    let mut data = Vec::with_capacity(PACKET_SIZE);
+    data.extend_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+    data.extend_from_slice(&PTYPE_IPV4.to_be_bytes());
+    data.extend_from_slice(&[6, 4]);
+    data.extend_from_slice(&packet.op.to_be_bytes());
+    data.extend_from_slice(&packet.sender_mac.0);
+    data.extend_from_slice(&packet.sender_ip.octets());
+    data.extend_from_slice(&packet.target_mac.0);
+    data.extend_from_slice(&packet.target_ip.octets());
+    data
+}
+
+/// Learned IPv4 to MAC mappings.
+pub struct Cache {
+    entries: BTreeMap<Ipv4Addr, (MacAddress, u64)>,
+}
+
+impl Cache {
+    pub const fn new() -> Self {
+        Cache {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, ip: Ipv4Addr, mac: MacAddress, now: u64) {
+        self.entries.insert(ip, (mac, now + ENTRY_LIFETIME_MS));
+    }
+
+    pub fn lookup(&self, ip: Ipv4Addr, now: u64) -> Option<MacAddress> {
+        match self.entries.get(&ip) {
+            Some(&(mac, expires)) if expires > now => Some(mac),
+            _ => None,
+        }
+    }
+
+    pub fn expire(&mut self, now: u64) {
+        self.entries.retain(|_, (_, expires)| *expires > now);
+    }
+
+    pub fn entries(&self) -> Vec<(Ipv4Addr, MacAddress)> {
+        self.entries
+            .iter()
+            .map(|(&ip, &(mac, _))| (ip, mac))
+            .collect()
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,41 @@
+use crate::drivers::net::MacAddress;
+use alloc::vec::Vec;
+
+pub const HEADER_SIZE: usize = 14;
+
+pub const TYPE_IPV4: u16 = 0x0800;
+pub const TYPE_ARP: u16 = 0x0806;
+
+pub struct Frame<'a> {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+    pub ethertype: u16,
+    pub payload: &'a [u8],
+}
+
+fn mac_at(data: &[u8], offset: usize) -> MacAddress {
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&data[offset..offset + 6]);
+    MacAddress(mac)
+}
+
+pub fn parse(frame: &[u8]) -> Option<Frame<'_>> {
+    if frame.len() < HEADER_SIZE {
+        return None;
+    }
+    Some(Frame {
+        dst: mac_at(frame, 0),
+        src: mac_at(frame, 6),
+        ethertype: u16::from_be_bytes([frame[12], frame[13]]),
+        payload: &frame[HEADER_SIZE..],
+    })
+}
+
+pub fn build(dst: MacAddress, src: MacAddress, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&dst.0);
+    frame.extend_from_slice(&src.0);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
@@ -0,0 +1,56 @@
+use super::ipv4;
+use alloc::vec::Vec;
+
+pub const HEADER_SIZE: usize = 8;
+
+pub const TYPE_ECHO_REPLY: u8 = 0;
+pub const TYPE_DEST_UNREACHABLE: u8 = 3;
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+
+pub const CODE_PORT_UNREACHABLE: u8 = 3;
+
+pub struct Message<'a> {
+    pub kind: u8,
+    pub code: u8,
+    /// The type-specific second word: identifier and sequence number for echoes.
+    pub rest: [u8; 4],
+    pub payload: &'a [u8],
+}
+
+impl Message<'_> {
+    pub fn echo_id(&self) -> u16 {
+        u16::from_be_bytes([self.rest[0], self.rest[1]])
+    }
+
+    pub fn echo_seq(&self) -> u16 {
+        u16::from_be_bytes([self.rest[2], self.rest[3]])
+    }
+}
+
+pub fn parse(data: &[u8]) -> Option<Message<'_>> {
+    if data.len() < HEADER_SIZE || ipv4::checksum(data) != 0 {
+        return None;
+    }
+    Some(Message {
+        kind: data[0],
+        code: data[1],
+        rest: [data[4], data[5], data[6], data[7]],
+        payload: &data[HEADER_SIZE..],
+    })
+}
+
+pub fn build(kind: u8, code: u8, rest: [u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + payload.len());
+    data.extend_from_slice(&[kind, code, 0, 0]);
+    data.extend_from_slice(&rest);
+    data.extend_from_slice(payload);
+    let sum = ipv4::checksum(&data);
+    data[2..4].copy_from_slice(&sum.to_be_bytes());
+    data
+}
+
+pub fn echo_request(id: u16, seq: u16, payload: &[u8]) -> Vec<u8> {
+    let [a, b] = id.to_be_bytes();
+    let [c, d] = seq.to_be_bytes();
+    build(TYPE_ECHO_REQUEST, 0, [a, b, c, d], payload)
+}
@@ -0,0 +1,112 @@
+use super::Ipv4Addr;
+use alloc::vec::Vec;
+
+pub const HEADER_SIZE: usize = 20;
+
+pub const PROTO_ICMP: u8 = 1;
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+
+const DEFAULT_TTL: u8 = 64;
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1FFF;
+
+pub struct Packet<'a> {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub payload: &'a [u8],
+}
+
+// one's complement sum of big-endian 16-bit words, not yet folded
+fn sum(data: &[u8], mut acc: u32) -> u32 {
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        acc += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+    if let [last] = words.remainder() {
+        acc += (*last as u32) << 8;
+    }
+    acc
+}
+
+fn fold(mut acc: u32) -> u16 {
+    while acc > 0xFFFF {
+        acc = (acc & 0xFFFF) + (acc >> 16);
+    }
+    !(acc as u16)
+}
+
+/// The Internet checksum of `data`. Over data that includes its own checksum, 0 means valid.
+pub fn checksum(data: &[u8]) -> u16 {
+    fold(sum(data, 0))
+}
+
+/// The checksum UDP and TCP use: `data` plus a pseudo header of the addresses and protocol.
+pub fn pseudo_checksum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, data: &[u8]) -> u16 {
+    let mut acc = sum(&src.octets(), 0);
+    acc = sum(&dst.octets(), acc);
+    acc += protocol as u32 + data.len() as u32;
+    fold(sum(data, acc))
+}
+
+pub fn netmask(prefix: u8) -> Ipv4Addr {
+    match prefix {
+        0 => Ipv4Addr::UNSPECIFIED,
+        n => Ipv4Addr::from(u32::MAX << (32 - n.min(32) as u32)),
+    }
+}
+
+/// Whether `a` and `b` are on the same `/prefix` network.
+pub fn same_subnet(a: Ipv4Addr, b: Ipv4Addr, prefix: u8) -> bool {
+    let mask = u32::from(netmask(prefix));
+    u32::from(a) & mask == u32::from(b) & mask
+}
+
+pub fn subnet_broadcast(addr: Ipv4Addr, prefix: u8) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from(addr) | !u32::from(netmask(prefix)))
+}
+
+/// Parses and checks a packet. Fragments are not reassembled, so they are rejected too.
+pub fn parse(data: &[u8]) -> Option<Packet<'_>> {
+    if data.len() < HEADER_SIZE || data[0] >> 4 != 4 {
+        return None;
+    }
+    let header_len = (data[0] & 0x0F) as usize * 4;
+    let total_len = u16::from_be_bytes([data[2], data[3]]) as usize;
+    if header_len < HEADER_SIZE || total_len < header_len || total_len > data.len() {
+        return None;
+    }
+    if checksum(&data[..header_len]) != 0 {
+        return None;
+    }
+    let flags = u16::from_be_bytes([data[6], data[7]]);
+    if flags & (FLAG_MORE_FRAGMENTS | FRAGMENT_OFFSET) != 0 {
+        return None;
+    }
+    Some(Packet {
+        src: Ipv4Addr::new(data[12], data[13], data[14], data[15]),
+        dst: Ipv4Addr::new(data[16], data[17], data[18], data[19]),
+        protocol: data[9],
+        ttl: data[8],
+        payload: &data[header_len..total_len],
+    })
+}
+
+pub fn build(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, id: u16, payload: &[u8]) -> Vec<u8> {
+    let total_len = (HEADER_SIZE + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&FLAG_DONT_FRAGMENT.to_be_bytes());
+    packet.extend_from_slice(&[DEFAULT_TTL, protocol, 0, 0]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    let sum = checksum(&packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
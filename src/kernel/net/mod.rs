@@ -0,0 +1,589 @@
+pub mod arp;
+pub mod ethernet;
+pub mod icmp;
+pub mod ipv4;
+pub mod socket;
+pub mod tcp;
+pub mod udp;
+
+pub use core::net::{Ipv4Addr, SocketAddrV4};
+pub use socket::Socket;
+
+use crate::arch::x86_64::timer;
+use crate::drivers::net::{self as netdev, MacAddress, NetRef};
+use crate::kernel::fs::FsError;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+use spin::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    WouldBlock,
+    AddressInUse,
+    AddressNotAvailable,
+    AlreadyConnected,
+    NotConnected,
+    ConnectionRefused,
+    ConnectionReset,
+    TimedOut,
+    Unreachable,
+    MessageTooLong,
+    InvalidArgument,
+    /// A signal came in while waiting.
+    Interrupted,
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            NetError::WouldBlock => "operation would block",
+            NetError::AddressInUse => "address already in use",
+            NetError::AddressNotAvailable => "address not available",
+            NetError::AlreadyConnected => "already connected",
+            NetError::NotConnected => "not connected",
+            NetError::ConnectionRefused => "connection refused",
+            NetError::ConnectionReset => "connection reset by peer",
+            NetError::TimedOut => "timed out",
+            NetError::Unreachable => "network unreachable",
+            NetError::MessageTooLong => "message too long",
+            NetError::InvalidArgument => "invalid argument",
+            NetError::Interrupted => "interrupted",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl From<NetError> for FsError {
+    fn from(err: NetError) -> Self {
+        match err {
+            NetError::InvalidArgument => FsError::InvalidArgument,
+            NetError::AddressInUse => FsError::Busy,
+            NetError::WouldBlock => FsError::WouldBlock,
+            NetError::Interrupted => FsError::Interrupted,
+            _ => FsError::Io,
+        }
+    }
+}
+
+pub type NetResult<T> = Result<T, NetError>;
+
+/// Where `bind(0)` and `connect` pick local ports from.
+pub(crate) const EPHEMERAL_PORTS: Range<u16> = 49152..65535;
+
+/// Finds a free port in `EPHEMERAL_PORTS`, starting the search at `next`.
+fn ephemeral_port(next: u16, in_use: impl Fn(u16) -> bool) -> NetResult<u16> {
+    let count = EPHEMERAL_PORTS.len() as u16;
+    let start = next.saturating_sub(EPHEMERAL_PORTS.start) % count;
+    (0..count)
+        .map(|i| EPHEMERAL_PORTS.start + (start + i) % count)
+        .find(|&port| !in_use(port))
+        .ok_or(NetError::AddressInUse)
+}
+
+// how often the stack looks at polled devices and TCP timers
+const POLL_INTERVAL_MS: u64 = 50;
+// how long packets wait for an ARP reply before they are dropped
+const ARP_TIMEOUT_MS: u64 = 3000;
+// echo replies kept for `take_echo_reply`
+const ECHO_REPLIES: usize = 16;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InterfaceStats {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub dropped: u64,
+}
+
+/// A network device with its IPv4 configuration.
+#[derive(Clone)]
+pub struct Interface {
+    pub name: String,
+    pub mac: MacAddress,
+    pub mtu: usize,
+    pub addr: Ipv4Addr,
+    pub prefix: u8,
+    pub gateway: Option<Ipv4Addr>,
+    pub loopback: bool,
+    pub stats: InterfaceStats,
+    dev: NetRef,
+}
+
+impl Interface {
+    pub fn is_up(&self) -> bool {
+        !self.addr.is_unspecified()
+    }
+
+    fn accepts(&self, dst: Ipv4Addr) -> bool {
+        dst == self.addr
+            || dst == Ipv4Addr::BROADCAST
+            || (self.is_up() && dst == ipv4::subnet_broadcast(self.addr, self.prefix))
+    }
+}
+
+// an interface to send through, the host the frame goes to, and the source address to use
+struct Route {
+    iface: usize,
+    next_hop: Ipv4Addr,
+    src: Ipv4Addr,
+}
+
+struct Stack {
+    interfaces: Vec<Interface>,
+    arp: arp::Cache,
+    // IPv4 packets waiting for an ARP reply: interface, next hop, packet, queued at
+    waiting: Vec<(usize, Ipv4Addr, Vec<u8>, u64)>,
+    udp: udp::Table,
+    tcp: tcp::Table,
+    // source, id, sequence number and arrival of recent echo replies
+    echo_replies: VecDeque<(Ipv4Addr, u16, u16, u64)>,
+    next_ip_id: u16,
+}
+
+static STACK: Mutex<Stack> = Mutex::new(Stack {
+    interfaces: Vec::new(),
+    arp: arp::Cache::new(),
+    waiting: Vec::new(),
+    udp: udp::Table::new(),
+    tcp: tcp::Table::new(),
+    echo_replies: VecDeque::new(),
+    next_ip_id: 0,
+});
+
+fn with_stack<R>(f: impl FnOnce(&mut Stack) -> R) -> R {
+    f(&mut STACK.lock())
+}
+
+impl Stack {
+    fn loopback(&self) -> Option<usize> {
+        self.interfaces.iter().position(|i| i.loopback)
+    }
+
+    fn is_local(&self, addr: Ipv4Addr) -> bool {
+        addr.is_loopback() || self.interfaces.iter().any(|i| i.is_up() && i.addr == addr)
+    }
+
+    fn route(&self, dst: Ipv4Addr) -> NetResult<Route> {
+        // traffic to ourselves goes through the loopback device, whatever address it is for
+        if self.is_local(dst) {
+            let iface = self.loopback().ok_or(NetError::Unreachable)?;
+            let src = if dst.is_loopback() {
+                self.interfaces[iface].addr
+            } else {
+                dst
+            };
+            return Ok(Route {
+                iface,
+                next_hop: dst,
+                src,
+            });
+        }
+        let external = || {
+            self.interfaces
+                .iter()
+                .enumerate()
+                .filter(|(_, i)| !i.loopback && i.is_up())
+        };
+        if dst == Ipv4Addr::BROADCAST {
+            let (iface, i) = external().next().ok_or(NetError::Unreachable)?;
+            return Ok(Route {
+                iface,
+                next_hop: dst,
+                src: i.addr,
+            });
+        }
+        if let Some((iface, i)) = external().find(|(_, i)| ipv4::same_subnet(i.addr, dst, i.prefix))
+        {
+            return Ok(Route {
+                iface,
+                next_hop: dst,
+                src: i.addr,
+            });
+        }
+        let (iface, i) = external()
+            .find(|(_, i)| i.gateway.is_some())
+            .ok_or(NetError::Unreachable)?;
+        Ok(Route {
+            iface,
+            next_hop: i.gateway.unwrap(),
+            src: i.addr,
+        })
+    }
+
+    /// The largest TCP segment that fits the interface `dst` is reached through.
+    fn mss_for(&self, dst: Ipv4Addr) -> NetResult<usize> {
+        let route = self.route(dst)?;
+        Ok(self.interfaces[route.iface].mtu - ipv4::HEADER_SIZE - tcp::HEADER_SIZE)
+    }
+
+    fn send_frame(&mut self, iface: usize, dst: MacAddress, ethertype: u16, payload: &[u8]) {
+        let i = &mut self.interfaces[iface];
+        let frame = ethernet::build(dst, i.mac, ethertype, payload);
+        if i.dev.transmit(&frame) {
+            i.stats.tx_packets += 1;
+            i.stats.tx_bytes += frame.len() as u64;
+        } else {
+            i.stats.dropped += 1;
+        }
+    }
+
+    fn send_arp(&mut self, iface: usize, op: u16, target_mac: MacAddress, target_ip: Ipv4Addr) {
+        let i = &self.interfaces[iface];
+        let packet = arp::build(&arp::Packet {
+            op,
+            sender_mac: i.mac,
+            sender_ip: i.addr,
+            target_mac,
+            target_ip,
+        });
+        let dst = match op {
+            arp::OP_REQUEST => MacAddress::BROADCAST,
+            _ => target_mac,
+        };
+        self.send_frame(iface, dst, ethernet::TYPE_ARP, &packet);
+    }
+
+    // puts an IPv4 packet on the wire, resolving the next hop first if needed
+    fn transmit_ip(&mut self, iface: usize, next_hop: Ipv4Addr, packet: Vec<u8>, now: u64) {
+        let i = &self.interfaces[iface];
+        let dst = if i.loopback {
+            MacAddress::ZERO
+        } else if i.accepts(next_hop) && next_hop != i.addr {
+            MacAddress::BROADCAST
+        } else if let Some(mac) = self.arp.lookup(next_hop, now) {
+            mac
+        } else {
+            // only the first packet for a host asks; the rest wait for the same reply
+            let asked = self.waiting.iter().any(|w| w.1 == next_hop);
+            self.waiting.push((iface, next_hop, packet, now));
+            if !asked {
+                self.send_arp(iface, arp::OP_REQUEST, MacAddress::ZERO, next_hop);
+            }
+            return;
+        };
+        self.send_frame(iface, dst, ethernet::TYPE_IPV4, &packet);
+    }
+
+    fn send_ip(
+        &mut self,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        protocol: u8,
+        payload: &[u8],
+        now: u64,
+    ) -> NetResult<()> {
+        let route = self.route(dst)?;
+        if ipv4::HEADER_SIZE + payload.len() > self.interfaces[route.iface].mtu {
+            return Err(NetError::MessageTooLong);
+        }
+        self.next_ip_id = self.next_ip_id.wrapping_add(1);
+        let packet = ipv4::build(src, dst, protocol, self.next_ip_id, payload);
+        self.transmit_ip(route.iface, route.next_hop, packet, now);
+        Ok(())
+    }
+
+    fn send_tcp(&mut self, out: Vec<tcp::Outgoing>, now: u64) {
+        for segment in out {
+            // unroutable segments are lost like any other; TCP retransmits
+            let _ = self.send_ip(
+                segment.src,
+                segment.dst,
+                ipv4::PROTO_TCP,
+                &segment.data,
+                now,
+            );
+        }
+    }
+
+    fn send_udp(
+        &mut self,
+        id: udp::SocketId,
+        dst: Option<SocketAddrV4>,
+        payload: &[u8],
+        now: u64,
+    ) -> NetResult<usize> {
+        let dst = dst
+            .or_else(|| self.udp.remote(id))
+            .ok_or(NetError::NotConnected)?;
+        let local = self.udp.local(id).ok_or(NetError::InvalidArgument)?;
+        let src = match local.ip().is_unspecified() {
+            true => self.route(*dst.ip())?.src,
+            false => *local.ip(),
+        };
+        let datagram = udp::build(SocketAddrV4::new(src, local.port()), dst, payload);
+        self.send_ip(src, *dst.ip(), ipv4::PROTO_UDP, &datagram, now)?;
+        Ok(payload.len())
+    }
+
+    fn receive_frame(&mut self, iface: usize, frame: &[u8], now: u64) {
+        let i = &mut self.interfaces[iface];
+        i.stats.rx_packets += 1;
+        i.stats.rx_bytes += frame.len() as u64;
+        let frame = match ethernet::parse(frame) {
+            Some(frame) => frame,
+            None => return,
+        };
+        match frame.ethertype {
+            ethernet::TYPE_ARP => self.receive_arp(iface, frame.payload, now),
+            ethernet::TYPE_IPV4 => self.receive_ip(iface, frame.payload, now),
+            _ => {}
+        }
+    }
+
+    fn receive_arp(&mut self, iface: usize, data: &[u8], now: u64) {
+        let packet = match arp::parse(data) {
+            Some(packet) => packet,
+            None => return,
+        };
+        let addr = self.interfaces[iface].addr;
+        if packet.target_ip == addr || self.arp.lookup(packet.sender_ip, now).is_some() {
+            self.arp.insert(packet.sender_ip, packet.sender_mac, now);
+        }
+        if packet.op == arp::OP_REQUEST && packet.target_ip == addr && !addr.is_unspecified() {
+            self.send_arp(iface, arp::OP_REPLY, packet.sender_mac, packet.sender_ip);
+        }
+
+        // release whatever was waiting for this host
+        let (ready, waiting): (Vec<_>, Vec<_>) = core::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition(|w| w.1 == packet.sender_ip && w.0 == iface);
+        self.waiting = waiting;
+        if let Some(mac) = self.arp.lookup(packet.sender_ip, now) {
+            for (iface, _, packet, _) in ready {
+                self.send_frame(iface, mac, ethernet::TYPE_IPV4, &packet);
+            }
+        } else {
+            self.waiting.extend(ready);
+        }
+    }
+
+    fn receive_ip(&mut self, iface: usize, data: &[u8], now: u64) {
+        let packet = match ipv4::parse(data) {
+            Some(packet) => packet,
+            None => return,
+        };
+        let i = &self.interfaces[iface];
+        let ours = if i.loopback {
+            self.is_local(packet.dst)
+        } else {
+            i.accepts(packet.dst)
+        };
+        if !ours {
+            return;
+        }
+        match packet.protocol {
+            ipv4::PROTO_ICMP => self.receive_icmp(&packet, now),
+            ipv4::PROTO_UDP => self.receive_udp(&packet, data, now),
+            ipv4::PROTO_TCP => {
+                if let Some(segment) = tcp::parse(packet.src, packet.dst, packet.payload) {
+                    let mss = self.interfaces[iface].mtu - ipv4::HEADER_SIZE - tcp::HEADER_SIZE;
+                    let mut out = Vec::new();
+                    self.tcp
+                        .receive(packet.src, packet.dst, &segment, mss, now, &mut out);
+                    self.send_tcp(out, now);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_icmp(&mut self, packet: &ipv4::Packet, now: u64) {
+        let message = match icmp::parse(packet.payload) {
+            Some(message) => message,
+            None => return,
+        };
+        match message.kind {
+            icmp::TYPE_ECHO_REQUEST => {
+                let reply = icmp::build(icmp::TYPE_ECHO_REPLY, 0, message.rest, message.payload);
+                // answer from the address that was asked, unless it was a broadcast
+                let src = match self.is_local(packet.dst) {
+                    true => packet.dst,
+                    false => match self.route(packet.src) {
+                        Ok(route) => route.src,
+                        Err(_) => return,
+                    },
+                };
+                let _ = self.send_ip(src, packet.src, ipv4::PROTO_ICMP, &reply, now);
+            }
+            icmp::TYPE_ECHO_REPLY => {
+                if self.echo_replies.len() >= ECHO_REPLIES {
+                    self.echo_replies.pop_front();
+                }
+                let reply = (packet.src, message.echo_id(), message.echo_seq(), now);
+                self.echo_replies.push_back(reply);
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_udp(&mut self, packet: &ipv4::Packet, raw: &[u8], now: u64) {
+        let datagram = match udp::parse(packet.src, packet.dst, packet.payload) {
+            Some(datagram) => datagram,
+            None => return,
+        };
+        let src = SocketAddrV4::new(packet.src, datagram.src_port);
+        let dst = SocketAddrV4::new(packet.dst, datagram.dst_port);
+        if self.udp.deliver(src, dst, datagram.payload) || !self.is_local(packet.dst) {
+            return;
+        }
+        // nobody listens: quote the IP header and 8 bytes back to the sender
+        let header_len = (raw[0] & 0x0F) as usize * 4;
+        let quoted = &raw[..(header_len + 8).min(raw.len())];
+        let message = icmp::build(
+            icmp::TYPE_DEST_UNREACHABLE,
+            icmp::CODE_PORT_UNREACHABLE,
+            [0; 4],
+            quoted,
+        );
+        let _ = self.send_ip(packet.dst, packet.src, ipv4::PROTO_ICMP, &message, now);
+    }
+
+    fn on_timer(&mut self, now: u64) {
+        let mut out = Vec::new();
+        self.tcp.on_timer(now, &mut out);
+        self.send_tcp(out, now);
+        self.arp.expire(now);
+        let before = self.waiting.len();
+        self.waiting.retain(|w| now - w.3 < ARP_TIMEOUT_MS);
+        let dropped = before - self.waiting.len();
+        if dropped > 0 {
+            if let Some(i) = self.interfaces.iter_mut().find(|i| !i.loopback) {
+                i.stats.dropped += dropped as u64;
+            }
+        }
+    }
+}
+
+/// Takes received frames off every device and runs them through the stack, then
+/// services TCP timers. Everything the stack does happens here or in a socket call.
+pub fn poll() {
+    let now = timer::uptime_ms();
+    let mut stack = STACK.lock();
+    // bounded: loopback traffic can keep producing more frames
+    for _ in 0..64 {
+        let mut any = false;
+        for iface in 0..stack.interfaces.len() {
+            let dev = stack.interfaces[iface].dev.clone();
+            while let Some(frame) = dev.receive() {
+                any = true;
+                stack.receive_frame(iface, &frame, now);
+            }
+        }
+        if !any {
+            break;
+        }
+    }
+    stack.on_timer(now);
+}
+
+/// Creates an interface for every registered network device. The loopback device gets
+/// 127.0.0.1/8 and the first card QEMU's user-mode network defaults, 10.0.2.15/24 via 10.0.2.2.
+pub fn init() {
+    let mut stack = STACK.lock();
+    for (name, dev) in netdev::devices() {
+        if stack.interfaces.iter().any(|i| i.name == name) {
+            continue;
+        }
+        let loopback = name == "lo";
+        let (addr, prefix, gateway) = match name.as_str() {
+            "lo" => (Ipv4Addr::LOCALHOST, 8, None),
+            "eth0" => (
+                Ipv4Addr::new(10, 0, 2, 15),
+                24,
+                Some(Ipv4Addr::new(10, 0, 2, 2)),
+            ),
+            _ => (Ipv4Addr::UNSPECIFIED, 0, None),
+        };
+        stack.interfaces.push(Interface {
+            mac: dev.mac(),
+            mtu: dev.mtu(),
+            name,
+            addr,
+            prefix,
+            gateway,
+            loopback,
+            stats: InterfaceStats::default(),
+            dev,
+        });
+    }
+}
+
+/// Snapshots of every interface.
+pub fn interfaces() -> Vec<Interface> {
+    STACK.lock().interfaces.clone()
+}
+
+/// Sets the address of interface `name`; an unspecified address takes it down.
+pub fn configure(
+    name: &str,
+    addr: Ipv4Addr,
+    prefix: u8,
+    gateway: Option<Ipv4Addr>,
+) -> NetResult<()> {
+    if prefix > 32 {
+        return Err(NetError::InvalidArgument);
+    }
+    let mut stack = STACK.lock();
+    let iface = stack
+        .interfaces
+        .iter_mut()
+        .find(|i| i.name == name)
+        .ok_or(NetError::AddressNotAvailable)?;
+    iface.addr = addr;
+    iface.prefix = prefix;
+    iface.gateway = gateway;
+    Ok(())
+}
+
+pub fn arp_entries() -> Vec<(Ipv4Addr, MacAddress)> {
+    STACK.lock().arp.entries()
+}
+
+pub fn tcp_connections() -> Vec<tcp::ConnectionInfo> {
+    STACK.lock().tcp.connections()
+}
+
+pub fn udp_sockets() -> Vec<(SocketAddrV4, Option<SocketAddrV4>)> {
+    STACK.lock().udp.sockets()
+}
+
+/// Sends an ICMP echo request; the answer shows up in `take_echo_reply`.
+pub fn ping(dst: Ipv4Addr, id: u16, seq: u16, payload: &[u8]) -> NetResult<()> {
+    let now = timer::uptime_ms();
+    let mut stack = STACK.lock();
+    let src = stack.route(dst)?.src;
+    let request = icmp::echo_request(id, seq, payload);
+    stack.send_ip(src, dst, ipv4::PROTO_ICMP, &request, now)
+}
+
+/// Removes the reply to echo request `id`/`seq`, returning who sent it and when it came.
+pub fn take_echo_reply(id: u16, seq: u16) -> Option<(Ipv4Addr, u64)> {
+    let mut stack = STACK.lock();
+    let index = stack
+        .echo_replies
+        .iter()
+        .position(|r| r.1 == id && r.2 == seq)?;
+    let (from, _, _, at) = stack.echo_replies.remove(index)?;
+    Some((from, at))
+}
+
+/// Called by the timer interrupt handler.
+///
+/// Must not block or allocate.
+pub(crate) fn on_timer_tick(ticks: u64) {
+    if ticks.is_multiple_of(POLL_INTERVAL_MS * timer::TIMER_HZ / 1000) {
+        netdev::signal();
+    }
+}
+
+/// Drives the stack whenever a device or the timer signals; runs as an executor task.
+pub async fn run() {
+    loop {
+        netdev::signalled().await;
+        poll();
+    }
+}
@@ -0,0 +1,269 @@
+use super::{tcp, udp, with_stack, NetError, NetResult, SocketAddrV4, Stack};
+use crate::arch::x86_64::timer;
+use crate::kernel::fs::{FsError, FsResult, Stream};
+use crate::kernel::process;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Stream,
+    Datagram,
+}
+
+#[derive(Clone, Copy)]
+enum Binding {
+    Unbound,
+    Tcp(tcp::ConnId),
+    Udp(udp::SocketId),
+}
+
+/// A BSD-style socket over the stack's TCP and UDP tables.
+///
+/// Calls block until they can complete unless the socket is non-blocking, in which case
+/// they fail with `WouldBlock` instead.
+pub struct Socket {
+    kind: Kind,
+    binding: Mutex<Binding>,
+    nonblocking: AtomicBool,
+}
+
+impl Socket {
+    pub fn new(kind: Kind) -> Socket {
+        Self::with_binding(kind, Binding::Unbound)
+    }
+
+    fn with_binding(kind: Kind, binding: Binding) -> Socket {
+        Socket {
+            kind,
+            binding: Mutex::new(binding),
+            nonblocking: AtomicBool::new(false),
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
+
+    fn binding(&self) -> Binding {
+        *self.binding.lock()
+    }
+
+    // retries `attempt` until it stops asking to wait, driving the stack in between; see
+    // `process::park_until` for how a user thread waits
+    fn wait<T>(&self, mut attempt: impl FnMut(&mut Stack, u64) -> NetResult<T>) -> NetResult<T> {
+        let blocking = !self.nonblocking.load(Ordering::Relaxed);
+        let mut result = None;
+        process::park_until(|| {
+            super::poll();
+            match with_stack(|stack| attempt(stack, timer::uptime_ms())) {
+                Err(NetError::WouldBlock) if blocking => false,
+                done => {
+                    result = Some(done);
+                    true
+                }
+            }
+        })
+        .map_err(|e| match e {
+            FsError::Interrupted => NetError::Interrupted,
+            _ => NetError::WouldBlock,
+        })?;
+        result.ok_or(NetError::WouldBlock)?
+    }
+
+    fn check_local(stack: &Stack, addr: SocketAddrV4) -> NetResult<()> {
+        if addr.ip().is_unspecified() || stack.is_local(*addr.ip()) {
+            Ok(())
+        } else {
+            Err(NetError::AddressNotAvailable)
+        }
+    }
+
+    pub fn bind(&self, addr: SocketAddrV4) -> NetResult<()> {
+        let mut binding = self.binding.lock();
+        if !matches!(*binding, Binding::Unbound) {
+            return Err(NetError::InvalidArgument);
+        }
+        *binding = with_stack(|stack| {
+            Self::check_local(stack, addr)?;
+            Ok(match self.kind {
+                Kind::Stream => Binding::Tcp(stack.tcp.bind(addr)?),
+                Kind::Datagram => Binding::Udp(stack.udp.bind(addr)?),
+            })
+        })?;
+        Ok(())
+    }
+
+    // binds to an ephemeral port on any address if nothing is bound yet
+    fn auto_bind(&self) -> NetResult<Binding> {
+        if let Binding::Unbound = self.binding() {
+            let any = SocketAddrV4::new(super::Ipv4Addr::UNSPECIFIED, 0);
+            // a concurrent bind may win; that's as good
+            let _ = self.bind(any);
+        }
+        Ok(self.binding())
+    }
+
+    pub fn listen(&self, backlog: usize) -> NetResult<()> {
+        match (self.kind, self.auto_bind()?) {
+            (Kind::Stream, Binding::Tcp(id)) => with_stack(|stack| stack.tcp.listen(id, backlog)),
+            _ => Err(NetError::InvalidArgument),
+        }
+    }
+
+    /// Waits for a connection on a listening socket.
+    pub fn accept(&self) -> NetResult<(Socket, SocketAddrV4)> {
+        let id = match self.binding() {
+            Binding::Tcp(id) => id,
+            _ => return Err(NetError::InvalidArgument),
+        };
+        let child = self.wait(|stack, _| stack.tcp.accept(id))?;
+        let remote = with_stack(|stack| stack.tcp.remote(child)).ok_or(NetError::NotConnected)?;
+        Ok((
+            Self::with_binding(Kind::Stream, Binding::Tcp(child)),
+            remote,
+        ))
+    }
+
+    /// Connects a stream socket, or sets a datagram socket's default destination.
+    pub fn connect(&self, addr: SocketAddrV4) -> NetResult<()> {
+        match self.auto_bind()? {
+            Binding::Udp(id) => with_stack(|stack| stack.udp.connect(id, addr)),
+            Binding::Tcp(id) => {
+                // a connect run again while its handshake is under way only waits for it
+                let connecting = with_stack(|stack| {
+                    matches!(
+                        stack.tcp.state(id),
+                        Some(tcp::State::SynSent | tcp::State::SynReceived)
+                    )
+                });
+                if !connecting {
+                    with_stack(|stack| {
+                        let now = timer::uptime_ms();
+                        let src = stack.route(*addr.ip())?.src;
+                        let mss = stack.mss_for(*addr.ip())?;
+                        let mut out = Vec::new();
+                        let result = stack.tcp.connect(id, src, addr, mss, now, &mut out);
+                        stack.send_tcp(out, now);
+                        result
+                    })?;
+                }
+                self.wait(|stack, _| match stack.tcp.state(id) {
+                    Some(tcp::State::SynSent | tcp::State::SynReceived) => {
+                        Err(NetError::WouldBlock)
+                    }
+                    Some(tcp::State::Closed) | None => {
+                        Err(stack.tcp.error(id).unwrap_or(NetError::ConnectionRefused))
+                    }
+                    Some(_) => Ok(()),
+                })
+            }
+            Binding::Unbound => Err(NetError::InvalidArgument),
+        }
+    }
+
+    /// Sends on a connected socket. Stream sockets may take only part of `data`.
+    pub fn send(&self, data: &[u8]) -> NetResult<usize> {
+        match self.binding() {
+            Binding::Tcp(id) => self.wait(|stack, now| {
+                let mut out = Vec::new();
+                let result = stack.tcp.send(id, data, now, &mut out);
+                stack.send_tcp(out, now);
+                result
+            }),
+            Binding::Udp(id) => {
+                with_stack(|stack| stack.send_udp(id, None, data, timer::uptime_ms()))
+            }
+            Binding::Unbound => Err(NetError::NotConnected),
+        }
+    }
+
+    /// Sends one datagram to `addr`.
+    pub fn send_to(&self, data: &[u8], addr: SocketAddrV4) -> NetResult<usize> {
+        match (self.kind, self.auto_bind()?) {
+            (Kind::Datagram, Binding::Udp(id)) => {
+                with_stack(|stack| stack.send_udp(id, Some(addr), data, timer::uptime_ms()))
+            }
+            _ => Err(NetError::InvalidArgument),
+        }
+    }
+
+    /// Receives into `buf`. For streams, Ok(0) means the peer closed the connection;
+    /// a datagram longer than `buf` is cut short.
+    pub fn recv(&self, buf: &mut [u8]) -> NetResult<usize> {
+        self.recv_from(buf).map(|(len, _)| len)
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> NetResult<(usize, SocketAddrV4)> {
+        match self.binding() {
+            Binding::Tcp(id) => {
+                let len = self.wait(|stack, now| {
+                    let mut out = Vec::new();
+                    let result = stack.tcp.recv(id, buf, now, &mut out);
+                    stack.send_tcp(out, now);
+                    result
+                })?;
+                let remote = with_stack(|stack| stack.tcp.remote(id));
+                Ok((len, remote.ok_or(NetError::NotConnected)?))
+            }
+            Binding::Udp(id) => {
+                let (from, datagram) = self.wait(|stack, _| stack.udp.recv(id))?;
+                let len = datagram.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram[..len]);
+                Ok((len, from))
+            }
+            Binding::Unbound => Err(NetError::NotConnected),
+        }
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddrV4> {
+        match self.binding() {
+            Binding::Tcp(id) => with_stack(|stack| stack.tcp.local(id)),
+            Binding::Udp(id) => with_stack(|stack| stack.udp.local(id)),
+            Binding::Unbound => None,
+        }
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddrV4> {
+        match self.binding() {
+            Binding::Tcp(id) => with_stack(|stack| stack.tcp.remote(id)),
+            Binding::Udp(id) => with_stack(|stack| stack.udp.remote(id)),
+            Binding::Unbound => None,
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        match self.binding() {
+            Binding::Tcp(id) => with_stack(|stack| {
+                let now = timer::uptime_ms();
+                let mut out = Vec::new();
+                stack.tcp.close(id, now, &mut out);
+                stack.send_tcp(out, now);
+            }),
+            Binding::Udp(id) => with_stack(|stack| stack.udp.close(id)),
+            Binding::Unbound => {}
+        }
+    }
+}
+
+impl Stream for Socket {
+    fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        Ok(self.recv(buf)?)
+    }
+
+    fn write(&self, data: &[u8]) -> FsResult<usize> {
+        Ok(self.send(data)?)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,922 @@
+use super::{ipv4, Ipv4Addr, NetError, NetResult, SocketAddrV4};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const HEADER_SIZE: usize = 20;
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+
+/// Assumed when the peer doesn't send an MSS option.
+const DEFAULT_MSS: usize = 536;
+/// Send and receive buffer size of each connection.
+pub const BUFFER_SIZE: usize = 16 * 1024;
+const RTO_MS: u64 = 1000;
+const MAX_RETRIES: u32 = 6;
+// 2*MSL, kept short: nothing here outlives a few seconds of lost segments
+const TIME_WAIT_MS: u64 = 2000;
+pub const MAX_BACKLOG: usize = 16;
+
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+pub struct Segment<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub mss: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+impl Segment<'_> {
+    fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    // sequence space taken: the data plus one for each of SYN and FIN
+    fn len(&self) -> u32 {
+        self.payload.len() as u32 + self.has(SYN) as u32 + self.has(FIN) as u32
+    }
+}
+
+pub fn parse(src: Ipv4Addr, dst: Ipv4Addr, data: &[u8]) -> Option<Segment<'_>> {
+    if data.len() < HEADER_SIZE || ipv4::pseudo_checksum(src, dst, ipv4::PROTO_TCP, data) != 0 {
+        return None;
+    }
+    let offset = (data[12] >> 4) as usize * 4;
+    if offset < HEADER_SIZE || offset > data.len() {
+        return None;
+    }
+
+    let mut mss = None;
+    let mut options = &data[HEADER_SIZE..offset];
+    while let Some(&kind) = options.first() {
+        match kind {
+            OPTION_END => break,
+            OPTION_NOP => options = &options[1..],
+            _ => {
+                let len = *options.get(1)? as usize;
+                if len < 2 || len > options.len() {
+                    return None;
+                }
+                if kind == OPTION_MSS && len == 4 {
+                    mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                }
+                options = &options[len..];
+            }
+        }
+    }
+
+    let word = |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    Some(Segment {
+        src_port: u16::from_be_bytes([data[0], data[1]]),
+        dst_port: u16::from_be_bytes([data[2], data[3]]),
+        seq: word(4),
+        ack: word(8),
+        flags: data[13],
+        window: u16::from_be_bytes([data[14], data[15]]),
+        mss,
+        payload: &data[offset..],
+    })
+}
+
+/// A segment ready for the IP layer, checksum included.
+pub struct Outgoing {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub data: Vec<u8>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build(
+    src: SocketAddrV4,
+    dst: SocketAddrV4,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    payload: &[u8],
+) -> Outgoing {
+    let header_len = HEADER_SIZE + if mss.is_some() { 4 } else { 0 };
+    let mut data = Vec::with_capacity(header_len + payload.len());
+    data.extend_from_slice(&src.port().to_be_bytes());
+    data.extend_from_slice(&dst.port().to_be_bytes());
+    data.extend_from_slice(&seq.to_be_bytes());
+    data.extend_from_slice(&ack.to_be_bytes());
+    data.extend_from_slice(&[((header_len / 4) as u8) << 4, flags]);
+    data.extend_from_slice(&window.to_be_bytes());
+    data.extend_from_slice(&[0, 0, 0, 0]);
+    if let Some(mss) = mss {
+        data.extend_from_slice(&[OPTION_MSS, 4]);
+        data.extend_from_slice(&mss.to_be_bytes());
+    }
+    data.extend_from_slice(payload);
+    let sum = ipv4::pseudo_checksum(*src.ip(), *dst.ip(), ipv4::PROTO_TCP, &data);
+    data[16..18].copy_from_slice(&sum.to_be_bytes());
+    Outgoing {
+        src: *src.ip(),
+        dst: *dst.ip(),
+        data,
+    }
+}
+
+// the reply to a segment that belongs to no connection
+fn reset_for(local: SocketAddrV4, remote: SocketAddrV4, seg: &Segment) -> Outgoing {
+    if seg.has(ACK) {
+        build(local, remote, seg.ack, 0, RST, 0, None, &[])
+    } else {
+        let ack = seg.seq.wrapping_add(seg.len());
+        build(local, remote, 0, ack, RST | ACK, 0, None, &[])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            State::Closed => "CLOSED",
+            State::Listen => "LISTEN",
+            State::SynSent => "SYN-SENT",
+            State::SynReceived => "SYN-RECEIVED",
+            State::Established => "ESTABLISHED",
+            State::FinWait1 => "FIN-WAIT-1",
+            State::FinWait2 => "FIN-WAIT-2",
+            State::CloseWait => "CLOSE-WAIT",
+            State::Closing => "CLOSING",
+            State::LastAck => "LAST-ACK",
+            State::TimeWait => "TIME-WAIT",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub type ConnId = usize;
+
+/// A transmission control block: one endpoint of a connection, or a listener.
+struct Tcb {
+    state: State,
+    local: SocketAddrV4,
+    remote: SocketAddrV4,
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u32,
+    rcv_nxt: u32,
+    mss: usize,
+    // bytes from snd_una on: sent but unacknowledged, then not yet sent
+    tx: VecDeque<u8>,
+    rx: VecDeque<u8>,
+    // the user is done sending; a FIN follows the queued data
+    closing: bool,
+    fin_sent: bool,
+    peer_closed: bool,
+    ack_now: bool,
+    retransmit_at: Option<u64>,
+    retries: u32,
+    time_wait_until: u64,
+    error: Option<NetError>,
+    // owns its local port, as opposed to sharing its listener's
+    bound: bool,
+    // nothing refers to it any more; dropped once closed
+    orphan: bool,
+    parent: Option<ConnId>,
+    backlog: VecDeque<ConnId>,
+    backlog_max: usize,
+}
+
+impl Tcb {
+    fn new(local: SocketAddrV4) -> Tcb {
+        Tcb {
+            state: State::Closed,
+            local,
+            remote: SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0),
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            mss: DEFAULT_MSS,
+            tx: VecDeque::new(),
+            rx: VecDeque::new(),
+            closing: false,
+            fin_sent: false,
+            peer_closed: false,
+            ack_now: false,
+            retransmit_at: None,
+            retries: 0,
+            time_wait_until: 0,
+            error: None,
+            bound: false,
+            orphan: false,
+            parent: None,
+            backlog: VecDeque::new(),
+            backlog_max: 0,
+        }
+    }
+
+    fn window(&self) -> u16 {
+        (BUFFER_SIZE - self.rx.len()).min(u16::MAX as usize) as u16
+    }
+
+    fn rto(&self) -> u64 {
+        RTO_MS << self.retries.min(4)
+    }
+
+    fn segment(&self, seq: u32, flags: u8, payload: &[u8]) -> Outgoing {
+        build(
+            self.local,
+            self.remote,
+            seq,
+            self.rcv_nxt,
+            flags,
+            self.window(),
+            None,
+            payload,
+        )
+    }
+
+    fn syn(&self, flags: u8, mss: usize) -> Outgoing {
+        let ack = if flags & ACK != 0 { self.rcv_nxt } else { 0 };
+        let mss = Some(mss.min(u16::MAX as usize) as u16);
+        build(
+            self.local,
+            self.remote,
+            self.iss,
+            ack,
+            flags,
+            self.window(),
+            mss,
+            &[],
+        )
+    }
+
+    fn arm(&mut self, now: u64) {
+        if self.retransmit_at.is_none() {
+            self.retransmit_at = Some(now + self.rto());
+        }
+    }
+
+    fn in_flight(&self) -> usize {
+        self.snd_nxt.wrapping_sub(self.snd_una) as usize
+    }
+
+    /// Sends whatever the state calls for: a SYN, new data, a FIN or an ACK.
+    fn output(&mut self, now: u64, out: &mut Vec<Outgoing>) {
+        match self.state {
+            State::Closed | State::Listen => {
+                self.ack_now = false;
+                return;
+            }
+            State::SynSent | State::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let flags = if self.state == State::SynSent {
+                        SYN
+                    } else {
+                        SYN | ACK
+                    };
+                    out.push(self.syn(flags, self.mss));
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                    self.arm(now);
+                }
+                self.ack_now = false;
+                return;
+            }
+            _ => {}
+        }
+
+        while !self.fin_sent {
+            let offset = self.in_flight();
+            let unsent = self.tx.len() - offset;
+            let window = self.snd_wnd as usize;
+            let len = unsent.min(window.saturating_sub(offset)).min(self.mss);
+            if len == 0 {
+                break;
+            }
+            let payload: Vec<u8> = self.tx.range(offset..offset + len).copied().collect();
+            out.push(self.segment(self.snd_nxt, ACK | PSH, &payload));
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+            self.ack_now = false;
+            self.arm(now);
+        }
+
+        let all_sent = self.in_flight() == self.tx.len();
+        if self.closing && !self.fin_sent && all_sent {
+            out.push(self.segment(self.snd_nxt, FIN | ACK, &[]));
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+            self.ack_now = false;
+            self.arm(now);
+            match self.state {
+                State::Established | State::SynReceived => self.state = State::FinWait1,
+                State::CloseWait => self.state = State::LastAck,
+                _ => {}
+            }
+        }
+
+        if self.ack_now {
+            out.push(self.segment(self.snd_nxt, ACK, &[]));
+            self.ack_now = false;
+        }
+    }
+
+    fn on_timer(&mut self, now: u64, out: &mut Vec<Outgoing>) {
+        if self.state == State::TimeWait {
+            if now >= self.time_wait_until {
+                self.state = State::Closed;
+            }
+            return;
+        }
+        match self.retransmit_at {
+            Some(at) if at <= now => {}
+            _ => return,
+        }
+        self.retransmit_at = None;
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.error = Some(NetError::TimedOut);
+            self.state = State::Closed;
+            return;
+        }
+        self.retransmit(out);
+        self.arm(now);
+    }
+
+    // resends the oldest unacknowledged segment, or probes a closed window
+    fn retransmit(&mut self, out: &mut Vec<Outgoing>) {
+        match self.state {
+            State::SynSent => return out.push(self.syn(SYN, self.mss)),
+            State::SynReceived => return out.push(self.syn(SYN | ACK, self.mss)),
+            State::Closed | State::Listen => return,
+            _ => {}
+        }
+        let len = self.in_flight().min(self.tx.len()).min(self.mss);
+        if len > 0 {
+            let payload: Vec<u8> = self.tx.range(..len).copied().collect();
+            out.push(self.segment(self.snd_una, ACK | PSH, &payload));
+        } else if self.fin_sent && self.in_flight() > 0 {
+            out.push(self.segment(self.snd_una, FIN | ACK, &[]));
+        } else if self.snd_wnd == 0 && !self.tx.is_empty() {
+            // one byte past the window; the ACK it draws reports when there is room again
+            out.push(self.segment(self.snd_nxt, ACK | PSH, &[self.tx[0]]));
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+        }
+    }
+
+    fn syn_sent_arrives(&mut self, seg: &Segment, our_mss: usize, out: &mut Vec<Outgoing>) {
+        if seg.has(ACK) && (seq_le(seg.ack, self.iss) || seq_lt(self.snd_nxt, seg.ack)) {
+            if !seg.has(RST) {
+                out.push(reset_for(self.local, self.remote, seg));
+            }
+            return;
+        }
+        if seg.has(RST) {
+            if seg.has(ACK) {
+                self.error = Some(NetError::ConnectionRefused);
+                self.state = State::Closed;
+            }
+            return;
+        }
+        if !seg.has(SYN) {
+            return;
+        }
+        self.rcv_nxt = seg.seq.wrapping_add(1);
+        self.snd_wnd = seg.window as u32;
+        self.mss = seg.mss.map_or(DEFAULT_MSS, |m| m as usize).min(our_mss);
+        if seg.has(ACK) {
+            self.snd_una = seg.ack;
+            self.state = State::Established;
+            self.retransmit_at = None;
+            self.retries = 0;
+            self.ack_now = true;
+        } else {
+            // both ends opened at once: answer with a SYN-ACK
+            self.state = State::SynReceived;
+            self.snd_nxt = self.iss;
+        }
+    }
+
+    /// Handles a segment in any state past SYN-SENT. Returns true when it completed the
+    /// handshake of a connection.
+    fn segment_arrives(&mut self, seg: &Segment, now: u64, out: &mut Vec<Outgoing>) -> bool {
+        let end = seg.seq.wrapping_add(seg.len());
+        let acceptable =
+            seg.seq == self.rcv_nxt || (seq_le(seg.seq, self.rcv_nxt) && seq_lt(self.rcv_nxt, end));
+        if !acceptable {
+            // out of order or a duplicate: say what we expect instead
+            if !seg.has(RST) {
+                self.ack_now = true;
+            }
+            return false;
+        }
+
+        if seg.has(RST) {
+            if self.state == State::SynReceived && self.parent.is_some() {
+                self.orphan = true;
+            } else {
+                self.error = Some(NetError::ConnectionReset);
+            }
+            self.state = State::Closed;
+            return false;
+        }
+        if seg.has(SYN) {
+            self.ack_now = true;
+            return false;
+        }
+        if !seg.has(ACK) {
+            return false;
+        }
+
+        let mut established = false;
+        if self.state == State::SynReceived {
+            if seg.ack != self.iss.wrapping_add(1) {
+                out.push(reset_for(self.local, self.remote, seg));
+                return false;
+            }
+            self.state = State::Established;
+            established = true;
+        }
+
+        if seq_lt(self.snd_una, seg.ack) && seq_le(seg.ack, self.snd_nxt) {
+            let acked = seg.ack.wrapping_sub(self.snd_una) as usize;
+            let data = acked.min(self.tx.len());
+            self.tx.drain(..data);
+            self.snd_una = seg.ack;
+            self.retries = 0;
+            self.retransmit_at = None;
+            if self.snd_nxt != self.snd_una {
+                self.arm(now);
+            }
+        } else if seq_lt(self.snd_nxt, seg.ack) {
+            // acknowledges something never sent
+            self.ack_now = true;
+            return established;
+        }
+        if seq_le(self.snd_una, seg.ack) {
+            self.snd_wnd = seg.window as u32;
+        }
+        if self.snd_wnd == 0 && self.tx.len() > self.in_flight() {
+            // persist: probe the window when the timer fires
+            self.arm(now);
+        }
+
+        let fin_acked = self.fin_sent && self.snd_una == self.snd_nxt;
+        if fin_acked {
+            match self.state {
+                State::FinWait1 => self.state = State::FinWait2,
+                State::Closing => {
+                    self.state = State::TimeWait;
+                    self.time_wait_until = now + TIME_WAIT_MS;
+                }
+                State::LastAck => {
+                    self.state = State::Closed;
+                    return established;
+                }
+                _ => {}
+            }
+        }
+
+        let mut complete = true;
+        if !seg.payload.is_empty() {
+            if matches!(
+                self.state,
+                State::Established | State::FinWait1 | State::FinWait2
+            ) {
+                let skip = self.rcv_nxt.wrapping_sub(seg.seq) as usize;
+                let new = &seg.payload[skip.min(seg.payload.len())..];
+                let take = new.len().min(BUFFER_SIZE - self.rx.len());
+                self.rx.extend(&new[..take]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(take as u32);
+                complete = take == new.len();
+            }
+            self.ack_now = true;
+        }
+
+        if seg.has(FIN) && complete {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.peer_closed = true;
+            self.ack_now = true;
+            match self.state {
+                State::Established => self.state = State::CloseWait,
+                State::FinWait1 => self.state = State::Closing,
+                State::FinWait2 | State::TimeWait => {
+                    self.state = State::TimeWait;
+                    self.time_wait_until = now + TIME_WAIT_MS;
+                }
+                _ => {}
+            }
+        }
+        established
+    }
+}
+
+/// One row of `connections`.
+pub struct ConnectionInfo {
+    pub local: SocketAddrV4,
+    pub remote: SocketAddrV4,
+    pub state: State,
+    pub queued: usize,
+    pub unread: usize,
+}
+
+/// Every TCP control block, keyed by the id sockets refer to them by.
+pub struct Table {
+    conns: BTreeMap<ConnId, Tcb>,
+    next_id: ConnId,
+    next_port: u16,
+    iss_counter: u32,
+}
+
+impl Table {
+    pub const fn new() -> Self {
+        Table {
+            conns: BTreeMap::new(),
+            next_id: 0,
+            next_port: super::EPHEMERAL_PORTS.start,
+            iss_counter: 0,
+        }
+    }
+
+    fn insert(&mut self, tcb: Tcb) -> ConnId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.conns.insert(id, tcb);
+        id
+    }
+
+    fn tcb(&mut self, id: ConnId) -> NetResult<&mut Tcb> {
+        self.conns.get_mut(&id).ok_or(NetError::InvalidArgument)
+    }
+
+    fn initial_sequence(&mut self, now: u64) -> u32 {
+        // the RFC's 4 microsecond clock, plus a step so quick reconnects don't overlap
+        self.iss_counter = self.iss_counter.wrapping_add(64_000);
+        (now as u32)
+            .wrapping_mul(250)
+            .wrapping_add(self.iss_counter)
+    }
+
+    fn port_in_use(&self, addr: Ipv4Addr, port: u16) -> bool {
+        self.conns.values().any(|c| {
+            c.bound
+                && c.local.port() == port
+                && (c.local.ip() == &addr || c.local.ip().is_unspecified() || addr.is_unspecified())
+        })
+    }
+
+    /// Claims `local` for a new, closed connection; port 0 picks a free ephemeral port.
+    pub fn bind(&mut self, local: SocketAddrV4) -> NetResult<ConnId> {
+        let ip = *local.ip();
+        let port = match local.port() {
+            0 => {
+                let port =
+                    super::ephemeral_port(self.next_port, |port| self.port_in_use(ip, port))?;
+                self.next_port = port.wrapping_add(1);
+                port
+            }
+            port if self.port_in_use(ip, port) => return Err(NetError::AddressInUse),
+            port => port,
+        };
+        let mut tcb = Tcb::new(SocketAddrV4::new(ip, port));
+        tcb.bound = true;
+        Ok(self.insert(tcb))
+    }
+
+    pub fn listen(&mut self, id: ConnId, backlog: usize) -> NetResult<()> {
+        let tcb = self.tcb(id)?;
+        if tcb.state != State::Closed && tcb.state != State::Listen {
+            return Err(NetError::InvalidArgument);
+        }
+        tcb.state = State::Listen;
+        tcb.backlog_max = backlog.clamp(1, MAX_BACKLOG);
+        Ok(())
+    }
+
+    /// Starts the handshake with `remote` from address `src`, using segments up to `mss`.
+    pub fn connect(
+        &mut self,
+        id: ConnId,
+        src: Ipv4Addr,
+        remote: SocketAddrV4,
+        mss: usize,
+        now: u64,
+        out: &mut Vec<Outgoing>,
+    ) -> NetResult<()> {
+        let iss = self.initial_sequence(now);
+        let clash = {
+            let tcb = self.tcb(id)?;
+            let local = match tcb.local.ip().is_unspecified() {
+                true => SocketAddrV4::new(src, tcb.local.port()),
+                false => tcb.local,
+            };
+            (local, tcb.state)
+        };
+        match clash.1 {
+            State::Closed => {}
+            State::SynSent | State::SynReceived => return Err(NetError::WouldBlock),
+            _ => return Err(NetError::AlreadyConnected),
+        }
+        let local = clash.0;
+        if self
+            .conns
+            .iter()
+            .any(|(&other, c)| other != id && c.local == local && c.remote == remote)
+        {
+            return Err(NetError::AddressInUse);
+        }
+
+        let tcb = self.tcb(id)?;
+        tcb.local = local;
+        tcb.remote = remote;
+        tcb.iss = iss;
+        tcb.snd_una = iss;
+        tcb.snd_nxt = iss;
+        tcb.mss = mss;
+        tcb.error = None;
+        tcb.state = State::SynSent;
+        tcb.output(now, out);
+        Ok(())
+    }
+
+    /// Takes the next fully established connection off a listener.
+    pub fn accept(&mut self, id: ConnId) -> NetResult<ConnId> {
+        let listener = self.tcb(id)?;
+        if listener.state != State::Listen {
+            return Err(NetError::InvalidArgument);
+        }
+        let child = listener.backlog.pop_front().ok_or(NetError::WouldBlock)?;
+        if let Some(tcb) = self.conns.get_mut(&child) {
+            tcb.parent = None;
+        }
+        Ok(child)
+    }
+
+    pub fn send(
+        &mut self,
+        id: ConnId,
+        data: &[u8],
+        now: u64,
+        out: &mut Vec<Outgoing>,
+    ) -> NetResult<usize> {
+        let tcb = self.tcb(id)?;
+        if let Some(error) = tcb.error {
+            return Err(error);
+        }
+        match tcb.state {
+            State::Established | State::CloseWait if !tcb.closing => {}
+            State::SynSent | State::SynReceived => return Err(NetError::WouldBlock),
+            _ => return Err(NetError::NotConnected),
+        }
+        let room = BUFFER_SIZE - tcb.tx.len();
+        if room == 0 {
+            return Err(NetError::WouldBlock);
+        }
+        let len = data.len().min(room);
+        tcb.tx.extend(&data[..len]);
+        tcb.output(now, out);
+        Ok(len)
+    }
+
+    /// Reads received data. Ok(0) means the peer closed its side.
+    pub fn recv(
+        &mut self,
+        id: ConnId,
+        buf: &mut [u8],
+        now: u64,
+        out: &mut Vec<Outgoing>,
+    ) -> NetResult<usize> {
+        let tcb = self.tcb(id)?;
+        if !tcb.rx.is_empty() {
+            let was_full = tcb.window() < tcb.mss as u16;
+            let len = buf.len().min(tcb.rx.len());
+            for (dst, src) in buf.iter_mut().zip(tcb.rx.drain(..len)) {
+                *dst = src;
+            }
+            // tell a peer stalled on our window that there is room again
+            if was_full {
+                tcb.ack_now = true;
+                tcb.output(now, out);
+            }
+            return Ok(len);
+        }
+        if tcb.peer_closed {
+            return Ok(0);
+        }
+        if let Some(error) = tcb.error {
+            return Err(error);
+        }
+        match tcb.state {
+            State::SynSent
+            | State::SynReceived
+            | State::Established
+            | State::FinWait1
+            | State::FinWait2 => Err(NetError::WouldBlock),
+            _ => Err(NetError::NotConnected),
+        }
+    }
+
+    /// Gives up the connection: queued data is still sent, followed by a FIN.
+    /// The block goes away once the connection is fully closed.
+    pub fn close(&mut self, id: ConnId, now: u64, out: &mut Vec<Outgoing>) {
+        let tcb = match self.conns.get_mut(&id) {
+            Some(tcb) => tcb,
+            None => return,
+        };
+        tcb.orphan = true;
+        match tcb.state {
+            State::Listen => {
+                tcb.state = State::Closed;
+                // nobody will accept these any more
+                let children: Vec<ConnId> = self
+                    .conns
+                    .iter()
+                    .filter(|(_, c)| c.parent == Some(id))
+                    .map(|(&child, _)| child)
+                    .collect();
+                for child in children {
+                    let tcb = self.conns.get_mut(&child).unwrap();
+                    out.push(tcb.segment(tcb.snd_nxt, RST | ACK, &[]));
+                    tcb.state = State::Closed;
+                    tcb.orphan = true;
+                }
+            }
+            State::SynSent => tcb.state = State::Closed,
+            State::SynReceived | State::Established | State::CloseWait => {
+                tcb.closing = true;
+                tcb.output(now, out);
+            }
+            _ => {}
+        }
+        self.reap();
+    }
+
+    fn reap(&mut self) {
+        self.conns
+            .retain(|_, c| !(c.orphan && c.state == State::Closed));
+    }
+
+    pub fn state(&self, id: ConnId) -> Option<State> {
+        self.conns.get(&id).map(|c| c.state)
+    }
+
+    pub fn error(&self, id: ConnId) -> Option<NetError> {
+        self.conns.get(&id).and_then(|c| c.error)
+    }
+
+    pub fn local(&self, id: ConnId) -> Option<SocketAddrV4> {
+        self.conns.get(&id).map(|c| c.local)
+    }
+
+    pub fn remote(&self, id: ConnId) -> Option<SocketAddrV4> {
+        self.conns
+            .get(&id)
+            .filter(|c| c.remote.port() != 0)
+            .map(|c| c.remote)
+    }
+
+    /// Handles a segment from `src` to `dst`; `our_mss` is what fits the interface it came in on.
+    pub fn receive(
+        &mut self,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        seg: &Segment,
+        our_mss: usize,
+        now: u64,
+        out: &mut Vec<Outgoing>,
+    ) {
+        let local = SocketAddrV4::new(dst, seg.dst_port);
+        let remote = SocketAddrV4::new(src, seg.src_port);
+
+        let conn = self.conns.iter().find(|(_, c)| {
+            c.local == local
+                && c.remote == remote
+                && !matches!(c.state, State::Closed | State::Listen)
+        });
+        if let Some((&id, _)) = conn {
+            let tcb = self.conns.get_mut(&id).unwrap();
+            let established = if tcb.state == State::SynSent {
+                tcb.syn_sent_arrives(seg, our_mss, out);
+                false
+            } else {
+                tcb.segment_arrives(seg, now, out)
+            };
+            tcb.output(now, out);
+            if established {
+                if let Some(parent) = tcb.parent {
+                    match self.conns.get_mut(&parent) {
+                        Some(listener) => listener.backlog.push_back(id),
+                        None => self.conns.get_mut(&id).unwrap().orphan = true,
+                    }
+                }
+            }
+            self.reap();
+            return;
+        }
+
+        let listener = self.conns.iter().find(|(_, c)| {
+            c.state == State::Listen
+                && c.local.port() == seg.dst_port
+                && (c.local.ip() == &dst || c.local.ip().is_unspecified())
+        });
+        let (&lid, listener) = match listener {
+            Some(found) => found,
+            None => {
+                if !seg.has(RST) {
+                    out.push(reset_for(local, remote, seg));
+                }
+                return;
+            }
+        };
+        if seg.has(RST) {
+            return;
+        }
+        if seg.has(ACK) {
+            out.push(reset_for(local, remote, seg));
+            return;
+        }
+        if !seg.has(SYN) {
+            return;
+        }
+        let waiting = self
+            .conns
+            .values()
+            .filter(|c| c.parent == Some(lid))
+            .count();
+        if waiting >= listener.backlog_max {
+            // the peer will retry the SYN
+            return;
+        }
+
+        let iss = self.initial_sequence(now);
+        let mut tcb = Tcb::new(local);
+        tcb.remote = remote;
+        tcb.state = State::SynReceived;
+        tcb.iss = iss;
+        tcb.snd_una = iss;
+        tcb.snd_nxt = iss;
+        tcb.snd_wnd = seg.window as u32;
+        tcb.rcv_nxt = seg.seq.wrapping_add(1);
+        tcb.mss = seg.mss.map_or(DEFAULT_MSS, |m| m as usize).min(our_mss);
+        tcb.parent = Some(lid);
+        tcb.output(now, out);
+        self.insert(tcb);
+    }
+
+    /// Retransmits, probes and expires connections whose timers ran out.
+    pub fn on_timer(&mut self, now: u64, out: &mut Vec<Outgoing>) {
+        for tcb in self.conns.values_mut() {
+            tcb.on_timer(now, out);
+            tcb.output(now, out);
+        }
+        self.reap();
+    }
+
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.conns
+            .values()
+            .map(|c| ConnectionInfo {
+                local: c.local,
+                remote: c.remote,
+                state: c.state,
+                queued: c.tx.len(),
+                unread: c.rx.len(),
+            })
+            .collect()
+    }
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Self::new()
+    }
+}
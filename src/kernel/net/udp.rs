@@ -0,0 +1,162 @@
+use super::{ipv4, Ipv4Addr, NetError, NetResult, SocketAddrV4};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+pub const HEADER_SIZE: usize = 8;
+
+// datagrams kept per socket before new ones are dropped
+const QUEUE_LIMIT: usize = 64;
+
+pub struct Datagram<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: &'a [u8],
+}
+
+pub fn parse(src: Ipv4Addr, dst: Ipv4Addr, data: &[u8]) -> Option<Datagram<'_>> {
+    if data.len() < HEADER_SIZE {
+        return None;
+    }
+    let len = u16::from_be_bytes([data[4], data[5]]) as usize;
+    if len < HEADER_SIZE || len > data.len() {
+        return None;
+    }
+    // a zero checksum means the sender didn't compute one
+    let sum = u16::from_be_bytes([data[6], data[7]]);
+    if sum != 0 && ipv4::pseudo_checksum(src, dst, ipv4::PROTO_UDP, &data[..len]) != 0 {
+        return None;
+    }
+    Some(Datagram {
+        src_port: u16::from_be_bytes([data[0], data[1]]),
+        dst_port: u16::from_be_bytes([data[2], data[3]]),
+        payload: &data[HEADER_SIZE..len],
+    })
+}
+
+pub fn build(src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let len = (HEADER_SIZE + payload.len()) as u16;
+    let mut data = Vec::with_capacity(len as usize);
+    data.extend_from_slice(&src.port().to_be_bytes());
+    data.extend_from_slice(&dst.port().to_be_bytes());
+    data.extend_from_slice(&len.to_be_bytes());
+    data.extend_from_slice(&[0, 0]);
+    data.extend_from_slice(payload);
+    let sum = match ipv4::pseudo_checksum(*src.ip(), *dst.ip(), ipv4::PROTO_UDP, &data) {
+        0 => 0xFFFF,
+        sum => sum,
+    };
+    data[6..8].copy_from_slice(&sum.to_be_bytes());
+    data
+}
+
+pub type SocketId = usize;
+
+struct UdpSocket {
+    local: SocketAddrV4,
+    remote: Option<SocketAddrV4>,
+    queue: VecDeque<(SocketAddrV4, Vec<u8>)>,
+}
+
+/// The bound UDP sockets and their queued datagrams.
+pub struct Table {
+    sockets: BTreeMap<SocketId, UdpSocket>,
+    next_id: SocketId,
+    next_port: u16,
+}
+
+impl Table {
+    pub const fn new() -> Self {
+        Table {
+            sockets: BTreeMap::new(),
+            next_id: 0,
+            next_port: super::EPHEMERAL_PORTS.start,
+        }
+    }
+
+    fn port_in_use(&self, addr: Ipv4Addr, port: u16) -> bool {
+        self.sockets.values().any(|s| {
+            s.local.port() == port
+                && (s.local.ip() == &addr || s.local.ip().is_unspecified() || addr.is_unspecified())
+        })
+    }
+
+    /// Claims `local`; port 0 picks a free ephemeral port.
+    pub fn bind(&mut self, local: SocketAddrV4) -> NetResult<SocketId> {
+        let port = match local.port() {
+            0 => {
+                let ip = *local.ip();
+                let port =
+                    super::ephemeral_port(self.next_port, |port| self.port_in_use(ip, port))?;
+                self.next_port = port.wrapping_add(1);
+                port
+            }
+            port if self.port_in_use(*local.ip(), port) => return Err(NetError::AddressInUse),
+            port => port,
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sockets.insert(
+            id,
+            UdpSocket {
+                local: SocketAddrV4::new(*local.ip(), port),
+                remote: None,
+                queue: VecDeque::new(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Sets the default destination, and only accepts datagrams from it from now on.
+    pub fn connect(&mut self, id: SocketId, remote: SocketAddrV4) -> NetResult<()> {
+        let socket = self.sockets.get_mut(&id).ok_or(NetError::InvalidArgument)?;
+        socket.remote = Some(remote);
+        socket.queue.retain(|(from, _)| *from == remote);
+        Ok(())
+    }
+
+    pub fn local(&self, id: SocketId) -> Option<SocketAddrV4> {
+        self.sockets.get(&id).map(|s| s.local)
+    }
+
+    pub fn remote(&self, id: SocketId) -> Option<SocketAddrV4> {
+        self.sockets.get(&id).and_then(|s| s.remote)
+    }
+
+    /// Queues an arriving datagram on the socket it is for. Returns false if there is none.
+    pub fn deliver(&mut self, src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> bool {
+        let socket = self.sockets.values_mut().find(|s| {
+            s.local.port() == dst.port()
+                && (s.local.ip() == dst.ip() || s.local.ip().is_unspecified())
+                && s.remote.is_none_or(|remote| remote == src)
+        });
+        match socket {
+            Some(socket) => {
+                if socket.queue.len() < QUEUE_LIMIT {
+                    socket.queue.push_back((src, payload.into()));
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Takes the oldest queued datagram and its sender.
+    pub fn recv(&mut self, id: SocketId) -> NetResult<(SocketAddrV4, Vec<u8>)> {
+        let socket = self.sockets.get_mut(&id).ok_or(NetError::InvalidArgument)?;
+        socket.queue.pop_front().ok_or(NetError::WouldBlock)
+    }
+
+    pub fn close(&mut self, id: SocketId) {
+        self.sockets.remove(&id);
+    }
+
+    pub fn sockets(&self) -> Vec<(SocketAddrV4, Option<SocketAddrV4>)> {
+        self.sockets.values().map(|s| (s.local, s.remote)).collect()
+    }
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Self::new()
+    }
+}
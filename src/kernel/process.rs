@@ -0,0 +1,186 @@
+use crate::kernel::fs::{FdTable, File, FsError, FsResult, OpenOptions, VFS};
+use crate::kernel::signal::{self, SignalState};
+use crate::kernel::thread;
+use crate::kernel::user::Credentials;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub type Pid = u64;
+
+const CONSOLE_PATH: &str = "/dev/console";
+
+/// A mapped range of a process's address space, as listed in `/proc/<pid>/maps`.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub start: u64,
+    pub end: u64,
+    pub writable: bool,
+    pub executable: bool,
+    pub name: String,
+}
+
+/// Whether a process is running, parked waiting for something, or stopped by a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    Sleeping,
+    Stopped,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            State::Running => write!(f, "R (running)"),
+            State::Sleeping => write!(f, "S (sleeping)"),
+            State::Stopped => write!(f, "T (stopped)"),
+        }
+    }
+}
+
+pub struct Process {
+    pub pid: Pid,
+    pub name: String,
+    pub cwd: String,
+    pub fds: FdTable,
+    pub cred: Credentials,
+    pub regions: Vec<Region>,
+    pub state: State,
+    pub signals: SignalState,
+}
+
+static PROCESSES: Mutex<BTreeMap<Pid, Process>> = Mutex::new(BTreeMap::new());
+// pid of the process currently running in ring 3, 0 while the kernel runs on its own
+static CURRENT: AtomicU64 = AtomicU64::new(0);
+
+static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+
+/// Takes a fresh id. Threads draw theirs from the same space, so a process's first
+/// thread can share its pid.
+pub fn allocate_id() -> Pid {
+    NEXT_PID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Registers a new process running with `cred` and returns its pid.
+pub fn create(name: &str, cred: Credentials) -> Pid {
+    let pid = allocate_id();
+
+    // stdin, stdout and stderr all start out on the console
+    let mut fds = FdTable::new();
+    for _ in 0..3 {
+        let options = OpenOptions::new().read(true).write(true);
+        if let Ok(file) = File::open(CONSOLE_PATH, options) {
+            fds.insert(file);
+        }
+    }
+
+    PROCESSES.lock().insert(
+        pid,
+        Process {
+            pid,
+            name: name.into(),
+            cwd: String::from("/"),
+            fds,
+            cred,
+            regions: Vec::new(),
+            state: State::Running,
+            signals: SignalState::new(),
+        },
+    );
+    pid
+}
+
+pub fn remove(pid: Pid) {
+    thread::remove_process(pid);
+    PROCESSES.lock().remove(&pid);
+    let _ = CURRENT.compare_exchange(pid, 0, Ordering::SeqCst, Ordering::SeqCst);
+}
+
+pub fn set_current(pid: Pid) {
+    CURRENT.store(pid, Ordering::SeqCst);
+}
+
+pub fn current_pid() -> Option<Pid> {
+    match CURRENT.load(Ordering::SeqCst) {
+        0 => None,
+        pid => Some(pid),
+    }
+}
+
+pub fn pids() -> Vec<Pid> {
+    PROCESSES.lock().keys().copied().collect()
+}
+
+/// Runs `f` on process `pid`, if it exists.
+pub fn with_process<R>(pid: Pid, f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    PROCESSES.lock().get_mut(&pid).map(f)
+}
+
+/// Runs `f` on the current process, if there is one.
+pub fn with_current<R>(f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    let pid = current_pid()?;
+    PROCESSES.lock().get_mut(&pid).map(f)
+}
+
+/// Whether `[start, end)` lies within one of the current process's mappings.
+pub fn user_range_mapped(start: u64, end: u64, writable: bool) -> bool {
+    with_current(|p| {
+        p.regions
+            .iter()
+            .any(|r| start >= r.start && end <= r.end && (r.writable || !writable))
+    })
+    .unwrap_or(false)
+}
+
+/// Resolves `path` against the current process's working directory.
+pub fn resolve_path(path: &str) -> String {
+    with_current(|p| VFS::resolve(&p.cwd, path)).unwrap_or_else(|| VFS::normalize_path(path))
+}
+
+/// Waits until `ready` returns true. Fails with `Interrupted` if a signal came in first.
+///
+/// In a user thread it doesn't wait here: it marks the thread blocked and fails with
+/// `WouldBlock`, and the syscall, which must give up without side effects, is run again
+/// from the start after other threads have had the CPU. Elsewhere it halts the CPU
+/// between checks, with interrupts enabled so that whatever makes `ready` true gets to
+/// run; they are restored to their previous state afterwards.
+pub fn park_until(mut ready: impl FnMut() -> bool) -> FsResult<()> {
+    if ready() {
+        return Ok(());
+    }
+    if signal::interrupted() {
+        return Err(FsError::Interrupted);
+    }
+    if thread::block_current() {
+        return Err(FsError::WouldBlock);
+    }
+    with_current(|p| p.state = State::Sleeping);
+    let enabled = interrupts::are_enabled();
+    let result = loop {
+        if ready() {
+            break Ok(());
+        }
+        if signal::interrupted() {
+            break Err(FsError::Interrupted);
+        }
+        interrupts::enable_and_hlt();
+    };
+    if !enabled {
+        interrupts::disable();
+    }
+    with_current(|p| p.state = State::Running);
+    result
+}
+
+/// Ends the current process with `status`, closing its files, and returns to the kernel
+/// code that started it. Only for use on the way back to ring 3.
+pub fn exit(status: u64) -> ! {
+    if let Some(pid) = current_pid() {
+        remove(pid);
+    }
+    unsafe { crate::kernel::userspace::return_to_kernel(status) }
+}
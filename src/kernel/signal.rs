@@ -0,0 +1,447 @@
+use crate::arch::x86_64::trap::TrapFrame;
+use crate::kernel::process::{self, Pid, State};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+
+pub type Signal = u32;
+
+// signal numbers as on Linux
+pub const SIGHUP: Signal = 1;
+pub const SIGINT: Signal = 2;
+pub const SIGQUIT: Signal = 3;
+pub const SIGILL: Signal = 4;
+pub const SIGTRAP: Signal = 5;
+pub const SIGABRT: Signal = 6;
+pub const SIGBUS: Signal = 7;
+pub const SIGFPE: Signal = 8;
+pub const SIGKILL: Signal = 9;
+pub const SIGUSR1: Signal = 10;
+pub const SIGSEGV: Signal = 11;
+pub const SIGUSR2: Signal = 12;
+pub const SIGPIPE: Signal = 13;
+pub const SIGALRM: Signal = 14;
+pub const SIGTERM: Signal = 15;
+pub const SIGCHLD: Signal = 17;
+pub const SIGCONT: Signal = 18;
+pub const SIGSTOP: Signal = 19;
+pub const SIGTSTP: Signal = 20;
+
+/// One more than the highest signal number.
+pub const NSIG: usize = 32;
+
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+pub const SA_NODEFER: u64 = 0x4000_0000;
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+pub const SA_RESTORER: u64 = 0x0400_0000;
+
+// sigprocmask's `how`
+pub const SIG_BLOCK: u64 = 0;
+pub const SIG_UNBLOCK: u64 = 1;
+pub const SIG_SETMASK: u64 = 2;
+
+// below the user's stack pointer the System V ABI lets leaf functions scribble
+const RED_ZONE: u64 = 128;
+
+// flags a signal frame may restore: the arithmetic ones, TF, DF and AC
+const USER_RFLAGS: u64 = 0x4_0DD5;
+const RFLAGS_IF: u64 = 0x200;
+const RFLAGS_RESERVED: u64 = 0x2;
+const RFLAGS_TF_DF: u64 = 0x500;
+
+// user addresses end where the canonical lower half does
+const USER_END: u64 = 0x0000_8000_0000_0000;
+
+pub const fn bit(sig: Signal) -> u64 {
+    1 << (sig - 1)
+}
+
+// the two signals that can't be caught, blocked or ignored
+const UNBLOCKABLE: u64 = bit(SIGKILL) | bit(SIGSTOP);
+const STOP_SIGNALS: u64 = bit(SIGSTOP) | bit(SIGTSTP);
+
+pub fn is_valid(sig: Signal) -> bool {
+    sig >= 1 && (sig as usize) < NSIG
+}
+
+/// What a signal does when its action is `SIG_DFL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    /// Terminate with a "core dumped" message; no core file is written.
+    Core,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+pub fn default_action(sig: Signal) -> DefaultAction {
+    match sig {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV => DefaultAction::Core,
+        SIGCHLD => DefaultAction::Ignore,
+        SIGCONT => DefaultAction::Continue,
+        SIGSTOP | SIGTSTP => DefaultAction::Stop,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+pub fn description(sig: Signal) -> &'static str {
+    match sig {
+        SIGHUP => "Hangup",
+        SIGINT => "Interrupt",
+        SIGQUIT => "Quit",
+        SIGILL => "Illegal instruction",
+        SIGTRAP => "Trace/breakpoint trap",
+        SIGABRT => "Aborted",
+        SIGBUS => "Bus error",
+        SIGFPE => "Floating point exception",
+        SIGKILL => "Killed",
+        SIGSEGV => "Segmentation fault",
+        SIGPIPE => "Broken pipe",
+        SIGALRM => "Alarm clock",
+        SIGTERM => "Terminated",
+        SIGSTOP | SIGTSTP => "Stopped",
+        _ => "Unknown signal",
+    }
+}
+
+/// `struct sigaction` as the kernel sees it on Linux x86_64.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN` or the address of a handler taking the signal number.
+    pub handler: u64,
+    pub flags: u64,
+    /// Where the handler returns to; it must make the sigreturn syscall.
+    pub restorer: u64,
+    /// Signals blocked while the handler runs, on top of the current mask.
+    pub mask: u64,
+}
+
+/// A process's signal dispositions, mask and pending set.
+#[derive(Debug, Clone)]
+pub struct SignalState {
+    pub actions: [SigAction; NSIG],
+    pub pending: u64,
+    pub blocked: u64,
+}
+
+impl SignalState {
+    pub const fn new() -> Self {
+        SignalState {
+            actions: [SigAction {
+                handler: SIG_DFL,
+                flags: 0,
+                restorer: 0,
+                mask: 0,
+            }; NSIG],
+            pending: 0,
+            blocked: 0,
+        }
+    }
+
+    fn ignores(&self, sig: Signal) -> bool {
+        match self.actions[sig as usize].handler {
+            SIG_IGN => true,
+            SIG_DFL => default_action(sig) == DefaultAction::Ignore,
+            _ => false,
+        }
+    }
+
+    /// Marks `sig` pending, unless it would be ignored anyway.
+    pub fn raise(&mut self, sig: Signal) {
+        if sig == SIGCONT {
+            self.pending &= !STOP_SIGNALS;
+        } else if STOP_SIGNALS & bit(sig) != 0 {
+            self.pending &= !bit(SIGCONT);
+        }
+        // SIGCONT resumes a stopped process even when ignored
+        if !self.ignores(sig) || sig == SIGCONT {
+            self.pending |= bit(sig);
+        }
+    }
+
+    // the lowest-numbered pending signal that isn't blocked
+    fn next(&self) -> Option<Signal> {
+        let deliverable = self.pending & (!self.blocked | UNBLOCKABLE);
+        match deliverable {
+            0 => None,
+            set => Some(set.trailing_zeros() + 1),
+        }
+    }
+
+    /// Signals whose action is a handler, for `/proc/<pid>/status`.
+    pub fn caught(&self) -> u64 {
+        (1..NSIG as Signal)
+            .filter(|&sig| self.actions[sig as usize].handler > SIG_IGN)
+            .fold(0, |set, sig| set | bit(sig))
+    }
+
+    pub fn ignored(&self) -> u64 {
+        (1..NSIG as Signal)
+            .filter(|&sig| self.actions[sig as usize].handler == SIG_IGN)
+            .fold(0, |set, sig| set | bit(sig))
+    }
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// raised from interrupt handlers for whatever process is running, merged in on delivery
+static FOREGROUND_PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// Sends `sig` to the foreground process, the one running in ring 3, if there is one.
+///
+/// Called by the keyboard interrupt handler; must not block or allocate.
+pub(crate) fn raise_foreground(sig: Signal) {
+    if process::current_pid().is_some() {
+        FOREGROUND_PENDING.fetch_or(bit(sig), Ordering::SeqCst);
+    }
+}
+
+fn merge_foreground(state: &mut SignalState) {
+    let pending = FOREGROUND_PENDING.swap(0, Ordering::SeqCst);
+    for sig in 1..NSIG as Signal {
+        if pending & bit(sig) != 0 {
+            state.raise(sig);
+        }
+    }
+}
+
+/// Sends `sig` to process `pid`; signal 0 only checks that it exists.
+/// Returns false if there is no such process.
+pub fn send(pid: Pid, sig: Signal) -> bool {
+    process::with_process(pid, |p| {
+        if sig != 0 {
+            p.signals.raise(sig);
+        }
+    })
+    .is_some()
+}
+
+/// Raises a signal caused by the current process's own fault. If it is blocked or
+/// ignored, it gets its default action instead, as retrying the instruction would only
+/// fault again.
+pub fn force(sig: Signal) {
+    process::with_current(|p| {
+        let action = &mut p.signals.actions[sig as usize];
+        if action.handler == SIG_IGN || p.signals.blocked & bit(sig) != 0 {
+            *action = SigAction::default();
+            p.signals.blocked &= !bit(sig);
+        }
+        p.signals.pending |= bit(sig);
+    });
+}
+
+/// Changes the current process's action for `sig`, returning the old one.
+/// SIGKILL and SIGSTOP keep their default action.
+pub fn set_action(sig: Signal, action: Option<SigAction>) -> Option<SigAction> {
+    if !is_valid(sig) {
+        return None;
+    }
+    if let Some(action) = action {
+        if UNBLOCKABLE & bit(sig) != 0 {
+            return None;
+        }
+        // without a restorer the handler would have nowhere to return to
+        let handles = action.handler > SIG_IGN;
+        if handles && (action.flags & SA_RESTORER == 0 || action.restorer == 0) {
+            return None;
+        }
+    }
+    process::with_current(|p| {
+        let old = p.signals.actions[sig as usize];
+        if let Some(action) = action {
+            p.signals.actions[sig as usize] = action;
+            // setting an action that ignores a signal discards it
+            if p.signals.ignores(sig) {
+                p.signals.pending &= !bit(sig);
+            }
+        }
+        old
+    })
+}
+
+/// Changes the current process's blocked set as `sigprocmask` does, returning the old one.
+pub fn set_mask(how: u64, set: Option<u64>) -> Option<u64> {
+    process::with_current(|p| {
+        let old = p.signals.blocked;
+        let blocked = match (how, set) {
+            (_, None) => Some(old),
+            (SIG_BLOCK, Some(set)) => Some(old | set),
+            (SIG_UNBLOCK, Some(set)) => Some(old & !set),
+            (SIG_SETMASK, Some(set)) => Some(set),
+            _ => None,
+        }?;
+        p.signals.blocked = blocked & !UNBLOCKABLE;
+        Some(old)
+    })
+    .flatten()
+}
+
+/// Whether the current process has a signal to take, so a blocking syscall should give up.
+pub fn interrupted() -> bool {
+    process::with_current(|p| {
+        merge_foreground(&mut p.signals);
+        p.signals.next().is_some()
+    })
+    .unwrap_or(false)
+}
+
+/// The signal frame pushed on the user stack. The handler is entered with the stack
+/// pointer at `restorer`, as if called from there.
+#[repr(C)]
+struct SignalFrame {
+    restorer: u64,
+    context: TrapFrame,
+    blocked: u64,
+}
+
+/// Acts on the current process's pending signals before `frame` returns to ring 3:
+/// runs default actions, or rewrites `frame` to enter a handler.
+pub fn deliver(frame: &mut TrapFrame) {
+    if !frame.from_user() {
+        return;
+    }
+    loop {
+        let next = process::with_current(|p| {
+            merge_foreground(&mut p.signals);
+            let sig = p.signals.next()?;
+            p.signals.pending &= !bit(sig);
+            let action = p.signals.actions[sig as usize];
+            if action.flags & SA_RESETHAND != 0 {
+                p.signals.actions[sig as usize] = SigAction::default();
+            }
+            Some((sig, action, p.signals.blocked))
+        })
+        .flatten();
+        let Some((sig, action, blocked)) = next else {
+            return;
+        };
+
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(sig) {
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+                DefaultAction::Terminate => terminate(sig, false),
+                DefaultAction::Core => terminate(sig, true),
+                DefaultAction::Stop => stop(sig),
+            },
+            handler => {
+                if !enter_handler(frame, sig, handler, &action, blocked) {
+                    // nowhere to put the frame
+                    terminate(SIGSEGV, true);
+                }
+                return;
+            }
+        }
+    }
+}
+
+fn enter_handler(
+    frame: &mut TrapFrame,
+    sig: Signal,
+    handler: u64,
+    action: &SigAction,
+    blocked: u64,
+) -> bool {
+    let size = size_of::<SignalFrame>() as u64;
+    // handlers start with rsp 8 off 16-byte alignment, like any called function
+    let Some(top) = frame.rsp.checked_sub(RED_ZONE + size) else {
+        return false;
+    };
+    let start = (top & !0xF) - 8;
+    if !process::user_range_mapped(start, start + size, true) {
+        return false;
+    }
+
+    let signal_frame = SignalFrame {
+        restorer: action.restorer,
+        context: *frame,
+        blocked,
+    };
+    unsafe { core::ptr::write_unaligned(start as *mut SignalFrame, signal_frame) };
+
+    let mut mask = blocked | action.mask;
+    if action.flags & SA_NODEFER == 0 {
+        mask |= bit(sig);
+    }
+    process::with_current(|p| p.signals.blocked = mask & !UNBLOCKABLE);
+
+    frame.rip = handler;
+    frame.rsp = start;
+    frame.rdi = sig as u64;
+    frame.rsi = 0;
+    frame.rdx = 0;
+    frame.rax = 0;
+    frame.rflags &= !RFLAGS_TF_DF;
+    true
+}
+
+/// Restores the context a handler interrupted, from the signal frame just above the
+/// user stack pointer. A bad frame gets the process SIGSEGV.
+pub fn sigreturn(frame: &mut TrapFrame) {
+    let size = size_of::<SignalFrame>() as u64;
+    let start = frame.rsp.wrapping_sub(8);
+    if !process::user_range_mapped(start, start.saturating_add(size), false) {
+        force(SIGSEGV);
+        return;
+    }
+    let signal_frame = unsafe { core::ptr::read_unaligned(start as *const SignalFrame) };
+    let saved = signal_frame.context;
+    if saved.rip >= USER_END || saved.rsp >= USER_END {
+        force(SIGSEGV);
+        return;
+    }
+
+    // the segments stay the user's and the flags can't grant I/O privileges
+    *frame = TrapFrame {
+        error_code: 0,
+        cs: frame.cs,
+        ss: frame.ss,
+        rflags: (saved.rflags & USER_RFLAGS) | RFLAGS_IF | RFLAGS_RESERVED,
+        ..saved
+    };
+    process::with_current(|p| p.signals.blocked = signal_frame.blocked & !UNBLOCKABLE);
+}
+
+fn terminate(sig: Signal, core: bool) -> ! {
+    if let Some((pid, name)) = process::with_current(|p| (p.pid, p.name.clone())) {
+        crate::println!(
+            "[SIGNAL] {} ({}): {}{}",
+            name,
+            pid,
+            description(sig),
+            if core { " (core dumped)" } else { "" }
+        );
+    }
+    process::exit(128 + sig as u64)
+}
+
+// parks the process until SIGCONT, or SIGKILL, comes in
+fn stop(sig: Signal) {
+    process::with_current(|p| {
+        crate::println!("[SIGNAL] {} ({}): {}", p.name, p.pid, description(sig));
+        p.state = State::Stopped;
+    });
+    let enabled = interrupts::are_enabled();
+    loop {
+        let resumed = process::with_current(|p| {
+            merge_foreground(&mut p.signals);
+            p.signals.pending & (bit(SIGCONT) | bit(SIGKILL)) != 0
+        });
+        if resumed != Some(false) {
+            break;
+        }
+        interrupts::enable_and_hlt();
+    }
+    if !enabled {
+        interrupts::disable();
+    }
+    process::with_current(|p| p.state = State::Running);
+}
@@ -1,60 +1,258 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use super::join::{self, JoinHandle};
+use super::{Priority, Task, TaskId};
+use crate::arch::x86_64::timer;
+use alloc::string::String;
+use alloc::{collections::BTreeMap, collections::VecDeque, sync::Arc, task::Wake, vec::Vec};
+use conquer_once::spin::OnceCell;
+use core::future::Future;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use core::task::{Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+// ids of woken tasks, per priority; a task is queued at most once, so this only fills up
+// when more tasks than it holds are woken between two polls
+const READY_CAPACITY: usize = 256;
+
+// what wakers and spawners hand to the executor; neither side may block or allocate here
+// beyond the spawned task itself, since wakers run in interrupt handlers
+struct Shared {
+    ready: [ArrayQueue<TaskId>; Priority::COUNT],
+    // set when a wake didn't fit in `ready`; the executor then looks at every task's flag
+    overflowed: AtomicBool,
+    // tasks from spawners, for the executor to take in
+    spawned: Mutex<VecDeque<SendTask>>,
+    // every live task's counters, for `tasks` to read while the executor runs
+    stats: Mutex<BTreeMap<TaskId, Arc<TaskStats>>>,
+    // tasks to drop on the executor's next round
+    cancelled: Mutex<Vec<TaskId>>,
+}
+
+// built only from `Send` futures, by `Spawner::spawn`
+struct SendTask(Task);
+
+unsafe impl Send for SendTask {}
+
+impl Shared {
+    fn is_idle(&self) -> bool {
+        self.ready.iter().all(|ready| ready.is_empty())
+            && !self.overflowed.load(Ordering::Acquire)
+            && without_interrupts(|| {
+                self.spawned.lock().is_empty() && self.cancelled.lock().is_empty()
+            })
+    }
+
+    // the next woken task of the highest priority that has one
+    fn next_ready(&self) -> Option<TaskId> {
+        Priority::ALL
+            .iter()
+            .find_map(|priority| self.ready[priority.index()].pop())
+    }
+
+    fn tasks(&self) -> Vec<TaskInfo> {
+        without_interrupts(|| {
+            self.stats
+                .lock()
+                .values()
+                .map(|stats| stats.info())
+                .collect()
+        })
+    }
+}
+
+// what the executor counts for each task
+struct TaskStats {
+    id: TaskId,
+    name: String,
+    priority: Priority,
+    polls: AtomicU64,
+    poll_cycles: AtomicU64,
+    wakes: AtomicU64,
+}
+
+impl TaskStats {
+    fn info(&self) -> TaskInfo {
+        TaskInfo {
+            id: self.id,
+            name: self.name.clone(),
+            priority: self.priority,
+            polls: self.polls.load(Ordering::Relaxed),
+            poll_cycles: self.poll_cycles.load(Ordering::Relaxed),
+            wakes: self.wakes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: String,
+    pub priority: Priority,
+    /// How often the task has been polled.
+    pub polls: u64,
+    /// Time-stamp counter cycles spent in those polls; see `timer::cycles_to_us`.
+    pub poll_cycles: u64,
+    /// How often its waker was called, repeated wakes before a poll included.
+    pub wakes: u64,
+}
 
 pub struct Executor {
     tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    shared: Arc<Shared>,
+    waker_cache: BTreeMap<TaskId, Arc<TaskWaker>>,
+}
+
+static SPAWNER: OnceCell<Spawner> = OnceCell::uninit();
+
+/// A spawner for the executor the kernel runs, once it's running.
+pub fn spawner() -> Option<Spawner> {
+    SPAWNER.get().cloned()
+}
+
+/// The tasks of the executor the kernel runs, with what it has counted for each.
+pub fn tasks() -> Vec<TaskInfo> {
+    SPAWNER
+        .get()
+        .map(|spawner| spawner.shared.tasks())
+        .unwrap_or_default()
+}
+
+/// Cancels a task of the executor the kernel runs; see `Spawner::cancel`.
+pub fn cancel(id: TaskId) -> bool {
+    SPAWNER.get().is_some_and(|spawner| spawner.cancel(id))
 }
 
 impl Executor {
     pub fn new() -> Self {
         Executor {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
+            shared: Arc::new(Shared {
+                ready: core::array::from_fn(|_| ArrayQueue::new(READY_CAPACITY)),
+                overflowed: AtomicBool::new(false),
+                spawned: Mutex::new(VecDeque::new()),
+                stats: Mutex::new(BTreeMap::new()),
+                cancelled: Mutex::new(Vec::new()),
+            }),
             waker_cache: BTreeMap::new(),
         }
     }
 
     pub fn spawn(&mut self, task: Task) {
         let task_id = task.id;
+        let stats = Arc::new(TaskStats {
+            id: task_id,
+            name: task.name.clone(),
+            priority: task.priority,
+            polls: AtomicU64::new(0),
+            poll_cycles: AtomicU64::new(0),
+            wakes: AtomicU64::new(0),
+        });
         if self.tasks.insert(task.id, task).is_some() {
             panic!("task with same ID already in tasks");
         }
-        self.task_queue.push(task_id).expect("queue full");
+        without_interrupts(|| self.shared.stats.lock().insert(task_id, stats.clone()));
+        let waker = TaskWaker::new(task_id, stats, self.shared.clone());
+        waker.wake_task();
+        self.waker_cache.insert(task_id, waker);
+    }
+
+    /// Drops the task's future, so it's never polled again, along with its cached waker;
+    /// returns false if there's no such task. Wakers still held elsewhere go nowhere.
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        let Some(task) = self.tasks.remove(&id) else {
+            return false;
+        };
+        self.waker_cache.remove(&id);
+        without_interrupts(|| self.shared.stats.lock().remove(&id));
+        // dropping the future may run any code, so not while holding a lock
+        drop(task);
+        true
+    }
+
+    /// Every task on this executor, with what it has counted for each.
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        self.shared.tasks()
+    }
+
+    /// A handle that spawns tasks on this executor from anywhere, tasks included.
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            shared: self.shared.clone(),
+        }
     }
 
     pub fn run(&mut self) -> ! {
+        let _ = SPAWNER.try_init_once(|| self.spawner());
         loop {
             self.run_ready_tasks();
             self.sleep_if_idle();
         }
     }
 
+    /// Polls tasks until none is ready, then returns; for driving an executor by hand.
+    pub fn run_until_idle(&mut self) {
+        while !self.shared.is_idle() {
+            self.run_ready_tasks();
+        }
+    }
+
     fn run_ready_tasks(&mut self) {
+        let spawned: Vec<SendTask> =
+            without_interrupts(|| self.shared.spawned.lock().drain(..).collect());
+        for SendTask(task) in spawned {
+            self.spawn(task);
+        }
+
+        // after taking in spawned tasks, so a task cancelled right away goes too
+        let cancelled = without_interrupts(|| core::mem::take(&mut *self.shared.cancelled.lock()));
+        for task_id in cancelled {
+            self.cancel(task_id);
+        }
+
+        // wakes that didn't fit in the queue left their flags set
+        if self.shared.overflowed.swap(false, Ordering::AcqRel) {
+            for waker in self.waker_cache.values() {
+                if waker.queued.swap(false, Ordering::AcqRel) {
+                    waker.wake_task();
+                }
+            }
+        }
+
         // destructure `self` to avoid borrow checker errors
         let Self {
             tasks,
-            task_queue,
+            shared,
             waker_cache,
         } = self;
 
-        while let Some(task_id) = task_queue.pop() {
+        // a higher-priority task woken by a poll goes next
+        while let Some(task_id) = shared.next_ready() {
             let task = match tasks.get_mut(&task_id) {
                 Some(task) => task,
                 None => continue, // task no longer exists
             };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
+            let task_waker = match waker_cache.get(&task_id) {
+                Some(waker) => waker,
+                None => continue,
+            };
+            // wakes from here on queue the task again
+            task_waker.queued.store(false, Ordering::Release);
+            let waker = Waker::from(task_waker.clone());
+            let mut context = Context::from_waker(&waker);
+            let start = timer::tsc();
+            let poll = task.poll(&mut context);
+            let stats = &task_waker.stats;
+            stats.polls.fetch_add(1, Ordering::Relaxed);
+            stats
+                .poll_cycles
+                .fetch_add(timer::tsc().wrapping_sub(start), Ordering::Relaxed);
+            match poll {
                 Poll::Ready(()) => {
-                    // task done -> remove it and its cached waker
+                    // task done -> remove it, its cached waker and its counters
                     tasks.remove(&task_id);
                     waker_cache.remove(&task_id);
+                    without_interrupts(|| shared.stats.lock().remove(&task_id));
                 }
                 Poll::Pending => {}
             }
@@ -62,42 +260,104 @@ impl Executor {
     }
 
     fn sleep_if_idle(&self) {
-        use x86_64::instructions::interrupts::{self, enable_and_hlt};
+        use x86_64::instructions::interrupts;
 
         interrupts::disable();
-        if self.task_queue.is_empty() {
-            enable_and_hlt();
+        if self.shared.is_idle() {
+            // other kernel threads get the CPU while no task is ready
+            crate::kernel::kthread::idle();
         } else {
             interrupts::enable();
         }
     }
 }
 
+/// Spawns tasks on an `Executor`. It can be cloned and sent anywhere, and `spawn` works
+/// with interrupts off; the executor picks the task up on its next round.
+#[derive(Clone)]
+pub struct Spawner {
+    shared: Arc<Shared>,
+}
+
+impl Spawner {
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawn_with("task", Priority::Normal, future)
+    }
+
+    /// Like `spawn`, for a task listed under `name` and polled at `priority`.
+    pub fn spawn_with<F>(&self, name: &str, priority: Priority, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let task_id = TaskId::new();
+        let (sender, handle) = join::channel(task_id, self.clone());
+        let task = Task::with_id(task_id, name, async move { sender.send(future.await) })
+            .with_priority(priority);
+        without_interrupts(|| self.shared.spawned.lock().push_back(SendTask(task)));
+        handle
+    }
+
+    /// Has the executor drop the task when it starts its next round of polls;
+    /// returns false if there's no such task. Works with interrupts off, like `spawn`.
+    pub fn cancel(&self, id: TaskId) -> bool {
+        without_interrupts(|| {
+            let running = self.shared.stats.lock().contains_key(&id);
+            let known = running
+                || self
+                    .shared
+                    .spawned
+                    .lock()
+                    .iter()
+                    .any(|SendTask(task)| task.id == id);
+            if known {
+                self.shared.cancelled.lock().push(id);
+            }
+            known
+        })
+    }
+}
+
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    stats: Arc<TaskStats>,
+    // whether the task is due a poll, so repeated wakes queue it once
+    queued: AtomicBool,
+    shared: Arc<Shared>,
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
+    fn new(task_id: TaskId, stats: Arc<TaskStats>, shared: Arc<Shared>) -> Arc<TaskWaker> {
+        Arc::new(TaskWaker {
             task_id,
-            task_queue,
-        }))
+            stats,
+            queued: AtomicBool::new(false),
+            shared,
+        })
     }
 
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let ready = &self.shared.ready[self.stats.priority.index()];
+        if ready.push(self.task_id).is_err() {
+            self.shared.overflowed.store(true, Ordering::Release);
+        }
     }
 }
 
 impl Wake for TaskWaker {
     fn wake(self: Arc<Self>) {
-        self.wake_task();
+        self.wake_by_ref();
     }
 
     fn wake_by_ref(self: &Arc<Self>) {
+        self.stats.wakes.fetch_add(1, Ordering::Relaxed);
         self.wake_task();
     }
 }
-
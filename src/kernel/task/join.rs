@@ -0,0 +1,127 @@
+use super::{Spawner, TaskId};
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+
+/// Why a `JoinHandle` has no output: its task was cancelled before it finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+struct JoinState<T> {
+    output: Option<Result<T, Cancelled>>,
+    // set once the task has finished or been dropped, whether or not the output was taken
+    done: bool,
+    waker: Option<Waker>,
+}
+
+/// Resolves to the output of a task started with `Spawner::spawn`. Dropping it cancels
+/// the task unless it was detached first.
+#[must_use = "dropping a JoinHandle cancels the task; call detach()"]
+pub struct JoinHandle<T> {
+    task_id: TaskId,
+    // None once detached
+    spawner: Option<Spawner>,
+    state: Arc<Mutex<JoinState<T>>>,
+}
+
+/// The task's end of a `JoinHandle`. Dropping it unsent, as happens when the task is
+/// cancelled, completes the handle with `Cancelled`.
+pub(super) struct JoinSender<T> {
+    state: Arc<Mutex<JoinState<T>>>,
+}
+
+pub(super) fn channel<T>(task_id: TaskId, spawner: Spawner) -> (JoinSender<T>, JoinHandle<T>) {
+    let state = Arc::new(Mutex::new(JoinState {
+        output: None,
+        done: false,
+        waker: None,
+    }));
+    (
+        JoinSender {
+            state: state.clone(),
+        },
+        JoinHandle {
+            task_id,
+            spawner: Some(spawner),
+            state,
+        },
+    )
+}
+
+impl<T> JoinSender<T> {
+    pub(super) fn send(self, output: T) {
+        self.finish(Ok(output));
+    }
+
+    fn finish(&self, output: Result<T, Cancelled>) {
+        let waker = {
+            let mut state = self.state.lock();
+            if state.done {
+                return;
+            }
+            state.output = Some(output);
+            state.done = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for JoinSender<T> {
+    fn drop(&mut self) {
+        self.finish(Err(Cancelled));
+    }
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> TaskId {
+        self.task_id
+    }
+
+    /// Whether the task has finished or been cancelled, so awaiting the handle won't wait.
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().done
+    }
+
+    /// Cancels the task; awaiting the handle then gives `Cancelled` unless it had already
+    /// finished.
+    pub fn abort(&self) {
+        if let Some(spawner) = &self.spawner {
+            if !self.is_finished() {
+                spawner.cancel(self.task_id);
+            }
+        }
+    }
+
+    /// Lets the task run on without anyone waiting for its output.
+    pub fn detach(mut self) {
+        self.spawner = None;
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, Cancelled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, Cancelled>> {
+        let mut state = self.state.lock();
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
@@ -1,34 +1,102 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, string::String};
 use core::{
+    fmt,
     future::Future,
+    num::ParseIntError,
     pin::Pin,
+    str::FromStr,
     sync::atomic::{AtomicU64, Ordering},
     task::{Context, Poll},
 };
 
 pub mod executor;
+pub mod join;
+pub mod mpsc;
+pub mod select;
+pub mod sync;
+pub mod time;
 pub mod yield_now;
 
+pub use executor::{cancel, spawner, tasks, Spawner, TaskInfo};
+pub use join::{Cancelled, JoinHandle};
+pub use select::{select, timeout, Either};
+pub use time::sleep_ms;
+
+/// How urgently the executor polls a task: a ready task is only polled once no task of
+/// a higher priority is ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    const COUNT: usize = 3;
+
+    // highest first, the order the executor looks for ready tasks in
+    const ALL: [Priority; Priority::COUNT] = [Priority::High, Priority::Normal, Priority::Low];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Priority::Low => write!(f, "low"),
+            Priority::Normal => write!(f, "normal"),
+            Priority::High => write!(f, "high"),
+        }
+    }
+}
+
 pub struct Task {
     id: TaskId,
+    name: String,
+    priority: Priority,
     future: Pin<Box<dyn Future<Output = ()>>>,
 }
 
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task::named("task", future)
+    }
+
+    /// A task that `tasks` lists under `name`.
+    pub fn named(name: &str, future: impl Future<Output = ()> + 'static) -> Task {
+        Task::with_id(TaskId::new(), name, future)
+    }
+
+    // for a spawner, whose join handle needs the id before the task exists
+    fn with_id(id: TaskId, name: &str, future: impl Future<Output = ()> + 'static) -> Task {
         Task {
-            id: TaskId::new(),
+            id,
+            name: String::from(name),
+            priority: Priority::Normal,
             future: Box::pin(future),
         }
     }
 
+    pub fn with_priority(mut self, priority: Priority) -> Task {
+        self.priority = priority;
+        self
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
 }
 
+/// Names a task for as long as the kernel runs; ids are never reused.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
+pub struct TaskId(u64);
 
 impl TaskId {
     fn new() -> Self {
@@ -37,6 +105,20 @@ impl TaskId {
     }
 }
 
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for TaskId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(TaskId)
+    }
+}
+
 //helper for yield_now
 pub fn yield_now() -> yield_now::YieldNow {
     yield_now::YieldNow::new()
@@ -0,0 +1,217 @@
+//! Multi-producer, single-consumer channels between tasks. `channel` holds a fixed number
+//! of messages and makes senders wait for room; `unbounded` never makes them wait.
+
+use super::sync::Notify;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::future::poll_fn;
+use core::task::Poll;
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Why a send failed: the receiver is gone. Holds the message that wasn't delivered.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// A bounded channel is at capacity.
+    Full(T),
+    Closed(T),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    /// Every sender is gone and nothing is left to receive.
+    Closed,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+struct Chan<T> {
+    state: Mutex<State<T>>,
+    capacity: Option<usize>,
+    receiver: AtomicWaker,
+    // signalled when a bounded channel makes room or the receiver goes away
+    room: Notify,
+}
+
+impl<T> Chan<T> {
+    fn new(capacity: Option<usize>) -> Arc<Self> {
+        Arc::new(Chan {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                senders: 1,
+                receiver_alive: true,
+            }),
+            capacity,
+            receiver: AtomicWaker::new(),
+            room: Notify::new(),
+        })
+    }
+
+    fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            if !state.receiver_alive {
+                return Err(TrySendError::Closed(value));
+            }
+            if self.capacity.is_some_and(|cap| state.queue.len() >= cap) {
+                return Err(TrySendError::Full(value));
+            }
+            state.queue.push_back(value);
+            Ok(())
+        })?;
+        self.receiver.wake();
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        let result = without_interrupts(|| {
+            let mut state = self.state.lock();
+            match state.queue.pop_front() {
+                Some(value) => Ok(value),
+                None if state.senders == 0 => Err(TryRecvError::Closed),
+                None => Err(TryRecvError::Empty),
+            }
+        });
+        if result.is_ok() && self.capacity.is_some() {
+            self.room.notify_one();
+        }
+        result
+    }
+
+    fn drop_sender(&self) {
+        let last = without_interrupts(|| {
+            let mut state = self.state.lock();
+            state.senders -= 1;
+            state.senders == 0
+        });
+        if last {
+            self.receiver.wake();
+        }
+    }
+}
+
+/// Creates a channel that holds up to `capacity` messages.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must be at least 1");
+    let chan = Chan::new(Some(capacity));
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+/// Creates a channel with no limit on the messages it holds.
+pub fn unbounded<T>() -> (UnboundedSender<T>, Receiver<T>) {
+    let chan = Chan::new(None);
+    (UnboundedSender { chan: chan.clone() }, Receiver { chan })
+}
+
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, waiting while the channel is full.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut value = value;
+        loop {
+            match self.chan.try_send(value) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Closed(v)) => return Err(SendError(v)),
+                Err(TrySendError::Full(v)) => value = v,
+            }
+            // room made while nobody waited is remembered by `notify_one`, so it isn't missed
+            self.chan.room.notified().await;
+        }
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.chan.try_send(value)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        without_interrupts(|| self.chan.state.lock().senders += 1);
+        Sender {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.chan.drop_sender();
+    }
+}
+
+/// The sending half of an `unbounded` channel; sending never waits, so it also works
+/// outside of tasks.
+pub struct UnboundedSender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> UnboundedSender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.chan.try_send(value).map_err(|e| match e {
+            TrySendError::Full(v) | TrySendError::Closed(v) => SendError(v),
+        })
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        without_interrupts(|| self.chan.state.lock().senders += 1);
+        UnboundedSender {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        self.chan.drop_sender();
+    }
+}
+
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next message; `None` once every sender is gone and the channel is empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| match self.chan.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => {
+                self.chan.receiver.register(cx.waker());
+                // a message sent before the waker was registered woke nobody
+                match self.chan.try_recv() {
+                    Ok(value) => Poll::Ready(Some(value)),
+                    Err(TryRecvError::Closed) => Poll::Ready(None),
+                    Err(TryRecvError::Empty) => Poll::Pending,
+                }
+            }
+        })
+        .await
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.chan.try_recv()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        without_interrupts(|| self.chan.state.lock().receiver_alive = false);
+        // senders waiting for room find the channel closed
+        self.chan.room.notify_waiters();
+    }
+}
@@ -0,0 +1,389 @@
+//! Primitives for coordinating async tasks. Waiting tasks are parked through their `Waker`
+//! and queued first come, first served; nothing spins.
+//!
+//! `Notify::notify_one`, `Notify::notify_waiters` and `Semaphore::add_permits` may be
+//! called from interrupt handlers: they neither block nor allocate.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::task::{Context, Poll, Waker};
+use x86_64::instructions::interrupts::without_interrupts;
+
+// what became of a queued waiter
+const QUEUED: u8 = 0;
+// handed a permit or notification of its own, which it must pass on if it goes away
+const WOKEN: u8 = 1;
+// woken along with every other waiter; nothing to pass on
+const WOKEN_ALL: u8 = 2;
+
+struct Waiter {
+    state: AtomicU8,
+    waker: spin::Mutex<Option<Waker>>,
+}
+
+impl Waiter {
+    fn new(waker: &Waker) -> Arc<Waiter> {
+        Arc::new(Waiter {
+            state: AtomicU8::new(QUEUED),
+            waker: spin::Mutex::new(Some(waker.clone())),
+        })
+    }
+
+    fn state(&self) -> u8 {
+        self.state.load(Ordering::Acquire)
+    }
+
+    fn set_waker(&self, waker: &Waker) {
+        let mut slot = self.waker.lock();
+        if !slot.as_ref().is_some_and(|w| w.will_wake(waker)) {
+            *slot = Some(waker.clone());
+        }
+    }
+
+    fn wake(&self, state: u8) {
+        self.state.store(state, Ordering::Release);
+        if let Some(waker) = self.waker.lock().take() {
+            waker.wake();
+        }
+    }
+}
+
+struct WaitQueue {
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+impl WaitQueue {
+    const fn new() -> Self {
+        WaitQueue {
+            waiters: VecDeque::new(),
+        }
+    }
+
+    fn wake_one(&mut self) -> bool {
+        match self.waiters.pop_front() {
+            Some(waiter) => {
+                waiter.wake(WOKEN);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn wake_all(&mut self) {
+        for waiter in self.waiters.drain(..) {
+            waiter.wake(WOKEN_ALL);
+        }
+    }
+
+    // takes `waiter` out if it's still queued
+    fn remove(&mut self, waiter: &Arc<Waiter>) {
+        self.waiters.retain(|w| !Arc::ptr_eq(w, waiter));
+    }
+}
+
+struct SemaphoreState {
+    permits: usize,
+    queue: WaitQueue,
+}
+
+impl SemaphoreState {
+    fn release(&mut self) {
+        if !self.queue.wake_one() {
+            self.permits += 1;
+        }
+    }
+}
+
+/// Counts out permits to tasks; `acquire` waits while none are left.
+pub struct Semaphore {
+    state: spin::Mutex<SemaphoreState>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            state: spin::Mutex::new(SemaphoreState {
+                permits,
+                queue: WaitQueue::new(),
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        without_interrupts(|| self.state.lock().permits)
+    }
+
+    /// Waits for a permit, which goes back when the returned guard is dropped.
+    pub fn acquire(&self) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            waiter: None,
+        }
+    }
+
+    /// Takes a permit if one is free and nobody is waiting ahead.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            if state.permits > 0 && state.queue.waiters.is_empty() {
+                state.permits -= 1;
+                Some(SemaphorePermit { semaphore: self })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns `n` permits, handing them straight to waiting tasks first.
+    pub fn add_permits(&self, n: usize) {
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            for _ in 0..n {
+                state.release();
+            }
+        })
+    }
+}
+
+/// A permit from a `Semaphore`, given back on drop.
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl SemaphorePermit<'_> {
+    /// Keeps the permit taken for good, for when something else gives it back later.
+    pub fn forget(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(1);
+    }
+}
+
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<SemaphorePermit<'a>> {
+        let semaphore = self.semaphore;
+        without_interrupts(|| {
+            let mut state = semaphore.state.lock();
+            match &self.waiter {
+                // `add_permits` handed this waiter a permit of its own
+                Some(waiter) if waiter.state() == WOKEN => {}
+                Some(waiter) => {
+                    waiter.set_waker(cx.waker());
+                    return Poll::Pending;
+                }
+                // nobody may take a permit ahead of tasks already waiting
+                None if state.permits > 0 && state.queue.waiters.is_empty() => {
+                    state.permits -= 1;
+                }
+                None => {
+                    let waiter = Waiter::new(cx.waker());
+                    state.queue.waiters.push_back(waiter.clone());
+                    drop(state);
+                    self.waiter = Some(waiter);
+                    return Poll::Pending;
+                }
+            }
+            self.waiter = None;
+            Poll::Ready(SemaphorePermit { semaphore })
+        })
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(waiter) = self.waiter.take() else {
+            return;
+        };
+        without_interrupts(|| {
+            let mut state = self.semaphore.state.lock();
+            if waiter.state() == WOKEN {
+                // given a permit that's no longer wanted
+                state.release();
+            } else {
+                state.queue.remove(&waiter);
+            }
+        })
+    }
+}
+
+/// A mutual exclusion lock for tasks: `lock` waits without spinning while another task
+/// holds the guard, even across `.await`s.
+pub struct Mutex<T: ?Sized> {
+    semaphore: Semaphore,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            semaphore: Semaphore::new(1),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        let permit = self.semaphore.acquire().await;
+        permit.forget();
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let permit = self.semaphore.try_acquire()?;
+        permit.forget();
+        Some(MutexGuard { mutex: self })
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.semaphore.add_permits(1);
+    }
+}
+
+struct NotifyState {
+    // a `notify_one` that found nobody waiting, kept for the next `notified`
+    permit: bool,
+    queue: WaitQueue,
+}
+
+/// Wakes tasks waiting for an event.
+pub struct Notify {
+    state: spin::Mutex<NotifyState>,
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Notify {
+            state: spin::Mutex::new(NotifyState {
+                permit: false,
+                queue: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Completes a future from `notified`, the longest-waiting one. With none waiting, the
+    /// next `notified` completes right away; several calls still count as one.
+    pub fn notify_one(&self) {
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            if !state.queue.wake_one() {
+                state.permit = true;
+            }
+        })
+    }
+
+    /// Completes every future from `notified` that is waiting now.
+    pub fn notify_waiters(&self) {
+        without_interrupts(|| self.state.lock().queue.wake_all())
+    }
+
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            waiter: None,
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let notify = self.notify;
+        without_interrupts(|| {
+            let mut state = notify.state.lock();
+            match &self.waiter {
+                Some(waiter) if waiter.state() != QUEUED => {}
+                Some(waiter) => {
+                    waiter.set_waker(cx.waker());
+                    return Poll::Pending;
+                }
+                None if state.permit => state.permit = false,
+                None => {
+                    let waiter = Waiter::new(cx.waker());
+                    state.queue.waiters.push_back(waiter.clone());
+                    drop(state);
+                    self.waiter = Some(waiter);
+                    return Poll::Pending;
+                }
+            }
+            self.waiter = None;
+            Poll::Ready(())
+        })
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(waiter) = self.waiter.take() else {
+            return;
+        };
+        without_interrupts(|| {
+            let mut state = self.notify.state.lock();
+            match waiter.state() {
+                // a notify_one meant for this waiter goes to the next one instead
+                WOKEN => {
+                    if !state.queue.wake_one() {
+                        state.permit = true;
+                    }
+                }
+                WOKEN_ALL => {}
+                _ => state.queue.remove(&waiter),
+            }
+        })
+    }
+}
@@ -0,0 +1,135 @@
+//! Timers for tasks. Sleeping tasks are kept in deadline order and woken by `run`, which
+//! the timer interrupt wakes when the earliest deadline passes.
+
+use crate::arch::x86_64::timer;
+use alloc::collections::BTreeMap;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+// (deadline in ms of uptime, sleep id) -> the sleeping task
+static SLEEPERS: Mutex<BTreeMap<(u64, u64), Waker>> = Mutex::new(BTreeMap::new());
+// the earliest deadline in SLEEPERS, u64::MAX when there's none
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+static DUE: AtomicBool = AtomicBool::new(false);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Called by the timer interrupt handler.
+///
+/// Must not block or allocate.
+pub(crate) fn on_timer_tick() {
+    if timer::uptime_ms() >= NEXT_DEADLINE.load(Ordering::Acquire) {
+        DUE.store(true, Ordering::Release);
+        WAKER.wake();
+    }
+}
+
+struct TimerDue;
+
+impl Future for TimerDue {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if DUE.swap(false, Ordering::AcqRel) {
+            return Poll::Ready(());
+        }
+        WAKER.register(cx.waker());
+        if DUE.swap(false, Ordering::AcqRel) {
+            WAKER.take();
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+/// Wakes sleeping tasks as their deadlines pass; runs as an executor task.
+pub async fn run() {
+    loop {
+        TimerDue.await;
+        wake_expired();
+    }
+}
+
+fn wake_expired() {
+    let now = timer::uptime_ms();
+    let expired = without_interrupts(|| {
+        let mut sleepers = SLEEPERS.lock();
+        let later = sleepers.split_off(&(now + 1, 0));
+        let expired = core::mem::replace(&mut *sleepers, later);
+        update_next_deadline(&sleepers);
+        expired
+    });
+    for waker in expired.into_values() {
+        waker.wake();
+    }
+}
+
+fn update_next_deadline(sleepers: &BTreeMap<(u64, u64), Waker>) {
+    let next = sleepers
+        .keys()
+        .next()
+        .map_or(u64::MAX, |&(deadline, _)| deadline);
+    NEXT_DEADLINE.store(next, Ordering::Release);
+}
+
+/// Completes `ms` milliseconds from now, at the resolution of the timer tick.
+pub fn sleep_ms(ms: u64) -> Sleep {
+    Sleep {
+        deadline: timer::uptime_ms() + ms,
+        id: None,
+    }
+}
+
+pub struct Sleep {
+    deadline: u64,
+    // key of its entry in SLEEPERS once it has waited
+    id: Option<u64>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        if timer::uptime_ms() >= self.deadline {
+            self.cancel();
+            return Poll::Ready(());
+        }
+        let deadline = self.deadline;
+        let id = *self
+            .id
+            .get_or_insert_with(|| NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        without_interrupts(|| {
+            let mut sleepers = SLEEPERS.lock();
+            sleepers.insert((deadline, id), cx.waker().clone());
+            update_next_deadline(&sleepers);
+        });
+        Poll::Pending
+    }
+}
+
+impl Sleep {
+    fn cancel(&mut self) {
+        if let Some(id) = self.id.take() {
+            let deadline = self.deadline;
+            without_interrupts(|| {
+                let mut sleepers = SLEEPERS.lock();
+                if sleepers.remove(&(deadline, id)).is_some() {
+                    update_next_deadline(&sleepers);
+                }
+            });
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
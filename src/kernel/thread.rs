@@ -0,0 +1,401 @@
+use crate::arch::x86_64::timer;
+use crate::arch::x86_64::trap::TrapFrame;
+use crate::kernel::process::{self, Pid};
+use crate::kernel::signal;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::FsBase;
+use x86_64::VirtAddr;
+
+pub type Tid = u64;
+
+// timer ticks a thread runs before the next runnable one gets the CPU
+const TIME_SLICE: u64 = 5;
+
+// user addresses end where the canonical lower half does
+const USER_END: u64 = 0x0000_8000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    Runnable,
+    /// In `futex_wait` on `addr`, until woken or, if set, until `deadline` (uptime in ms).
+    Waiting {
+        addr: u64,
+        deadline: Option<u64>,
+    },
+    /// In a syscall that can't finish yet, since `since` (uptime in ms). It gives up the
+    /// CPU on the way out and runs the syscall again from the start on a later tick.
+    Blocked {
+        since: u64,
+    },
+}
+
+/// A user thread. Threads of a process share its address space, files and signal
+/// handlers; each has its own registers and FS base for thread-local storage.
+struct Thread {
+    pid: Pid,
+    state: ThreadState,
+    /// Registers to resume with; stale while the thread is running.
+    context: TrapFrame,
+    fs_base: u64,
+    /// Zeroed and woken as a futex when the thread exits, for joining it.
+    clear_tid: u64,
+}
+
+struct Threads {
+    threads: BTreeMap<Tid, Thread>,
+    /// Runnable threads other than the current one, in the order they get the CPU.
+    run_queue: VecDeque<Tid>,
+    current: Option<Tid>,
+    slice_start: u64,
+    // what the FS base MSR holds, to skip rewriting it
+    loaded_fs: u64,
+}
+
+static THREADS: Mutex<Threads> = Mutex::new(Threads {
+    threads: BTreeMap::new(),
+    run_queue: VecDeque::new(),
+    current: None,
+    slice_start: 0,
+    loaded_fs: 0,
+});
+
+/// Registers the first thread of process `pid`, about to enter ring 3; its tid is the pid.
+pub fn start(pid: Pid) {
+    let mut threads = THREADS.lock();
+    threads.threads.insert(
+        pid,
+        Thread {
+            pid,
+            state: ThreadState::Runnable,
+            context: TrapFrame::default(),
+            fs_base: 0,
+            clear_tid: 0,
+        },
+    );
+    threads.current = Some(pid);
+    threads.slice_start = timer::ticks();
+    threads.loaded_fs = 0;
+}
+
+/// The thread running in ring 3, if any.
+pub fn current_tid() -> Option<Tid> {
+    THREADS.lock().current
+}
+
+/// The thread ids of process `pid`.
+pub fn tids(pid: Pid) -> Vec<Tid> {
+    let threads = THREADS.lock();
+    threads
+        .threads
+        .iter()
+        .filter(|(_, t)| t.pid == pid)
+        .map(|(&tid, _)| tid)
+        .collect()
+}
+
+/// Forgets every thread of process `pid`.
+pub fn remove_process(pid: Pid) {
+    let mut threads = THREADS.lock();
+    threads.threads.retain(|_, t| t.pid != pid);
+    let Threads {
+        threads: table,
+        run_queue,
+        ..
+    } = &mut *threads;
+    run_queue.retain(|tid| table.contains_key(tid));
+    if threads
+        .current
+        .is_some_and(|tid| !threads.threads.contains_key(&tid))
+    {
+        threads.current = None;
+    }
+}
+
+/// Creates a thread in the current process that resumes from `frame` with rax 0, on
+/// `stack` if that's non-zero, and with FS base `tls` if given. Returns its tid.
+pub fn spawn(frame: &TrapFrame, stack: u64, tls: Option<u64>, clear_tid: u64) -> Option<Tid> {
+    let pid = process::current_pid()?;
+    if stack >= USER_END || tls.is_some_and(|tls| tls >= USER_END) {
+        return None;
+    }
+    let mut threads = THREADS.lock();
+    let parent = threads.threads.get(&threads.current?)?;
+    let fs_base = tls.unwrap_or(parent.fs_base);
+
+    let tid = process::allocate_id();
+    let mut context = *frame;
+    context.rax = 0;
+    if stack != 0 {
+        context.rsp = stack;
+    }
+    threads.threads.insert(
+        tid,
+        Thread {
+            pid,
+            state: ThreadState::Runnable,
+            context,
+            fs_base,
+            clear_tid,
+        },
+    );
+    threads.run_queue.push_back(tid);
+    Some(tid)
+}
+
+/// Sets the current thread's FS base, which user code uses for thread-local storage.
+pub fn set_fs_base(base: u64) -> bool {
+    if base >= USER_END {
+        return false;
+    }
+    let mut threads = THREADS.lock();
+    let Some(tid) = threads.current else {
+        return false;
+    };
+    if let Some(thread) = threads.threads.get_mut(&tid) {
+        thread.fs_base = base;
+    }
+    load_fs(&mut threads, base);
+    true
+}
+
+pub fn fs_base() -> Option<u64> {
+    let threads = THREADS.lock();
+    threads.threads.get(&threads.current?).map(|t| t.fs_base)
+}
+
+pub fn set_clear_tid(addr: u64) -> bool {
+    let mut threads = THREADS.lock();
+    let Some(tid) = threads.current else {
+        return false;
+    };
+    match threads.threads.get_mut(&tid) {
+        Some(thread) => {
+            thread.clear_tid = addr;
+            true
+        }
+        None => false,
+    }
+}
+
+fn load_fs(threads: &mut Threads, base: u64) {
+    if threads.loaded_fs != base {
+        FsBase::write(VirtAddr::new(base));
+        threads.loaded_fs = base;
+    }
+}
+
+// reads the futex word, if it's in the current process's memory
+fn futex_value(addr: u64) -> Option<u32> {
+    let mapped = addr
+        .checked_add(4)
+        .is_some_and(|end| process::user_range_mapped(addr, end, false));
+    if !addr.is_multiple_of(4) || !mapped {
+        return None;
+    }
+    Some(unsafe { core::ptr::read_volatile(addr as *const u32) })
+}
+
+/// Puts the current thread to sleep on `addr` if it still holds `expected`, until
+/// `futex_wake` or the `timeout_ms` runs out. The switch happens in `schedule`, on the
+/// way out of the syscall; the thread later returns 0 if woken, u64::MAX otherwise.
+pub fn futex_wait(addr: u64, expected: u32, timeout_ms: Option<u64>) -> bool {
+    if futex_value(addr) != Some(expected) {
+        return false;
+    }
+    let deadline = timeout_ms.map(|ms| timer::uptime_ms().saturating_add(ms));
+    let mut threads = THREADS.lock();
+    let Some(tid) = threads.current else {
+        return false;
+    };
+    if let Some(thread) = threads.threads.get_mut(&tid) {
+        thread.state = ThreadState::Waiting { addr, deadline };
+    }
+    true
+}
+
+/// Marks the current thread blocked in its syscall, which `syscall_trap` then rewinds to
+/// run again once another thread has had the CPU. Returns false outside of a thread.
+pub fn block_current() -> bool {
+    let mut threads = THREADS.lock();
+    let Some(tid) = threads.current else {
+        return false;
+    };
+    match threads.threads.get_mut(&tid) {
+        Some(thread) => {
+            thread.state = ThreadState::Blocked {
+                since: timer::uptime_ms(),
+            };
+            true
+        }
+        None => false,
+    }
+}
+
+/// Whether the current thread's syscall blocked and has to be run again.
+pub fn current_blocked() -> bool {
+    let threads = THREADS.lock();
+    threads
+        .current
+        .and_then(|tid| threads.threads.get(&tid))
+        .is_some_and(|t| matches!(t.state, ThreadState::Blocked { .. }))
+}
+
+/// Wakes up to `count` threads waiting on `addr`, oldest first. Returns how many woke.
+pub fn futex_wake(addr: u64, count: usize) -> usize {
+    let mut threads = THREADS.lock();
+    wake(&mut threads, addr, count)
+}
+
+fn wake(threads: &mut Threads, addr: u64, count: usize) -> usize {
+    // waiters queue up in tid order, which is creation order, good enough for fairness
+    let waiters: Vec<Tid> = threads
+        .threads
+        .iter()
+        .filter(|(_, t)| matches!(t.state, ThreadState::Waiting { addr: a, .. } if a == addr))
+        .map(|(&tid, _)| tid)
+        .take(count)
+        .collect();
+    for &tid in &waiters {
+        make_runnable(threads, tid, 0);
+    }
+    waiters.len()
+}
+
+// sets the result a waiting thread returns from its futex_wait with; a blocked thread
+// runs its syscall again instead, so its registers stay as they are
+fn make_runnable(threads: &mut Threads, tid: Tid, result: u64) {
+    if let Some(thread) = threads.threads.get_mut(&tid) {
+        if !matches!(thread.state, ThreadState::Blocked { .. }) {
+            thread.context.rax = result;
+        }
+        thread.state = ThreadState::Runnable;
+        threads.run_queue.push_back(tid);
+    }
+}
+
+// wakes waiters whose timeout has passed, failing their futex_wait, and retries threads
+// blocked since before the last tick
+fn expire(threads: &mut Threads, now: u64) {
+    let expired: Vec<Tid> = threads
+        .threads
+        .iter()
+        .filter(|(_, t)| match t.state {
+            ThreadState::Waiting {
+                deadline: Some(d), ..
+            } => d <= now,
+            ThreadState::Blocked { since } => since < now,
+            _ => false,
+        })
+        .map(|(&tid, _)| tid)
+        .collect();
+    for tid in expired {
+        make_runnable(threads, tid, u64::MAX);
+    }
+}
+
+/// Ends the current thread: clears and wakes its `clear_tid` word, then leaves it for
+/// `schedule` to drop. Returns false if it was the process's last thread.
+pub fn exit_current() -> bool {
+    let mut threads = THREADS.lock();
+    let Some(tid) = threads.current else {
+        return false;
+    };
+    let Some(thread) = threads.threads.remove(&tid) else {
+        return false;
+    };
+    if thread.clear_tid != 0 && futex_value(thread.clear_tid).is_some() {
+        unsafe { core::ptr::write_volatile(thread.clear_tid as *mut u32, 0) };
+        wake(&mut threads, thread.clear_tid, 1);
+    }
+    threads.threads.values().any(|t| t.pid == thread.pid)
+}
+
+/// Called by the timer interrupt handler while a thread runs in ring 3: wakes timed-out
+/// waiters and, once the time slice is used up, switches to the next runnable thread.
+pub(crate) fn on_timer_tick(frame: &mut TrapFrame, ticks: u64) {
+    let mut threads = THREADS.lock();
+    expire(&mut threads, timer::uptime_ms());
+    if ticks.saturating_sub(threads.slice_start) >= TIME_SLICE {
+        switch(&mut threads, frame, ticks);
+    }
+}
+
+/// Gives up the CPU if the current thread can't go on, loading the next runnable thread
+/// into `frame`. With nothing runnable, halts until a timeout or a signal changes that.
+pub fn schedule(frame: &mut TrapFrame) {
+    let enabled = interrupts::are_enabled();
+    // its frame is already rewound to run the syscall again
+    let blocked = current_blocked();
+    loop {
+        let mut threads = THREADS.lock();
+        let Some(tid) = threads.current else {
+            break;
+        };
+        match threads.threads.get(&tid).map(|t| (t.state, t.context.rax)) {
+            Some((ThreadState::Runnable, rax)) => {
+                // woken while we idled on its behalf: it carries on right here
+                if let Some(i) = threads.run_queue.iter().position(|&t| t == tid) {
+                    threads.run_queue.remove(i);
+                    if !blocked {
+                        frame.rax = rax;
+                    }
+                }
+                break;
+            }
+            Some((ThreadState::Waiting { .. } | ThreadState::Blocked { .. }, _)) | None => {}
+        }
+        if !threads.run_queue.is_empty() {
+            switch(&mut threads, frame, timer::ticks());
+            break;
+        }
+
+        // a signal fails a wait, so some thread of the process is there to take it
+        if signal::interrupted() {
+            let pid = process::current_pid();
+            let waiter = threads
+                .threads
+                .iter()
+                .find(|(_, t)| Some(t.pid) == pid && t.state != ThreadState::Runnable)
+                .map(|(&tid, _)| tid);
+            if let Some(waiter) = waiter {
+                make_runnable(&mut threads, waiter, u64::MAX);
+                continue;
+            }
+        }
+        expire(&mut threads, timer::uptime_ms());
+        if !threads.run_queue.is_empty() {
+            continue;
+        }
+        drop(threads);
+        interrupts::enable_and_hlt();
+        interrupts::disable();
+    }
+    if enabled {
+        interrupts::enable();
+    }
+}
+
+// saves `frame` for the current thread, if it's still around, and loads the next one
+fn switch(threads: &mut Threads, frame: &mut TrapFrame, ticks: u64) {
+    let Some(next) = threads.run_queue.pop_front() else {
+        threads.slice_start = ticks;
+        return;
+    };
+    if let Some(tid) = threads.current {
+        if let Some(thread) = threads.threads.get_mut(&tid) {
+            thread.context = *frame;
+            if thread.state == ThreadState::Runnable {
+                threads.run_queue.push_back(tid);
+            }
+        }
+    }
+    let thread = &threads.threads[&next];
+    *frame = thread.context;
+    let fs_base = thread.fs_base;
+    load_fs(threads, fs_base);
+    threads.current = Some(next);
+    threads.slice_start = ticks;
+}
@@ -0,0 +1,117 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+pub type Uid = u32;
+pub type Gid = u32;
+
+pub const ROOT_UID: Uid = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: Uid,
+    pub gid: Gid,
+}
+
+impl Credentials {
+    pub const ROOT: Credentials = Credentials { uid: 0, gid: 0 };
+
+    pub fn is_root(&self) -> bool {
+        self.uid == ROOT_UID
+    }
+}
+
+/// One line of `/etc/passwd`: `name:password:uid:gid:gecos:home:shell`.
+///
+/// The password field is normally `x`; the hash itself is in `/etc/shadow`, which only
+/// root can read.
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub password: String,
+    pub uid: Uid,
+    pub gid: Gid,
+    pub home: String,
+    pub shell: String,
+}
+
+impl User {
+    pub fn credentials(&self) -> Credentials {
+        Credentials {
+            uid: self.uid,
+            gid: self.gid,
+        }
+    }
+
+    /// Checks `password` against the user's `/etc/shadow` entry. An empty hash means no
+    /// password; a missing entry, or one that isn't a hash like `!`, never matches.
+    pub fn check_password(&self, password: &str) -> bool {
+        match shadow_hash(&self.name) {
+            Some(hash) if hash.is_empty() => true,
+            Some(hash) => crate::kernel::password::verify(&hash, password),
+            None => false,
+        }
+    }
+}
+
+pub const PASSWD_PATH: &str = "/etc/passwd";
+pub const SHADOW_PATH: &str = "/etc/shadow";
+
+// credentials of the logged-in console session (the kernel shell)
+static SESSION: Mutex<Credentials> = Mutex::new(Credentials::ROOT);
+
+pub fn parse_passwd(contents: &str) -> Vec<User> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 7 {
+                return None;
+            }
+            Some(User {
+                name: fields[0].into(),
+                password: fields[1].into(),
+                uid: fields[2].parse().ok()?,
+                gid: fields[3].parse().ok()?,
+                home: fields[5].into(),
+                shell: fields[6].into(),
+            })
+        })
+        .collect()
+}
+
+/// Reads the user database from `/etc/passwd`.
+pub fn users() -> Vec<User> {
+    let data = crate::kernel::fs::root().and_then(|fs| fs.read_file(PASSWD_PATH).ok());
+    match data {
+        Some(data) => parse_passwd(&String::from_utf8_lossy(&data)),
+        None => Vec::new(),
+    }
+}
+
+/// The hash field of `name`'s line in `/etc/shadow`: `name:hash:...`.
+fn shadow_hash(name: &str) -> Option<String> {
+    let data = crate::kernel::fs::root()?.read_file(SHADOW_PATH).ok()?;
+    String::from_utf8_lossy(&data).lines().find_map(|line| {
+        let mut fields = line.split(':');
+        (fields.next() == Some(name)).then(|| fields.next().unwrap_or("").into())
+    })
+}
+
+pub fn find_by_name(name: &str) -> Option<User> {
+    users().into_iter().find(|u| u.name == name)
+}
+
+pub fn find_by_uid(uid: Uid) -> Option<User> {
+    users().into_iter().find(|u| u.uid == uid)
+}
+
+pub fn set_session(cred: Credentials) {
+    *SESSION.lock() = cred;
+}
+
+/// Credentials of whoever is asking: the running user process, or the console session.
+pub fn current() -> Credentials {
+    crate::kernel::process::with_current(|p| p.cred).unwrap_or_else(|| *SESSION.lock())
+}
@@ -1,36 +1,82 @@
+use x86_64::instructions::interrupts;
 use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
 use x86_64::VirtAddr;
 
+use crate::kernel::{process, thread};
+
 const USER_STACK_SIZE: usize = 4096 * 20;
 
-pub fn jump_to_userspace(entry_point: VirtAddr, user_stack: VirtAddr) -> ! {
+// kernel stack pointer to resume at when the user program is done, see `return_to_kernel`
+#[no_mangle]
+static mut KERNEL_RETURN_RSP: u64 = 0;
+
+/// Runs the current process's code at `entry_point` in ring 3 until it exits or is killed,
+/// and returns its exit status.
+pub fn run(entry_point: VirtAddr, user_stack: VirtAddr) -> u64 {
     let code_selector = crate::arch::x86_64::gdt::user_code_selector();
     let data_selector = crate::arch::x86_64::gdt::user_data_selector();
 
-    unsafe {
-        core::arch::asm!(
-            "mov ax, {0:x}",
-            "mov ds, ax",
-            "mov es, ax",
-            "mov fs, ax",
-            "mov gs, ax",
-
-            //iretq stack frame
-            "push {0:r}",            // SS (stack segment)
-            "push {1:r}",            // RSP (user stack pointer)
-            "push 0x200",            // RFLAGS (interrupt enable)
-            "push {2:r}",            // CS (code segment)
-            "push {3:r}",            // RIP (entry point)
-
-            "iretq",
-
-            in(reg) data_selector.0,
-            in(reg) user_stack.as_u64(),
-            in(reg) code_selector.0,
-            in(reg) entry_point.as_u64(),
-            options(noreturn)
-        );
+    if let Some(pid) = process::current_pid() {
+        thread::start(pid);
     }
+    let status = unsafe {
+        enter_user(
+            entry_point.as_u64(),
+            user_stack.as_u64(),
+            code_selector.0 as u64,
+            data_selector.0 as u64,
+        )
+    };
+    // we come back from a syscall or an exception, where interrupts are off
+    interrupts::enable();
+    status
+}
+
+#[unsafe(naked)]
+unsafe extern "C" fn enter_user(entry: u64, stack: u64, code: u64, data: u64) -> u64 {
+    core::arch::naked_asm!(
+        // what the System V ABI says survives the call, for return_to_kernel
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rip + KERNEL_RETURN_RSP], rsp",
+        "mov ax, cx",
+        "mov ds, ax",
+        "mov es, ax",
+        "mov fs, ax",
+        "mov gs, ax",
+        //iretq stack frame
+        "push rcx",   // SS (stack segment)
+        "push rsi",   // RSP (user stack pointer)
+        "push 0x202", // RFLAGS (interrupt enable)
+        "push rdx",   // CS (code segment)
+        "push rdi",   // RIP (entry point)
+        "iretq",
+    );
+}
+
+/// Abandons the current kernel stack and makes `run` return `status`. Called once the
+/// process has been removed, from a syscall or an exception taken in ring 3.
+///
+/// # Safety
+///
+/// Must only be called while a `run` is in progress, with no locks held.
+#[unsafe(naked)]
+pub unsafe extern "C" fn return_to_kernel(status: u64) -> ! {
+    core::arch::naked_asm!(
+        "mov rsp, [rip + KERNEL_RETURN_RSP]",
+        "mov rax, rdi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "ret",
+    );
 }
 
 //allocates fresh phy frames and maps them user virtual address
@@ -62,6 +108,8 @@ pub fn allocate_user_stack(
         }
     }
 
+    record_region(stack_start, stack_end, false, "[stack]");
+
     // Return stack END (remember: stacks grow DOWN)
     Ok(stack_end)
 }
@@ -103,6 +151,20 @@ pub fn load_user_program(
         let dest = code_start.as_u64() as *mut u8;
         core::ptr::copy_nonoverlapping(code.as_ptr(), dest, code_size);
     }
+    record_region(code_start, code_start + pages_needed * 4096, true, "[text]");
 
     Ok(code_start)
 }
+
+// notes a mapping in the current process for /proc/<pid>/maps
+fn record_region(start: VirtAddr, end: VirtAddr, executable: bool, name: &str) {
+    crate::kernel::process::with_current(|p| {
+        p.regions.push(crate::kernel::process::Region {
+            start: start.as_u64(),
+            end: end.as_u64(),
+            writable: true,
+            executable,
+            name: name.into(),
+        })
+    });
+}
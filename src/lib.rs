@@ -64,6 +64,7 @@ pub fn init() {
     arch::x86_64::gdt::init();
     arch::x86_64::interrupts::init_idt();
     arch::x86_64::syscall::init();
+    arch::x86_64::timer::init();
     unsafe {
         arch::x86_64::interrupts::PICS.lock().initialize();
     }
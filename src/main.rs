@@ -9,10 +9,11 @@ extern crate alloc;
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 use zero::drivers::keyboard;
+use zero::kernel::kthread;
 use zero::kernel::memory::allocator;
 use zero::kernel::memory::memory;
 use zero::kernel::memory::memory::BootInfoFrameAllocator;
-use zero::kernel::task::{executor::Executor, Task};
+use zero::kernel::task::{executor::Executor, Priority, Task};
 use zero::println;
 use zero::ui::shell;
 
@@ -31,6 +32,13 @@ fn kernel_main(_boot_info: &'static BootInfo) -> ! {
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap inititalization failed");
     println!("heap allocator initialized...");
 
+    zero::kernel::memory::dma::init(&mut frame_allocator);
+    zero::arch::x86_64::apic::init(&mut mapper, &mut frame_allocator);
+    zero::drivers::pci::init(&mut mapper, &mut frame_allocator);
+    zero::drivers::block::init();
+    zero::drivers::net::init();
+    zero::kernel::net::init();
+
     zero::kernel::fs::init();
     println!("ramfs initialized...\n");
 
@@ -56,6 +64,9 @@ fn kernel_main(_boot_info: &'static BootInfo) -> ! {
             0x6f, 0x6d, 0x20, 0x52, 0x33, 0x21, 0x0a, // "om R3!\n"
         ];
 
+        let pid = zero::kernel::process::create("init", zero::kernel::user::Credentials::ROOT);
+        zero::kernel::process::set_current(pid);
+
         //loading user program into memory space:
         let entry = zero::kernel::userspace::load_user_program(
             user_code,
@@ -63,20 +74,32 @@ fn kernel_main(_boot_info: &'static BootInfo) -> ! {
             &mut frame_allocator,
         )
         .expect("failed to load user program");
+
         //allocating the user stack
         let user_stack =
             zero::kernel::userspace::allocate_user_stack(&mut mapper, &mut frame_allocator)
                 .expect("failed to allocate user stack");
 
-        unsafe {
-            zero::kernel::userspace::jump_to_userspace(entry, user_stack);
-        }
+        let status = zero::kernel::userspace::run(entry, user_stack);
+        println!("[USERSPACE]: init exited with status {}", status);
     }
 
-    let mut executor = Executor::new();
-    executor.spawn(Task::new(keyboard::print_keypresses()));
-    executor.spawn(Task::new(shell::shell()));
-    executor.run();
+    kthread::spawn_kthread("executor", || {
+        let mut executor = Executor::new();
+        executor.spawn(
+            Task::named("keyboard", keyboard::print_keypresses()).with_priority(Priority::High),
+        );
+        executor.spawn(Task::named("shell", shell::shell()));
+        executor.spawn(Task::named("net", zero::kernel::net::run()));
+        executor.spawn(
+            Task::named("timers", zero::kernel::task::time::run()).with_priority(Priority::High),
+        );
+        executor.run();
+    });
+    kthread::spawn_kthread("writeback", zero::drivers::block::cache::writeback);
+
+    // everything else happens on the threads above
+    kthread::exit();
 }
 
 // This function is called on panic.
@@ -1,13 +1,39 @@
+use crate::kernel::task::sync::Notify;
 use alloc::string::{String, ToString};
+use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
 
 static INPUT_BUFFER: Mutex<String> = Mutex::new(String::new());
+static ECHO: AtomicBool = AtomicBool::new(true);
+// signalled for every complete line typed
+static LINE_READY: Notify = Notify::new();
+
+/// Turns echoing of typed characters on or off (e.g. for password prompts).
+pub fn set_echo(enabled: bool) {
+    ECHO.store(enabled, Ordering::SeqCst);
+}
+
+pub fn echo_enabled() -> bool {
+    ECHO.load(Ordering::SeqCst)
+}
+
+// user threads check for input from syscalls, with interrupts off, so whoever holds the
+// buffer must not be preempted
+fn with_input<R>(f: impl FnOnce(&mut String) -> R) -> R {
+    without_interrupts(|| f(&mut INPUT_BUFFER.lock()))
+}
 
 pub fn push_char(c: char) {
-    let mut buf = INPUT_BUFFER.lock();
+    with_input(|buf| match c {
+        '\n' => {
+            buf.push('\n');
+            LINE_READY.notify_one();
+        }
 
-    match c {
-        '\n' => buf.push('\n'),
+        '\x08' | '\x7f' if !echo_enabled() => {
+            buf.pop();
+        }
 
         '\x08' | '\x7f' => {
             if crate::ui::terminal::can_backspace() {
@@ -17,19 +43,44 @@ pub fn push_char(c: char) {
         }
 
         _ => buf.push(c),
-    }
+    })
+}
+
+/// Whether a complete line is waiting to be read.
+pub fn line_ready() -> bool {
+    with_input(|input| input.contains('\n'))
+}
+
+/// Takes as much of the next complete line (including its `\n`) as fits in `buf`
+/// without waiting; returns 0 if no full line has been typed yet.
+pub fn try_read(buf: &mut [u8]) -> usize {
+    with_input(|input| {
+        let line_len = match input.find('\n') {
+            Some(pos) => pos + 1,
+            None => return 0,
+        };
+
+        let mut len = line_len.min(buf.len());
+        while !input.is_char_boundary(len) {
+            len -= 1;
+        }
+        buf[..len].copy_from_slice(&input.as_bytes()[..len]);
+        input.drain(..len);
+        len
+    })
 }
 
 pub async fn read_line() -> String {
     loop {
-        {
-            let mut buf = INPUT_BUFFER.lock();
-            if let Some(pos) = buf.find('\n') {
-                let line = buf[..pos].to_string();
-                *buf = buf[pos + 1..].to_string();
-                return line;
-            }
+        let line = with_input(|buf| {
+            let pos = buf.find('\n')?;
+            let line = buf[..pos].to_string();
+            *buf = buf[pos + 1..].to_string();
+            Some(line)
+        });
+        if let Some(line) = line {
+            return line;
         }
-        crate::kernel::task::yield_now().await;
+        LINE_READY.notified().await;
     }
 }
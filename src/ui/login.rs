@@ -0,0 +1,40 @@
+use crate::kernel::user::{self, User};
+use crate::ui::{input, terminal};
+use alloc::string::String;
+
+/// Prompts for a user name and password until they match an `/etc/passwd` entry.
+pub async fn login() -> User {
+    loop {
+        let users = user::users();
+        if users.is_empty() {
+            terminal::write("login: no users in /etc/passwd, continuing as root\n");
+            return root_user();
+        }
+
+        terminal::write("zero-os login: ");
+        terminal::mark_input_start();
+        let name = input::read_line().await;
+
+        terminal::write("Password: ");
+        terminal::mark_input_start();
+        input::set_echo(false);
+        let password = input::read_line().await;
+        input::set_echo(true);
+
+        match users.into_iter().find(|u| u.name == name.trim()) {
+            Some(user) if user.check_password(password.trim()) => return user,
+            _ => terminal::write("Login incorrect\n\n"),
+        }
+    }
+}
+
+fn root_user() -> User {
+    User {
+        name: String::from("root"),
+        password: String::new(),
+        uid: user::ROOT_UID,
+        gid: 0,
+        home: String::from("/"),
+        shell: String::new(),
+    }
+}
@@ -1,3 +1,4 @@
 pub mod input;
+pub mod login;
 pub mod shell;
 pub mod terminal;
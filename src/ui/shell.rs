@@ -1,24 +1,72 @@
 use crate::arch::x86_64::cpu::reboot;
+use crate::arch::x86_64::timer;
+use crate::drivers::block;
+use crate::drivers::pci::{self, msi};
 use crate::kernel::fs;
-use crate::ui::{input, terminal};
+use crate::kernel::net::{self, Ipv4Addr};
+use crate::kernel::task;
+use crate::kernel::user::{self, Credentials, User};
+use crate::ui::{input, login, terminal};
 use alloc::format;
-use alloc::string::String;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use spin::Mutex;
+
+static CWD: Mutex<String> = Mutex::new(String::new());
+
+// tasks spawned at boot that `kill` leaves alone
+const SYSTEM_TASKS: &[&str] = &["keyboard", "shell", "net", "timers"];
+
+// resolves a command argument against the shell's working directory
+fn path(arg: &str) -> String {
+    let cwd = CWD.lock();
+    let cwd = if cwd.is_empty() { "/" } else { cwd.as_str() };
+    fs::VFS::resolve(cwd, arg)
+}
 
 pub async fn shell() {
     loop {
-        terminal::write("kernel@zero-os:-# ");
-        terminal::mark_input_start();
+        user::set_session(Credentials::ROOT);
+        let user = login::login().await;
+        start_session(&user);
+
+        let sigil = if user.uid == user::ROOT_UID { '#' } else { '$' };
+        loop {
+            let prompt = format!("{}@zero-os:{}{} ", user.name, path("."), sigil);
+            terminal::write(&prompt);
+            terminal::mark_input_start();
+
+            let line = input::read_line().await;
+            if !run_command(line).await {
+                break;
+            }
+        }
+        terminal::write("\n");
+    }
+}
 
-        let line = input::read_line().await;
-        run_command(line);
+fn start_session(user: &User) {
+    if let Some(fs) = fs::root() {
+        // still running as root here, so a missing home directory can be handed over
+        if !fs.exists(&user.home) && fs.create_dir(&user.home).is_ok() {
+            let _ = fs.chmod(&user.home, 0o700);
+            let _ = fs.chown(&user.home, user.uid, user.gid);
+        }
+        if let Ok(motd) = fs.read_file("/etc/motd") {
+            terminal::write(&String::from_utf8_lossy(&motd));
+        }
     }
+
+    user::set_session(user.credentials());
+    *CWD.lock() = String::from("/");
+    cmd_cd(&[user.home.as_str()]);
 }
 
-fn run_command(line: String) {
+/// Runs one command line; returns false once the user logs out.
+async fn run_command(line: String) -> bool {
     let parts: Vec<&str> = line.split_whitespace().collect();
     if parts.is_empty() {
-        return;
+        return true;
     }
 
     match parts[0] {
@@ -32,11 +80,34 @@ fn run_command(line: String) {
         "touch" => cmd_touch(&parts[1..]),
         "rm" => cmd_rm(&parts[1..]),
         "write" => cmd_write(&parts[1..]),
+        "append" => cmd_append(&parts[1..]),
         "stat" => cmd_stat(&parts[1..]),
+        "cd" => cmd_cd(&parts[1..]),
+        "pwd" => cmd_pwd(),
+        "mv" => cmd_mv(&parts[1..]),
+        "cp" => cmd_cp(&parts[1..]),
+        "mount" => cmd_mount(&parts[1..]),
+        "umount" => cmd_umount(&parts[1..]),
+        "lsblk" => cmd_lsblk(),
+        "sync" => cmd_sync(),
+        "cache" => cmd_cache(),
+        "lspci" => cmd_lspci(&parts[1..]),
+        "ifconfig" => cmd_ifconfig(&parts[1..]),
+        "ping" => cmd_ping(&parts[1..]).await,
+        "netstat" => cmd_netstat(),
+        "ps" | "tasks" => cmd_tasks(),
+        "kill" => cmd_kill(&parts[1..]),
+        "chmod" => cmd_chmod(&parts[1..]),
+        "chown" => cmd_chown(&parts[1..]),
+        "whoami" => cmd_whoami(),
+        "ln" => cmd_ln(&parts[1..]),
+        "readlink" => cmd_readlink(&parts[1..]),
+        "logout" | "exit" => return false,
         _ => {
             terminal::write("command not found\n");
         }
     }
+    true
 }
 
 fn cmd_help() {
@@ -51,7 +122,29 @@ fn cmd_help() {
     terminal::write("  touch <file> - create empty file\n");
     terminal::write("  rm <path>    - remove file or empty directory\n");
     terminal::write("  write <file> <text> - write text to file\n");
+    terminal::write("  append <file> <text> - append a line to file\n");
     terminal::write("  stat <path>  - show file/directory information\n");
+    terminal::write("  cd [dir]     - change working directory\n");
+    terminal::write("  pwd          - print working directory\n");
+    terminal::write("  mv <src> <dst> - move or rename file/directory\n");
+    terminal::write("  cp [-r] <src> <dst> - copy file (or directory with -r)\n");
+    terminal::write("  mount [<type> <source> <dir>] - list or add mounts\n");
+    terminal::write("  umount <dir> - unmount filesystem\n");
+    terminal::write("  lsblk        - list block devices\n");
+    terminal::write("  sync         - write cached disk blocks back\n");
+    terminal::write("  cache        - show buffer cache statistics\n");
+    terminal::write("  lspci [-v]   - list PCI devices\n");
+    terminal::write("  ifconfig [<if> <addr>/<prefix> [gw]] - show or set interface addresses\n");
+    terminal::write("  ping <addr> [count] - send ICMP echo requests\n");
+    terminal::write("  netstat      - list TCP connections and UDP sockets\n");
+    terminal::write("  ps, tasks    - list async tasks and their poll statistics\n");
+    terminal::write("  kill <id>    - cancel an async task\n");
+    terminal::write("  chmod <mode> <path> - change permission bits (octal)\n");
+    terminal::write("  chown <user>[:<gid>] <path> - change owner\n");
+    terminal::write("  whoami       - print current user\n");
+    terminal::write("  ln [-s] <target> <link> - create a hard or symbolic link\n");
+    terminal::write("  readlink <path> - print symlink target\n");
+    terminal::write("  logout       - end session\n");
 }
 
 fn cmd_echo(args: &[&str]) {
@@ -68,20 +161,31 @@ fn cmd_reboot() {
 }
 
 fn cmd_ls(args: &[&str]) {
-    let path = if args.is_empty() { "/" } else { args[0] };
+    let path = path(args.first().copied().unwrap_or("."));
 
     if let Some(fs) = fs::root() {
-        match fs.list_dir(path) {
+        match fs.list_dir(&path) {
             Ok(entries) => {
                 if entries.is_empty() {
                     terminal::write("(empty)\n");
                 } else {
                     for entry in entries {
-                        let type_char = match entry.file_type {
-                            fs::FileType::Directory => 'd',
-                            fs::FileType::File => 'f',
-                        };
-                        let output = format!("{} {:8} {}\n", type_char, entry.size, entry.name);
+                        let mut output = format!(
+                            "{} {:>2} {:>5} {:>5} {:8} {}",
+                            fs::perm::mode_string(entry.file_type, entry.mode),
+                            entry.nlink,
+                            entry.uid,
+                            entry.gid,
+                            entry.size,
+                            entry.name
+                        );
+                        if entry.file_type == fs::FileType::Symlink {
+                            if let Ok(link) = fs.readlink(&fs::VFS::join(&path, &entry.name)) {
+                                output.push_str(" -> ");
+                                output.push_str(&link);
+                            }
+                        }
+                        output.push('\n');
                         terminal::write(&output);
                     }
                 }
@@ -103,26 +207,47 @@ fn cmd_cat(args: &[&str]) {
     }
 
     if let Some(fs) = fs::root() {
-        match fs.read_file(args[0]) {
-            Ok(data) => match core::str::from_utf8(&data) {
-                Ok(s) => {
-                    terminal::write(s);
-                    terminal::write("\n");
+        // stream the file in chunks instead of copying it whole
+        let mut buf = [0u8; 512];
+        let mut offset = 0;
+        // the start of a character split across chunks, kept at the front of buf
+        let mut carried = 0;
+        loop {
+            match fs.read_at(&path(args[0]), offset, &mut buf[carried..]) {
+                Ok(0) => break,
+                Ok(read) => {
+                    let len = carried + read;
+                    let end = len - incomplete_utf8_tail(&buf[..len]);
+                    terminal::write(&String::from_utf8_lossy(&buf[..end]));
+                    buf.copy_within(end..len, 0);
+                    carried = len - end;
+                    offset += read;
                 }
-                Err(_) => {
-                    terminal::write("cat: file contains non-UTF8 data\n");
+                Err(e) => {
+                    let msg = format!("cat: {}\n", e);
+                    terminal::write(&msg);
+                    return;
                 }
-            },
-            Err(e) => {
-                let msg = format!("cat: {}\n", e);
-                terminal::write(&msg);
             }
         }
+        // the file ended mid-character
+        terminal::write(&String::from_utf8_lossy(&buf[..carried]));
+        terminal::write("\n");
     } else {
         terminal::write("filesystem not initialized\n");
     }
 }
 
+// how many bytes at the end of `bytes` begin a UTF-8 character that isn't complete yet
+fn incomplete_utf8_tail(bytes: &[u8]) -> usize {
+    (bytes.len().saturating_sub(3)..bytes.len())
+        .find(|&i| {
+            matches!(core::str::from_utf8(&bytes[i..]),
+                Err(e) if e.valid_up_to() == 0 && e.error_len().is_none())
+        })
+        .map_or(0, |i| bytes.len() - i)
+}
+
 fn cmd_mkdir(args: &[&str]) {
     if args.is_empty() {
         terminal::write("mkdir: missing operand\n");
@@ -130,7 +255,7 @@ fn cmd_mkdir(args: &[&str]) {
     }
 
     if let Some(fs) = fs::root() {
-        match fs.create_dir(args[0]) {
+        match fs.create_dir(&path(args[0])) {
             Ok(_) => {}
             Err(e) => {
                 let msg = format!("mkdir: {}\n", e);
@@ -149,7 +274,7 @@ fn cmd_touch(args: &[&str]) {
     }
 
     if let Some(fs) = fs::root() {
-        match fs.create_file(args[0]) {
+        match fs.create_file(&path(args[0])) {
             Ok(_) => {}
             Err(e) => {
                 let msg = format!("touch: {}\n", e);
@@ -168,7 +293,7 @@ fn cmd_rm(args: &[&str]) {
     }
 
     if let Some(fs) = fs::root() {
-        match fs.remove(args[0]) {
+        match fs.remove(&path(args[0])) {
             Ok(_) => {}
             Err(e) => {
                 let msg = format!("rm: {}\n", e);
@@ -187,7 +312,7 @@ fn cmd_write(args: &[&str]) {
         return;
     }
 
-    let filename = args[0];
+    let filename = &path(args[0]);
     let text = args[1..].join(" ");
 
     if let Some(fs) = fs::root() {
@@ -212,6 +337,25 @@ fn cmd_write(args: &[&str]) {
     }
 }
 
+fn cmd_append(args: &[&str]) {
+    if args.len() < 2 {
+        terminal::write("append: missing operands\n");
+        terminal::write("usage: append <file> <text>\n");
+        return;
+    }
+
+    let options = fs::OpenOptions::new().write(true).create(true).append(true);
+    let result = fs::File::open(&path(args[0]), options).and_then(|mut file| {
+        let mut line = args[1..].join(" ");
+        line.push('\n');
+        file.write(line.as_bytes())
+    });
+    if let Err(e) = result {
+        let msg = format!("append: {}\n", e);
+        terminal::write(&msg);
+    }
+}
+
 fn cmd_stat(args: &[&str]) {
     if args.is_empty() {
         terminal::write("stat: missing operand\n");
@@ -219,19 +363,38 @@ fn cmd_stat(args: &[&str]) {
     }
 
     if let Some(fs) = fs::root() {
-        match fs.stat(args[0]) {
+        let target = path(args[0]);
+        match fs.lstat(&target) {
             Ok(info) => {
                 let type_str = match info.file_type {
                     fs::FileType::Directory => "directory",
                     fs::FileType::File => "file",
+                    fs::FileType::Symlink => "symbolic link",
+                    fs::FileType::CharDevice => "character device",
                 };
                 terminal::write("  File: ");
                 terminal::write(&info.name);
+                if info.file_type == fs::FileType::Symlink {
+                    if let Ok(link) = fs.readlink(&target) {
+                        terminal::write(" -> ");
+                        terminal::write(&link);
+                    }
+                }
                 terminal::write("\n  Type: ");
                 terminal::write(type_str);
                 terminal::write("\n  Size: ");
                 let size_str = format!("{} bytes\n", info.size);
                 terminal::write(&size_str);
+                let inode_str = format!("  Inode: {}  Links: {}\n", info.ino, info.nlink);
+                terminal::write(&inode_str);
+                let mode_str = format!(
+                    "  Mode: {:04o} ({})\n",
+                    info.mode,
+                    fs::perm::mode_string(info.file_type, info.mode)
+                );
+                terminal::write(&mode_str);
+                let owner_str = format!("  Uid: {}  Gid: {}\n", info.uid, info.gid);
+                terminal::write(&owner_str);
             }
             Err(e) => {
                 let msg = format!("stat: {}\n", e);
@@ -242,3 +405,542 @@ fn cmd_stat(args: &[&str]) {
         terminal::write("filesystem not initialized\n");
     }
 }
+
+// `dst` naming an existing directory means "put it inside", like coreutils
+fn destination_path(fs: &dyn fs::FileSystem, src: &str, dst: &str) -> String {
+    match fs.stat(dst) {
+        Ok(info) if info.file_type == fs::FileType::Directory => match fs::VFS::filename(src) {
+            Some(name) => fs::VFS::join(dst, &name),
+            None => String::from(dst),
+        },
+        _ => String::from(dst),
+    }
+}
+
+fn cmd_cd(args: &[&str]) {
+    let target = path(args.first().copied().unwrap_or("/"));
+
+    if let Some(fs) = fs::root() {
+        match fs.stat(&target) {
+            Ok(info) if info.file_type == fs::FileType::Directory => *CWD.lock() = target,
+            Ok(_) => terminal::write("cd: Not a directory\n"),
+            Err(e) => {
+                let msg = format!("cd: {}\n", e);
+                terminal::write(&msg);
+            }
+        }
+    } else {
+        terminal::write("filesystem not initialized\n");
+    }
+}
+
+fn cmd_pwd() {
+    terminal::write(&path("."));
+    terminal::write("\n");
+}
+
+fn cmd_mv(args: &[&str]) {
+    if args.len() < 2 {
+        terminal::write("mv: missing operands\n");
+        terminal::write("usage: mv <src> <dst>\n");
+        return;
+    }
+
+    if let Some(fs) = fs::root() {
+        let src = path(args[0]);
+        let dst = destination_path(&*fs, &src, &path(args[1]));
+        let result = match fs.rename(&src, &dst) {
+            // different filesystems: fall back to copy and delete
+            Err(fs::FsError::CrossDevice) => {
+                copy_tree(&*fs, &src, &dst).and_then(|_| remove_tree(&*fs, &src))
+            }
+            result => result,
+        };
+        if let Err(e) = result {
+            let msg = format!("mv: {}\n", e);
+            terminal::write(&msg);
+        }
+    } else {
+        terminal::write("filesystem not initialized\n");
+    }
+}
+
+fn cmd_cp(args: &[&str]) {
+    let recursive = args.first() == Some(&"-r");
+    let args = if recursive { &args[1..] } else { args };
+    if args.len() < 2 {
+        terminal::write("cp: missing operands\n");
+        terminal::write("usage: cp [-r] <src> <dst>\n");
+        return;
+    }
+
+    if let Some(fs) = fs::root() {
+        let src = path(args[0]);
+        let dst = destination_path(&*fs, &src, &path(args[1]));
+        let result = match fs.stat(&src) {
+            Ok(info) if info.file_type == fs::FileType::Directory && !recursive => {
+                let msg = format!("cp: -r not specified; omitting directory '{}'\n", args[0]);
+                terminal::write(&msg);
+                return;
+            }
+            Ok(_) if fs::VFS::is_within(&dst, &src) => Err(fs::FsError::InvalidPath),
+            Ok(_) => copy_tree(&*fs, &src, &dst),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            let msg = format!("cp: {}\n", e);
+            terminal::write(&msg);
+        }
+    } else {
+        terminal::write("filesystem not initialized\n");
+    }
+}
+
+// symlinks are copied as links, never followed
+fn copy_tree(fs: &dyn fs::FileSystem, src: &str, dst: &str) -> fs::FsResult<()> {
+    let info = fs.lstat(src)?;
+    match info.file_type {
+        fs::FileType::Directory => {
+            if !fs.exists(dst) {
+                fs.create_dir(dst)?;
+            }
+            for entry in fs.list_dir(src)? {
+                let from = fs::VFS::join(src, &entry.name);
+                let to = fs::VFS::join(dst, &entry.name);
+                copy_tree(fs, &from, &to)?;
+            }
+        }
+        fs::FileType::File => {
+            let data = fs.read_file(src)?;
+            if !fs.exists(dst) {
+                fs.create_file(dst)?;
+            }
+            fs.write_file(dst, &data)?;
+        }
+        fs::FileType::Symlink => return fs.symlink(&fs.readlink(src)?, dst),
+        fs::FileType::CharDevice => return Err(fs::FsError::NotSupported),
+    }
+    fs.chmod(dst, info.mode)
+}
+
+fn remove_tree(fs: &dyn fs::FileSystem, path: &str) -> fs::FsResult<()> {
+    if fs.lstat(path)?.file_type == fs::FileType::Directory {
+        for entry in fs.list_dir(path)? {
+            remove_tree(fs, &fs::VFS::join(path, &entry.name))?;
+        }
+    }
+    fs.remove(path)
+}
+
+fn cmd_mount(args: &[&str]) {
+    if args.is_empty() {
+        for info in fs::mounts() {
+            let line = format!("{} on {} type {}\n", info.source, info.target, info.fs_type);
+            terminal::write(&line);
+        }
+        return;
+    }
+
+    if args.len() < 3 {
+        terminal::write("mount: missing operands\n");
+        terminal::write("usage: mount <type> <source> <dir>\n");
+        return;
+    }
+
+    if let Err(e) = fs::mount(args[0], args[1], &path(args[2])) {
+        let msg = format!("mount: {}\n", e);
+        terminal::write(&msg);
+    }
+}
+
+fn cmd_umount(args: &[&str]) {
+    if args.is_empty() {
+        terminal::write("umount: missing operand\n");
+        return;
+    }
+
+    if let Err(e) = fs::umount(&path(args[0])) {
+        let msg = format!("umount: {}\n", e);
+        terminal::write(&msg);
+    }
+}
+
+fn cmd_chmod(args: &[&str]) {
+    if args.len() < 2 {
+        terminal::write("chmod: missing operands\n");
+        terminal::write("usage: chmod <mode> <path>\n");
+        return;
+    }
+
+    let mode = match u16::from_str_radix(args[0], 8) {
+        Ok(mode) if mode <= 0o7777 => mode,
+        _ => {
+            let msg = format!("chmod: invalid mode: '{}'\n", args[0]);
+            terminal::write(&msg);
+            return;
+        }
+    };
+
+    if let Some(fs) = fs::root() {
+        if let Err(e) = fs.chmod(&path(args[1]), mode) {
+            let msg = format!("chmod: {}\n", e);
+            terminal::write(&msg);
+        }
+    } else {
+        terminal::write("filesystem not initialized\n");
+    }
+}
+
+fn cmd_chown(args: &[&str]) {
+    if args.len() < 2 {
+        terminal::write("chown: missing operands\n");
+        terminal::write("usage: chown <user>[:<gid>] <path>\n");
+        return;
+    }
+
+    let (owner, group) = match args[0].split_once(':') {
+        Some((owner, group)) => (owner, Some(group)),
+        None => (args[0], None),
+    };
+    // owners can be given by name or number
+    let user = owner
+        .parse::<u32>()
+        .ok()
+        .map(|uid| (uid, user::find_by_uid(uid).map(|u| u.gid).unwrap_or(uid)))
+        .or_else(|| user::find_by_name(owner).map(|u| (u.uid, u.gid)));
+    let (uid, default_gid) = match user {
+        Some(ids) => ids,
+        None => {
+            let msg = format!("chown: invalid user: '{}'\n", owner);
+            terminal::write(&msg);
+            return;
+        }
+    };
+    let gid = match group.map(|g| g.parse::<u32>()) {
+        None => default_gid,
+        Some(Ok(gid)) => gid,
+        Some(Err(_)) => {
+            terminal::write("chown: invalid group\n");
+            return;
+        }
+    };
+
+    if let Some(fs) = fs::root() {
+        if let Err(e) = fs.chown(&path(args[1]), uid, gid) {
+            let msg = format!("chown: {}\n", e);
+            terminal::write(&msg);
+        }
+    } else {
+        terminal::write("filesystem not initialized\n");
+    }
+}
+
+fn cmd_lsblk() {
+    terminal::write("NAME      SIZE        SECTORS  SECTOR\n");
+    for (name, dev) in block::devices() {
+        let bytes = dev.block_count() * dev.block_size() as u64;
+        let line = format!(
+            "{:<8}  {:>7} KiB  {:>8}  {}\n",
+            name,
+            bytes / 1024,
+            dev.block_count(),
+            dev.block_size()
+        );
+        terminal::write(&line);
+    }
+}
+
+fn cmd_sync() {
+    if let Err(e) = block::cache::sync() {
+        let msg = format!("sync: {}\n", e);
+        terminal::write(&msg);
+    }
+}
+
+fn cmd_cache() {
+    let stats = block::cache::stats();
+    let lookups = stats.hits + stats.misses;
+    let hit_rate = (stats.hits * 100).checked_div(lookups).unwrap_or(0);
+    let buffers_str = format!("buffers:    {} ({} dirty)\n", stats.buffers, stats.dirty);
+    terminal::write(&buffers_str);
+    let size_str = format!(
+        "size:       {} / {} KiB\n",
+        stats.bytes / 1024,
+        stats.capacity / 1024
+    );
+    terminal::write(&size_str);
+    let hits_str = format!("hits:       {} ({}%)\n", stats.hits, hit_rate);
+    terminal::write(&hits_str);
+    let misses_str = format!("misses:     {}\n", stats.misses);
+    terminal::write(&misses_str);
+    let evictions_str = format!("evictions:  {}\n", stats.evictions);
+    terminal::write(&evictions_str);
+    let writebacks_str = format!("writebacks: {}\n", stats.writebacks);
+    terminal::write(&writebacks_str);
+}
+
+fn cmd_lspci(args: &[&str]) {
+    let verbose = args.first() == Some(&"-v");
+    for dev in pci::devices() {
+        let mut line = format!(
+            "{} {} [{:02x}{:02x}]: {:04x}:{:04x} (rev {:02x})",
+            dev.address,
+            dev.class_name(),
+            dev.class,
+            dev.subclass,
+            dev.vendor_id,
+            dev.device_id,
+            dev.revision
+        );
+        if let Some(driver) = pci::driver_of(dev.address) {
+            line.push_str(&format!(" [{}]", driver));
+        }
+        line.push('\n');
+        terminal::write(&line);
+        if !verbose {
+            continue;
+        }
+
+        if dev.interrupt_pin != 0 {
+            let pin = (b'A' + dev.interrupt_pin - 1) as char;
+            let line = format!("    IRQ {} (INT{})\n", dev.interrupt_line, pin);
+            terminal::write(&line);
+        }
+        for (index, bar) in dev.bars() {
+            let line = format!("    BAR{}: {}\n", index, bar);
+            terminal::write(&line);
+        }
+        for (id, offset) in dev.capabilities() {
+            let line = format!(
+                "    [{:02x}] capability {:02x} {}\n",
+                offset,
+                id,
+                msi::capability_name(id)
+            );
+            terminal::write(&line);
+        }
+    }
+}
+
+fn cmd_ifconfig(args: &[&str]) {
+    if args.is_empty() {
+        for iface in net::interfaces() {
+            let mut line = format!("{}: mtu {}", iface.name, iface.mtu);
+            if iface.loopback {
+                line.push_str(" loopback");
+            }
+            line.push('\n');
+            if iface.is_up() {
+                line.push_str(&format!("    inet {}/{}", iface.addr, iface.prefix));
+                if let Some(gateway) = iface.gateway {
+                    line.push_str(&format!(" gw {}", gateway));
+                }
+                line.push('\n');
+            }
+            if !iface.loopback {
+                line.push_str(&format!("    ether {}\n", iface.mac));
+            }
+            let stats = iface.stats;
+            line.push_str(&format!(
+                "    rx {} packets {} bytes, tx {} packets {} bytes, {} dropped\n",
+                stats.rx_packets, stats.rx_bytes, stats.tx_packets, stats.tx_bytes, stats.dropped
+            ));
+            terminal::write(&line);
+        }
+        return;
+    }
+    if args.len() < 2 {
+        terminal::write("usage: ifconfig [<if> <addr>/<prefix> [gw]]\n");
+        return;
+    }
+
+    let (addr, prefix) = args[1].split_once('/').unwrap_or((args[1], "24"));
+    let gateway = args.get(2).map(|gw| gw.parse::<Ipv4Addr>());
+    let (addr, prefix, gateway) = match (addr.parse(), prefix.parse(), gateway) {
+        (Ok(addr), Ok(prefix), None) => (addr, prefix, None),
+        (Ok(addr), Ok(prefix), Some(Ok(gw))) => (addr, prefix, Some(gw)),
+        _ => {
+            terminal::write("ifconfig: invalid address\n");
+            return;
+        }
+    };
+    if let Err(e) = net::configure(args[0], addr, prefix, gateway) {
+        let msg = format!("ifconfig: {}: {}\n", args[0], e);
+        terminal::write(&msg);
+    }
+}
+
+async fn cmd_ping(args: &[&str]) {
+    let dst = match args.first().map(|a| a.parse::<Ipv4Addr>()) {
+        Some(Ok(dst)) => dst,
+        Some(Err(_)) => {
+            terminal::write("ping: invalid address\n");
+            return;
+        }
+        None => {
+            terminal::write("usage: ping <addr> [count]\n");
+            return;
+        }
+    };
+    let count = args.get(1).and_then(|c| c.parse::<u16>().ok()).unwrap_or(4);
+    // tells this ping's replies apart from any other's
+    let id = timer::ticks() as u16;
+    let payload = [0x5A; 56];
+
+    let mut received = 0;
+    for seq in 0..count {
+        let sent = timer::uptime_ms();
+        if let Err(e) = net::ping(dst, id, seq, &payload) {
+            let msg = format!("ping: {}\n", e);
+            terminal::write(&msg);
+            return;
+        }
+        // one second for the reply, then the rest of that second before the next request
+        loop {
+            net::poll();
+            if let Some((from, at)) = net::take_echo_reply(id, seq) {
+                received += 1;
+                let line = format!("reply from {}: seq={} time={} ms\n", from, seq, at - sent);
+                terminal::write(&line);
+                break;
+            }
+            if timer::uptime_ms() - sent >= 1000 {
+                let line = format!("seq={}: timed out\n", seq);
+                terminal::write(&line);
+                break;
+            }
+            // let the other tasks run while the reply is on its way
+            task::sleep_ms(1000 / timer::TIMER_HZ).await;
+        }
+        let elapsed = timer::uptime_ms() - sent;
+        if seq + 1 < count && elapsed < 1000 {
+            task::sleep_ms(1000 - elapsed).await;
+        }
+    }
+    let line = format!("{} sent, {} received\n", count, received);
+    terminal::write(&line);
+}
+
+fn cmd_netstat() {
+    terminal::write("PROTO  LOCAL                  REMOTE                 STATE\n");
+    for conn in net::tcp_connections() {
+        let line = format!(
+            "tcp    {:<21}  {:<21}  {}\n",
+            conn.local.to_string(),
+            conn.remote.to_string(),
+            conn.state
+        );
+        terminal::write(&line);
+    }
+    for (local, remote) in net::udp_sockets() {
+        let remote = remote
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| String::from("*:*"));
+        let line = format!("udp    {:<21}  {}\n", local.to_string(), remote);
+        terminal::write(&line);
+    }
+}
+
+fn cmd_tasks() {
+    let tasks = task::tasks();
+    let total: u64 = tasks.iter().map(|t| t.poll_cycles).sum();
+    terminal::write("ID    PRIO       POLLS      WAKES     TIME(us)  CPU%  NAME\n");
+    for t in tasks {
+        let share = (t.poll_cycles as u128 * 100)
+            .checked_div(total as u128)
+            .unwrap_or(0);
+        let line = format!(
+            "{:<4}  {:<6}  {:>8}  {:>9}  {:>11}  {:>4}  {}\n",
+            t.id,
+            t.priority.to_string(),
+            t.polls,
+            t.wakes,
+            timer::cycles_to_us(t.poll_cycles),
+            share,
+            t.name
+        );
+        terminal::write(&line);
+    }
+}
+
+fn cmd_kill(args: &[&str]) {
+    let id = match args.first().map(|a| a.parse::<task::TaskId>()) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => {
+            terminal::write("kill: invalid task id\n");
+            return;
+        }
+        None => {
+            terminal::write("usage: kill <id>\n");
+            return;
+        }
+    };
+    // the console and the kernel's own services can't be brought back once cancelled
+    let system = task::tasks()
+        .iter()
+        .any(|t| t.id == id && SYSTEM_TASKS.contains(&t.name.as_str()));
+    if system {
+        let msg = format!("kill: task {} is a system task\n", id);
+        terminal::write(&msg);
+    } else if !task::cancel(id) {
+        let msg = format!("kill: no task {}\n", id);
+        terminal::write(&msg);
+    }
+}
+
+fn cmd_whoami() {
+    let uid = user::current().uid;
+    let name = user::find_by_uid(uid)
+        .map(|u| u.name)
+        .unwrap_or_else(|| format!("{}", uid));
+    terminal::write(&name);
+    terminal::write("\n");
+}
+
+fn cmd_ln(args: &[&str]) {
+    let symbolic = args.first() == Some(&"-s");
+    let args = if symbolic { &args[1..] } else { args };
+    if args.len() < 2 {
+        terminal::write("ln: missing operands\n");
+        terminal::write("usage: ln [-s] <target> <link>\n");
+        return;
+    }
+
+    if let Some(fs) = fs::root() {
+        let link = destination_path(&*fs, args[0], &path(args[1]));
+        // a symlink's target is stored as typed; a hard link needs the real file
+        let result = if symbolic {
+            fs.symlink(args[0], &link)
+        } else {
+            fs.link(&path(args[0]), &link)
+        };
+        if let Err(e) = result {
+            let msg = format!("ln: {}\n", e);
+            terminal::write(&msg);
+        }
+    } else {
+        terminal::write("filesystem not initialized\n");
+    }
+}
+
+fn cmd_readlink(args: &[&str]) {
+    if args.is_empty() {
+        terminal::write("readlink: missing operand\n");
+        return;
+    }
+
+    if let Some(fs) = fs::root() {
+        match fs.readlink(&path(args[0])) {
+            Ok(target) => {
+                terminal::write(&target);
+                terminal::write("\n");
+            }
+            Err(e) => {
+                let msg = format!("readlink: {}\n", e);
+                terminal::write(&msg);
+            }
+        }
+    } else {
+        terminal::write("filesystem not initialized\n");
+    }
+}
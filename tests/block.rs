@@ -0,0 +1,180 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use zero::drivers::block::partition::{self, Partition, PartitionInfo};
+use zero::drivers::block::{self, BlockDevice, BlockError, MemDisk};
+use zero::kernel::fs::{Fat32, FileSystem};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+// writes a 16-byte MBR/EBR entry into `sector`
+fn mbr_entry(sector: &mut [u8], index: usize, kind: u8, start: u32, count: u32) {
+    let entry = &mut sector[446 + index * 16..][..16];
+    entry[4] = kind;
+    entry[8..12].copy_from_slice(&start.to_le_bytes());
+    entry[12..16].copy_from_slice(&count.to_le_bytes());
+    sector[510] = 0x55;
+    sector[511] = 0xAA;
+}
+
+fn part(number: usize, start: u64, count: u64) -> PartitionInfo {
+    PartitionInfo {
+        number,
+        start,
+        count,
+    }
+}
+
+#[test_case]
+fn byte_io_spans_blocks() {
+    let disk = MemDisk::new(512, 4);
+    let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    block::write_bytes(&disk, 300, &data).unwrap();
+
+    let mut back = vec![0u8; 1000];
+    block::read_bytes(&disk, 300, &mut back).unwrap();
+    assert_eq!(back, data);
+
+    let mut sector = [0u8; 512];
+    assert_eq!(
+        disk.read_blocks(4, &mut sector),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        disk.read_blocks(0, &mut sector[..100]),
+        Err(BlockError::BadBuffer)
+    );
+}
+
+#[test_case]
+fn mbr_with_logical_partitions() {
+    let disk = MemDisk::new(512, 4096);
+    let mut mbr = [0u8; 512];
+    mbr_entry(&mut mbr, 0, 0x83, 2048, 1024);
+    mbr_entry(&mut mbr, 1, 0x05, 3072, 1024);
+    disk.write_blocks(0, &mbr).unwrap();
+
+    // each EBR describes one logical partition and links to the next
+    let mut ebr = [0u8; 512];
+    mbr_entry(&mut ebr, 0, 0x0B, 16, 100);
+    mbr_entry(&mut ebr, 1, 0x05, 200, 300);
+    disk.write_blocks(3072, &ebr).unwrap();
+    let mut ebr = [0u8; 512];
+    mbr_entry(&mut ebr, 0, 0x83, 8, 200);
+    disk.write_blocks(3272, &ebr).unwrap();
+
+    assert_eq!(
+        partition::scan(&disk).unwrap(),
+        [part(1, 2048, 1024), part(5, 3088, 100), part(6, 3280, 200)]
+    );
+}
+
+#[test_case]
+fn gpt_partitions() {
+    let disk = MemDisk::new(512, 4096);
+    let mut mbr = [0u8; 512];
+    mbr_entry(&mut mbr, 0, 0xEE, 1, 4095);
+    disk.write_blocks(0, &mbr).unwrap();
+
+    let mut header = [0u8; 512];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    disk.write_blocks(1, &header).unwrap();
+
+    // entries 1 and 3 are used, entry 2 is empty
+    let mut entries = [0u8; 512];
+    for (slot, first, last) in [(0usize, 34u64, 1033u64), (2, 2048, 4000)] {
+        let entry = &mut entries[slot * 128..][..128];
+        entry[0] = 0xAF; // any non-zero type GUID
+        entry[32..40].copy_from_slice(&first.to_le_bytes());
+        entry[40..48].copy_from_slice(&last.to_le_bytes());
+    }
+    disk.write_blocks(2, &entries).unwrap();
+
+    assert_eq!(
+        partition::scan(&disk).unwrap(),
+        [part(1, 34, 1000), part(3, 2048, 1953)]
+    );
+}
+
+#[test_case]
+fn gpt_entries_past_the_disk() {
+    let disk = MemDisk::new(512, 4096);
+    let mut mbr = [0u8; 512];
+    mbr_entry(&mut mbr, 0, 0xEE, 1, 4095);
+    disk.write_blocks(0, &mbr).unwrap();
+
+    // an entry array starting this close to u64::MAX would wrap around
+    let mut header = [0u8; 512];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[72..80].copy_from_slice(&(u64::MAX - 1).to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    disk.write_blocks(1, &header).unwrap();
+
+    assert!(partition::scan(&disk).unwrap().is_empty());
+}
+
+#[test_case]
+fn unpartitioned_fat_disk() {
+    // a FAT boot sector also ends in 55 AA but is no partition table
+    let disk = MemDisk::new(512, 1024);
+    Fat32::format(&disk, "whole").unwrap();
+    assert!(partition::scan(&disk).unwrap().is_empty());
+}
+
+#[test_case]
+fn filesystem_on_a_partition() {
+    let disk: Arc<MemDisk> = Arc::new(MemDisk::new(512, 2048));
+    let mut mbr = [0u8; 512];
+    mbr_entry(&mut mbr, 0, 0x0C, 1024, 1024);
+    disk.write_blocks(0, &mbr).unwrap();
+
+    let info = partition::scan(&*disk).unwrap()[0];
+    let part = Arc::new(Partition::new(disk.clone(), info.start, info.count));
+    let mut sector = [0u8; 512];
+    assert_eq!(
+        part.read_blocks(1024, &mut sector),
+        Err(BlockError::OutOfRange)
+    );
+
+    Fat32::format(&*part, "part").unwrap();
+    let fs = Fat32::new(part).unwrap();
+    fs.create_file("/inside").unwrap();
+    // the table in front of the partition is untouched
+    disk.read_blocks(0, &mut sector).unwrap();
+    assert_eq!(sector, mbr);
+}
@@ -0,0 +1,181 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use zero::drivers::block::cache::BufferCache;
+use zero::drivers::block::{BlockDevice, BlockError, BlockResult, MemDisk};
+use zero::kernel::fs::{Fat32, FileSystem};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+fn block(fill: u8) -> [u8; 512] {
+    [fill; 512]
+}
+
+#[test_case]
+fn reads_hit_after_the_first_miss() {
+    static CACHE: BufferCache = BufferCache::new(16 * 512);
+    let disk = Arc::new(MemDisk::new(512, 64));
+    disk.write_blocks(3, &block(3)).unwrap();
+    let cached = CACHE.wrap(disk.clone());
+
+    let mut buf = [0u8; 4 * 512];
+    cached.read_blocks(2, &mut buf).unwrap();
+    assert_eq!(buf[512..1024], block(3));
+    cached.read_blocks(3, &mut buf[..512]).unwrap();
+    let stats = CACHE.stats();
+    assert_eq!((stats.hits, stats.misses), (1, 4));
+    assert_eq!(stats.buffers, 4);
+}
+
+#[test_case]
+fn writes_reach_the_disk_on_sync() {
+    static CACHE: BufferCache = BufferCache::new(16 * 512);
+    let disk = Arc::new(MemDisk::new(512, 64));
+    let cached = CACHE.wrap(disk.clone());
+
+    cached.write_blocks(10, &[7u8; 2 * 512]).unwrap();
+    let mut raw = block(0);
+    disk.read_blocks(10, &mut raw).unwrap();
+    assert_eq!(raw, block(0));
+    cached.read_blocks(11, &mut raw).unwrap();
+    assert_eq!(raw, block(7));
+    assert_eq!(CACHE.stats().dirty, 2);
+
+    CACHE.sync().unwrap();
+    disk.read_blocks(11, &mut raw).unwrap();
+    assert_eq!(raw, block(7));
+    let stats = CACHE.stats();
+    assert_eq!((stats.dirty, stats.writebacks), (0, 2));
+}
+
+#[test_case]
+fn eviction_drops_the_oldest_and_writes_it_back() {
+    static CACHE: BufferCache = BufferCache::new(4 * 512);
+    let disk = Arc::new(MemDisk::new(512, 64));
+    let cached = CACHE.wrap(disk.clone());
+
+    cached.write_blocks(0, &block(1)).unwrap();
+    let mut buf = block(0);
+    for lba in 1..4 {
+        cached.read_blocks(lba, &mut buf).unwrap();
+    }
+    // touching block 0 makes block 1 the oldest, so it goes first, then block 2
+    cached.read_blocks(0, &mut buf).unwrap();
+    cached.read_blocks(4, &mut buf).unwrap();
+    cached.read_blocks(1, &mut buf).unwrap();
+    assert_eq!(CACHE.stats().misses, 5);
+
+    // then 3, the dirty block 0 and 4
+    cached.read_blocks(5, &mut buf).unwrap();
+    cached.read_blocks(6, &mut buf).unwrap();
+    cached.read_blocks(7, &mut buf).unwrap();
+    disk.read_blocks(0, &mut buf).unwrap();
+    assert_eq!(buf, block(1));
+    let stats = CACHE.stats();
+    assert_eq!((stats.evictions, stats.writebacks), (5, 1));
+    assert_eq!(stats.bytes, 4 * 512);
+}
+
+// a disk whose writes fail while `broken` is set
+struct FlakyDisk {
+    disk: MemDisk,
+    broken: AtomicBool,
+}
+
+impl BlockDevice for FlakyDisk {
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.disk.block_count()
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        self.disk.read_blocks(lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        if self.broken.load(Ordering::Relaxed) {
+            return Err(BlockError::Io);
+        }
+        self.disk.write_blocks(lba, buf)
+    }
+}
+
+#[test_case]
+fn failed_write_back_keeps_the_block_dirty() {
+    static CACHE: BufferCache = BufferCache::new(2 * 512);
+    let disk = Arc::new(FlakyDisk {
+        disk: MemDisk::new(512, 64),
+        broken: AtomicBool::new(true),
+    });
+    let cached = CACHE.wrap(disk.clone());
+
+    // evicting the dirty block 0 fails, so it stays cached
+    cached.write_blocks(0, &block(9)).unwrap();
+    let mut buf = block(0);
+    cached.read_blocks(1, &mut buf).unwrap();
+    cached.read_blocks(2, &mut buf).unwrap();
+    let stats = CACHE.stats();
+    assert_eq!((stats.dirty, stats.writebacks), (1, 0));
+    cached.read_blocks(0, &mut buf).unwrap();
+    assert_eq!(buf, block(9));
+
+    assert_eq!(CACHE.sync(), Err(BlockError::Io));
+    assert_eq!(cached.flush(), Err(BlockError::Io));
+
+    disk.broken.store(false, Ordering::Relaxed);
+    CACHE.sync().unwrap();
+    disk.read_blocks(0, &mut buf).unwrap();
+    assert_eq!(buf, block(9));
+    assert_eq!(CACHE.stats().dirty, 0);
+}
+
+#[test_case]
+fn filesystem_through_the_cache() {
+    static CACHE: BufferCache = BufferCache::new(64 * 1024);
+    let disk = Arc::new(MemDisk::new(512, 2048));
+    let cached = CACHE.wrap(disk.clone());
+
+    Fat32::format(&*cached, "cached").unwrap();
+    let fs = Fat32::new(cached.clone()).unwrap();
+    fs.create_file("/file").unwrap();
+    fs.write_file("/file", b"kept").unwrap();
+    CACHE.sync().unwrap();
+
+    // a fresh mount of the raw disk sees everything
+    let fs = Fat32::new(disk).unwrap();
+    assert_eq!(&fs.read_file("/file").unwrap()[..], b"kept");
+    assert!(CACHE.stats().hits > 0);
+}
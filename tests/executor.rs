@@ -0,0 +1,194 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts::without_interrupts;
+use zero::kernel::task::{self, executor::Executor, Priority, Task};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+#[test_case]
+fn tasks_spawn_tasks_and_join_them() {
+    static RESULT: AtomicU64 = AtomicU64::new(0);
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.spawn(Task::new(async move {
+        let first = spawner.spawn(async { 40u64 });
+        let second = spawner.spawn(async {
+            task::yield_now().await;
+            2u64
+        });
+        RESULT.store(
+            first.await.unwrap() + second.await.unwrap(),
+            Ordering::SeqCst,
+        );
+    }));
+    executor.run_until_idle();
+    assert_eq!(RESULT.load(Ordering::SeqCst), 42);
+}
+
+#[test_case]
+fn spawner_works_with_interrupts_off() {
+    static RAN: AtomicU64 = AtomicU64::new(0);
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let handle = without_interrupts(|| {
+        spawner.spawn(async {
+            RAN.fetch_add(1, Ordering::SeqCst);
+        })
+    });
+    assert!(!handle.is_finished());
+    executor.run_until_idle();
+    assert!(handle.is_finished());
+    assert_eq!(RAN.load(Ordering::SeqCst), 1);
+}
+
+#[test_case]
+fn more_ready_tasks_than_the_queue_holds() {
+    static DONE: AtomicU64 = AtomicU64::new(0);
+    let mut executor = Executor::new();
+    for _ in 0..1000 {
+        executor.spawn(Task::new(async {
+            task::yield_now().await;
+            task::yield_now().await;
+            DONE.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    executor.run_until_idle();
+    assert_eq!(DONE.load(Ordering::SeqCst), 1000);
+}
+
+#[test_case]
+fn higher_priority_tasks_are_polled_first() {
+    // each task appends its digit
+    static ORDER: AtomicU64 = AtomicU64::new(0);
+    let mut executor = Executor::new();
+    for (digit, priority) in [
+        (1, Priority::Low),
+        (2, Priority::Normal),
+        (3, Priority::High),
+    ] {
+        executor.spawn(
+            Task::new(async move {
+                ORDER
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |o| Some(o * 10 + digit))
+                    .unwrap();
+            })
+            .with_priority(priority),
+        );
+    }
+    executor.run_until_idle();
+    assert_eq!(ORDER.load(Ordering::SeqCst), 321);
+}
+
+#[test_case]
+fn executor_counts_polls_and_wakes() {
+    let mut executor = Executor::new();
+    let (tx, mut rx) = task::mpsc::channel::<()>(1);
+    executor.spawn(Task::named("yielder", async move {
+        task::yield_now().await;
+        task::yield_now().await;
+        rx.recv().await;
+    }));
+    executor.run_until_idle();
+
+    let tasks = executor.tasks();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].name, "yielder");
+    assert_eq!(tasks[0].priority, Priority::Normal);
+    assert_eq!(tasks[0].polls, 3);
+    assert_eq!(tasks[0].wakes, 2);
+
+    tx.try_send(()).unwrap();
+    executor.run_until_idle();
+    // finished tasks aren't listed
+    assert!(executor.tasks().is_empty());
+}
+
+// counts how many futures holding one were dropped
+struct DropCounter(&'static AtomicU64);
+
+impl Drop for DropCounter {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn cancel_drops_the_future() {
+    static DROPPED: AtomicU64 = AtomicU64::new(0);
+    let mut executor = Executor::new();
+    let task = Task::new(async {
+        let _counter = DropCounter(&DROPPED);
+        core::future::pending::<()>().await;
+    });
+    let id = task.id();
+    executor.spawn(task);
+    executor.run_until_idle();
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+
+    assert!(executor.cancel(id));
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+    assert!(executor.tasks().is_empty());
+    assert!(!executor.cancel(id));
+}
+
+#[test_case]
+fn dropping_a_join_handle_cancels_its_task() {
+    static DROPPED: AtomicU64 = AtomicU64::new(0);
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    // the counter goes with the future even if it's never polled
+    let pending = || {
+        let counter = DropCounter(&DROPPED);
+        async move {
+            let _counter = counter;
+            core::future::pending::<()>().await;
+        }
+    };
+
+    drop(spawner.spawn(pending()));
+    spawner.spawn(pending()).detach();
+    let aborted = spawner.spawn(pending());
+    executor.run_until_idle();
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+    assert_eq!(executor.tasks().len(), 2);
+
+    aborted.abort();
+    executor.run_until_idle();
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 2);
+    assert!(aborted.is_finished());
+    executor.spawn(Task::new(async move {
+        assert_eq!(aborted.await, Err(task::Cancelled));
+    }));
+    executor.run_until_idle();
+    assert_eq!(executor.tasks().len(), 1);
+}
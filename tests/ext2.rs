@@ -0,0 +1,201 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use zero::drivers::block::MemDisk;
+use zero::kernel::fs::{Ext2, FileSystem, FileType, FsError};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+// made by tests/images/mkimages.sh
+static IMAGE: &[u8] = include_bytes!("images/ext2.img");
+
+fn mounted() -> (Arc<MemDisk>, Ext2) {
+    let disk = Arc::new(MemDisk::from_image(512, IMAGE));
+    let fs = Ext2::new(disk.clone()).unwrap();
+    (disk, fs)
+}
+
+fn big_pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 256) as u8).collect()
+}
+
+#[test_case]
+fn ext2_reads_mke2fs_image() {
+    let (_disk, fs) = mounted();
+    assert_eq!(fs.read_file("/hello.txt").unwrap(), b"hello ext2\n");
+    // past the twelve direct blocks
+    assert_eq!(fs.read_file("/docs/big.bin").unwrap(), big_pattern(20000));
+
+    let mut names: Vec<_> = fs
+        .list_dir("/")
+        .unwrap()
+        .into_iter()
+        .map(|i| i.name)
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        ["docs", "hello.txt", "link", "longlink", "lost+found"]
+    );
+
+    let root = fs.stat("/").unwrap();
+    assert_eq!(root.ino, 2);
+    assert_eq!(root.nlink, 4);
+}
+
+#[test_case]
+fn ext2_symlinks() {
+    let (_disk, fs) = mounted();
+    assert_eq!(fs.lstat("/link").unwrap().file_type, FileType::Symlink);
+    assert_eq!(fs.readlink("/link").unwrap(), "hello.txt");
+    // too long to fit in the inode, so stored in a data block
+    assert!(fs.readlink("/longlink").unwrap().ends_with("d/d/target"));
+
+    let long = format!("{}end", "x/".repeat(50));
+    fs.symlink("short", "/s1").unwrap();
+    fs.symlink(&long, "/s2").unwrap();
+    assert_eq!(fs.readlink("/s1").unwrap(), "short");
+    assert_eq!(fs.readlink("/s2").unwrap(), long);
+    assert!(matches!(
+        fs.readlink("/hello.txt"),
+        Err(FsError::InvalidArgument)
+    ));
+}
+
+#[test_case]
+fn ext2_changes_survive_remount() {
+    let (disk, fs) = mounted();
+    fs.create_dir("/new").unwrap();
+    fs.create_file("/new/file").unwrap();
+    fs.write_file("/new/file", b"persistent").unwrap();
+    fs.chmod("/new/file", 0o600).unwrap();
+    fs.chown("/new/file", 1000, 100).unwrap();
+    let ino = fs.stat("/new/file").unwrap().ino;
+
+    let fs = Ext2::new(disk).unwrap();
+    let info = fs.stat("/new/file").unwrap();
+    assert_eq!(fs.read_file("/new/file").unwrap(), b"persistent");
+    assert_eq!(
+        (info.ino, info.mode, info.uid, info.gid),
+        (ino, 0o600, 1000, 100)
+    );
+    assert_eq!(fs.stat("/new").unwrap().nlink, 2);
+    assert_eq!(fs.stat("/").unwrap().nlink, 5);
+}
+
+#[test_case]
+fn ext2_indirect_blocks_are_freed() {
+    let (_disk, fs) = mounted();
+    let data = big_pattern(100 * 1024);
+
+    // the image has ~200 free blocks: leaking would run out
+    for _ in 0..10 {
+        fs.create_file("/tmp").unwrap();
+        fs.write_file("/tmp", &data).unwrap();
+        assert_eq!(fs.read_file("/tmp").unwrap(), data);
+        fs.remove("/tmp").unwrap();
+    }
+
+    fs.create_file("/sparse").unwrap();
+    fs.write_at("/sparse", 50 * 1024, b"tail").unwrap();
+    let mut buf = [1u8; 16];
+    fs.read_at("/sparse", 1000, &mut buf).unwrap();
+    assert_eq!(buf, [0; 16]);
+    fs.truncate("/sparse", 3).unwrap();
+    fs.truncate("/sparse", 50 * 1024).unwrap();
+    assert!(fs.read_file("/sparse").unwrap().iter().all(|&b| b == 0));
+}
+
+#[test_case]
+fn ext2_directories_and_links() {
+    let (_disk, fs) = mounted();
+    fs.create_dir("/d").unwrap();
+    // enough entries to spill into more directory blocks
+    for i in 0..20 {
+        fs.create_file(&format!("/d/a fairly long file name {}", i))
+            .unwrap();
+    }
+    assert_eq!(fs.list_dir("/d").unwrap().len(), 20);
+    for i in (0..20).step_by(2) {
+        fs.remove(&format!("/d/a fairly long file name {}", i))
+            .unwrap();
+    }
+    assert_eq!(fs.list_dir("/d").unwrap().len(), 10);
+    assert!(matches!(fs.remove("/d"), Err(FsError::PermissionDenied)));
+
+    fs.link("/hello.txt", "/d/hard").unwrap();
+    assert_eq!(fs.stat("/hello.txt").unwrap().nlink, 2);
+    fs.remove("/hello.txt").unwrap();
+    assert_eq!(fs.read_file("/d/hard").unwrap(), b"hello ext2\n");
+    assert!(matches!(
+        fs.link("/d", "/d2"),
+        Err(FsError::PermissionDenied)
+    ));
+}
+
+#[test_case]
+fn ext2_rename() {
+    let (_disk, fs) = mounted();
+    fs.create_dir("/a").unwrap();
+    fs.create_dir("/a/sub").unwrap();
+    fs.rename("/a/sub", "/docs/sub").unwrap();
+    assert_eq!(fs.stat("/a").unwrap().nlink, 2);
+    assert_eq!(fs.stat("/docs").unwrap().nlink, 3);
+
+    fs.create_file("/docs/sub/f").unwrap();
+    fs.rename("/docs/sub/f", "/hello.txt").unwrap();
+    assert_eq!(fs.read_file("/hello.txt").unwrap(), b"");
+    assert!(matches!(
+        fs.rename("/docs", "/docs/sub/x"),
+        Err(FsError::InvalidPath)
+    ));
+    assert!(matches!(
+        fs.rename("/a", "/hello.txt"),
+        Err(FsError::NotADirectory)
+    ));
+}
+
+#[test_case]
+fn ext2_rejects_other_filesystems() {
+    let disk = Arc::new(MemDisk::new(512, 512));
+    assert!(matches!(Ext2::new(disk), Err(FsError::InvalidData)));
+
+    // an unknown incompatible feature, like ext4 extents
+    let mut image = vec![0u8; IMAGE.len()];
+    image.copy_from_slice(IMAGE);
+    image[1024 + 96] |= 0x40;
+    let disk = Arc::new(MemDisk::from_image(512, &image));
+    assert!(matches!(Ext2::new(disk), Err(FsError::NotSupported)));
+}
@@ -0,0 +1,256 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use zero::drivers::block::MemDisk;
+use zero::kernel::fs::{Fat32, FileSystem, FileType, FsError};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+// 512 KiB with 512-byte clusters
+fn formatted() -> (Arc<MemDisk>, Fat32) {
+    let disk = Arc::new(MemDisk::new(512, 1024));
+    Fat32::format(&*disk, "test").unwrap();
+    let fs = Fat32::new(disk.clone()).unwrap();
+    (disk, fs)
+}
+
+// made by tests/images/mkimages.sh: 2 MiB with 1 KiB clusters
+static IMAGE: &[u8] = include_bytes!("images/fat32.img");
+
+fn mkfs_image() -> (Arc<MemDisk>, Fat32) {
+    let disk = Arc::new(MemDisk::from_image(512, IMAGE));
+    let fs = Fat32::new(disk.clone()).unwrap();
+    (disk, fs)
+}
+
+fn big_pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 256) as u8).collect()
+}
+
+#[test_case]
+fn fat32_rejects_unformatted_disk() {
+    let disk = Arc::new(MemDisk::new(512, 1024));
+    assert!(matches!(Fat32::new(disk), Err(FsError::InvalidData)));
+}
+
+#[test_case]
+fn fat32_files_survive_remount() {
+    let (disk, fs) = formatted();
+    fs.create_dir("/docs").unwrap();
+    fs.create_file("/docs/A Long File Name.txt").unwrap();
+    fs.write_file("/docs/A Long File Name.txt", b"hello fat")
+        .unwrap();
+    fs.create_file("/README.TXT").unwrap();
+
+    let fs = Fat32::new(disk).unwrap();
+    assert_eq!(
+        fs.read_file("/docs/A Long File Name.txt").unwrap(),
+        b"hello fat"
+    );
+    // lookups ignore case, and long names keep theirs
+    assert!(fs.exists("/DOCS/a long file name.TXT"));
+    let names: Vec<_> = fs
+        .list_dir("/docs")
+        .unwrap()
+        .into_iter()
+        .map(|i| i.name)
+        .collect();
+    assert_eq!(names, ["A Long File Name.txt"]);
+    assert_eq!(fs.stat("/README.TXT").unwrap().file_type, FileType::File);
+}
+
+#[test_case]
+fn fat32_short_aliases_are_unique() {
+    let (_disk, fs) = formatted();
+    for i in 0..5 {
+        fs.create_file(&format!("/long file {}.text", i)).unwrap();
+    }
+    assert_eq!(fs.list_dir("/").unwrap().len(), 5);
+    assert!(matches!(
+        fs.create_file("/LONG FILE 3.TEXT"),
+        Err(FsError::AlreadyExists)
+    ));
+}
+
+#[test_case]
+fn fat32_files_span_clusters() {
+    let (_disk, fs) = formatted();
+    let data: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+    fs.create_file("/big").unwrap();
+    fs.write_file("/big", &data).unwrap();
+    assert_eq!(fs.read_file("/big").unwrap(), data);
+
+    let mut buf = [0u8; 100];
+    assert_eq!(fs.read_at("/big", 1000, &mut buf).unwrap(), 100);
+    assert_eq!(&buf[..], &data[1000..1100]);
+
+    // a write past the end zero-fills the gap
+    fs.write_at("/big", 6000, b"end").unwrap();
+    assert_eq!(fs.stat("/big").unwrap().size, 6003);
+    fs.read_at("/big", 5500, &mut buf).unwrap();
+    assert!(buf.iter().all(|&b| b == 0));
+
+    fs.truncate("/big", 10).unwrap();
+    assert_eq!(fs.read_file("/big").unwrap(), &data[..10]);
+}
+
+#[test_case]
+fn fat32_frees_clusters() {
+    let (disk, fs) = formatted();
+    let data = vec![7u8; 20 * 512];
+
+    // filling and deleting a file repeatedly must not leak space
+    for _ in 0..100 {
+        fs.create_file("/tmp").unwrap();
+        fs.write_file("/tmp", &data).unwrap();
+        fs.remove("/tmp").unwrap();
+    }
+    fs.create_file("/last").unwrap();
+    fs.write_file("/last", &data).unwrap();
+    assert_eq!(Fat32::new(disk).unwrap().read_file("/last").unwrap(), data);
+}
+
+#[test_case]
+fn fat32_directories() {
+    let (_disk, fs) = formatted();
+    fs.create_dir("/a").unwrap();
+    fs.create_dir("/a/b").unwrap();
+    fs.create_file("/a/b/f").unwrap();
+
+    // directories grow past one cluster
+    for i in 0..40 {
+        fs.create_file(&format!("/a/entry number {}", i)).unwrap();
+    }
+    assert_eq!(fs.list_dir("/a").unwrap().len(), 41);
+
+    assert!(matches!(fs.remove("/a/b"), Err(FsError::PermissionDenied)));
+    fs.remove("/a/b/f").unwrap();
+    fs.remove("/a/b").unwrap();
+    assert!(!fs.exists("/a/b"));
+}
+
+#[test_case]
+fn fat32_rename() {
+    let (_disk, fs) = formatted();
+    fs.create_dir("/src").unwrap();
+    fs.create_dir("/dst").unwrap();
+    fs.create_dir("/src/sub").unwrap();
+    fs.create_file("/src/sub/file").unwrap();
+    fs.write_file("/src/sub/file", b"moved").unwrap();
+
+    fs.rename("/src/sub", "/dst/renamed dir").unwrap();
+    assert_eq!(fs.read_file("/dst/renamed dir/file").unwrap(), b"moved");
+    assert!(!fs.exists("/src/sub"));
+
+    fs.create_dir("/dst/renamed dir/inner").unwrap();
+    fs.rename("/dst/renamed dir/inner", "/dst/inner").unwrap();
+    assert!(fs.exists("/dst/inner"));
+
+    fs.create_file("/src/other").unwrap();
+    fs.rename("/src/other", "/dst/renamed dir/file").unwrap();
+    assert_eq!(fs.read_file("/dst/renamed dir/file").unwrap(), b"");
+
+    assert!(matches!(
+        fs.rename("/dst", "/dst/inner/x"),
+        Err(FsError::InvalidPath)
+    ));
+}
+
+#[test_case]
+fn fat32_read_only_attribute() {
+    let (_disk, fs) = formatted();
+    fs.create_file("/f").unwrap();
+    assert_eq!(fs.stat("/f").unwrap().mode, 0o644);
+    fs.chmod("/f", 0o444).unwrap();
+    assert_eq!(fs.stat("/f").unwrap().mode, 0o444);
+    assert!(matches!(
+        fs.chown("/f", 1000, 1000),
+        Err(FsError::NotSupported)
+    ));
+}
+
+#[test_case]
+fn fat32_reads_mkfs_image() {
+    let (_disk, fs) = mkfs_image();
+    assert_eq!(fs.read_file("/hello.txt").unwrap(), b"hello fat\n");
+    assert_eq!(fs.read_file("/docs/big.bin").unwrap(), big_pattern(20000));
+    assert_eq!(
+        fs.read_file("/docs/A Long File Name.txt").unwrap(),
+        b"a long name\n"
+    );
+
+    // the volume label entry isn't listed
+    let mut names: Vec<_> = fs
+        .list_dir("/")
+        .unwrap()
+        .into_iter()
+        .map(|i| i.name)
+        .collect();
+    names.sort();
+    assert_eq!(names, ["docs", "hello.txt"]);
+    let mut names: Vec<_> = fs
+        .list_dir("/docs")
+        .unwrap()
+        .into_iter()
+        .map(|i| i.name)
+        .collect();
+    names.sort();
+    assert_eq!(names, ["A Long File Name.txt", "big.bin"]);
+    assert_eq!(fs.stat("/docs").unwrap().file_type, FileType::Directory);
+}
+
+#[test_case]
+fn fat32_writes_to_mkfs_image_survive_remount() {
+    let (disk, fs) = mkfs_image();
+    let data: Vec<u8> = (0..50_000).map(|i| (i % 251) as u8).collect();
+    fs.create_dir("/docs/new dir").unwrap();
+    fs.create_file("/docs/new dir/data").unwrap();
+    fs.write_file("/docs/new dir/data", &data).unwrap();
+    fs.write_at("/hello.txt", 10, b"more\n").unwrap();
+    fs.remove("/docs/big.bin").unwrap();
+    fs.rename("/docs/A Long File Name.txt", "/moved.txt")
+        .unwrap();
+
+    let fs = Fat32::new(disk).unwrap();
+    assert_eq!(fs.read_file("/docs/new dir/data").unwrap(), data);
+    assert_eq!(fs.read_file("/hello.txt").unwrap(), b"hello fat\nmore\n");
+    assert!(!fs.exists("/docs/big.bin"));
+    assert_eq!(fs.read_file("/moved.txt").unwrap(), b"a long name\n");
+
+    // space freed by big.bin is handed out again
+    fs.create_file("/again").unwrap();
+    fs.write_file("/again", &big_pattern(20000)).unwrap();
+    assert_eq!(fs.read_file("/again").unwrap(), big_pattern(20000));
+}
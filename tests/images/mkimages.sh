@@ -0,0 +1,29 @@
+#!/bin/sh
+# Regenerates the disk images used by the filesystem tests. Needs e2fsprogs, dosfstools
+# and mtools.
+set -e
+cd "$(dirname "$0")"
+root=$(mktemp -d)
+fat=$(mktemp -d)
+trap 'rm -rf "$root" "$fat"' EXIT
+
+mkdir "$root/docs"
+printf 'hello ext2\n' > "$root/hello.txt"
+# 20000 bytes: needs the single indirect block with 1 KiB blocks
+python3 -c 'import sys; sys.stdout.buffer.write(bytes(i * 7 % 256 for i in range(20000)))' > "$root/docs/big.bin"
+ln -s hello.txt "$root/link"
+ln -s "$(printf 'd/%.0s' $(seq 40))target" "$root/longlink"
+
+rm -f ext2.img
+mke2fs -q -F -t ext2 -b 1024 -N 64 -L test -m 0 -E root_owner=0:0 -d "$root" ext2.img 256
+
+# FAT32 with 1 KiB clusters, small enough that mkfs.fat stores the size in the 16-bit field
+printf 'hello fat\n' > "$fat/hello.txt"
+printf 'a long name\n' > "$fat/long.txt"
+rm -f fat32.img
+mkfs.fat -C -F 32 -s 2 -n TEST -i 12345678 --invariant fat32.img 2048 > /dev/null
+export MTOOLS_SKIP_CHECK=1
+mmd -i fat32.img ::docs
+mcopy -i fat32.img "$fat/hello.txt" ::hello.txt
+mcopy -i fat32.img "$root/docs/big.bin" ::docs/big.bin
+mcopy -i fat32.img "$fat/long.txt" "::docs/A Long File Name.txt"
@@ -0,0 +1,120 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use zero::kernel::fs::{initrd, FileSystem, FileType, RamFs};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+fn cpio_entry(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+    let header = format!(
+        "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+        0,
+        mode,
+        0,
+        0,
+        1,
+        0,
+        data.len(),
+        0,
+        0,
+        0,
+        0,
+        name.len() + 1,
+        0
+    );
+    archive.extend_from_slice(header.as_bytes());
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    while archive.len() % 4 != 0 {
+        archive.push(0);
+    }
+    archive.extend_from_slice(data);
+    while archive.len() % 4 != 0 {
+        archive.push(0);
+    }
+}
+
+fn tar_entry(archive: &mut Vec<u8>, name: &str, mode: u32, typeflag: u8, data: &[u8]) {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(format!("{:07o}", mode).as_bytes());
+    header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+    header[156] = typeflag;
+    header[257..263].copy_from_slice(b"ustar\0");
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(data);
+    while archive.len() % 512 != 0 {
+        archive.push(0);
+    }
+}
+
+#[test_case]
+fn unpack_cpio() {
+    let mut archive = Vec::new();
+    cpio_entry(&mut archive, "etc", 0o040755, b"");
+    cpio_entry(&mut archive, "etc/motd", 0o100600, b"hello");
+    cpio_entry(&mut archive, "etc/issue", 0o120777, b"motd");
+    cpio_entry(&mut archive, "TRAILER!!!", 0, b"");
+
+    let fs = RamFs::new();
+    assert_eq!(initrd::unpack(&fs, &archive).unwrap(), 3);
+
+    let motd = fs.stat("/etc/motd").unwrap();
+    assert_eq!(motd.file_type, FileType::File);
+    assert_eq!(motd.mode, 0o600);
+    assert_eq!(fs.read_file("/etc/motd").unwrap(), b"hello");
+    assert_eq!(fs.readlink("/etc/issue").unwrap(), "motd");
+}
+
+#[test_case]
+fn unpack_tar_creates_parents() {
+    let mut archive = Vec::new();
+    tar_entry(&mut archive, "./bin/hello", 0o755, b'0', b"\x7fELF");
+    archive.extend_from_slice(&[0u8; 1024]);
+
+    let fs = RamFs::new();
+    initrd::unpack(&fs, &archive).unwrap();
+
+    assert_eq!(fs.stat("/bin").unwrap().file_type, FileType::Directory);
+    let hello = fs.stat("/bin/hello").unwrap();
+    assert_eq!(hello.mode, 0o755);
+    assert_eq!(hello.size, 4);
+}
+
+#[test_case]
+fn unpack_rejects_garbage() {
+    let fs = RamFs::new();
+    let garbage = String::from("definitely not an archive");
+    assert!(initrd::unpack(&fs, garbage.as_bytes()).is_err());
+}
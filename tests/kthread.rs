@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use zero::arch::x86_64::timer;
+use zero::kernel::kthread::{self, KthreadState};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+fn state(id: u64) -> Option<KthreadState> {
+    kthread::list()
+        .into_iter()
+        .find(|t| t.id == id)
+        .map(|t| t.state)
+}
+
+#[test_case]
+fn kthread_runs_and_exits() {
+    static RAN: AtomicBool = AtomicBool::new(false);
+    let id = kthread::spawn_kthread("test", || RAN.store(true, Ordering::SeqCst));
+    assert_ne!(id, kthread::current());
+    while !RAN.load(Ordering::SeqCst) {
+        kthread::yield_now();
+    }
+    kthread::yield_now();
+    assert_eq!(state(id), Some(KthreadState::Exited));
+}
+
+#[test_case]
+fn busy_threads_are_preempted() {
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    static STOP: AtomicBool = AtomicBool::new(false);
+    let id = kthread::spawn_kthread("spinner", || {
+        while !STOP.load(Ordering::SeqCst) {
+            COUNT.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+
+    // neither side yields, so only the timer can get the spinner going
+    let start = timer::ticks();
+    while COUNT.load(Ordering::SeqCst) == 0 {
+        assert!(timer::ticks() - start < 100, "spinner never ran");
+        core::hint::spin_loop();
+    }
+    STOP.store(true, Ordering::SeqCst);
+    while state(id) != Some(KthreadState::Exited) {
+        kthread::yield_now();
+    }
+}
+
+#[test_case]
+fn sleep_lets_others_run() {
+    static WOKE_AT: AtomicU64 = AtomicU64::new(0);
+    let start = timer::uptime_ms();
+    let id = kthread::spawn_kthread("sleeper", || {
+        kthread::sleep_ms(50);
+        WOKE_AT.store(timer::uptime_ms(), Ordering::SeqCst);
+    });
+    kthread::yield_now();
+    assert!(matches!(state(id), Some(KthreadState::Sleeping { .. })));
+
+    while WOKE_AT.load(Ordering::SeqCst) == 0 {
+        kthread::sleep_ms(10);
+    }
+    assert!(WOKE_AT.load(Ordering::SeqCst) >= start + 50);
+}
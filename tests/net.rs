@@ -0,0 +1,126 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use zero::arch::x86_64::timer;
+use zero::kernel::net::socket::Kind;
+use zero::kernel::net::{self, ipv4, Ipv4Addr, NetError, Socket, SocketAddrV4};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    zero::drivers::net::init();
+    net::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+fn local(port: u16) -> SocketAddrV4 {
+    SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)
+}
+
+#[test_case]
+fn header_checksum_verifies() {
+    let mut header = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8, 0x00,
+        0x01, 0xc0, 0xa8, 0x00, 0xc7,
+    ];
+    assert_eq!(ipv4::checksum(&header), 0xb861);
+    header[10..12].copy_from_slice(&0xb861u16.to_be_bytes());
+    assert_eq!(ipv4::checksum(&header), 0);
+}
+
+#[test_case]
+fn udp_over_loopback() {
+    let server = Socket::new(Kind::Datagram);
+    server.bind(local(5000)).unwrap();
+    let client = Socket::new(Kind::Datagram);
+    client.send_to(b"hello", local(5000)).unwrap();
+
+    let mut buf = [0u8; 16];
+    let (len, from) = server.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"hello");
+    assert_eq!(Some(from), client.local_addr().map(|a| local(a.port())));
+
+    // a second bind of the same port is refused
+    let other = Socket::new(Kind::Datagram);
+    assert_eq!(other.bind(local(5000)), Err(NetError::AddressInUse));
+}
+
+#[test_case]
+fn tcp_connects_and_transfers() {
+    let listener = Socket::new(Kind::Stream);
+    listener.bind(local(8080)).unwrap();
+    listener.listen(4).unwrap();
+
+    let client = Socket::new(Kind::Stream);
+    client.connect(local(8080)).unwrap();
+    let (server, remote) = listener.accept().unwrap();
+    assert_eq!(Some(remote), client.local_addr());
+
+    assert_eq!(client.send(b"ping"), Ok(4));
+    let mut buf = [0u8; 16];
+    assert_eq!(server.recv(&mut buf), Ok(4));
+    assert_eq!(&buf[..4], b"ping");
+
+    // more than a segment's worth arrives intact
+    let data: alloc::vec::Vec<u8> = (0..10000u32).map(|i| i as u8).collect();
+    let mut sent = 0;
+    while sent < data.len() {
+        sent += server.send(&data[sent..]).unwrap();
+    }
+    let mut received = alloc::vec::Vec::new();
+    let mut buf = [0u8; 1024];
+    while received.len() < data.len() {
+        let len = client.recv(&mut buf).unwrap();
+        received.extend_from_slice(&buf[..len]);
+    }
+    assert_eq!(received, data);
+
+    // closing one end is seen as end of stream at the other
+    drop(server);
+    assert_eq!(client.recv(&mut buf), Ok(0));
+}
+
+#[test_case]
+fn connecting_to_a_closed_port_is_refused() {
+    let client = Socket::new(Kind::Stream);
+    assert_eq!(client.connect(local(9)), Err(NetError::ConnectionRefused));
+}
+
+#[test_case]
+fn loopback_answers_ping() {
+    net::ping(Ipv4Addr::LOCALHOST, 7, 1, b"abc").unwrap();
+    let start = timer::uptime_ms();
+    loop {
+        net::poll();
+        if let Some((from, _)) = net::take_echo_reply(7, 1) {
+            assert_eq!(from, Ipv4Addr::LOCALHOST);
+            break;
+        }
+        assert!(timer::uptime_ms() - start < 1000, "no echo reply");
+    }
+}
@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use zero::drivers::pci::{self, config, Bar, Match, PciAddress, PciDevice, PciDriver};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    pci::init(&mut mapper, &mut frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+#[test_case]
+fn address_formats_like_lspci() {
+    assert_eq!(format!("{}", PciAddress::new(0, 0x1f, 2)), "00:1f.2");
+}
+
+#[test_case]
+fn host_bridge_is_found() {
+    let devices = pci::devices();
+    let host = devices
+        .iter()
+        .find(|d| d.address == PciAddress::new(0, 0, 0))
+        .expect("no device at 00:00.0");
+    assert_eq!((host.class, host.subclass), (0x06, 0x00));
+    assert_eq!(host.class_name(), "Host bridge");
+
+    for dev in &devices {
+        assert_eq!(config::read16(dev.address, 0), dev.vendor_id);
+    }
+}
+
+#[test_case]
+fn ide_bus_master_bar_is_decoded() {
+    // QEMU's default machine has a PIIX IDE controller with 16 bus-master ports in BAR4
+    let ide = pci::devices()
+        .into_iter()
+        .find(|d| (d.class, d.subclass) == (0x01, 0x01))
+        .expect("no IDE controller");
+    match ide.bar(4) {
+        Some(Bar::Io { size, .. }) => assert_eq!(size, 16),
+        other => panic!("unexpected BAR4: {:?}", other),
+    }
+    // sizing puts the original value back
+    assert_eq!(ide.bar(4), ide.bar(4));
+}
+
+static PROBED: AtomicUsize = AtomicUsize::new(0);
+
+fn probe_host(_dev: &PciDevice) -> bool {
+    PROBED.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+static HOST_DRIVER: PciDriver = PciDriver {
+    name: "test-host",
+    matches: &[Match::Class {
+        class: 0x06,
+        subclass: 0x00,
+    }],
+    probe: probe_host,
+};
+
+#[test_case]
+fn drivers_bind_to_matching_devices() {
+    pci::register_driver(&HOST_DRIVER);
+    assert!(PROBED.load(Ordering::Relaxed) >= 1);
+    assert_eq!(pci::driver_of(PciAddress::new(0, 0, 0)), Some("test-host"));
+
+    // a bound device isn't offered again
+    let probed = PROBED.load(Ordering::Relaxed);
+    pci::register_driver(&HOST_DRIVER);
+    assert_eq!(PROBED.load(Ordering::Relaxed), probed);
+}
@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use zero::kernel::fs::{pipe, pipe::CAPACITY, FdTable, File, FsError, Stream};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+#[test_case]
+fn bytes_come_out_in_order() {
+    let (reader, writer) = pipe();
+    assert!(matches!(writer.write(b"hello "), Ok(6)));
+    assert!(matches!(writer.write(b"world"), Ok(5)));
+
+    let mut buf = [0u8; 8];
+    assert!(matches!(reader.read(&mut buf), Ok(8)));
+    assert_eq!(&buf, b"hello wo");
+    assert!(matches!(reader.read(&mut buf), Ok(3)));
+    assert_eq!(&buf[..3], b"rld");
+}
+
+#[test_case]
+fn reads_see_end_of_file_once_the_writer_is_gone() {
+    let (reader, writer) = pipe();
+    writer.write(b"x").unwrap();
+    drop(writer);
+
+    let mut buf = [0u8; 4];
+    assert!(matches!(reader.read(&mut buf), Ok(1)));
+    assert!(matches!(reader.read(&mut buf), Ok(0)));
+}
+
+#[test_case]
+fn writes_fail_once_the_reader_is_gone() {
+    let (reader, writer) = pipe();
+    drop(reader);
+    assert!(matches!(writer.write(b"x"), Err(FsError::BrokenPipe)));
+}
+
+#[test_case]
+fn a_full_pipe_holds_its_capacity() {
+    let (reader, writer) = pipe();
+    let data = [0xAB; CAPACITY];
+    assert!(matches!(writer.write(&data), Ok(CAPACITY)));
+
+    let mut buf = [0u8; CAPACITY + 1];
+    assert!(matches!(reader.read(&mut buf), Ok(CAPACITY)));
+    assert!(buf[..CAPACITY].iter().all(|&b| b == 0xAB));
+}
+
+#[test_case]
+fn writes_past_capacity_come_back_short() {
+    let (_reader, writer) = pipe();
+    let data = [0xAB; CAPACITY + 10];
+    assert!(matches!(writer.write(&data), Ok(CAPACITY)));
+}
+
+#[test_case]
+fn each_end_only_goes_one_way() {
+    let (reader, writer) = pipe();
+    assert!(matches!(reader.write(b"x"), Err(FsError::BadDescriptor)));
+    assert!(matches!(
+        writer.read(&mut [0u8; 1]),
+        Err(FsError::BadDescriptor)
+    ));
+}
+
+#[test_case]
+fn duplicated_descriptors_keep_the_pipe_open() {
+    let (reader, writer) = pipe();
+    let mut fds = FdTable::new();
+    let read_fd = fds.insert(File::from_stream("pipe", Arc::new(reader)));
+    let write_fd = fds.insert(File::from_stream("pipe", Arc::new(writer)));
+
+    assert!(matches!(fds.dup2(write_fd, 5), Ok(5)));
+    fds.close(write_fd).unwrap();
+    fds.get(5).unwrap().lock().write(b"via dup").unwrap();
+
+    let mut buf = [0u8; 16];
+    let input = fds.get(read_fd).unwrap();
+    assert!(matches!(input.lock().read(&mut buf), Ok(7)));
+    assert_eq!(&buf[..7], b"via dup");
+
+    // the last descriptor for the write end closes it
+    fds.close(5).unwrap();
+    assert!(matches!(input.lock().read(&mut buf), Ok(0)));
+    assert!(matches!(fds.dup(write_fd), Err(FsError::BadDescriptor)));
+}
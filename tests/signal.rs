@@ -0,0 +1,216 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use zero::arch::x86_64::trap::TrapFrame;
+use zero::kernel::process::{self, Region};
+use zero::kernel::signal::{self, bit, SigAction, SignalState, SA_RESTORER, SIG_IGN};
+use zero::kernel::user::Credentials;
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+const HANDLER: u64 = 0x4000_0000_1000;
+const RESTORER: u64 = 0x4000_0000_2000;
+
+// makes a fresh current process whose "stack" is a kernel buffer, and a frame that looks
+// like it came from ring 3 with its stack pointer at the top of that buffer
+fn user_context(stack: &mut [u8]) -> TrapFrame {
+    let pid = process::create("test", Credentials::ROOT);
+    process::set_current(pid);
+    let start = stack.as_ptr() as u64;
+    let end = start + stack.len() as u64;
+    process::with_current(|p| {
+        p.regions.push(Region {
+            start,
+            end,
+            writable: true,
+            executable: false,
+            name: "[stack]".into(),
+        })
+    });
+    TrapFrame {
+        rip: 0x4000_0000_0042,
+        cs: 0x1b,
+        rflags: 0x202,
+        rsp: end - 64,
+        ss: 0x23,
+        rax: 7,
+        rcx: 11,
+        r11: 13,
+        ..TrapFrame::default()
+    }
+}
+
+fn handle(sig: u32) {
+    let action = SigAction {
+        handler: HANDLER,
+        flags: SA_RESTORER,
+        restorer: RESTORER,
+        mask: 0,
+    };
+    assert!(signal::set_action(sig, Some(action)).is_some());
+}
+
+#[test_case]
+fn raise_skips_ignored_signals() {
+    let mut state = SignalState::new();
+    state.raise(signal::SIGCHLD);
+    assert_eq!(state.pending, 0);
+    state.raise(signal::SIGTERM);
+    assert_eq!(state.pending, bit(signal::SIGTERM));
+
+    // a continue discards pending stops and the other way round
+    state.raise(signal::SIGTSTP);
+    state.raise(signal::SIGCONT);
+    assert_eq!(state.pending, bit(signal::SIGTERM) | bit(signal::SIGCONT));
+}
+
+#[test_case]
+fn kill_and_stop_cannot_be_caught_or_blocked() {
+    let mut stack = vec![0u8; 4096];
+    user_context(&mut stack);
+    let ignore = SigAction {
+        handler: SIG_IGN,
+        ..SigAction::default()
+    };
+    assert!(signal::set_action(signal::SIGKILL, Some(ignore)).is_none());
+    assert!(signal::set_action(signal::SIGSTOP, Some(ignore)).is_none());
+
+    signal::set_mask(signal::SIG_SETMASK, Some(u64::MAX)).unwrap();
+    let blocked = signal::set_mask(signal::SIG_BLOCK, None).unwrap();
+    assert_eq!(blocked & (bit(signal::SIGKILL) | bit(signal::SIGSTOP)), 0);
+}
+
+#[test_case]
+fn handlers_need_a_restorer() {
+    let mut stack = vec![0u8; 4096];
+    user_context(&mut stack);
+    let action = SigAction {
+        handler: HANDLER,
+        ..SigAction::default()
+    };
+    assert!(signal::set_action(signal::SIGUSR1, Some(action)).is_none());
+}
+
+#[test_case]
+fn handler_runs_and_sigreturn_restores_the_context() {
+    let mut stack = vec![0u8; 4096];
+    let original = user_context(&mut stack);
+    handle(signal::SIGUSR1);
+    let pid = process::current_pid().unwrap();
+    assert!(signal::send(pid, signal::SIGUSR1));
+
+    let mut frame = original;
+    signal::deliver(&mut frame);
+    assert_eq!(frame.rip, HANDLER);
+    assert_eq!(frame.rdi, signal::SIGUSR1 as u64);
+    // entered as if called: the return address is the restorer, rsp + 8 is 16-aligned
+    assert_eq!((frame.rsp + 8) % 16, 0);
+    assert!(frame.rsp + 128 < original.rsp);
+    assert_eq!(unsafe { *(frame.rsp as *const u64) }, RESTORER);
+
+    // the signal is blocked while its handler runs
+    let blocked = signal::set_mask(signal::SIG_BLOCK, None).unwrap();
+    assert_eq!(blocked, bit(signal::SIGUSR1));
+
+    // the handler returns into the restorer, which makes the sigreturn syscall
+    frame.rsp += 8;
+    frame.rax = 39;
+    signal::sigreturn(&mut frame);
+    assert_eq!(frame.rip, original.rip);
+    assert_eq!(frame.rsp, original.rsp);
+    assert_eq!((frame.rax, frame.rcx, frame.r11), (7, 11, 13));
+    assert_eq!(signal::set_mask(signal::SIG_BLOCK, None), Some(0));
+}
+
+#[test_case]
+fn blocked_signals_wait_for_unblocking() {
+    let mut stack = vec![0u8; 4096];
+    let original = user_context(&mut stack);
+    handle(signal::SIGUSR2);
+    signal::set_mask(signal::SIG_BLOCK, Some(bit(signal::SIGUSR2)));
+    signal::send(process::current_pid().unwrap(), signal::SIGUSR2);
+
+    let mut frame = original;
+    signal::deliver(&mut frame);
+    assert_eq!(frame.rip, original.rip);
+    assert!(!signal::interrupted());
+
+    signal::set_mask(signal::SIG_UNBLOCK, Some(bit(signal::SIGUSR2)));
+    assert!(signal::interrupted());
+    signal::deliver(&mut frame);
+    assert_eq!(frame.rip, HANDLER);
+}
+
+#[test_case]
+fn ignored_signals_are_dropped() {
+    let mut stack = vec![0u8; 4096];
+    let original = user_context(&mut stack);
+    let ignore = SigAction {
+        handler: SIG_IGN,
+        ..SigAction::default()
+    };
+    signal::set_action(signal::SIGINT, Some(ignore)).unwrap();
+    signal::send(process::current_pid().unwrap(), signal::SIGINT);
+
+    let mut frame = original;
+    signal::deliver(&mut frame);
+    assert_eq!(frame.rip, original.rip);
+    assert!(!signal::interrupted());
+}
+
+#[test_case]
+fn forged_frames_cannot_leave_user_space() {
+    let mut stack = vec![0u8; 4096];
+    let original = user_context(&mut stack);
+    handle(signal::SIGSEGV);
+    handle(signal::SIGUSR1);
+    signal::send(process::current_pid().unwrap(), signal::SIGUSR1);
+    let mut frame = original;
+    signal::deliver(&mut frame);
+
+    // point the saved rip into the kernel and raise IOPL
+    let saved = (frame.rsp + 8) as *mut TrapFrame;
+    unsafe {
+        (*saved).rip = 0xFFFF_8000_0000_0000;
+    }
+    frame.rsp += 8;
+    signal::sigreturn(&mut frame);
+    assert_eq!(frame.rip, HANDLER, "the frame is left alone");
+    assert!(signal::interrupted(), "a bad frame raises SIGSEGV");
+
+    unsafe {
+        (*saved).rip = original.rip;
+        (*saved).rflags = 0x3202;
+    }
+    signal::sigreturn(&mut frame);
+    assert_eq!(frame.rflags & 0x3000, 0);
+}
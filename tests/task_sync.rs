@@ -0,0 +1,187 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use zero::kernel::task::mpsc::{self, SendError, TryRecvError, TrySendError};
+use zero::kernel::task::sync::{Mutex, Notify, Semaphore};
+use zero::kernel::task::{self, executor::Executor, Either, Task};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+#[test_case]
+fn mutex_is_held_across_awaits() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut executor = Executor::new();
+    for id in 0..3 {
+        let log = log.clone();
+        executor.spawn(Task::new(async move {
+            let mut guard = log.lock().await;
+            guard.push(id);
+            task::yield_now().await;
+            guard.push(id);
+        }));
+    }
+    executor.run_until_idle();
+    assert_eq!(*log.try_lock().unwrap(), vec![0, 0, 1, 1, 2, 2]);
+}
+
+#[test_case]
+fn semaphore_limits_and_hands_out_permits_in_order() {
+    static RUNNING: AtomicU64 = AtomicU64::new(0);
+    static PEAK: AtomicU64 = AtomicU64::new(0);
+    let semaphore = Arc::new(Semaphore::new(2));
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut executor = Executor::new();
+    for id in 0..5 {
+        let semaphore = semaphore.clone();
+        let order = order.clone();
+        executor.spawn(Task::new(async move {
+            let _permit = semaphore.acquire().await;
+            order.lock().await.push(id);
+            let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+            PEAK.fetch_max(running, Ordering::SeqCst);
+            task::yield_now().await;
+            RUNNING.fetch_sub(1, Ordering::SeqCst);
+        }));
+    }
+    executor.run_until_idle();
+    assert_eq!(PEAK.load(Ordering::SeqCst), 2);
+    assert_eq!(semaphore.available_permits(), 2);
+    assert_eq!(*order.try_lock().unwrap(), vec![0, 1, 2, 3, 4]);
+}
+
+#[test_case]
+fn notify_one_is_kept_and_notify_waiters_wakes_all() {
+    static WOKEN: AtomicU64 = AtomicU64::new(0);
+    let notify = Arc::new(Notify::new());
+    let mut executor = Executor::new();
+
+    // nobody waits yet, so the notification waits for the first task
+    notify.notify_one();
+    for _ in 0..3 {
+        let notify = notify.clone();
+        executor.spawn(Task::new(async move {
+            notify.notified().await;
+            WOKEN.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    executor.run_until_idle();
+    assert_eq!(WOKEN.load(Ordering::SeqCst), 1);
+
+    notify.notify_waiters();
+    executor.run_until_idle();
+    assert_eq!(WOKEN.load(Ordering::SeqCst), 3);
+
+    // notify_waiters with nobody waiting is lost
+    notify.notify_waiters();
+    assert!(!notify_ready(&notify));
+}
+
+// whether `notified` completes right away
+fn notify_ready(notify: &Arc<Notify>) -> bool {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    DONE.store(false, Ordering::SeqCst);
+    let notify = notify.clone();
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        if let Either::Left(()) = task::select(notify.notified(), task::yield_now()).await {
+            DONE.store(true, Ordering::SeqCst);
+        }
+    }));
+    executor.run_until_idle();
+    DONE.load(Ordering::SeqCst)
+}
+
+#[test_case]
+fn bounded_channel_makes_senders_wait() {
+    let (tx, mut rx) = mpsc::channel(2);
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        for i in 0..5 {
+            tx.send(i).await.unwrap();
+        }
+    }));
+    executor.run_until_idle();
+    // two fit; the sender waits for room with the third
+    assert_eq!(rx.try_recv(), Ok(0));
+    assert_eq!(rx.try_recv(), Ok(1));
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+    let log = received.clone();
+    executor.spawn(Task::new(async move {
+        while let Some(value) = rx.recv().await {
+            log.lock().await.push(value);
+        }
+    }));
+    executor.run_until_idle();
+    assert_eq!(*received.try_lock().unwrap(), vec![2, 3, 4]);
+}
+
+#[test_case]
+fn channels_close_when_one_side_goes() {
+    let (tx, rx) = mpsc::unbounded();
+    assert!(tx.send(1).is_ok());
+    drop(rx);
+    assert_eq!(tx.send(2), Err(SendError(2)));
+
+    let (tx, mut rx) = mpsc::channel::<u32>(1);
+    assert!(tx.try_send(1).is_ok());
+    assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+    drop(tx);
+    assert_eq!(rx.try_recv(), Ok(1));
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Closed));
+}
+
+#[test_case]
+fn sleep_and_timeout() {
+    static RESULT: AtomicU64 = AtomicU64::new(0);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(task::time::run()));
+    executor.spawn(Task::new(async {
+        let never = task::sync::Notify::new();
+        let slow = task::timeout(20, never.notified()).await;
+        let fast = task::timeout(1000, task::sleep_ms(10)).await;
+        if slow.is_err() && fast.is_ok() {
+            RESULT.store(1, Ordering::SeqCst);
+        } else {
+            RESULT.store(2, Ordering::SeqCst);
+        }
+    }));
+    while RESULT.load(Ordering::SeqCst) == 0 {
+        executor.run_until_idle();
+        x86_64::instructions::hlt();
+    }
+    assert_eq!(RESULT.load(Ordering::SeqCst), 1);
+}
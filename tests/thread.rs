@@ -0,0 +1,186 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use zero::arch::x86_64::timer;
+use zero::arch::x86_64::trap::TrapFrame;
+use zero::kernel::process::{self, Pid, Region};
+use zero::kernel::thread;
+use zero::kernel::user::Credentials;
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+// makes a fresh current process with its main thread, whose memory is a kernel buffer,
+// and a frame that looks like the main thread trapped from ring 3
+fn user_context(memory: &mut [u32]) -> (Pid, TrapFrame) {
+    let pid = process::create("test", Credentials::ROOT);
+    process::set_current(pid);
+    thread::start(pid);
+    let start = memory.as_ptr() as u64;
+    let end = start + (memory.len() * 4) as u64;
+    process::with_current(|p| {
+        p.regions.push(Region {
+            start,
+            end,
+            writable: true,
+            executable: false,
+            name: "[heap]".into(),
+        })
+    });
+    let frame = TrapFrame {
+        rip: 0x4000_0000_0042,
+        cs: 0x1b,
+        rflags: 0x202,
+        rsp: 0x7fff_0000,
+        ss: 0x23,
+        rax: 7,
+        ..TrapFrame::default()
+    };
+    (pid, frame)
+}
+
+#[test_case]
+fn clone_starts_a_thread_on_its_own_stack() {
+    let mut memory = vec![0u32; 16];
+    let (pid, frame) = user_context(&mut memory);
+    let tid = thread::spawn(&frame, 0x7ffe_0000, Some(0x5000), 0).unwrap();
+    assert_ne!(tid, pid);
+    assert_eq!(thread::tids(pid), vec![pid, tid]);
+    assert_eq!(thread::current_tid(), Some(pid));
+
+    // kernel addresses are no place for a stack or thread-local storage
+    assert!(thread::spawn(&frame, 0xffff_8000_0000_0000, None, 0).is_none());
+    assert!(!thread::set_fs_base(0xffff_8000_0000_0000));
+    process::remove(pid);
+    assert!(thread::tids(pid).is_empty());
+}
+
+#[test_case]
+fn futex_wait_hands_the_cpu_to_another_thread() {
+    let mut memory = vec![5u32; 16];
+    let (pid, mut frame) = user_context(&mut memory);
+    let addr = memory.as_ptr() as u64;
+    let tid = thread::spawn(&frame, 0x7ffe_0000, Some(0x5000), 0).unwrap();
+
+    // a changed value means there is nothing to wait for
+    assert!(!thread::futex_wait(addr, 4, None));
+    assert!(!thread::futex_wait(addr + 2, 5, None));
+
+    assert!(thread::futex_wait(addr, 5, None));
+    frame.rax = 0;
+    thread::schedule(&mut frame);
+    assert_eq!(thread::current_tid(), Some(tid));
+    assert_eq!((frame.rax, frame.rsp), (0, 0x7ffe_0000));
+    assert_eq!(thread::fs_base(), Some(0x5000));
+
+    assert_eq!(thread::futex_wake(addr, 10), 1);
+    assert_eq!(thread::futex_wake(addr, 10), 0);
+    assert!(thread::exit_current());
+    thread::schedule(&mut frame);
+    assert_eq!(thread::current_tid(), Some(pid));
+    assert_eq!((frame.rax, frame.rsp), (0, 0x7fff_0000));
+    assert_eq!(thread::fs_base(), Some(0));
+    process::remove(pid);
+}
+
+#[test_case]
+fn blocked_threads_give_way_and_retry_a_tick_later() {
+    let mut memory = vec![0u32; 16];
+    let (pid, mut frame) = user_context(&mut memory);
+    let tid = thread::spawn(&frame, 0x7ffe_0000, None, 0).unwrap();
+
+    assert!(thread::block_current());
+    assert!(thread::current_blocked());
+    thread::schedule(&mut frame);
+    assert_eq!(thread::current_tid(), Some(tid));
+
+    // the first has been blocked for a tick when the second blocks, so it's due a retry
+    let start = timer::uptime_ms();
+    while timer::uptime_ms() == start {
+        core::hint::spin_loop();
+    }
+    assert!(thread::block_current());
+    thread::schedule(&mut frame);
+    assert_eq!(thread::current_tid(), Some(pid));
+    assert!(!thread::current_blocked());
+    assert_eq!((frame.rax, frame.rsp), (7, 0x7fff_0000));
+    process::remove(pid);
+}
+
+#[test_case]
+fn exiting_thread_clears_and_wakes_its_tid() {
+    let mut memory = vec![0u32; 16];
+    let (pid, mut frame) = user_context(&mut memory);
+    let clear_tid = memory.as_ptr() as u64 + 8;
+    let tid = thread::spawn(&frame, 0x7ffe_0000, None, clear_tid).unwrap();
+    memory[2] = tid as u32;
+
+    // the main thread joins the new one
+    assert!(thread::futex_wait(clear_tid, tid as u32, None));
+    thread::schedule(&mut frame);
+    assert_eq!(thread::current_tid(), Some(tid));
+
+    assert!(thread::exit_current());
+    thread::schedule(&mut frame);
+    assert_eq!(thread::current_tid(), Some(pid));
+    assert_eq!(unsafe { core::ptr::read_volatile(&memory[2]) }, 0);
+    assert_eq!(frame.rax, 0);
+
+    // the last thread to go takes the process with it
+    assert!(!thread::exit_current());
+    process::remove(pid);
+}
+
+#[test_case]
+fn futex_wait_times_out() {
+    let mut memory = vec![1u32; 16];
+    let (pid, mut frame) = user_context(&mut memory);
+    let addr = memory.as_ptr() as u64;
+
+    assert!(thread::futex_wait(addr, 1, Some(20)));
+    frame.rax = 0;
+    thread::schedule(&mut frame);
+    assert_eq!(frame.rax, u64::MAX);
+    assert_eq!(thread::current_tid(), Some(pid));
+    process::remove(pid);
+}
+
+#[test_case]
+fn futex_arguments_near_the_end_of_memory_fail_cleanly() {
+    let mut memory = vec![1u32; 16];
+    let (pid, _frame) = user_context(&mut memory);
+    let addr = memory.as_ptr() as u64;
+
+    assert!(!thread::futex_wait(u64::MAX - 3, 1, None));
+    assert!(thread::futex_wait(addr, 1, Some(u64::MAX)));
+    assert_eq!(thread::futex_wake(addr, 1), 1);
+    process::remove(pid);
+}
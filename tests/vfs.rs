@@ -0,0 +1,414 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use zero::kernel::fs::{perm, DevFs, FileSystem, FileType, FsError, MountTable, ProcFs, RamFs};
+use zero::kernel::kmsg::LogBuffer;
+use zero::kernel::password;
+use zero::kernel::process;
+use zero::kernel::user::{self, Credentials};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+fn mounted_tree() -> (MountTable, Arc<RamFs>, Arc<RamFs>) {
+    let table = MountTable::new();
+    let root = Arc::new(RamFs::new());
+    let mnt = Arc::new(RamFs::new());
+    table.mount("/", "ramfs", "none", root.clone()).unwrap();
+    table.create_dir("/mnt").unwrap();
+    table.mount("/mnt", "ramfs", "none", mnt.clone()).unwrap();
+    (table, root, mnt)
+}
+
+#[test_case]
+fn mount_routes_to_longest_prefix() {
+    let (table, root, mnt) = mounted_tree();
+
+    table.create_file("/mnt/data").unwrap();
+    table.create_file("/mntx").unwrap();
+
+    assert!(mnt.exists("/data"));
+    assert!(!root.exists("/mnt/data"));
+    assert!(root.exists("/mntx"));
+    assert_eq!(table.stat("/mnt").unwrap().name, "mnt");
+    assert_eq!(table.stat("/mnt").unwrap().file_type, FileType::Directory);
+}
+
+#[test_case]
+fn umount_uncovers_directory() {
+    let (table, _root, _mnt) = mounted_tree();
+
+    table.create_file("/mnt/data").unwrap();
+    assert!(matches!(table.remove("/mnt"), Err(FsError::Busy)));
+    table.umount("/mnt").unwrap();
+    assert!(!table.exists("/mnt/data"));
+    assert!(matches!(table.umount("/mnt"), Err(FsError::NotFound)));
+}
+
+#[test_case]
+fn mount_requires_directory() {
+    let (table, _root, _mnt) = mounted_tree();
+
+    table.create_file("/file").unwrap();
+    let fs = Arc::new(RamFs::new());
+    assert!(table.mount("/file", "ramfs", "none", fs.clone()).is_err());
+    assert!(table.mount("/missing", "ramfs", "none", fs).is_err());
+}
+
+#[test_case]
+fn ramfs_inode_numbers() {
+    let fs = RamFs::new();
+    fs.create_dir("/a").unwrap();
+    fs.create_file("/a/b").unwrap();
+
+    let root = fs.stat("/").unwrap();
+    let dir = fs.stat("/a").unwrap();
+    let file = fs.stat("/a/b").unwrap();
+    assert!(root.ino != dir.ino && dir.ino != file.ino);
+
+    fs.write_file("/a/b", b"data").unwrap();
+    assert_eq!(fs.stat("/a/b").unwrap().ino, file.ino);
+    assert_eq!(fs.list_dir("/a").unwrap()[0].ino, file.ino);
+}
+
+#[test_case]
+fn rename_moves_and_replaces() {
+    let fs = RamFs::new();
+    fs.create_dir("/a").unwrap();
+    fs.create_dir("/b").unwrap();
+    fs.create_file("/a/f").unwrap();
+    fs.write_file("/a/f", b"new").unwrap();
+    fs.create_file("/b/g").unwrap();
+    fs.write_file("/b/g", b"old").unwrap();
+    let ino = fs.stat("/a/f").unwrap().ino;
+
+    fs.rename("/a/f", "/b/g").unwrap();
+    assert!(!fs.exists("/a/f"));
+    assert_eq!(fs.read_file("/b/g").unwrap(), b"new");
+    assert_eq!(fs.stat("/b/g").unwrap().ino, ino);
+
+    fs.rename("/b", "/a/b").unwrap();
+    assert!(fs.exists("/a/b/g"));
+    assert!(matches!(
+        fs.rename("/a", "/a/b/c"),
+        Err(FsError::InvalidPath)
+    ));
+}
+
+#[test_case]
+fn rename_across_mounts_fails() {
+    let (table, _root, _mnt) = mounted_tree();
+    table.create_file("/f").unwrap();
+    assert!(matches!(
+        table.rename("/f", "/mnt/f"),
+        Err(FsError::CrossDevice)
+    ));
+}
+
+#[test_case]
+fn offset_io() {
+    let fs = RamFs::new();
+    fs.create_file("/f").unwrap();
+    fs.write_at("/f", 4, b"tail").unwrap();
+    assert_eq!(fs.read_file("/f").unwrap(), b"\0\0\0\0tail");
+
+    fs.append("/f", b"!").unwrap();
+    let mut buf = [0u8; 3];
+    assert_eq!(fs.read_at("/f", 6, &mut buf).unwrap(), 3);
+    assert_eq!(&buf, b"il!");
+    assert_eq!(fs.read_at("/f", 100, &mut buf).unwrap(), 0);
+
+    fs.truncate("/f", 2).unwrap();
+    assert_eq!(fs.stat("/f").unwrap().size, 2);
+    assert!(matches!(fs.truncate("/", 0), Err(FsError::NotAFile)));
+}
+
+#[test_case]
+fn huge_sizes_fail_with_no_space() {
+    let fs = RamFs::new();
+    fs.create_file("/f").unwrap();
+    assert!(matches!(
+        fs.write_at("/f", 1 << 40, b"x"),
+        Err(FsError::NoSpace)
+    ));
+    assert!(matches!(
+        fs.write_at("/f", usize::MAX, b"x"),
+        Err(FsError::NoSpace)
+    ));
+    assert!(matches!(fs.truncate("/f", 1 << 40), Err(FsError::NoSpace)));
+    assert_eq!(fs.stat("/f").unwrap().size, 0);
+
+    fs.write_file("/f", b"kept").unwrap();
+    assert!(matches!(
+        fs.write_file("/f", &vec![0; 2 << 20]),
+        Err(FsError::NoSpace)
+    ));
+    assert_eq!(fs.read_file("/f").unwrap(), b"kept");
+}
+
+#[test_case]
+fn rename_to_itself_needs_an_existing_source() {
+    let fs = RamFs::new();
+    fs.create_file("/f").unwrap();
+    fs.rename("/f", "/f").unwrap();
+    assert!(fs.exists("/f"));
+    assert!(matches!(
+        fs.rename("/missing", "/missing"),
+        Err(FsError::NotFound)
+    ));
+}
+
+#[test_case]
+fn path_resolution() {
+    use zero::kernel::fs::VFS;
+
+    assert_eq!(VFS::normalize_path("/a/./b/../c/"), "/a/c");
+    assert_eq!(VFS::normalize_path("/../.."), "/");
+    assert_eq!(VFS::resolve("/home", "notes.txt"), "/home/notes.txt");
+    assert_eq!(VFS::resolve("/home/user", "../x"), "/home/x");
+    assert_eq!(VFS::resolve("/home", "/etc/./motd"), "/etc/motd");
+}
+
+#[test_case]
+fn permission_checks() {
+    let (table, _, _) = mounted_tree();
+    table.create_dir("/home").unwrap();
+    table.create_dir("/tmp").unwrap();
+    table.chmod("/tmp", 0o1777).unwrap();
+
+    let alice = Credentials {
+        uid: 1000,
+        gid: 1000,
+    };
+    let bob = Credentials {
+        uid: 1001,
+        gid: 1001,
+    };
+
+    user::set_session(alice);
+    assert!(matches!(
+        table.create_file("/home/a"),
+        Err(FsError::PermissionDenied)
+    ));
+    table.create_file("/tmp/a").unwrap();
+    assert_eq!(table.stat("/tmp/a").unwrap().uid, 1000);
+    table.chmod("/tmp/a", 0o600).unwrap();
+    assert!(matches!(
+        table.chown("/tmp/a", 1001, 1001),
+        Err(FsError::PermissionDenied)
+    ));
+
+    // sticky /tmp: bob can neither read nor delete alice's file
+    user::set_session(bob);
+    assert!(matches!(
+        table.read_file("/tmp/a"),
+        Err(FsError::PermissionDenied)
+    ));
+    assert!(matches!(
+        table.remove("/tmp/a"),
+        Err(FsError::PermissionDenied)
+    ));
+    assert!(matches!(
+        table.chmod("/tmp/a", 0o666),
+        Err(FsError::PermissionDenied)
+    ));
+
+    user::set_session(Credentials::ROOT);
+    table.remove("/tmp/a").unwrap();
+    assert_eq!(perm::mode_string(FileType::Directory, 0o1777), "drwxrwxrwt");
+    assert_eq!(perm::mode_string(FileType::File, 0o640), "-rw-r-----");
+}
+
+#[test_case]
+fn symlinks_are_followed() {
+    let (table, _, mnt) = mounted_tree();
+    table.create_dir("/etc").unwrap();
+    table.create_file("/mnt/data").unwrap();
+    table.write_file("/mnt/data", b"payload").unwrap();
+
+    // absolute target across a mount, relative target, and a link to a directory
+    table.symlink("/mnt/data", "/etc/abs").unwrap();
+    table.symlink("../mnt/data", "/etc/rel").unwrap();
+    table.symlink("/mnt", "/m").unwrap();
+
+    assert_eq!(table.read_file("/etc/abs").unwrap(), b"payload");
+    assert_eq!(table.read_file("/etc/rel").unwrap(), b"payload");
+    assert_eq!(table.read_file("/m/data").unwrap(), b"payload");
+    assert_eq!(table.lookup("/m/data", true).unwrap(), "/mnt/data");
+    assert_eq!(table.stat("/etc/abs").unwrap().file_type, FileType::File);
+    assert_eq!(
+        table.lstat("/etc/abs").unwrap().file_type,
+        FileType::Symlink
+    );
+    assert_eq!(table.readlink("/etc/rel").unwrap(), "../mnt/data");
+
+    // removing the link leaves the target alone
+    table.remove("/etc/abs").unwrap();
+    assert!(mnt.exists("/data"));
+}
+
+#[test_case]
+fn symlink_loops_fail() {
+    let (table, _, _) = mounted_tree();
+    table.symlink("/b", "/a").unwrap();
+    table.symlink("/a", "/b").unwrap();
+
+    assert!(matches!(table.stat("/a"), Err(FsError::TooManyLinks)));
+    assert!(matches!(
+        table.read_file("/b/x"),
+        Err(FsError::TooManyLinks)
+    ));
+    assert!(table.lstat("/a").is_ok());
+}
+
+#[test_case]
+fn hard_links_share_data() {
+    let (table, _, _) = mounted_tree();
+    table.create_file("/a").unwrap();
+    table.write_file("/a", b"shared").unwrap();
+    table.link("/a", "/b").unwrap();
+
+    let a = table.stat("/a").unwrap();
+    assert_eq!(a.nlink, 2);
+    assert_eq!(a.ino, table.stat("/b").unwrap().ino);
+
+    table.remove("/a").unwrap();
+    assert_eq!(table.read_file("/b").unwrap(), b"shared");
+    assert_eq!(table.stat("/b").unwrap().nlink, 1);
+
+    table.create_dir("/dir").unwrap();
+    assert!(matches!(
+        table.link("/dir", "/c"),
+        Err(FsError::PermissionDenied)
+    ));
+    assert!(matches!(
+        table.link("/b", "/mnt/b"),
+        Err(FsError::CrossDevice)
+    ));
+}
+
+#[test_case]
+fn devfs_devices() {
+    let (table, _, _) = mounted_tree();
+    table.create_dir("/dev").unwrap();
+    table
+        .mount(
+            "/dev",
+            "devfs",
+            "none",
+            Arc::new(DevFs::with_default_devices()),
+        )
+        .unwrap();
+
+    let null = table.stat("/dev/null").unwrap();
+    assert_eq!(null.file_type, FileType::CharDevice);
+    assert_eq!(null.mode, 0o666);
+    assert!(table
+        .list_dir("/dev")
+        .unwrap()
+        .iter()
+        .any(|n| n.name == "ttyS0"));
+
+    let mut buf = [0xffu8; 16];
+    assert_eq!(table.read_at("/dev/null", 0, &mut buf).unwrap(), 0);
+    assert_eq!(table.write_at("/dev/null", 0, b"gone").unwrap(), 4);
+    assert_eq!(table.read_at("/dev/zero", 0, &mut buf).unwrap(), 16);
+    assert!(buf.iter().all(|&b| b == 0));
+
+    assert!(matches!(
+        table.create_file("/dev/new"),
+        Err(FsError::NotSupported)
+    ));
+    assert!(matches!(
+        table.list_dir("/dev/null"),
+        Err(FsError::NotADirectory)
+    ));
+}
+
+#[test_case]
+fn kmsg_ring_buffer_wraps() {
+    let mut log = LogBuffer::new();
+    log.write(b"hello ");
+    let mut buf = [0u8; 16];
+    assert_eq!(log.read_at(0, &mut buf), 6);
+    assert_eq!(&buf[..6], b"hello ");
+
+    for _ in 0..2000 {
+        log.write(b"0123456789");
+    }
+    // the oldest bytes have been overwritten; the newest are still there
+    let len = log.len();
+    assert_eq!(log.read_at(len - 10, &mut buf), 10);
+    assert_eq!(&buf[..10], b"0123456789");
+    assert_eq!(log.read_at(len, &mut buf), 0);
+}
+
+#[test_case]
+fn procfs_generates_files() {
+    let (table, _, _) = mounted_tree();
+    table.create_dir("/proc").unwrap();
+    table
+        .mount("/proc", "proc", "none", Arc::new(ProcFs::new()))
+        .unwrap();
+
+    let meminfo = table.read_file("/proc/meminfo").unwrap();
+    assert!(meminfo.starts_with(b"MemTotal:"));
+    assert!(table.write_file("/proc/uptime", b"0").is_err());
+
+    let pid = process::create("procfs-test", Credentials { uid: 7, gid: 7 });
+    let dir = format!("/proc/{}", pid);
+    let status = table.read_file(&format!("{}/status", dir)).unwrap();
+    assert!(status.starts_with(b"Name:\tprocfs-test\n"));
+    assert_eq!(table.stat(&dir).unwrap().uid, 7);
+    assert!(table
+        .list_dir("/proc")
+        .unwrap()
+        .iter()
+        .any(|n| n.name == pid.to_string()));
+
+    process::remove(pid);
+    assert!(matches!(table.stat(&dir), Err(FsError::NotFound)));
+}
+
+#[test_case]
+fn password_hashes() {
+    assert_eq!(password::sha256(b"abc")[..4], [0xba, 0x78, 0x16, 0xbf]);
+    let hash = password::hash("5a1t", "zero");
+    assert!(hash.starts_with("$sha256$5a1t$"));
+    assert!(password::verify(&hash, "zero"));
+    assert!(!password::verify(&hash, "Zero"));
+    assert_ne!(password::hash("other", "zero"), hash);
+    // locked accounts match nothing, not even an empty password
+    assert!(!password::verify("!", ""));
+}
@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(zero::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use zero::drivers::virtio::{Segment, VirtQueue};
+use zero::kernel::memory::dma::{self, DmaBuffer};
+use zero::kernel::memory::memory::phys_to_virt;
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+    use zero::kernel::memory::allocator;
+    use zero::kernel::memory::memory;
+    use zero::kernel::memory::memory::BootInfoFrameAllocator;
+
+    zero::init();
+    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&_boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    dma::init(&mut frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    zero::test_panic_handler(info)
+}
+
+fn segment(buffer: &DmaBuffer, offset: u64, len: u32, device_writes: bool) -> Segment {
+    Segment {
+        addr: buffer.phys() + offset,
+        len,
+        device_writes,
+    }
+}
+
+#[test_case]
+fn dma_buffers_are_contiguous_and_reused() {
+    let (used, total) = dma::stats();
+    assert_eq!(total, dma::POOL_PAGES);
+
+    let mut a = DmaBuffer::new(3 * 4096).unwrap();
+    assert_eq!(a.phys().as_u64() % 4096, 0);
+    assert!(a.as_slice().iter().all(|&b| b == 0));
+    a.as_mut_slice()[3 * 4096 - 1] = 7;
+    let b = DmaBuffer::new(100).unwrap();
+    assert_eq!(b.phys(), a.phys() + 3 * 4096u64);
+    assert_eq!(dma::stats().0, used + 4);
+
+    let first = a.phys();
+    drop(a);
+    // the freed run is found again
+    let c = DmaBuffer::new(2 * 4096).unwrap();
+    assert_eq!(c.phys(), first);
+    drop((b, c));
+    assert_eq!(dma::stats().0, used);
+    assert!(DmaBuffer::new((dma::POOL_PAGES + 1) * 4096).is_none());
+}
+
+#[test_case]
+fn virtqueue_chains_and_completes() {
+    let mut queue = VirtQueue::new(0, 8).unwrap();
+    // legacy layout: the used ring starts on its own page
+    assert_eq!(queue.avail_addr(), queue.desc_addr() + 8 * 16u64);
+    assert_eq!(queue.used_addr().as_u64() % 4096, 0);
+
+    let buffer = DmaBuffer::new(64).unwrap();
+    let chain = [
+        segment(&buffer, 0, 16, false),
+        segment(&buffer, 16, 32, true),
+        segment(&buffer, 48, 1, true),
+    ];
+    let head = queue.add(&chain).unwrap();
+    assert_eq!(queue.free_count(), 5);
+    assert!(queue.pop_used().is_none());
+
+    // act as the device: avail ring entry 0 names the head, then report it used
+    let avail = phys_to_virt(queue.avail_addr()).as_mut_ptr::<u16>();
+    let used = phys_to_virt(queue.used_addr()).as_mut_ptr::<u32>();
+    unsafe {
+        assert_eq!(avail.add(1).read_volatile(), 1);
+        assert_eq!(avail.add(2).read_volatile(), head);
+        used.add(1).write_volatile(head as u32);
+        used.add(2).write_volatile(33);
+        (used as *mut u16).add(1).write_volatile(1);
+    }
+    assert_eq!(queue.pop_used(), Some((head, 33)));
+    assert!(queue.pop_used().is_none());
+
+    queue.free_chain(head);
+    assert_eq!(queue.free_count(), 8);
+}
+
+#[test_case]
+fn virtqueue_refuses_chains_it_cannot_hold() {
+    let mut queue = VirtQueue::new(0, 4).unwrap();
+    let buffer = DmaBuffer::new(16).unwrap();
+    let one = [segment(&buffer, 0, 16, false)];
+    let five = [one[0]; 5];
+    assert!(queue.add(&five).is_none());
+    assert!(queue.add(&[]).is_none());
+    for _ in 0..4 {
+        queue.add(&one).unwrap();
+    }
+    assert!(queue.add(&one).is_none());
+}